@@ -1,7 +1,28 @@
-const run_freq: any = 500.0;
-const leg_pos: any = 0.6;
+struct Bone {
+    sprite: String,
+    offset: (f32, f32),
+    pivot_offset: (f32, f32),
+    flip: bool,
+    scale: f32 = 1.0,
+    rotation: f32 = 0.0,
+}
+
+enum Shape {
+    Capsule { width: f32, height: f32 },
+    Ball { radius: f32 },
+}
+
+struct Skeleton {
+    shape: Shape,
+    scale: f32,
+    offset: (f32, f32),
+    bones: Vec<Bone>,
+}
+
+const run_freq: f32 = 500.0;
+const leg_pos: f32 = 0.6;
 
-fn vx_sin(context: any, velocity: any) {
+fn vx_sin(context: any, velocity: any) -> f32 {
     if let Jump = context.action {
         (context.timer / 50.0).min(4.0)
     } else {
@@ -9,24 +30,23 @@ fn vx_sin(context: any, velocity: any) {
     }
 }
 
-fn body_offset(context: any, velocity: any) {
+fn body_offset(context: any, velocity: any) -> f32 {
     vx_sin(context, velocity).abs() * 0.04
 }
 
-fn vector_mag(vector: any) {
+fn vector_mag(vector: any) -> f32 {
     (vector.0 * vector.0 + vector.1 * vector.1).sqrt()
 }
 
-fn vector_theta(vector: any) {
+fn vector_theta(vector: any) -> f32 {
     vector.1.atan2(vector.0)
 }
 
-fn vector_cos(vector: any) {
+fn vector_cos(vector: any) -> f32 {
     vector_theta(vector).cos()
 }
 
-// TODO actually maybe function arguments should be pass by reference.....
-fn arm_position(arm_action: any, flip: any) {
+fn arm_position(arm_action: any, flip: bool) {
     // log(arm_action);
     match arm_action {
         None => (
@@ -45,7 +65,7 @@ fn arm_position(arm_action: any, flip: any) {
         ),
         Throw(vec) => (
             ((0.0), (-0.2)),
-            ((0.0), ((-0.3) + ((0.02) * vector_mag(vec.clone())))),
+            ((0.0), ((-0.3) + ((0.02) * vector_mag(&vec)))),
             if (vec.0 > 0.0) {
                 vector_theta(vec) / pi * 180.0 + 270.0
             } else {
@@ -53,7 +73,7 @@ fn arm_position(arm_action: any, flip: any) {
             },
         ),
         Swing {position, forward, object, direction} => (
-            (0.0, if direction.clone() == Down { 0.1 } else { -0.02 }),
+            (0.0, if &direction == Down { 0.1 } else { -0.02 }),
             (0.0, -0.3),
             if flip {
                 (-180.0 + position * 140.0 + match direction {
@@ -72,26 +92,32 @@ fn arm_position(arm_action: any, flip: any) {
     }
 }
 
-fn tool_tip(arm_action: any, facing: any) {
+fn tool_tip(arm_action: any, facing: any) -> (f32, f32) {
     let flip = facing == Right;
-    let (offset, pivot_offset, rotation) = arm_position(arm_action, flip.clone());
-    let rotation = rotation + if flip.clone() { -80.0 } else { -50.0 };
+    let (offset, pivot_offset, rotation) = arm_position(arm_action, flip);
+    let rotation = rotation + if flip { -80.0 } else { -50.0 };
     let rotation = rotation * pi / 180.0;
     let pivot_offset = (-0.8, 0.4);
     let angle = pivot_offset.1.atan2(pivot_offset.0);
-    let mag = vector_mag(pivot_offset.clone());
+    let mag = vector_mag(&pivot_offset);
     (
         offset.0 + mag * (angle + rotation).cos() * 0.5,
         offset.1 + mag * (angle + rotation).sin() * 0.5,
     )
 }
 
-fn female(context: any, velocity: any) {
-    let vx_sin = vx_sin(context.clone(), velocity.clone());
-    let body_offset = body_offset(context.clone(), velocity.clone());
+// Sprites are named after the character they belong to, like `female_arm.png`
+fn sprite(character: String, part: String) -> String {
+    format!("{}_{}.png", character, part)
+}
+
+fn female(context: any, velocity: any) -> Skeleton {
+    let character = "female";
+    let vx_sin = vx_sin(&context, &velocity);
+    let body_offset = body_offset(&context, &velocity);
     let bones = vec![
         Bone {
-            sprite: "female_arm.png",
+            sprite: sprite(character, "arm"),
             pivot_offset: (0.0, -0.3),
             offset: (vx_sin * 0.01, -0.2),
             flip: context.facing == Right,
@@ -99,7 +125,7 @@ fn female(context: any, velocity: any) {
         },
         // back leg
         Bone {
-            sprite: "female_leg.png",
+            sprite: sprite(character, "leg"),
             offset: (vx_sin * -0.05, leg_pos + body_offset * -1.0),
             pivot_offset: (0.0, -0.3),
             rotation: vx_sin * 5.0,
@@ -107,7 +133,7 @@ fn female(context: any, velocity: any) {
         },
         // front leg
         Bone {
-            sprite: "female_leg.png",
+            sprite: sprite(character, "leg"),
             offset: (vx_sin * 0.05, leg_pos + body_offset * -1.0),
             pivot_offset: (0.0, -0.3),
             rotation: vx_sin * -5.0,
@@ -115,14 +141,14 @@ fn female(context: any, velocity: any) {
         },
         // body
         Bone {
-            sprite: "female_body.png",
+            sprite: sprite(character, "body"),
             flip: context.facing == Right,
             offset: (0.0, 0.0),
             rotation: 0.0,
             pivot_offset: (0.0, 0.0),
         },
         Bone {
-            sprite: "female_head.png",
+            sprite: sprite(character, "head"),
             flip: if let Throw(vec) = context.arm_action {
                 vec.0 > 0.0
             } else {
@@ -134,7 +160,7 @@ fn female(context: any, velocity: any) {
         },
     ];
     if let Throw(throw) = context.arm_action {
-        let theta = vector_theta(throw.clone());
+        let theta = vector_theta(&throw);
         bones.push(Bone {
             sprite: "arrow_thinner.png",
             flip: true,
@@ -142,7 +168,7 @@ fn female(context: any, velocity: any) {
                 theta.cos() * 0.3,
                 -0.2 + 0.3 * theta.sin(),
             ),
-            pivot_offset: (0.0, 0.5 + -0.02 * vector_mag(throw.clone())),
+            pivot_offset: (0.0, 0.5 + -0.02 * vector_mag(&throw)),
             scale: 1.5,
             rotation: if (throw.0 > 0.0) {
                 ((theta / pi) * 180.0) + 180.0 + -90.0
@@ -162,7 +188,7 @@ fn female(context: any, velocity: any) {
             scale: 1.3,
         });
         bones.push(Bone {
-            sprite: "female_arm.png",
+            sprite: sprite(character, "arm"),
             flip: context.facing == Right,
             offset: offset.clone(),
             pivot_offset: pivot_offset.clone(),
@@ -170,7 +196,7 @@ fn female(context: any, velocity: any) {
         })
     } else if let Some(vec) = context.pointing {
         bones.push(Bone {
-            sprite: "female_arm.png",
+            sprite: sprite(character, "arm"),
             flip: context.facing == Right,
             offset: ((0.0), (-0.02)),
             pivot_offset: ((0), (-0.3)),
@@ -179,7 +205,7 @@ fn female(context: any, velocity: any) {
     } else {
         let (offset, pivot_offset, rotation) = arm_position(context.arm_action, context.facing == Right);
         bones.push(Bone {
-            sprite: "female_arm.png",
+            sprite: sprite(character, "arm"),
             flip: context.facing == Right,
             offset: offset.clone(),
             pivot_offset: pivot_offset.clone(),
@@ -188,7 +214,7 @@ fn female(context: any, velocity: any) {
     };
 
     if let Throw(vec) = context.arm_action {
-        let theta = vector_theta(vec.clone());
+        let theta = vector_theta(&vec);
         bones.push(Bone {
             sprite: "bow.png",
             flip: true,
@@ -212,7 +238,7 @@ fn female(context: any, velocity: any) {
             height: 0.1,
         },
         scale: 0.3,
-        offset: (0.0, body_offset(context.clone(), velocity.clone())),
+        offset: (0.0, body_offset(&context, &velocity)),
         bones: bones,
     }
 }
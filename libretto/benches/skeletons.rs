@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const SKELETONS: &str = include_str!("../../assets/skeletons.lt.rs");
+
+fn call(scope: &mut libretto::Scope, name: &str, args: &[libretto::Expr]) -> libretto::Expr {
+    scope
+        .call_fn_raw(name, args.to_vec(), libretto::Pos::default())
+        .unwrap()
+}
+
+fn skeletons(c: &mut Criterion) {
+    let ctx = libretto::eval_expr(
+        r#"Ctx { facing: Right, action: Walk, pointing: None, arm_action: Throw((1.0, -1.0)), timer: 120.0 }"#,
+    )
+    .unwrap();
+    let velocity = libretto::eval_expr("(1.0, 0.0)").unwrap();
+    let swing = libretto::eval_expr(
+        r#"Swing { position: 0.5, forward: true, object: "sword.png", direction: Up }"#,
+    )
+    .unwrap();
+    let facing = libretto::eval_expr("Left").unwrap();
+
+    for (label, bytecode) in &[("bytecode", true), ("tree-walker", false)] {
+        let mut scope = libretto::eval_file(SKELETONS).unwrap();
+        scope.set_bytecode(*bytecode);
+        c.bench_function(&format!("female ({})", label), |b| {
+            b.iter(|| call(&mut scope, "female", &[ctx.clone(), velocity.clone()]))
+        });
+        c.bench_function(&format!("tool_tip ({})", label), |b| {
+            b.iter(|| call(&mut scope, "tool_tip", &[swing.clone(), facing.clone()]))
+        });
+    }
+}
+
+criterion_group!(benches, skeletons);
+criterion_main!(benches);
@@ -1,8 +1,20 @@
+use crate::collections::{array_method, cmp, object_method};
+use crate::compile::Function;
 use crate::error::{EvalError, EvalErrorDesc};
-use crate::scope::Scope;
+use crate::numeric::{float_method, int_method};
+use crate::schema::{check, Decl, Signature, TypeDecl};
+use crate::scope::{copy_value, Scope};
+use crate::strings::{display, string_method};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::sync::Arc;
 
 pub type Args = Vec<String>;
 
+/// A variable bound by a pattern: name, value, and whether it was declared `mut`.
+/// The name is borrowed from the pattern, so matching doesn't copy it.
+pub type Binding<'p> = (&'p str, Expr, bool);
+
 trait TryMap<T> {
     fn try_map<U, E, F: Fn(T) -> Result<U, E>>(self, f: F) -> Result<Vec<U>, E>;
 }
@@ -19,9 +31,17 @@ impl<T> TryMap<T> for Vec<T> {
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Statement {
-    Let(Pattern, Expr),
+    /// `let` or `const`, with the type it was annotated with
+    Let(Pattern, Option<TypeDecl>, Expr),
+    Assign(Place, Expr),
     ExprDesc(Expr),
-    FnDefn(String, Args, Expr),
+    FnDefn(String, Args, Signature, Expr),
+    /// `use "path.lt.rs";` evaluates another file into this scope
+    Use(String, Pos),
+    /// `mod name;` loads `name.lt.rs` so its contents are available as `name::item`
+    Mod(String, Pos),
+    /// A `struct` or `enum` declaration
+    Decl(Decl, Pos),
 }
 
 pub struct Locals {
@@ -59,20 +79,75 @@ impl LocalVars {
         self.last().fns.push(key.to_owned());
     }
     fn check(&mut self, key: &str) -> bool {
-        self.last().vbls.contains(&key.to_owned())
+        self.0.iter().any(|locals| locals.vbls.iter().any(|name| name == key))
     }
     fn check_fn(&mut self, key: &str) -> bool {
-        self.last().fns.contains(&key.to_owned())
+        self.0.iter().any(|locals| locals.fns.iter().any(|name| name == key))
     }
 }
 
 impl Statement {
     pub fn walk<E, F: Fn(&mut Expr) -> Result<(), E>>(&mut self, f: &F) -> Result<(), E> {
         match self {
-            Statement::Let(_, v) => v.walk(f),
+            Statement::Let(_, _, v) => v.walk(f),
+            Statement::Assign(place, v) => {
+                for member in place.members.iter_mut() {
+                    for index in member.exprs_mut() {
+                        index.walk(f)?;
+                    }
+                }
+                v.walk(f)
+            }
             Statement::ExprDesc(v) => v.walk(f),
-            Statement::FnDefn(_, _, body) => body.walk(f),
+            Statement::FnDefn(_, _, _, body) => body.walk(f),
+            Statement::Decl(decl, _) => {
+                for default in decl.defaults_mut() {
+                    default.walk(f)?;
+                }
+                Ok(())
+            }
+            Statement::Use(..) | Statement::Mod(..) => Ok(()),
+        }
+    }
+
+    /// Mark every position in this statement as coming from the given file
+    pub fn set_file(&mut self, file: usize) {
+        fn set_place(stmt: &mut Statement, file: usize) {
+            match stmt {
+                Statement::Assign(place, _) => {
+                    place.pos.file = file;
+                    for member in place.members.iter_mut() {
+                        if let Access::Index(_, pos) = member {
+                            pos.file = file;
+                        }
+                    }
+                }
+                Statement::Use(_, pos) | Statement::Mod(_, pos) | Statement::Decl(_, pos) => {
+                    pos.file = file
+                }
+                _ => (),
+            }
         }
+        set_place(self, file);
+        let _ = self.walk::<(), _>(&|e: &mut Expr| {
+            e.pos.file = file;
+            match &mut e.desc {
+                ExprDesc::Block(stmts, _) => {
+                    for stmt in stmts {
+                        set_place(stmt, file);
+                    }
+                }
+                ExprDesc::MemberAccess(_, items) => {
+                    for item in items {
+                        if let Access::Index(_, pos) = item {
+                            pos.file = file;
+                        }
+                    }
+                }
+                _ => (),
+            }
+            Ok(())
+        });
     }
 
     pub fn move_nonlocal_vars(
@@ -81,7 +156,7 @@ impl Statement {
         scope: &mut Scope,
     ) -> Result<(), EvalError> {
         match self {
-            Statement::Let(pattern, value) => {
+            Statement::Let(pattern, _, value) => {
                 value.move_nonlocal_vars(local_vars, scope)?;
                 let mut bindings = vec![];
                 pattern_names(pattern, &mut bindings);
@@ -91,12 +166,27 @@ impl Statement {
                     local_vars.add(&name);
                 }
             }
+            Statement::Assign(place, value) => {
+                value.move_nonlocal_vars(local_vars, scope)?;
+                for member in place.members.iter_mut() {
+                    for index in member.exprs_mut() {
+                        index.move_nonlocal_vars(local_vars, scope)?;
+                    }
+                }
+                // Captured variables are copies, so changing them wouldn't do what you expect
+                if !local_vars.check(&place.name) {
+                    return Err(
+                        EvalErrorDesc::AssignToImmutable(place.name.clone()).with_pos(place.pos)
+                    );
+                }
+            }
             Statement::ExprDesc(e) => {
                 e.move_nonlocal_vars(local_vars, scope)?;
             }
-            Statement::FnDefn(name, _args, _body) => {
+            Statement::FnDefn(name, _args, _signature, _body) => {
                 local_vars.add_fn(name);
             }
+            Statement::Use(..) | Statement::Mod(..) | Statement::Decl(..) => (),
         }
         Ok(())
     }
@@ -104,55 +194,132 @@ impl Statement {
     pub fn eval(self, scope: &mut Scope) -> Result<(), EvalError> {
         // println!(">> Statement eval {:?} with scope: {}", self, scope.show());
         match self {
-            Statement::Let(pattern, mut value) => {
+            Statement::Let(pattern, _, mut value) => {
                 value.eval(scope)?;
                 let pos = value.pos;
                 if let Some(bindings) = match_pattern(
-                    pattern,
+                    &pattern,
                     value,
                     pos
                 )? {
-                    for (name, value) in bindings {
-                        scope.set_raw(&name, value)
-                    }
+                    set_bindings(scope, bindings);
                 } else {
                     return Err(EvalErrorDesc::Unmatched("if let pattern".to_owned()).with_pos(pos))
                 }
             }
+            Statement::Assign(mut place, mut value) => {
+                value.eval(scope)?;
+                for member in place.members.iter_mut() {
+                    for index in member.exprs_mut() {
+                        index.eval(scope)?;
+                    }
+                }
+                let mut target = scope
+                    .get_assignable(&place.name)
+                    .map_err(|desc| desc.with_pos(place.pos))?;
+                for member in place.members.iter_mut() {
+                    target = step_mut(target, member.step(), place.pos)?;
+                }
+                *target = value;
+            }
             Statement::ExprDesc(mut e) => {
                 e.eval(scope)?;
             }
-            Statement::FnDefn(name, args, body) => {
-                scope.set_fn(&name, args, body)
+            Statement::FnDefn(name, args, signature, body) => {
+                let function = Function::compile(args, body).with_signature(signature);
+                scope.set_compiled_fn(&name, Arc::new(function))
             }
+            Statement::Use(path, pos) => scope.use_file(&path, pos)?,
+            Statement::Mod(name, pos) => scope.load_module(&name, pos)?,
+            Statement::Decl(decl, _) => scope.declare(decl),
         };
         Ok(())
     }
 }
 
+/// One step of a member access chain: `.offset`, `.len()` or `[i]`
+#[derive(PartialEq, Debug, Clone)]
+pub enum Access {
+    Field(String),
+    Method(String, Vec<Expr>),
+    /// The index, and where it was written so that bounds errors can point at it
+    Index(Box<Expr>, Pos),
+}
+
+impl Access {
+    /// The method arguments or the index
+    pub fn exprs_mut(&mut self) -> &mut [Expr] {
+        match self {
+            Access::Field(_) => &mut [],
+            Access::Method(_, args) => args,
+            Access::Index(index, _) => std::slice::from_mut(&mut **index),
+        }
+    }
+
+    /// Takes out the evaluated arguments or index, ready to be applied to a value
+    pub(crate) fn step(&mut self) -> Step<'_> {
+        match self {
+            Access::Field(name) => Step::Field(name),
+            Access::Method(name, args) => Step::Method(name, std::mem::take(args)),
+            Access::Index(index, pos) => {
+                Step::Index(std::mem::replace(&mut **index, ExprDesc::Unit.into()), *pos)
+            }
+        }
+    }
+}
+
+/// An access with its arguments or index already evaluated
+pub(crate) enum Step<'a> {
+    Field(&'a str),
+    Method(&'a str, Vec<Expr>),
+    Index(Expr, Pos),
+}
+
+/// The target of an assignment, like `bone.offset.0` or `bones[i].rotation`.
+/// Only has `Field` and `Index` accesses.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Place {
+    pub name: String,
+    pub members: Vec<Access>,
+    pub pos: Pos,
+}
+
+impl Place {
+    pub fn to_expr(&self) -> Expr {
+        let ident = ExprDesc::Ident(self.name.clone()).with_pos(self.pos);
+        if self.members.is_empty() {
+            ident
+        } else {
+            ExprDesc::MemberAccess(Box::new(ident), self.members.clone()).with_pos(self.pos)
+        }
+    }
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum Type {
     F32,
     I32,
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
 pub struct Pos {
     pub start: (usize, usize),
     pub end: (usize, usize),
+    /// Which file this came from. 0 is source that wasn't loaded from a file, see `Scope::file_path`
+    pub file: usize,
 }
 
 impl Pos {
     pub fn is_empty(&self) -> bool {
         self.start == (0, 0) && self.end == (0, 0)
     }
-}
 
-impl Default for Pos {
-    fn default() -> Self {
-        Pos {
-            start: (0, 0),
-            end: (0, 0),
+    /// This position, or `other` if this one is empty
+    pub fn or(self, other: Pos) -> Pos {
+        if self.is_empty() {
+            other
+        } else {
+            self
         }
     }
 }
@@ -168,6 +335,7 @@ impl From<&pest::Span<'_>> for Pos {
         Pos {
             start: span.start_pos().line_col(),
             end: span.end_pos().line_col(),
+            file: 0,
         }
     }
 }
@@ -209,6 +377,11 @@ impl std::ops::Deref for Expr {
 pub enum ExprDesc {
     Float(f32),
     Int(i32),
+    /// An integer that doesn't fit in an `i32`, like `5000000000i64`. These come from
+    /// serializing Rust values; scripts can pass them around, or convert them with `as`.
+    Long(i128),
+    /// A float that an `f32` can't hold exactly, like `0.1f64`
+    Double(f64),
     Bool(bool),
     Char(char),
     String(String),
@@ -227,21 +400,51 @@ pub enum ExprDesc {
     Minus(Box<Expr>, Box<Expr>),
     Times(Box<Expr>, Box<Expr>),
     Divide(Box<Expr>, Box<Expr>),
+    Modulo(Box<Expr>, Box<Expr>),
+
+    BitAnd(Box<Expr>, Box<Expr>),
+    BitOr(Box<Expr>, Box<Expr>),
+    BitXor(Box<Expr>, Box<Expr>),
+    Shl(Box<Expr>, Box<Expr>),
+    Shr(Box<Expr>, Box<Expr>),
 
     Eq(Box<Expr>, Box<Expr>),
     Neq(Box<Expr>, Box<Expr>),
     Lt(Box<Expr>, Box<Expr>),
     Gt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
+
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
 
-    MemberAccess(Box<Expr>, Vec<(String, Option<Vec<Expr>>)>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    /// `&value`: a copy of a variable, which stays in scope. The same as `value.clone()`;
+    /// nothing can be changed through it.
+    Ref(Box<Expr>),
+
+    MemberAccess(Box<Expr>, Vec<Access>),
     Cast(Box<Expr>, Type),
 
     Block(Vec<Statement>, Box<Expr>),
-    // Lambda(Args, Box<Expr>),
+    Lambda(Args, Box<Expr>),
+    /// An evaluated lambda: its compiled body, and the values of the variables it captured,
+    /// in the order of the function's `captures`
+    Closure(Arc<Function>, Arc<[Expr]>),
     FnCall(String, Vec<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
 
     IfChain(Vec<(IfCond, Expr)>, Option<Box<Expr>>),
-    Match(Box<Expr>, Vec<(Pattern, Expr)>),
+    /// Arms are a pattern, an optional `if` guard, and the body
+    Match(Box<Expr>, Vec<(Pattern, Option<Expr>, Expr)>),
+
+    For(Pattern, Box<Expr>, Box<Expr>),
+    While(Box<IfCond>, Box<Expr>),
+    Break,
+    Continue,
+    /// `a..b`, or `a..=b` when inclusive
+    Range(Box<Expr>, Box<Expr>, bool),
 
     Moved,
 }
@@ -258,11 +461,22 @@ pub enum Const {
 #[derive(PartialEq, Debug, Clone)]
 pub enum Pattern {
     Ident(String),
+    MutIdent(String),
     Const(Const),
     Any,
     TupleStruct(String, Vec<Pattern>),
     Tuple(Vec<Pattern>),
     Struct(String, Vec<(String, Pattern)>),
+    /// `A | B`, the first alternative that matches is used
+    Or(Vec<Pattern>),
+    /// `0.0..=1.0`, and `0..10` when not inclusive
+    Range(Const, Const, bool),
+    /// `name @ pattern`
+    Bind(String, Box<Pattern>),
+    /// `[first, rest @ ..]`, matching arrays
+    Slice(Vec<Pattern>),
+    /// The `..` in a slice pattern, which can bind the items it covers
+    Rest(Option<String>),
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -275,10 +489,7 @@ impl From<ExprDesc> for Expr {
     fn from(desc: ExprDesc) -> Self {
         Expr {
             desc,
-            pos: Pos {
-                start: (0, 0),
-                end: (0, 0),
-            },
+            pos: Pos::default(),
         }
     }
 }
@@ -337,6 +548,8 @@ impl Expr {
             ExprDesc::Float(_)
             | ExprDesc::Moved
             | ExprDesc::Int(_)
+            | ExprDesc::Long(_)
+            | ExprDesc::Double(_)
             | ExprDesc::Bool(_)
             | ExprDesc::String(_)
             | ExprDesc::Char(_)
@@ -373,13 +586,26 @@ impl Expr {
             | ExprDesc::Minus(a, b)
             | ExprDesc::Times(a, b)
             | ExprDesc::Divide(a, b)
+            | ExprDesc::Modulo(a, b)
+            | ExprDesc::BitAnd(a, b)
+            | ExprDesc::BitOr(a, b)
+            | ExprDesc::BitXor(a, b)
+            | ExprDesc::Shl(a, b)
+            | ExprDesc::Shr(a, b)
             | ExprDesc::Eq(a, b)
             | ExprDesc::Neq(a, b)
             | ExprDesc::Lt(a, b)
-            | ExprDesc::Gt(a, b) => {
+            | ExprDesc::Gt(a, b)
+            | ExprDesc::Le(a, b)
+            | ExprDesc::Ge(a, b)
+            | ExprDesc::And(a, b)
+            | ExprDesc::Or(a, b) => {
                 a.walk(f)?;
                 b.walk(f)?;
             }
+            ExprDesc::Neg(a) | ExprDesc::Not(a) | ExprDesc::Ref(a) => {
+                a.walk(f)?;
+            }
             ExprDesc::Block(stmts, last) => {
                 for stmt in stmts {
                     stmt.walk(f)?;
@@ -393,12 +619,30 @@ impl Expr {
                 }
             }
 
+            ExprDesc::Lambda(_args, body) => {
+                body.walk(f)?;
+            }
+            // already evaluated, and shared with its copies
+            ExprDesc::Closure(_, _) => (),
+
+            ExprDesc::Call(target, args) => {
+                target.walk(f)?;
+                for arg in args.iter_mut() {
+                    arg.walk(f)?;
+                }
+            }
+
             ExprDesc::Cast(expr, _typ) => {
                 expr.walk(f)?;
             }
 
-            ExprDesc::MemberAccess(expr, _items) => {
+            ExprDesc::MemberAccess(expr, items) => {
                 expr.walk(f)?;
+                for item in items.iter_mut() {
+                    for arg in item.exprs_mut() {
+                        arg.walk(f)?;
+                    }
+                }
             }
 
             ExprDesc::IfChain(chain, else_) => {
@@ -413,39 +657,58 @@ impl Expr {
                         }
                     }
                 }
-                match else_.take() {
-                    None => (),
-                    Some(mut block) => {
-                        block.walk(f)?;
-                    }
+                if let Some(block) = else_ {
+                    block.walk(f)?;
                 }
             }
 
             ExprDesc::Match(value, cases) => {
                 value.walk(f)?;
-                for (_pattern, body) in cases {
+                for (_pattern, guard, body) in cases {
+                    if let Some(guard) = guard {
+                        guard.walk(f)?;
+                    }
                     body.walk(f)?;
                 }
             }
+
+            ExprDesc::For(_pattern, iterable, body) => {
+                iterable.walk(f)?;
+                body.walk(f)?;
+            }
+
+            ExprDesc::While(cond, body) => {
+                match cond.as_mut() {
+                    IfCond::Value(value) | IfCond::IfLet(_, value) => value.walk(f)?,
+                }
+                body.walk(f)?;
+            }
+
+            ExprDesc::Break | ExprDesc::Continue => (),
+
+            ExprDesc::Range(start, end, _inclusive) => {
+                start.walk(f)?;
+                end.walk(f)?;
+            }
         }
         Ok(())
     }
 
     pub fn eval(&mut self, scope: &mut Scope) -> Result<(), EvalError> {
+        let pos = self.pos;
+        scope.usage.tick(pos)?;
         match &mut self.desc {
             ExprDesc::Float(_)
             | ExprDesc::Moved
             | ExprDesc::Int(_)
+            | ExprDesc::Long(_)
+            | ExprDesc::Double(_)
             | ExprDesc::Bool(_)
             | ExprDesc::String(_)
             | ExprDesc::Char(_)
+            | ExprDesc::Closure(_, _)
             | ExprDesc::Unit => Ok(()),
-            ExprDesc::Tuple(items) | ExprDesc::Array(items) => {
-                for item in items {
-                    item.eval(scope)?;
-                }
-                Ok(())
-            }
+            ExprDesc::Tuple(items) | ExprDesc::Array(items) => eval_all(items, scope),
             ExprDesc::Object(items) => {
                 for (_key, value) in items {
                     value.eval(scope)?;
@@ -458,306 +721,130 @@ impl Expr {
                 }
                 Ok(())
             }
-            ExprDesc::Ident(name) => match scope.move_raw(&name) {
-                None => Err(EvalErrorDesc::MissingReference(name.to_string()).with_pos(self.pos)),
-                Some(expr) => {
-                    *self = expr;
-                    Ok(())
-                }
-            },
+            ExprDesc::Ident(name) => {
+                *self = scope.move_raw(name, self.pos)?;
+                Ok(())
+            }
             ExprDesc::Struct(_name, items) => {
                 for (_key, value) in items {
                     value.eval(scope)?;
                 }
-                Ok(())
+                check(self, scope)
             }
             ExprDesc::NamedTuple(_name, items) => {
-                for item in items {
-                    item.eval(scope)?;
-                }
-                Ok(())
+                eval_all(items, scope)?;
+                check(self, scope)
             }
 
             // some computation!
-            ExprDesc::Plus(a, b) => {
-                a.eval(scope)?;
-                b.eval(scope)?;
-                self.desc = match (&mut a.as_mut().desc, &mut b.as_mut().desc) {
-                    (ExprDesc::Int(a), ExprDesc::Int(b)) => ExprDesc::Int(*a + *b),
-                    (ExprDesc::Float(a), ExprDesc::Float(b)) => ExprDesc::Float(*a + *b),
-                    _ => return Err(EvalErrorDesc::InvalidType("Cannot add").with_pos(self.pos)),
-                };
-                Ok(())
-            }
-            ExprDesc::Minus(a, b) => {
+            ExprDesc::Plus(a, b)
+            | ExprDesc::Minus(a, b)
+            | ExprDesc::Times(a, b)
+            | ExprDesc::Divide(a, b)
+            | ExprDesc::Modulo(a, b)
+            | ExprDesc::BitAnd(a, b)
+            | ExprDesc::BitOr(a, b)
+            | ExprDesc::BitXor(a, b)
+            | ExprDesc::Shl(a, b)
+            | ExprDesc::Shr(a, b)
+            | ExprDesc::Eq(a, b)
+            | ExprDesc::Neq(a, b)
+            | ExprDesc::Lt(a, b)
+            | ExprDesc::Gt(a, b)
+            | ExprDesc::Le(a, b)
+            | ExprDesc::Ge(a, b) => {
                 a.eval(scope)?;
                 b.eval(scope)?;
-                self.desc = match (&mut a.as_mut().desc, &mut b.as_mut().desc) {
-                    (ExprDesc::Int(a), ExprDesc::Int(b)) => ExprDesc::Int(*a - *b),
-                    (ExprDesc::Float(a), ExprDesc::Float(b)) => ExprDesc::Float(*a - *b),
-                    _ => {
-                        return Err(EvalErrorDesc::InvalidType("Cannot subtract").with_pos(self.pos))
-                    }
-                };
-                Ok(())
+                self.desc = binary_op(&self.desc).map_err(|desc| desc.with_pos(pos))?;
+                scope.usage.check_size(self, pos)
             }
-            ExprDesc::Times(a, b) => {
-                a.eval(scope)?;
-                b.eval(scope)?;
-                self.desc = match (&mut a.as_mut().desc, &mut b.as_mut().desc) {
-                    (ExprDesc::Int(a), ExprDesc::Int(b)) => ExprDesc::Int(*a * *b),
-                    (ExprDesc::Float(a), ExprDesc::Float(b)) => ExprDesc::Float(*a * *b),
-                    _ => {
-                        return Err(EvalErrorDesc::InvalidType("Cannot multiply").with_pos(self.pos))
-                    }
-                };
+
+            // short-circuit: `b` is only evaluated if `a` doesn't decide the result
+            ExprDesc::And(a, b) => {
+                self.desc = ExprDesc::Bool(eval_bool(a, scope)? && eval_bool(b, scope)?);
                 Ok(())
             }
-            ExprDesc::Divide(a, b) => {
-                a.eval(scope)?;
-                b.eval(scope)?;
-                self.desc = match (&mut a.as_mut().desc, &mut b.as_mut().desc) {
-                    (ExprDesc::Int(a), ExprDesc::Int(b)) => ExprDesc::Int(*a / *b),
-                    (ExprDesc::Float(a), ExprDesc::Float(b)) => ExprDesc::Float(*a / *b),
-                    _ => return Err(EvalErrorDesc::InvalidType("Cannot divide").with_pos(self.pos)),
-                };
+            ExprDesc::Or(a, b) => {
+                self.desc = ExprDesc::Bool(eval_bool(a, scope)? || eval_bool(b, scope)?);
                 Ok(())
             }
 
-            ExprDesc::Eq(a, b) => {
+            ExprDesc::Neg(a) | ExprDesc::Not(a) => {
                 a.eval(scope)?;
-                b.eval(scope)?;
-                // println!("Eq check: {:?} == {:?}", a, b);
-                self.desc = ExprDesc::Bool(a == b);
+                self.desc = unary_op(&self.desc).map_err(|desc| desc.with_pos(pos))?;
                 Ok(())
             }
-
-            ExprDesc::Neq(a, b) => {
-                a.eval(scope)?;
-                b.eval(scope)?;
-                self.desc = ExprDesc::Bool(a != b);
+            ExprDesc::Ref(a) => {
+                *self = eval_ref(a, scope, pos)?;
                 Ok(())
             }
 
-            ExprDesc::Lt(a, b) => {
-                a.eval(scope)?;
-                b.eval(scope)?;
-                self.desc = match (&mut a.as_mut().desc, &mut b.as_mut().desc) {
-                    (ExprDesc::Int(a), ExprDesc::Int(b)) => ExprDesc::Bool(*a < *b),
-                    (ExprDesc::Float(a), ExprDesc::Float(b)) => ExprDesc::Bool(*a < *b),
-                    _ => {
-                        return Err(EvalErrorDesc::InvalidType("Cannot compare").with_pos(self.pos))
-                    }
-                };
+            ExprDesc::Block(stmts, last) => {
+                self.desc = eval_block(std::mem::take(stmts), last, scope)?;
                 Ok(())
             }
 
-            ExprDesc::Gt(a, b) => {
-                a.eval(scope)?;
-                b.eval(scope)?;
-                self.desc = match (&mut a.as_mut().desc, &mut b.as_mut().desc) {
-                    (ExprDesc::Int(a), ExprDesc::Int(b)) => ExprDesc::Bool(*a > *b),
-                    (ExprDesc::Float(a), ExprDesc::Float(b)) => ExprDesc::Bool(*a > *b),
-                    _ => {
-                        return Err(EvalErrorDesc::InvalidType("Cannot compare").with_pos(self.pos))
-                    }
-                };
+            ExprDesc::FnCall(name, args) => {
+                eval_all(args, scope)?;
+                let args = std::mem::take(args);
+                self.desc = scope.call_fn_raw(name, args, self.pos)?.desc;
                 Ok(())
             }
 
-            //
-            ExprDesc::Block(stmts, last) => {
-                // println!("Block start");
-                let stmts = std::mem::replace(stmts, vec![]);
-
-                // let mut sub = scope.sub();
-                scope.push();
-                for stmt in stmts {
-                    // println!("Procesing {:?} : scope {:?}", stmt, scope.vbls.keys());
-                    stmt.eval(scope)?;
-                }
-                // println!("Block end");
-                last.eval(scope)?;
-                scope.pop();
-                self.desc = std::mem::replace(last, ExprDesc::Unit);
+            ExprDesc::Lambda(args, body) => {
+                self.desc = eval_lambda(args, body, scope)?;
                 Ok(())
             }
 
-            ExprDesc::FnCall(name, args) => {
-                for arg in args.iter_mut() {
-                    arg.eval(scope)?;
-                }
-                // println!("Fn Call {:?}", args);
-                let args = std::mem::replace(args, vec![]);
-                self.desc = scope.call_fn_raw(&name, args, self.pos)?.desc;
+            ExprDesc::Call(target, args) => {
+                target.eval(scope)?;
+                eval_all(args, scope)?;
+                let args = std::mem::take(args);
+                self.desc = scope.call_closure(target, args, self.pos)?.desc;
                 Ok(())
             }
 
             ExprDesc::Cast(expr, typ) => {
                 expr.eval(scope)?;
-                self.desc = match (&mut expr.as_mut().desc, typ) {
-                    (ExprDesc::Float(f), Type::I32) => Ok(ExprDesc::Int(*f as i32)),
-                    (ExprDesc::Float(f), Type::F32) => Ok(ExprDesc::Float(*f)),
-                    (ExprDesc::Int(i), Type::F32) => Ok(ExprDesc::Float(*i as f32)),
-                    (ExprDesc::Int(i), Type::I32) => Ok(ExprDesc::Int(*i)),
-                    _ => Err(EvalErrorDesc::InvalidType("Cannot cast")),
-                }?;
+                self.desc = cast(&expr.desc, typ).map_err(|desc| desc.with_pos(pos))?;
                 Ok(())
             }
 
             ExprDesc::MemberAccess(expr, items) => {
-                let mut target = match &mut expr.as_mut().desc {
-                    ExprDesc::Ident(name) => {
-                        // If I don't want to auto-clone, re-enable this stuff
-                        // let can_borrow = items.iter().any(|(_, x)| x.is_some());
-                        // if can_borrow {
-
-                        for (_name, args) in items.iter_mut() {
-                            if let Some(args) = args {
-                                for arg in args {
-                                    arg.eval(scope)?;
-                                }
-                            }
-                        }
-                        let mut target = match scope.get_raw_mut(&name) {
-                            None => {
-                                return Err(EvalErrorDesc::MissingReference(name.to_owned())
-                                    .with_pos(self.pos))
-                            }
-                            Some(v) => v,
-                        };
-                        let mut items = items.into_iter();
-                        let mut owned = loop {
-                            if let Some((name, args)) = items.next() {
-                                if let Some(args) = args {
-                                    break member_function(
-                                        target,
-                                        name,
-                                        std::mem::replace(args, vec![]),
-                                    )?;
-                                } else {
-                                    target = member_access(target, name, self.pos)?;
-                                }
-                            } else {
-                                // ok now we auto-clone
-                                *self = target.clone();
-                                return Ok(());
-                            }
-                        };
-                        for (name, args) in items {
-                            if let Some(args) = args.take() {
-                                owned = member_function(&mut owned, name, args)?;
-                            } else {
-                                owned = member_move(owned, name, self.pos)?;
-                            }
-                        }
-                        // TODO preserve location?
-                        *self = owned;
-                        // do the borrow one I guess
-                        return Ok(());
-
-                        // } else {
-                        //     match scope.move_raw(&name) {
-                        //         None => {
-                        //             return Err(EvalErrorDesc::MissingReference(name.to_owned())
-                        //                 .with_pos(self.pos))
-                        //         }
-                        //         Some(v) => v,
-                        //     }
-                        // }
-                    }
-                    _ => {
-                        expr.eval(scope)?;
-                        std::mem::replace(&mut **expr, ExprDesc::Unit.into())
-                    }
-                };
-
-                for (name, args) in items {
-                    if let Some(args) = args.take() {
-                        target = member_function(&mut target, name, args)?;
-                    } else {
-                        target = member_move(target, name, self.pos)?;
-                    }
-                }
-                *self = target;
+                *self = eval_member_access(expr, items, scope, pos)?;
                 Ok(())
             }
-
             ExprDesc::IfChain(chain, else_) => {
-                for (cond, body) in chain {
-                    match cond {
-                        IfCond::Value(value) => {
-                            // println!("if cond {:?}", value);
-                            value.eval(scope)?;
-                            match value.desc {
-                                ExprDesc::Bool(true) => {
-                                    body.eval(scope)?;
-                                    self.desc = std::mem::replace(body, ExprDesc::Unit);
-                                    return Ok(());
-                                }
-                                ExprDesc::Bool(false) => {
-                                    // println!("if cond faaallthrough {:?}", value);
-                                    ()
-                                }
-                                _ => {
-                                    return Err(EvalErrorDesc::InvalidType(
-                                        "If condition must be a bool",
-                                    )
-                                    .with_pos(self.pos))
-                                }
-                            };
-                        }
-                        IfCond::IfLet(pattern, value) => {
-                            value.eval(scope)?;
-                            if let Some(bindings) = match_pattern(
-                                std::mem::replace(pattern, Pattern::Any),
-                                std::mem::replace(value, ExprDesc::Unit.into()),
-                                self.pos
-                            )? {
-                                scope.push();
-                                // let mut sub = scope.sub();
-                                for (name, value) in bindings {
-                                    scope.set_raw(&name, value)
-                                }
-                                body.eval(scope)?;
-                                self.desc = std::mem::replace(body, ExprDesc::Unit);
-                                scope.pop();
-                                return Ok(());
-                            }
-                        }
-                    }
-                }
-                match else_.take() {
-                    None => {
-                        self.desc = ExprDesc::Unit;
-                        Ok(())
-                    }
-                    Some(mut block) => {
-                        block.eval(scope)?;
-                        *self = *block;
-                        Ok(())
-                    }
-                }
+                *self = eval_if_chain(chain, else_, scope, pos)?;
+                Ok(())
             }
-
             ExprDesc::Match(value, cases) => {
-                value.eval(scope)?;
-                for (pattern, body) in cases {
-                    if let Some(bindings) =
-                        match_pattern(std::mem::replace(pattern, Pattern::Any), *value.clone(), self.pos)?
-                    {
-                        scope.push();
-                        // let mut sub = scope.sub();
-                        for (name, value) in bindings {
-                            scope.set_raw(&name, value)
-                        }
-                        body.eval(scope)?;
-                        self.desc = std::mem::replace(body, ExprDesc::Moved);
-                        scope.pop();
-                        return Ok(());
-                    }
+                self.desc = eval_match(value, cases, scope, pos)?;
+                Ok(())
+            }
+            ExprDesc::For(pattern, iterable, body) => {
+                eval_for(pattern, iterable, body, scope, pos)?;
+                self.desc = ExprDesc::Unit;
+                Ok(())
+            }
+            ExprDesc::While(cond, body) => {
+                eval_while(cond, body, scope, pos)?;
+                self.desc = ExprDesc::Unit;
+                Ok(())
+            }
+
+            ExprDesc::Break => Err(EvalErrorDesc::Break.with_pos(self.pos)),
+            ExprDesc::Continue => Err(EvalErrorDesc::Continue.with_pos(self.pos)),
+
+            ExprDesc::Range(start, end, _inclusive) => {
+                start.eval(scope)?;
+                end.eval(scope)?;
+                match (&start.desc, &end.desc) {
+                    (ExprDesc::Int(_), ExprDesc::Int(_))
+                    | (ExprDesc::Float(_), ExprDesc::Float(_)) => Ok(()),
+                    _ => Err(EvalErrorDesc::InvalidType("Range bounds must both be ints or floats")
+                        .with_pos(self.pos)),
                 }
-                Err(EvalErrorDesc::Unmatched(format!("{:?}", value)).with_pos(self.pos))
             }
         }
     }
@@ -771,9 +858,12 @@ impl Expr {
             ExprDesc::Float(_)
             | ExprDesc::Moved
             | ExprDesc::Int(_)
+            | ExprDesc::Long(_)
+            | ExprDesc::Double(_)
             | ExprDesc::Bool(_)
             | ExprDesc::String(_)
             | ExprDesc::Char(_)
+            | ExprDesc::Closure(_, _)
             | ExprDesc::Unit => Ok(()),
             ExprDesc::Tuple(items) | ExprDesc::Array(items) => {
                 for item in items {
@@ -795,15 +885,7 @@ impl Expr {
             }
             ExprDesc::Ident(name) => {
                 if !local_vars.check(name) {
-                    match scope.move_raw(&name) {
-                        None => {
-                            return Err(EvalErrorDesc::MissingReference(name.to_string())
-                                .with_pos(self.pos))
-                        }
-                        Some(expr) => {
-                            *self = expr;
-                        }
-                    }
+                    *self = scope.move_raw(name, self.pos)?;
                 }
                 Ok(())
             }
@@ -825,14 +907,38 @@ impl Expr {
             | ExprDesc::Minus(a, b)
             | ExprDesc::Times(a, b)
             | ExprDesc::Divide(a, b)
+            | ExprDesc::Modulo(a, b)
+            | ExprDesc::BitAnd(a, b)
+            | ExprDesc::BitOr(a, b)
+            | ExprDesc::BitXor(a, b)
+            | ExprDesc::Shl(a, b)
+            | ExprDesc::Shr(a, b)
             | ExprDesc::Eq(a, b)
             | ExprDesc::Neq(a, b)
             | ExprDesc::Lt(a, b)
-            | ExprDesc::Gt(a, b) => {
+            | ExprDesc::Gt(a, b)
+            | ExprDesc::Le(a, b)
+            | ExprDesc::Ge(a, b)
+            | ExprDesc::And(a, b)
+            | ExprDesc::Or(a, b) => {
                 a.move_nonlocal_vars(local_vars, scope)?;
                 b.move_nonlocal_vars(local_vars, scope)?;
                 Ok(())
             }
+            ExprDesc::Neg(a) | ExprDesc::Not(a) => a.move_nonlocal_vars(local_vars, scope),
+            ExprDesc::Ref(a) => match &a.desc {
+                ExprDesc::Ident(name) if !local_vars.check(name) => {
+                    *self = match scope.get_raw(name) {
+                        None => {
+                            return Err(EvalErrorDesc::MissingReference(name.to_string())
+                                .with_pos(a.pos))
+                        }
+                        Some(value) => copy_value(value, self.pos)?,
+                    };
+                    Ok(())
+                }
+                _ => a.move_nonlocal_vars(local_vars, scope),
+            },
 
             //
             ExprDesc::Block(stmts, last) => {
@@ -845,31 +951,61 @@ impl Expr {
                 Ok(())
             }
 
-            ExprDesc::FnCall(_name, args) => {
+            ExprDesc::FnCall(name, args) => {
+                for arg in args.iter_mut() {
+                    arg.move_nonlocal_vars(local_vars, scope)?;
+                }
+                // Calling a closure that lives outside captures a copy of it
+                if !local_vars.check(name) && !local_vars.check_fn(name) {
+                    if let Some(f) = scope.get_raw(name) {
+                        if let ExprDesc::Closure(_, _) = f.desc {
+                            let target = Box::new(f.clone());
+                            *self = ExprDesc::Call(target, std::mem::take(args)).with_pos(self.pos);
+                        }
+                    }
+                }
+                Ok(())
+            }
+
+            ExprDesc::Call(target, args) => {
+                target.move_nonlocal_vars(local_vars, scope)?;
                 for arg in args.iter_mut() {
                     arg.move_nonlocal_vars(local_vars, scope)?;
                 }
                 Ok(())
             }
 
+            ExprDesc::Lambda(args, body) => {
+                local_vars.push();
+                for arg in args.iter() {
+                    local_vars.add(arg);
+                }
+                body.move_nonlocal_vars(local_vars, scope)?;
+                local_vars.pop();
+                Ok(())
+            }
+
             ExprDesc::Cast(expr, _typ) => {
                 expr.move_nonlocal_vars(local_vars, scope)?;
                 Ok(())
             }
 
             ExprDesc::MemberAccess(expr, items) => {
+                for item in items.iter_mut() {
+                    for arg in item.exprs_mut() {
+                        arg.move_nonlocal_vars(local_vars, scope)?;
+                    }
+                }
                 // if it's a .clone(), then don't move. Otherwise, we go ahead and move.
                 if let ExprDesc::Ident(ident) = &mut expr.as_mut().desc {
-                    if let Some(args) = &items[0].1 {
-                        if items[0].0 == "clone" && args.is_empty() {
-                            if let Some(expr) = scope.move_raw(&ident) {
+                    if let Access::Method(name, args) = &items[0] {
+                        if name == "clone" && args.is_empty() && !local_vars.check(ident) {
+                            if let Some(expr) = scope.get_raw(ident) {
+                                let expr = Box::new(expr.clone());
                                 items.remove(0);
                                 // its a clone
-                                *self = ExprDesc::MemberAccess(
-                                    Box::new(expr),
-                                    std::mem::replace(items, vec![]),
-                                )
-                                .with_pos(self.pos);
+                                *self = ExprDesc::MemberAccess(expr, std::mem::take(items))
+                                    .with_pos(self.pos);
                                 return Ok(());
                             }
                         }
@@ -882,10 +1018,12 @@ impl Expr {
             ExprDesc::IfChain(chain, else_) => {
                 for (cond, body) in chain {
                     match cond {
-                        IfCond::Value(_) => {
+                        IfCond::Value(value) => {
+                            value.move_nonlocal_vars(local_vars, scope)?;
                             body.move_nonlocal_vars(local_vars, scope)?;
                         }
-                        IfCond::IfLet(pattern, _value) => {
+                        IfCond::IfLet(pattern, value) => {
+                            value.move_nonlocal_vars(local_vars, scope)?;
                             let mut bindings = vec![];
                             pattern_names(pattern, &mut bindings);
 
@@ -896,7 +1034,6 @@ impl Expr {
                             }
                             body.move_nonlocal_vars(local_vars, scope)?;
                             local_vars.pop();
-                            return Ok(());
                         }
                     }
                 }
@@ -908,36 +1045,138 @@ impl Expr {
             }
 
             ExprDesc::Match(value, cases) => {
-                value.eval(scope)?;
-                for (pattern, body) in cases {
+                value.move_nonlocal_vars(local_vars, scope)?;
+                for (pattern, guard, body) in cases {
                     let mut bindings = vec![];
                     pattern_names(pattern, &mut bindings);
                     local_vars.push();
                     for name in bindings {
                         local_vars.add(&name);
                     }
+                    if let Some(guard) = guard {
+                        guard.move_nonlocal_vars(local_vars, scope)?;
+                    }
                     body.move_nonlocal_vars(local_vars, scope)?;
                     local_vars.pop();
                 }
                 Ok(())
             }
-        }
-    }
-}
 
-impl ExprDesc {
-    pub fn kind(&self) -> &'static str {
-        match self {
-            ExprDesc::Float(_) => "float",
-            ExprDesc::Int(_) => "int",
-            ExprDesc::Bool(_) => "bool",
-            ExprDesc::Char(_) => "char",
-            ExprDesc::String(_) => "string",
+            ExprDesc::For(pattern, iterable, body) => {
+                iterable.move_nonlocal_vars(local_vars, scope)?;
+                let mut bindings = vec![];
+                pattern_names(pattern, &mut bindings);
+                local_vars.push();
+                for name in bindings {
+                    local_vars.add(&name);
+                }
+                body.move_nonlocal_vars(local_vars, scope)?;
+                local_vars.pop();
+                Ok(())
+            }
 
-            ExprDesc::Array(_) => "array",
-            ExprDesc::Tuple(_) => "tuple",
-            ExprDesc::Object(_) => "object",
-            ExprDesc::Option(_) => "option",
+            ExprDesc::While(cond, body) => {
+                let mut bindings = vec![];
+                match cond.as_mut() {
+                    IfCond::Value(value) => value.move_nonlocal_vars(local_vars, scope)?,
+                    IfCond::IfLet(pattern, value) => {
+                        value.move_nonlocal_vars(local_vars, scope)?;
+                        pattern_names(pattern, &mut bindings);
+                    }
+                }
+                local_vars.push();
+                for name in bindings {
+                    local_vars.add(&name);
+                }
+                body.move_nonlocal_vars(local_vars, scope)?;
+                local_vars.pop();
+                Ok(())
+            }
+
+            ExprDesc::Break | ExprDesc::Continue => Ok(()),
+
+            ExprDesc::Range(start, end, _inclusive) => {
+                start.move_nonlocal_vars(local_vars, scope)?;
+                end.move_nonlocal_vars(local_vars, scope)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// A short description of a value for error messages, like `Some(Throw((1.0, -1.0)))`.
+    /// Deeply nested parts are left out.
+    pub fn shape(&self) -> String {
+        self.shape_to(3)
+    }
+
+    /// The whole value written out like script source, which is what `{:?}` shows
+    pub fn debug(&self) -> String {
+        self.shape_to(usize::MAX)
+    }
+
+    fn shape_to(&self, depth: usize) -> String {
+        if depth == 0 {
+            return "..".to_owned();
+        }
+        let items = |items: &[Expr]| {
+            if depth == 1 && !items.is_empty() {
+                return "..".to_owned();
+            }
+            items
+                .iter()
+                .map(|item| item.shape_to(depth - 1))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let pairs = |items: &[(String, Expr)]| {
+            if depth == 1 && !items.is_empty() {
+                return "..".to_owned();
+            }
+            items
+                .iter()
+                .map(|(key, item)| format!("{}: {}", key, item.shape_to(depth - 1)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match &self.desc {
+            ExprDesc::Float(f) => format!("{:?}", f),
+            ExprDesc::Int(i) => i.to_string(),
+            ExprDesc::Long(i) => crate::printer::long(*i),
+            ExprDesc::Double(f) => crate::printer::double(*f),
+            ExprDesc::Bool(b) => b.to_string(),
+            ExprDesc::Char(c) => format!("{:?}", c),
+            ExprDesc::String(s) => format!("{:?}", s),
+            ExprDesc::Unit => "()".to_owned(),
+            ExprDesc::Array(children) => format!("vec![{}]", items(children)),
+            ExprDesc::Tuple(children) => format!("({})", items(children)),
+            ExprDesc::Option(inner) => match inner.as_ref() {
+                Some(inner) => format!("Some({})", inner.shape_to(depth - 1)),
+                None => "None".to_owned(),
+            },
+            ExprDesc::NamedTuple(name, children) if children.is_empty() => name.clone(),
+            ExprDesc::NamedTuple(name, children) => format!("{}({})", name, items(children)),
+            ExprDesc::Struct(name, children) => format!("{} {{ {} }}", name, pairs(children)),
+            ExprDesc::Object(children) => format!("{{ {} }}", pairs(children)),
+            other => format!("<{}>", other.kind()),
+        }
+    }
+}
+
+impl ExprDesc {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ExprDesc::Float(_) => "float",
+            ExprDesc::Int(_) => "int",
+            ExprDesc::Long(_) => "long",
+            ExprDesc::Double(_) => "double",
+            ExprDesc::Bool(_) => "bool",
+            ExprDesc::Char(_) => "char",
+            ExprDesc::String(_) => "string",
+
+            ExprDesc::Array(_) => "array",
+            ExprDesc::Tuple(_) => "tuple",
+            ExprDesc::Object(_) => "object",
+            ExprDesc::Option(_) => "option",
             ExprDesc::Ident(_) => "ident",
 
             ExprDesc::Unit => "unit",
@@ -948,25 +1187,58 @@ impl ExprDesc {
             ExprDesc::Minus(_, _) => "minus",
             ExprDesc::Times(_, _) => "times",
             ExprDesc::Divide(_, _) => "divide",
+            ExprDesc::Modulo(_, _) => "modulo",
+
+            ExprDesc::BitAnd(_, _) => "&",
+            ExprDesc::BitOr(_, _) => "|",
+            ExprDesc::BitXor(_, _) => "^",
+            ExprDesc::Shl(_, _) => "<<",
+            ExprDesc::Shr(_, _) => ">>",
 
             ExprDesc::Eq(_, _) => "==",
             ExprDesc::Neq(_, _) => "!=",
             ExprDesc::Lt(_, _) => "<",
             ExprDesc::Gt(_, _) => ">",
+            ExprDesc::Le(_, _) => "<=",
+            ExprDesc::Ge(_, _) => ">=",
+
+            ExprDesc::And(_, _) => "&&",
+            ExprDesc::Or(_, _) => "||",
+
+            ExprDesc::Neg(_) => "negate",
+            ExprDesc::Not(_) => "not",
+            ExprDesc::Ref(_) => "reference",
 
             ExprDesc::MemberAccess(_, _) => "member access",
             ExprDesc::Cast(_, _) => " as ",
 
             ExprDesc::Block(_, _) => "block",
+            ExprDesc::Lambda(_, _) => "lambda",
+            ExprDesc::Closure(_, _) => "closure",
             ExprDesc::FnCall(_, _) => "call()",
+            ExprDesc::Call(_, _) => "call()",
 
             ExprDesc::IfChain(_, _) => "if",
             ExprDesc::Match(_, _) => "match",
 
+            ExprDesc::For(_, _, _) => "for",
+            ExprDesc::While(_, _) => "while",
+            ExprDesc::Break => "break",
+            ExprDesc::Continue => "continue",
+            ExprDesc::Range(_, _, _) => "range",
+
             ExprDesc::Moved => "moved value",
         }
     }
 
+    pub fn range(start: Box<Expr>, end: Box<Expr>) -> Self {
+        ExprDesc::Range(start, end, false)
+    }
+
+    pub fn range_inclusive(start: Box<Expr>, end: Box<Expr>) -> Self {
+        ExprDesc::Range(start, end, true)
+    }
+
     pub fn with_span(self, span: &pest::Span) -> Expr {
         Expr {
             desc: self,
@@ -981,16 +1253,19 @@ impl ExprDesc {
     pub fn match_pos(self, other: &Expr) -> Expr {
         Expr {
             desc: self,
-            pos: other.pos.clone(),
+            pos: other.pos,
         }
     }
     pub fn needs_evaluation(&self) -> bool {
         match self {
             ExprDesc::Float(_)
             | ExprDesc::Int(_)
+            | ExprDesc::Long(_)
+            | ExprDesc::Double(_)
             | ExprDesc::Bool(_)
             | ExprDesc::String(_)
-            | ExprDesc::Char(_) => false,
+            | ExprDesc::Char(_)
+            | ExprDesc::Unit => false,
             ExprDesc::NamedTuple(_, items) | ExprDesc::Array(items) | ExprDesc::Tuple(items) => {
                 items.iter().any(|e| e.desc.needs_evaluation())
             }
@@ -1000,40 +1275,527 @@ impl ExprDesc {
             ExprDesc::Option(inner) => inner
                 .as_ref()
                 .as_ref()
-                .map_or(false, |expr| expr.desc.needs_evaluation()),
+                .is_some_and(|expr| expr.desc.needs_evaluation()),
             _ => true,
         }
     }
 }
 
+/// A number from any of the numeric types, widened for an operator
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl Number {
+    /// The number, and whether it's a 64-bit `Long` or `Double`
+    fn of(desc: &ExprDesc) -> Option<(Number, bool)> {
+        Some(match desc {
+            ExprDesc::Int(i) => (Number::Int(*i as i128), false),
+            ExprDesc::Long(i) => (Number::Int(*i), true),
+            ExprDesc::Float(f) => (Number::Float(*f as f64), false),
+            ExprDesc::Double(f) => (Number::Float(*f), true),
+            _ => return None,
+        })
+    }
+
+    /// As a float. An int mixed with an `f32` is made an `f32` first, and one mixed with a
+    /// `Double` an `f64`.
+    fn float(self, wide: bool) -> f64 {
+        match self {
+            Number::Int(i) if wide => i as f64,
+            Number::Int(i) => i as f32 as f64,
+            Number::Float(f) => f,
+        }
+    }
+}
+
+/// An `f32` as the `f64` that prints the same, so that `0.3` doesn't become 0.30000001192092896
+pub(crate) fn widen(f: f32) -> f64 {
+    f.to_string().parse().unwrap_or(f as f64)
+}
+
+/// Two numbers, and whether either of them is 64-bit
+fn numbers(a: &Expr, b: &Expr) -> Option<(Number, Number, bool)> {
+    let ((a, wide_a), (b, wide_b)) = (Number::of(&a.desc)?, Number::of(&b.desc)?);
+    Some((a, b, wide_a || wide_b))
+}
+
+/// Applies a numeric operator. Ints are promoted to floats when mixed with a float, and
+/// `i32`s and `f32`s to `Long`s and `Double`s when mixed with those. The operators work on
+/// the widest types, and results are checked to fit back in an `i32`.
+pub(crate) fn arithmetic<I, F>(a: &Expr, b: &Expr, int_op: I, float_op: F) -> Result<ExprDesc, EvalErrorDesc>
+where
+    I: Fn(i128, i128) -> Option<i128>,
+    F: Fn(f64, f64) -> f64,
+{
+    let (a, b, wide) = numbers(a, b).ok_or(EvalErrorDesc::InvalidType("Expected numbers"))?;
+    Ok(match (a, b) {
+        (Number::Int(a), Number::Int(b)) => match int_op(a, b) {
+            Some(i) if wide => ExprDesc::Long(i),
+            Some(i) => ExprDesc::Int(i32::try_from(i).map_err(|_| EvalErrorDesc::IntegerOverflow)?),
+            None if b == 0 => return Err(EvalErrorDesc::DivideByZero),
+            None => return Err(EvalErrorDesc::IntegerOverflow),
+        },
+        (a, b) => {
+            let f = float_op(a.float(wide), b.float(wide));
+            if wide {
+                ExprDesc::Double(f)
+            } else {
+                ExprDesc::Float(f as f32)
+            }
+        }
+    })
+}
+
+/// `+` also joins two strings
+pub(crate) fn add(a: &Expr, b: &Expr) -> Result<ExprDesc, EvalErrorDesc> {
+    match (&a.desc, &b.desc) {
+        (ExprDesc::String(a), ExprDesc::String(b)) => Ok(ExprDesc::String(format!("{}{}", a, b))),
+        _ => arithmetic(a, b, i128::checked_add, |a, b| a + b),
+    }
+}
+
+/// `as i32` and `as f32` on a `Long` or `Double`
+pub(crate) fn cast_wide(desc: &ExprDesc, typ: &Type) -> Result<ExprDesc, EvalErrorDesc> {
+    Ok(match (desc, typ) {
+        (ExprDesc::Long(i), Type::I32) if i32::MIN as i128 <= *i && *i <= i32::MAX as i128 => {
+            ExprDesc::Int(*i as i32)
+        }
+        (ExprDesc::Long(_), Type::I32) => return Err(EvalErrorDesc::IntegerOverflow),
+        (ExprDesc::Long(i), Type::F32) => ExprDesc::Float(*i as f32),
+        (ExprDesc::Double(f), Type::I32) => ExprDesc::Int(*f as i32),
+        (ExprDesc::Double(f), Type::F32) => ExprDesc::Float(*f as f32),
+        _ => return Err(EvalErrorDesc::InvalidType("Cannot cast")),
+    })
+}
+
+pub(crate) fn values_equal(a: &Expr, b: &Expr) -> bool {
+    match numbers(a, b) {
+        Some(_) => ordering(a, b) == Ok(Some(Ordering::Equal)),
+        None => a == b,
+    }
+}
+
+/// How two numbers, strings or chars compare. `None` when one of them is NaN.
+pub(crate) fn ordering(a: &Expr, b: &Expr) -> Result<Option<Ordering>, EvalErrorDesc> {
+    if let Some((a, b, wide)) = numbers(a, b) {
+        return Ok(match (a, b) {
+            (Number::Int(a), Number::Int(b)) => a.partial_cmp(&b),
+            (a, b) => a.float(wide).partial_cmp(&b.float(wide)),
+        });
+    }
+    Ok(match (&a.desc, &b.desc) {
+        (ExprDesc::String(a), ExprDesc::String(b)) => a.partial_cmp(b),
+        (ExprDesc::Char(a), ExprDesc::Char(b)) => a.partial_cmp(b),
+        _ => return Err(EvalErrorDesc::InvalidType("Cannot compare")),
+    })
+}
+
+pub(crate) fn compare<F: Fn(Ordering) -> bool>(a: &Expr, b: &Expr, test: F) -> Result<ExprDesc, EvalErrorDesc> {
+    // NaN is never less than, greater than, or equal to anything
+    Ok(ExprDesc::Bool(ordering(a, b)?.is_some_and(test)))
+}
+
+pub(crate) fn bitwise<I, B>(a: &Expr, b: &Expr, int_op: I, bool_op: B) -> Result<ExprDesc, EvalErrorDesc>
+where
+    I: Fn(i32, i32) -> i32,
+    B: Fn(bool, bool) -> bool,
+{
+    match (&a.desc, &b.desc) {
+        (ExprDesc::Int(a), ExprDesc::Int(b)) => Ok(ExprDesc::Int(int_op(*a, *b))),
+        (ExprDesc::Bool(a), ExprDesc::Bool(b)) => Ok(ExprDesc::Bool(bool_op(*a, *b))),
+        _ => Err(EvalErrorDesc::InvalidType(
+            "Bitwise operations need two ints or two bools",
+        )),
+    }
+}
+
+/// `<<` or `>>`, with `op` for `i32`s and `long_op` for `Long`s
+pub(crate) fn shift<F, L>(a: &Expr, b: &Expr, op: F, long_op: L) -> Result<ExprDesc, EvalErrorDesc>
+where
+    F: Fn(i32, u32) -> Option<i32>,
+    L: Fn(i128, u32) -> Option<i128>,
+{
+    match (&a.desc, &b.desc) {
+        (ExprDesc::Int(a), ExprDesc::Int(b)) => op(*a, *b as u32).map(ExprDesc::Int),
+        (ExprDesc::Long(a), ExprDesc::Int(b)) => long_op(*a, *b as u32).map(ExprDesc::Long),
+        _ => return Err(EvalErrorDesc::InvalidType("Can only shift ints")),
+    }
+    .ok_or(EvalErrorDesc::IntegerOverflow)
+}
+
+// The bigger kinds of expression are evaluated in functions of their own. `Expr::eval` is on
+// the native stack once for every level of nesting in a script, so its frame has to stay small.
+
+fn eval_all(items: &mut [Expr], scope: &mut Scope) -> Result<(), EvalError> {
+    for item in items {
+        item.eval(scope)?;
+    }
+    Ok(())
+}
+
+fn eval_block(stmts: Vec<Statement>, last: &mut Expr, scope: &mut Scope) -> Result<ExprDesc, EvalError> {
+    scope.push();
+    for stmt in stmts {
+        stmt.eval(scope)?;
+    }
+    last.eval(scope)?;
+    scope.pop();
+    Ok(std::mem::replace(last, ExprDesc::Unit))
+}
+
+fn eval_member_access(
+    expr: &mut Expr,
+    items: &mut [Access],
+    scope: &mut Scope,
+    pos: Pos,
+) -> Result<Expr, EvalError> {
+    for item in items.iter_mut() {
+        for arg in item.exprs_mut() {
+            arg.eval(scope)?;
+        }
+    }
+    let mut target = match &mut expr.desc {
+        ExprDesc::Ident(name) => {
+            // The variable is taken out of the scope while we work on it, so that
+            // member functions (which might call closures) can have the scope too.
+            let mut value = match scope.get_raw_mut(name) {
+                None => return Err(EvalErrorDesc::MissingReference(name.to_owned()).with_pos(pos)),
+                Some(v) => std::mem::replace(v, ExprDesc::Moved.into()),
+            };
+            let items = items.iter_mut().map(Access::step);
+            let result = borrowed_member_access(&mut value, items, scope, pos);
+            if let Some(v) = scope.get_raw_mut(name) {
+                *v = value;
+            }
+            // TODO preserve location?
+            return result;
+        }
+        _ => {
+            expr.eval(scope)?;
+            std::mem::replace(expr, ExprDesc::Unit.into())
+        }
+    };
+
+    for item in items.iter_mut() {
+        target = step_move(target, item.step(), scope, pos)?;
+    }
+    Ok(target)
+}
+
+fn eval_if_chain(
+    chain: &mut [(IfCond, Expr)],
+    else_: &mut Option<Box<Expr>>,
+    scope: &mut Scope,
+    pos: Pos,
+) -> Result<Expr, EvalError> {
+    for (cond, body) in chain {
+        match cond {
+            IfCond::Value(value) => {
+                value.eval(scope)?;
+                match value.desc {
+                    ExprDesc::Bool(true) => {
+                        body.eval(scope)?;
+                        return Ok(Expr {
+                            desc: std::mem::replace(body, ExprDesc::Unit),
+                            pos,
+                        });
+                    }
+                    ExprDesc::Bool(false) => (),
+                    _ => {
+                        return Err(EvalErrorDesc::InvalidType("If condition must be a bool")
+                            .with_pos(pos))
+                    }
+                };
+            }
+            IfCond::IfLet(pattern, value) => {
+                value.eval(scope)?;
+                if let Some(bindings) =
+                    match_pattern(pattern, std::mem::replace(value, ExprDesc::Unit.into()), pos)?
+                {
+                    scope.push();
+                    set_bindings(scope, bindings);
+                    body.eval(scope)?;
+                    scope.pop();
+                    return Ok(Expr {
+                        desc: std::mem::replace(body, ExprDesc::Unit),
+                        pos,
+                    });
+                }
+            }
+        }
+    }
+    match else_.take() {
+        None => Ok(Expr {
+            desc: ExprDesc::Unit,
+            pos,
+        }),
+        Some(mut block) => {
+            block.eval(scope)?;
+            Ok(*block)
+        }
+    }
+}
+
+fn eval_match(
+    value: &mut Expr,
+    cases: &mut [(Pattern, Option<Expr>, Expr)],
+    scope: &mut Scope,
+    pos: Pos,
+) -> Result<ExprDesc, EvalError> {
+    value.eval(scope)?;
+    for (pattern, guard, body) in cases {
+        if let Some(bindings) = match_pattern(pattern, value.clone(), pos)? {
+            scope.push();
+            set_bindings(scope, bindings);
+            if let Some(guard) = guard {
+                if let Err(err) = guard.eval(scope) {
+                    scope.pop();
+                    return Err(err);
+                }
+                match guard.desc {
+                    ExprDesc::Bool(true) => (),
+                    ExprDesc::Bool(false) => {
+                        scope.pop();
+                        continue;
+                    }
+                    _ => {
+                        scope.pop();
+                        return Err(
+                            EvalErrorDesc::InvalidType("Match guard must be a bool").with_pos(guard.pos)
+                        );
+                    }
+                }
+            }
+            body.eval(scope)?;
+            scope.pop();
+            return Ok(std::mem::replace(body, ExprDesc::Moved));
+        }
+    }
+    Err(EvalErrorDesc::NonExhaustive(value.shape()).with_pos(pos))
+}
+
+fn eval_for(
+    pattern: &Pattern,
+    iterable: &mut Expr,
+    body: &Expr,
+    scope: &mut Scope,
+    pos: Pos,
+) -> Result<(), EvalError> {
+    iterable.eval(scope)?;
+    let items: Box<dyn Iterator<Item = Expr>> = match &iterable.desc {
+        ExprDesc::Array(items) => Box::new(items.clone().into_iter()),
+        ExprDesc::Range(start, end, inclusive) => match (&start.desc, &end.desc) {
+            (ExprDesc::Int(start), ExprDesc::Int(end)) if *inclusive => {
+                Box::new((*start..=*end).map(|i| i.into()))
+            }
+            (ExprDesc::Int(start), ExprDesc::Int(end)) => Box::new((*start..*end).map(|i| i.into())),
+            _ => {
+                return Err(EvalErrorDesc::InvalidType("Can only iterate over a range of ints")
+                    .with_pos(iterable.pos))
+            }
+        },
+        _ => {
+            return Err(EvalErrorDesc::InvalidType("Can only iterate over an array or a range")
+                .with_pos(iterable.pos))
+        }
+    };
+    for item in items {
+        let bindings = match match_pattern(pattern, item, pos)? {
+            Some(bindings) => bindings,
+            None => return Err(EvalErrorDesc::Unmatched("for loop pattern".to_owned()).with_pos(pos)),
+        };
+        if !eval_loop_body(body, bindings, scope)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn eval_while(cond: &IfCond, body: &Expr, scope: &mut Scope, pos: Pos) -> Result<(), EvalError> {
+    loop {
+        let bindings = match cond {
+            IfCond::Value(value) => {
+                let mut value = value.clone();
+                value.eval(scope)?;
+                match value.desc {
+                    ExprDesc::Bool(true) => vec![],
+                    ExprDesc::Bool(false) => break,
+                    _ => {
+                        return Err(EvalErrorDesc::InvalidType("While condition must be a bool")
+                            .with_pos(value.pos))
+                    }
+                }
+            }
+            IfCond::IfLet(pattern, value) => {
+                let mut value = value.clone();
+                value.eval(scope)?;
+                match match_pattern(pattern, value, pos)? {
+                    Some(bindings) => bindings,
+                    None => break,
+                }
+            }
+        };
+        if !eval_loop_body(body, bindings, scope)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The result of `-` or `!` on an evaluated operand
+fn unary_op(desc: &ExprDesc) -> Result<ExprDesc, EvalErrorDesc> {
+    match desc {
+        ExprDesc::Neg(a) => match a.desc {
+            ExprDesc::Int(i) => i.checked_neg().map(ExprDesc::Int).ok_or(EvalErrorDesc::IntegerOverflow),
+            ExprDesc::Float(f) => Ok(ExprDesc::Float(-f)),
+            ExprDesc::Long(i) => i.checked_neg().map(ExprDesc::Long).ok_or(EvalErrorDesc::IntegerOverflow),
+            ExprDesc::Double(f) => Ok(ExprDesc::Double(-f)),
+            _ => Err(EvalErrorDesc::InvalidType("Cannot negate")),
+        },
+        ExprDesc::Not(a) => match a.desc {
+            ExprDesc::Bool(b) => Ok(ExprDesc::Bool(!b)),
+            ExprDesc::Int(i) => Ok(ExprDesc::Int(!i)),
+            _ => Err(EvalErrorDesc::InvalidType("Can only use ! on bools and ints")),
+        },
+        _ => unreachable!("not a unary operator"),
+    }
+}
+
+fn cast(desc: &ExprDesc, typ: &Type) -> Result<ExprDesc, EvalErrorDesc> {
+    match (desc, typ) {
+        (ExprDesc::Float(f), Type::I32) => Ok(ExprDesc::Int(*f as i32)),
+        (ExprDesc::Float(f), Type::F32) => Ok(ExprDesc::Float(*f)),
+        (ExprDesc::Int(i), Type::F32) => Ok(ExprDesc::Float(*i as f32)),
+        (ExprDesc::Int(i), Type::I32) => Ok(ExprDesc::Int(*i)),
+        (desc, typ) if matches!(desc, ExprDesc::Long(_) | ExprDesc::Double(_)) => cast_wide(desc, typ),
+        _ => Err(EvalErrorDesc::InvalidType("Cannot cast")),
+    }
+}
+
+fn eval_ref(a: &mut Expr, scope: &mut Scope, pos: Pos) -> Result<Expr, EvalError> {
+    if let ExprDesc::Ident(name) = &a.desc {
+        return match scope.get_raw(name) {
+            None => Err(EvalErrorDesc::MissingReference(name.to_string()).with_pos(a.pos)),
+            Some(value) => copy_value(value, pos),
+        };
+    }
+    a.eval(scope)?;
+    Ok(std::mem::replace(a, ExprDesc::Unit.into()))
+}
+
+fn eval_lambda(args: &mut Args, body: &mut Expr, scope: &mut Scope) -> Result<ExprDesc, EvalError> {
+    let mut local_vars = LocalVars::new();
+    for arg in args.iter() {
+        local_vars.add(arg);
+    }
+    body.move_nonlocal_vars(&mut local_vars, scope)?;
+    // the captured values are in the body now, so it doesn't capture anything else
+    let function = Function::compile(std::mem::take(args), body.clone());
+    Ok(ExprDesc::Closure(Arc::new(function), Arc::new([])))
+}
+
+/// The result of a binary operator whose operands have been evaluated
+fn binary_op(desc: &ExprDesc) -> Result<ExprDesc, EvalErrorDesc> {
+    match desc {
+        ExprDesc::Plus(a, b) => add(a, b).map_err(|desc| desc.or_invalid("Cannot add")),
+        ExprDesc::Minus(a, b) => arithmetic(a, b, i128::checked_sub, |a, b| a - b)
+            .map_err(|desc| desc.or_invalid("Cannot subtract")),
+        ExprDesc::Times(a, b) => arithmetic(a, b, i128::checked_mul, |a, b| a * b)
+            .map_err(|desc| desc.or_invalid("Cannot multiply")),
+        ExprDesc::Divide(a, b) => arithmetic(a, b, i128::checked_div, |a, b| a / b)
+            .map_err(|desc| desc.or_invalid("Cannot divide")),
+        ExprDesc::Modulo(a, b) => arithmetic(a, b, i128::checked_rem, |a, b| a % b)
+            .map_err(|desc| desc.or_invalid("Cannot take the remainder")),
+        ExprDesc::BitAnd(a, b) => bitwise(a, b, |a, b| a & b, |a, b| a & b),
+        ExprDesc::BitOr(a, b) => bitwise(a, b, |a, b| a | b, |a, b| a | b),
+        ExprDesc::BitXor(a, b) => bitwise(a, b, |a, b| a ^ b, |a, b| a ^ b),
+        ExprDesc::Shl(a, b) => shift(a, b, i32::checked_shl, i128::checked_shl),
+        ExprDesc::Shr(a, b) => shift(a, b, i32::checked_shr, i128::checked_shr),
+        ExprDesc::Eq(a, b) => Ok(ExprDesc::Bool(values_equal(a, b))),
+        ExprDesc::Neq(a, b) => Ok(ExprDesc::Bool(!values_equal(a, b))),
+        ExprDesc::Lt(a, b) => compare(a, b, |o| o == Ordering::Less),
+        ExprDesc::Gt(a, b) => compare(a, b, |o| o == Ordering::Greater),
+        ExprDesc::Le(a, b) => compare(a, b, |o| o != Ordering::Greater),
+        ExprDesc::Ge(a, b) => compare(a, b, |o| o != Ordering::Less),
+        _ => unreachable!("not a binary operator"),
+    }
+}
+
+fn eval_bool(value: &mut Expr, scope: &mut Scope) -> Result<bool, EvalError> {
+    value.eval(scope)?;
+    match value.desc {
+        ExprDesc::Bool(b) => Ok(b),
+        _ => Err(EvalErrorDesc::InvalidType("Expected a bool").with_pos(value.pos)),
+    }
+}
+
+/// Runs one iteration of a loop body in its own scope. Returns false if the loop should stop.
+fn eval_loop_body(
+    body: &Expr,
+    bindings: Vec<Binding<'_>>,
+    scope: &mut Scope,
+) -> Result<bool, EvalError> {
+    let mut body = body.clone();
+    scope.push();
+    set_bindings(scope, bindings);
+    let result = body.eval(scope);
+    scope.pop();
+    match result {
+        Err(EvalError {
+            desc: EvalErrorDesc::Break,
+            ..
+        }) => Ok(false),
+        Err(EvalError {
+            desc: EvalErrorDesc::Continue,
+            ..
+        }) => Ok(true),
+        Err(err) => Err(err),
+        Ok(()) => Ok(true),
+    }
+}
+
+fn set_bindings(scope: &mut Scope, bindings: Vec<Binding<'_>>) {
+    for (name, value, mutable) in bindings {
+        if mutable {
+            scope.set_mutable_raw(name, value)
+        } else {
+            scope.set_raw(name, value)
+        }
+    }
+}
+
 /// TODO this allocates a bunch of empty vectors
-fn match_pattern(pattern: Pattern, value: Expr, pos: Pos) -> Result<Option<Vec<(String, Expr)>>, EvalError> {
+pub(crate) fn match_pattern<'p>(pattern: &'p Pattern, value: Expr, pos: Pos) -> Result<Option<Vec<Binding<'p>>>, EvalError> {
     Ok(match (pattern, value) {
         (Pattern::Any, _) => Some(vec![]),
-        (Pattern::Ident(name), value) => Some(vec![(name, value)]),
+        (Pattern::Ident(name), value) => Some(vec![(name, value, false)]),
+        (Pattern::MutIdent(name), value) => Some(vec![(name, value, true)]),
         (
             Pattern::Const(Const::Bool(b)),
             Expr {
                 desc: ExprDesc::Bool(bb),
                 ..
             },
-        ) => if b == bb {Some(vec![])} else {None},
+        ) => if *b == bb {Some(vec![])} else {None},
         (
             Pattern::Const(Const::Int(b)),
             Expr {
                 desc: ExprDesc::Int(bb),
                 ..
             },
-        ) => if b == bb {Some(vec![])}else{None},
+        ) => if *b == bb {Some(vec![])}else{None},
         (
             Pattern::Const(Const::Float(b)),
             Expr {
                 desc: ExprDesc::Float(bb),
                 ..
             },
-        ) => if b == bb {Some(vec![])}else{None},
+        ) => if *b == bb {Some(vec![])}else{None},
         (
-            Pattern::Const(Const::String(ref b)),
+            Pattern::Const(Const::String(b)),
             Expr {
                 desc: ExprDesc::String(ref bb),
                 ..
@@ -1045,23 +1807,99 @@ fn match_pattern(pattern: Pattern, value: Expr, pos: Pos) -> Result<Option<Vec<(
                 desc: ExprDesc::Char(bb),
                 ..
             },
-        ) => if b == bb {Some(vec![])}else{None},
+        ) => if *b == bb {Some(vec![])}else{None},
 
-        (Pattern::TupleStruct(name, mut items), Expr {desc: ExprDesc::Option(contents), ..}) => {
+        (Pattern::Or(alternatives), value) => {
+            for alternative in alternatives {
+                if let Some(bindings) = match_pattern(alternative, value.clone(), pos)? {
+                    return Ok(Some(bindings));
+                }
+            }
+            None
+        }
+
+        (Pattern::Bind(name, inner), value) => match match_pattern(inner, value.clone(), pos)? {
+            Some(mut bindings) => {
+                bindings.insert(0, (name, value, false));
+                Some(bindings)
+            }
+            None => None,
+        },
+
+        (Pattern::Range(start, end, inclusive), value) => {
+            let ordering = |start: Ordering, end: Ordering| {
+                start != Ordering::Greater
+                    && (end == Ordering::Less || (*inclusive && end == Ordering::Equal))
+            };
+            let matched = match (start, end, &value.desc) {
+                (Const::Int(start), Const::Int(end), ExprDesc::Int(v)) => {
+                    ordering(start.cmp(v), v.cmp(end))
+                }
+                (Const::Char(start), Const::Char(end), ExprDesc::Char(v)) => {
+                    ordering(start.cmp(v), v.cmp(end))
+                }
+                (Const::Float(start), Const::Float(end), ExprDesc::Float(v)) => {
+                    match (start.partial_cmp(v), v.partial_cmp(end)) {
+                        (Some(start), Some(end)) => ordering(start, end),
+                        _ => false,
+                    }
+                }
+                _ => {
+                    return Err(EvalErrorDesc::InvalidType("Range pattern doesn't match the value's type").with_pos(pos))
+                }
+            };
+            if matched { Some(vec![]) } else { None }
+        }
+
+        (Pattern::Slice(items), Expr { desc: ExprDesc::Array(mut values), .. }) => {
+            let rest = items.iter().position(|item| matches!(item, Pattern::Rest(_)));
+            let fixed = items.len() - rest.map_or(0, |_| 1);
+            if values.len() < fixed || (rest.is_none() && values.len() != fixed) {
+                return Ok(None);
+            }
+            let mut bindings = vec![];
+            let after = values.split_off(values.len() - (items.len() - rest.map_or(items.len(), |r| r + 1)));
+            let mut middle = Some(values.split_off(rest.unwrap_or(values.len())));
+            let mut values = values.into_iter().chain(after);
+            for item in items {
+                let inner = match item {
+                    Pattern::Rest(Some(name)) => {
+                        let middle = middle.take().unwrap_or_default();
+                        Some(vec![(name.as_str(), ExprDesc::Array(middle).with_pos(pos), false)])
+                    }
+                    Pattern::Rest(None) => Some(vec![]),
+                    item => match values.next() {
+                        Some(value) => match_pattern(item, value, pos)?,
+                        None => None,
+                    },
+                };
+                match inner {
+                    Some(inner) => bindings.extend(inner),
+                    None => return Ok(None),
+                }
+            }
+            Some(bindings)
+        }
+
+        (Pattern::TupleStruct(name, items), Expr {desc: ExprDesc::Option(contents), ..}) => {
             if name == "None" {
-                if let None = *contents.as_ref() {
+                if contents.as_ref().is_none() {
                     Some(vec![])
                 } else {
                     None
                 }
             } else if name == "Some" {
+                if items.len() != 1 {
+                    return Err(EvalErrorDesc::InvalidType("Some takes one item").with_pos(pos))
+                }
                 if let Some(contents) = *contents {
-                    match_pattern(items.remove(0), contents, pos)?
+                    match_pattern(&items[0], contents, pos)?
                 } else {
                     None
                 }
             } else {
-                return Err(EvalErrorDesc::InvalidType("A tuple struct cannot match an optional").with_pos(pos))
+                // some other enum's variant
+                None
             }
         },
 
@@ -1078,7 +1916,7 @@ fn match_pattern(pattern: Pattern, value: Expr, pos: Pos) -> Result<Option<Vec<(
 
             let mut bindings = vec![];
             for (pat, val) in items.iter().zip(bitems) {
-                if let Some(inner) = match_pattern(pat.clone(), val, pos)? {
+                if let Some(inner) = match_pattern(pat, val, pos)? {
                     bindings.extend(inner)
                 } else {
                     return Ok(None);
@@ -1094,7 +1932,7 @@ fn match_pattern(pattern: Pattern, value: Expr, pos: Pos) -> Result<Option<Vec<(
                 ..
             },
         ) => {
-            if name != bname {
+            if *name != bname {
                 return Ok(None);
             }
             if items.len() != bitems.len() {
@@ -1103,7 +1941,7 @@ fn match_pattern(pattern: Pattern, value: Expr, pos: Pos) -> Result<Option<Vec<(
 
             let mut bindings = vec![];
             for (pat, val) in items.iter().zip(bitems) {
-                if let Some(inner) = match_pattern(pat.clone(), val, pos)? {
+                if let Some(inner) = match_pattern(pat, val, pos)? {
                     bindings.extend(inner)
                 } else {
                     return Ok(None);
@@ -1115,20 +1953,21 @@ fn match_pattern(pattern: Pattern, value: Expr, pos: Pos) -> Result<Option<Vec<(
         (
             Pattern::Struct(name, pitems),
             Expr {
-                desc: ExprDesc::Struct(bname, bitems),
+                desc: ExprDesc::Struct(bname, mut bitems),
                 ..
             },
         ) => {
-            if name != bname {
+            if *name != bname {
                 return Ok(None);
             }
             let mut bindings = vec![];
             for (ident, pat) in pitems {
-                match bitems.iter().find(|(iname, _)| iname == &ident) {
+                match bitems.iter_mut().find(|(iname, _)| iname == ident) {
                     // TODO maybe get pos up
-                    None => return Err(EvalErrorDesc::CannotGetMember(ident, "No").with_pos(pos)),
+                    None => return Err(EvalErrorDesc::CannotGetMember(ident.clone(), "No").with_pos(pos)),
                     Some((_, val)) => {
-                        if let Some(inner) = match_pattern(pat, val.clone(), pos)? {
+                        let val = std::mem::replace(val, ExprDesc::Unit.into());
+                        if let Some(inner) = match_pattern(pat, val, pos)? {
                             bindings.extend(inner);
                         } else {
                             return Ok(None);
@@ -1150,6 +1989,12 @@ fn match_pattern(pattern: Pattern, value: Expr, pos: Pos) -> Result<Option<Vec<(
                 desc: ExprDesc::Struct(_, _),
                 ..
             },
+        ) | (
+            Pattern::Struct(_, _),
+            Expr {
+                desc: ExprDesc::Option(_),
+                ..
+            },
         )=> {
             None
         }
@@ -1161,10 +2006,10 @@ fn match_pattern(pattern: Pattern, value: Expr, pos: Pos) -> Result<Option<Vec<(
     })
 }
 
-fn pattern_names(pattern: &Pattern, vbls: &mut Vec<String>) {
+pub(crate) fn pattern_names(pattern: &Pattern, vbls: &mut Vec<String>) {
     match pattern {
         Pattern::Any => (),
-        Pattern::Ident(name) => vbls.push(name.to_owned()),
+        Pattern::Ident(name) | Pattern::MutIdent(name) => vbls.push(name.to_owned()),
         Pattern::Const(_) => (),
         Pattern::Tuple(items) |
         Pattern::TupleStruct(_, items) => {
@@ -1177,15 +2022,32 @@ fn pattern_names(pattern: &Pattern, vbls: &mut Vec<String>) {
                 pattern_names(pat, vbls);
             }
         }
+        // every alternative binds the same names
+        Pattern::Or(alternatives) => pattern_names(&alternatives[0], vbls),
+        Pattern::Range(..) | Pattern::Rest(None) => (),
+        Pattern::Bind(name, inner) => {
+            vbls.push(name.to_owned());
+            pattern_names(inner, vbls);
+        }
+        Pattern::Slice(items) => {
+            for item in items {
+                pattern_names(item, vbls);
+            }
+        }
+        Pattern::Rest(Some(name)) => vbls.push(name.to_owned()),
     }
 }
 
-fn member_move<'a>(value: Expr, name: &str, pos: Pos) -> Result<Expr, EvalError> {
+pub(crate) fn member_move(value: Expr, name: &str, pos: Pos) -> Result<Expr, EvalError> {
     Ok(match name.parse::<usize>() {
         Ok(index) => match value.desc {
             ExprDesc::Array(mut children) | ExprDesc::Tuple(mut children) | ExprDesc::NamedTuple(_, mut children) => {
-                children.remove(index)
+                if index >= children.len() {
+                    return Err(EvalErrorDesc::IndexOutOfBounds(index as i32, children.len()).with_pos(pos));
+                }
+                children.swap_remove(index)
             }
+            ExprDesc::Moved => return Err(EvalErrorDesc::MemberMovedValue.with_pos(pos)),
             _ => {
                 return Err(
                     EvalErrorDesc::InvalidType("Can only get index of array or namedtuple or tuple")
@@ -1213,11 +2075,18 @@ fn member_move<'a>(value: Expr, name: &str, pos: Pos) -> Result<Expr, EvalError>
     })
 }
 
-fn member_access<'a>(value: &'a mut Expr, name: &str, pos: Pos) -> Result<&'a mut Expr, EvalError> {
+pub(crate) fn member_access<'a>(value: &'a mut Expr, name: &str, pos: Pos) -> Result<&'a mut Expr, EvalError> {
     let kind = value.desc.kind();
     Ok(match name.parse::<usize>() {
         Ok(index) => match &mut value.desc {
-            ExprDesc::Array(children) | ExprDesc::Tuple(children) | ExprDesc::NamedTuple(_, children) => &mut children[index],
+            ExprDesc::Array(children) | ExprDesc::Tuple(children) | ExprDesc::NamedTuple(_, children) => {
+                let len = children.len();
+                match children.get_mut(index) {
+                    Some(child) => child,
+                    None => return Err(EvalErrorDesc::IndexOutOfBounds(index as i32, len).with_pos(pos)),
+                }
+            }
+            ExprDesc::Moved => return Err(EvalErrorDesc::MemberMovedValue.with_pos(pos)),
             _ => {
                 return Err(
                     EvalErrorDesc::InvalidType("Can only get index of array or namedtuple or tuple")
@@ -1240,69 +2109,151 @@ fn member_access<'a>(value: &'a mut Expr, name: &str, pos: Pos) -> Result<&'a mu
     })
 }
 
-fn member_function(
+/// `value[index]`: an array item, or an object entry by key
+pub(crate) fn index_access<'a>(value: &'a mut Expr, index: &Expr, pos: Pos) -> Result<&'a mut Expr, EvalError> {
+    match (&mut value.desc, &index.desc) {
+        (ExprDesc::Array(items), ExprDesc::Int(i)) => {
+            let len = items.len();
+            match usize::try_from(*i).ok().and_then(move |i| items.get_mut(i)) {
+                Some(item) => Ok(item),
+                None => Err(EvalErrorDesc::IndexOutOfBounds(*i, len).with_pos(pos)),
+            }
+        }
+        (ExprDesc::Object(children), ExprDesc::String(key)) => {
+            match children.iter_mut().find(|(name, _)| name == key) {
+                Some((_, child)) => Ok(child),
+                None => Err(EvalErrorDesc::MissingMember(key.clone()).with_pos(pos)),
+            }
+        }
+        (ExprDesc::Array(_), _) => Err(EvalErrorDesc::InvalidType("Arrays are indexed by ints").with_pos(pos)),
+        (ExprDesc::Object(_), _) => Err(EvalErrorDesc::InvalidType("Objects are indexed by strings").with_pos(pos)),
+        (ExprDesc::Moved, _) => Err(EvalErrorDesc::MemberMovedValue.with_pos(pos)),
+        _ => Err(EvalErrorDesc::InvalidType("Can only index arrays and objects").with_pos(pos)),
+    }
+}
+
+/// Applies a field or index step to a value in place, for assignment
+pub(crate) fn step_mut<'a>(value: &'a mut Expr, step: Step, pos: Pos) -> Result<&'a mut Expr, EvalError> {
+    match step {
+        Step::Field(name) => member_access(value, name, pos),
+        Step::Index(index, index_pos) => index_access(value, &index, index_pos),
+        Step::Method(_, _) => Err(EvalErrorDesc::InvalidType("Cannot assign to a method call").with_pos(pos)),
+    }
+}
+
+/// Applies any step to an owned value
+pub(crate) fn step_move(mut value: Expr, step: Step, scope: &mut Scope, pos: Pos) -> Result<Expr, EvalError> {
+    match step {
+        Step::Field(name) => member_move(value, name, pos),
+        Step::Method(name, args) => member_function(&mut value, name, args, scope, pos),
+        Step::Index(index, index_pos) => {
+            let item = index_access(&mut value, &index, index_pos)?;
+            Ok(std::mem::replace(item, ExprDesc::Unit.into()))
+        }
+    }
+}
+
+/// Walks a chain of `.field`, `[index]` and `.method()` accesses on a value that lives in the
+/// scope, only cloning once we need an owned value.
+pub(crate) fn borrowed_member_access<'a, I>(
+    value: &mut Expr,
+    items: I,
+    scope: &mut Scope,
+    pos: Pos,
+) -> Result<Expr, EvalError>
+where
+    I: IntoIterator<Item = Step<'a>>,
+{
+    let mut target = value;
+    let mut items = items.into_iter();
+    let mut owned = loop {
+        match items.next() {
+            Some(Step::Method(name, args)) => break member_function(target, name, args, scope, pos)?,
+            Some(step) => target = step_mut(target, step, pos)?,
+            // ok now we auto-clone
+            None => return Ok(target.clone()),
+        }
+    };
+    for step in items {
+        owned = step_move(owned, step, scope, pos)?;
+    }
+    Ok(owned)
+}
+
+pub(crate) fn member_function(
+    value: &mut Expr,
+    name: &str,
+    args: Vec<Expr>,
+    scope: &mut Scope,
+    pos: Pos,
+) -> Result<Expr, EvalError> {
+    // A closure stored in a struct field can be called like a method
+    if let ExprDesc::Object(children) | ExprDesc::Struct(_, children) = &value.desc {
+        if let Some((_, f)) = children.iter().find(|(sname, _)| sname == name) {
+            if let ExprDesc::Closure(_, _) = f.desc {
+                let f = f.clone();
+                return scope.call_closure(&f, args, pos);
+            }
+        }
+    }
+    // `repeat` can build a string much bigger than its inputs, so it's checked first
+    if let (ExprDesc::String(s), "repeat", [Expr { desc: ExprDesc::Int(n), .. }]) = (&value.desc, name, &args[..]) {
+        scope.usage.check_alloc(s.len().saturating_mul((*n).max(0) as usize), pos)?;
+    }
+    let result = match &mut value.desc {
+        ExprDesc::Array(items) if !matches!(name, "clone" | "to_string") => {
+            let result = array_method(items, name, args, scope, pos)?;
+            result.match_pos(value)
+        }
+        // A range of ints has the methods of the array of its items, like `collect` and `map`
+        ExprDesc::Range(start, end, inclusive) if !matches!(name, "clone" | "to_string") => {
+            let mut items = match (&start.desc, &end.desc) {
+                (ExprDesc::Int(start), ExprDesc::Int(end)) => {
+                    let count = *end as i64 - *start as i64 + *inclusive as i64;
+                    scope.usage.check_alloc(count.max(0) as usize, pos)?;
+                    let item = |i| ExprDesc::Int(i).with_pos(pos);
+                    if *inclusive {
+                        (*start..=*end).map(item).collect()
+                    } else {
+                        (*start..*end).map(item).collect()
+                    }
+                }
+                _ => return Err(EvalErrorDesc::NoMethod(name.to_owned(), "range of floats").with_pos(pos)),
+            };
+            array_method(&mut items, name, args, scope, pos)
+                .map_err(|err| match err.desc {
+                    EvalErrorDesc::UnknownFunction(name) => EvalErrorDesc::NoMethod(name, "range").with_pos(err.pos),
+                    _ => err,
+                })?
+                .with_pos(pos)
+        }
+        _ => builtin_member_function(value, name, args).map_err(|desc| desc.with_pos(pos))?,
+    };
+    scope.usage.check_size(value, pos)?;
+    scope.usage.check_size(&result, pos)?;
+    Ok(result)
+}
+
+fn builtin_member_function(
     value: &mut Expr,
     name: &str,
-    mut args: Vec<Expr>,
+    args: Vec<Expr>,
 ) -> Result<Expr, EvalErrorDesc> {
     if name == "clone" {
         return Ok(value.clone());
     }
+    if name == "cmp" && args.len() == 1 {
+        return Ok(cmp(value, &args[0])?.match_pos(value));
+    }
+    if name == "to_string" && args.is_empty() {
+        return Ok(ExprDesc::String(display(value)).match_pos(value));
+    }
     Ok(match &mut value.desc {
-        ExprDesc::Array(items) => match name.as_ref() {
-            "len" if args.is_empty() => ExprDesc::Int(items.len() as i32),
-            "push" => {
-                if args.len() == 1 {
-                    items.push(args.remove(0));
-                    ExprDesc::Unit
-                } else {
-                    return Err(EvalErrorDesc::InvalidType(
-                        "vec.push() takes a single argument",
-                    ));
-                }
-            }
-            _ => {
-                println!("{} - {:?}", name, args);
-                return Err(EvalErrorDesc::UnknownFunction(name.to_owned()));
-            }
-        },
-        ExprDesc::Float(f) => match name.as_ref() {
-            "sin" if args.is_empty() => ExprDesc::Float(f.sin()),
-            "cos" if args.is_empty() => ExprDesc::Float(f.cos()),
-            "tan" if args.is_empty() => ExprDesc::Float(f.tan()),
-            "abs" if args.is_empty() => ExprDesc::Float(f.abs()),
-            "sqrt" if args.is_empty() => ExprDesc::Float(f.sqrt()),
-            "min" if args.len() == 1 => match args[0].desc {
-                ExprDesc::Float(x) => ExprDesc::Float(f.min(x)),
-                _ => return Err(EvalErrorDesc::InvalidType("min() takes a float argument")),
-            },
-            "max" if args.len() == 1 => match args[0].desc {
-                ExprDesc::Float(x) => ExprDesc::Float(f.max(x)),
-                _ => return Err(EvalErrorDesc::InvalidType("max() takes a float argument")),
-            },
-            "atan2" if args.len() == 1 => match args[0].desc {
-                ExprDesc::Float(x) => ExprDesc::Float(f.atan2(x)),
-                _ => return Err(EvalErrorDesc::InvalidType("atan2() takes a float argument")),
-            },
-            // "to_int" if args.is_empty() => ExprDesc::Int(f as i32),
-            _ => {
-                println!("{} - {:?}", name, args);
-                return Err(EvalErrorDesc::UnknownFunction(name.to_owned()));
-            }
-        },
-        ExprDesc::Int(i) => match name.as_ref() {
-            "to_float" if false => ExprDesc::Float(*i as f32),
-            _ => {
-                println!("int {} - {:?}", name, args);
-                return Err(EvalErrorDesc::InvalidType("Unknown int fn"));
-            }
-        },
-        _ => {
-            println!("other {:?} : {} - {:?}", value, name, args);
-            return Err(EvalErrorDesc::InvalidType(
-                "Can only do fns on floats and ints",
-            ));
-        }
+        ExprDesc::Object(children) => object_method(children, name, args)?,
+        ExprDesc::Float(f) => float_method(*f, name, &args)?,
+        ExprDesc::Int(i) => int_method(*i, name, &args)?,
+        ExprDesc::String(s) => string_method(s, name, &args)?,
+        other => return Err(EvalErrorDesc::NoMethod(name.to_owned(), other.kind())),
     }
     .match_pos(value))
 }
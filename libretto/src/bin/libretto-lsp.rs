@@ -0,0 +1,277 @@
+//! A language server for `.lt.rs` scripts, talking over stdin and stdout. It shows syntax
+//! and type errors when a file is opened or saved, and knows where the functions, consts,
+//! structs and enums of a file are declared.
+
+use libretto::{Outline, Pos, Symbol};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentSymbol, DocumentSymbolParams,
+    DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, SymbolKind, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions, Url,
+};
+use std::collections::HashMap;
+use std::error::Error;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Sync + Send>>;
+
+fn main() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+            open_close: Some(true),
+            change: Some(TextDocumentSyncKind::FULL),
+            save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+            ..Default::default()
+        })),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".to_owned()]),
+            ..Default::default()
+        }),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    serve(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Answers requests until the client shuts the server down
+fn serve(connection: Connection) -> Result<()> {
+    let mut documents: HashMap<Url, String> = HashMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = handle_request(&documents, request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(uri) = handle_notification(&mut documents, notification)? {
+                    let diagnostics = match documents.get(&uri) {
+                        Some(source) => diagnostics(source),
+                        // a closed file's errors are cleared
+                        None => vec![],
+                    };
+                    let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+                    let notification = Notification::new(PublishDiagnostics::METHOD.to_owned(), params);
+                    connection.sender.send(Message::Notification(notification))?;
+                }
+            }
+            Message::Response(_) => (),
+        }
+    }
+    Ok(())
+}
+
+/// Keeps track of the text of open files. Returns a file whose diagnostics should be updated.
+fn handle_notification(documents: &mut HashMap<Url, String>, notification: Notification) -> Result<Option<Url>> {
+    Ok(match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+            let uri = params.text_document.uri;
+            documents.insert(uri.clone(), params.text_document.text);
+            Some(uri)
+        }
+        DidChangeTextDocument::METHOD => {
+            let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+            // changes are always the whole text, since that's the sync kind we ask for
+            if let Some(change) = params.content_changes.into_iter().last() {
+                documents.insert(params.text_document.uri, change.text);
+            }
+            None
+        }
+        DidSaveTextDocument::METHOD => {
+            let params: DidSaveTextDocumentParams = serde_json::from_value(notification.params)?;
+            let uri = params.text_document.uri;
+            if let Some(text) = params.text {
+                documents.insert(uri.clone(), text);
+            }
+            Some(uri)
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+            documents.remove(&params.text_document.uri);
+            Some(params.text_document.uri)
+        }
+        _ => None,
+    })
+}
+
+fn handle_request(documents: &HashMap<Url, String>, request: Request) -> Response {
+    let id = request.id.clone();
+    let result = match request.method.as_str() {
+        GotoDefinition::METHOD => respond(request, |params: GotoDefinitionParams| {
+            let position = params.text_document_position_params;
+            let uri = position.text_document.uri;
+            let source = document(documents, &uri)?;
+            let at = from_position(source, position.position);
+            Ok(Outline::new(source).definition(source, at).map(|symbol| {
+                GotoDefinitionResponse::Scalar(Location::new(uri.clone(), to_range(source, symbol.name_pos)))
+            }))
+        }),
+        HoverRequest::METHOD => respond(request, |params: HoverParams| {
+            let position = params.text_document_position_params;
+            let source = document(documents, &position.text_document.uri)?;
+            let at = from_position(source, position.position);
+            Ok(Outline::new(source).definition(source, at).map(|symbol| Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: format!("```rust\n{}\n```", symbol.detail),
+                }),
+                range: None,
+            }))
+        }),
+        Completion::METHOD => respond(request, |params: CompletionParams| {
+            let position = params.text_document_position;
+            let source = document(documents, &position.text_document.uri)?;
+            let at = from_position(source, position.position);
+            let items = Outline::new(source)
+                .completions(source, at)
+                .into_iter()
+                .map(|symbol| CompletionItem {
+                    label: symbol.name.clone(),
+                    kind: Some(completion_kind(symbol.kind)),
+                    detail: Some(symbol.detail.clone()),
+                    ..Default::default()
+                })
+                .collect();
+            Ok(Some(CompletionResponse::Array(items)))
+        }),
+        DocumentSymbolRequest::METHOD => respond(request, |params: DocumentSymbolParams| {
+            let source = document(documents, &params.text_document.uri)?;
+            let symbols = Outline::new(source)
+                .symbols
+                .iter()
+                .map(|symbol| document_symbol(source, symbol))
+                .collect();
+            Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+        }),
+        _ => Err((ErrorCode::MethodNotFound, format!("Unsupported request {}", request.method))),
+    };
+    match result {
+        Ok(value) => Response::new_ok(id, value),
+        Err((code, message)) => Response::new_err(id, code as i32, message),
+    }
+}
+
+type RequestResult<T> = std::result::Result<T, (ErrorCode, String)>;
+
+/// Decodes a request's params and encodes the result of `handle`
+fn respond<P, R, F>(request: Request, handle: F) -> RequestResult<serde_json::Value>
+where
+    P: serde::de::DeserializeOwned,
+    R: serde::Serialize,
+    F: FnOnce(P) -> RequestResult<R>,
+{
+    let params = serde_json::from_value(request.params)
+        .map_err(|err| (ErrorCode::InvalidParams, err.to_string()))?;
+    serde_json::to_value(handle(params)?).map_err(|err| (ErrorCode::InternalError, err.to_string()))
+}
+
+fn document<'a>(documents: &'a HashMap<Url, String>, uri: &Url) -> RequestResult<&'a str> {
+    documents
+        .get(uri)
+        .map(|source| source.as_str())
+        .ok_or_else(|| (ErrorCode::InvalidParams, format!("{} isn't open", uri)))
+}
+
+fn diagnostics(source: &str) -> Vec<Diagnostic> {
+    libretto::check_file(source)
+        .into_iter()
+        .map(|diagnostic| {
+            let mut message = diagnostic.message;
+            for note in diagnostic.notes {
+                message.push('\n');
+                message.push_str(&note);
+            }
+            Diagnostic {
+                range: to_range(source, diagnostic.pos),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("libretto".to_owned()),
+                message,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+#[allow(deprecated)]
+fn document_symbol(source: &str, symbol: &Symbol) -> DocumentSymbol {
+    DocumentSymbol {
+        name: symbol.name.clone(),
+        detail: Some(symbol.detail.clone()),
+        kind: symbol_kind(symbol.kind),
+        tags: None,
+        deprecated: None,
+        range: to_range(source, symbol.pos),
+        selection_range: to_range(source, symbol.name_pos),
+        children: Some(symbol.children.iter().map(|child| document_symbol(source, child)).collect()),
+    }
+}
+
+fn symbol_kind(kind: libretto::SymbolKind) -> SymbolKind {
+    match kind {
+        libretto::SymbolKind::Function => SymbolKind::FUNCTION,
+        libretto::SymbolKind::Const => SymbolKind::CONSTANT,
+        libretto::SymbolKind::Struct => SymbolKind::STRUCT,
+        libretto::SymbolKind::Enum => SymbolKind::ENUM,
+        libretto::SymbolKind::Module => SymbolKind::MODULE,
+        libretto::SymbolKind::Field => SymbolKind::FIELD,
+        libretto::SymbolKind::Variant => SymbolKind::ENUM_MEMBER,
+        libretto::SymbolKind::Variable => SymbolKind::VARIABLE,
+    }
+}
+
+fn completion_kind(kind: libretto::SymbolKind) -> CompletionItemKind {
+    match kind {
+        libretto::SymbolKind::Function => CompletionItemKind::FUNCTION,
+        libretto::SymbolKind::Const => CompletionItemKind::CONSTANT,
+        libretto::SymbolKind::Struct => CompletionItemKind::STRUCT,
+        libretto::SymbolKind::Enum => CompletionItemKind::ENUM,
+        libretto::SymbolKind::Module => CompletionItemKind::MODULE,
+        libretto::SymbolKind::Field => CompletionItemKind::FIELD,
+        libretto::SymbolKind::Variant => CompletionItemKind::ENUM_MEMBER,
+        libretto::SymbolKind::Variable => CompletionItemKind::VARIABLE,
+    }
+}
+
+/// A `Pos` counts lines and characters from 1, and LSP counts lines from 0 and
+/// characters in UTF-16 code units
+fn to_position(source: &str, (line, col): (usize, usize)) -> Position {
+    let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    let character: usize = text.chars().take(col.saturating_sub(1)).map(char::len_utf16).sum();
+    Position::new(line.saturating_sub(1) as u32, character as u32)
+}
+
+fn from_position(source: &str, position: Position) -> (usize, usize) {
+    let text = source.lines().nth(position.line as usize).unwrap_or("");
+    let mut units = 0;
+    let col = text
+        .chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= position.character as usize
+        })
+        .count();
+    (position.line as usize + 1, col + 1)
+}
+
+fn to_range(source: &str, pos: Pos) -> Range {
+    Range::new(to_position(source, pos.start), to_position(source, pos.end))
+}
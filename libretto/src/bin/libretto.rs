@@ -0,0 +1,166 @@
+//! An interactive shell for trying out scripts without running the game.
+//!
+//! ```text
+//! cargo run --bin libretto -- assets/skeletons.lt.rs
+//! ```
+//!
+//! `libretto fmt [--check] <files>` formats script files instead.
+
+use libretto::Scope;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+const HELP: &str = "\
+Type statements and expressions to evaluate them. Variables and functions stick around.
+
+:load <path>   evaluate a script file into the scope
+:fns           list the functions that are defined
+:type <expr>   show the type of an expression without running it
+:ast <input>   show how a line is parsed
+:help          show this message
+:quit          leave (so does ctrl-d)";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|arg| arg.as_str()) == Some("fmt") {
+        std::process::exit(fmt(&args[1..]));
+    }
+    let mut scope = Scope::new();
+    for path in args {
+        load(&mut scope, &path);
+    }
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("Unable to start the editor: {}", err);
+            std::process::exit(1);
+        }
+    };
+    println!("libretto repl, :help for commands");
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { ">> " } else { ".. " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // ctrl-c drops a half typed input
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                break;
+            }
+        };
+        input.push_str(&line);
+        input.push('\n');
+        // a blank line runs the input even if it's unfinished, to show what's wrong with it
+        if libretto::input_is_unfinished(&input) && !line.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(input.trim_end());
+        let text = std::mem::take(&mut input);
+        if !run(&mut scope, text.trim()) {
+            break;
+        }
+    }
+}
+
+/// Handles one input, returning whether to keep going
+fn run(scope: &mut Scope, text: &str) -> bool {
+    if text.is_empty() {
+        return true;
+    }
+    let (command, arg) = match text.strip_prefix(':') {
+        Some(command) => match command.find(char::is_whitespace) {
+            Some(idx) => (&command[..idx], command[idx..].trim()),
+            None => (command, ""),
+        },
+        None => {
+            match scope.eval_input(text) {
+                Ok(Some(value)) => println!("{}", value),
+                Ok(None) => (),
+                Err(err) => eprintln!("{}", scope.render_error(&err)),
+            }
+            return true;
+        }
+    };
+    match command {
+        "q" | "quit" => return false,
+        "h" | "help" => println!("{}", HELP),
+        "l" | "load" if !arg.is_empty() => load(scope, arg),
+        "f" | "fns" => {
+            for (name, function) in scope.functions() {
+                let args: Vec<String> = function
+                    .args
+                    .iter()
+                    .zip(function.signature.args.iter())
+                    .map(|(arg, typ)| format!("{}: {}", arg, typ))
+                    .collect();
+                println!("fn {}({}) -> {}", name, args.join(", "), function.signature.ret);
+            }
+        }
+        "t" | "type" if !arg.is_empty() => match scope.type_of(arg) {
+            Ok(typ) => println!("{}", typ),
+            Err(err) => eprintln!("{}", scope.render_error(&err)),
+        },
+        "a" | "ast" if !arg.is_empty() => match libretto::process_input(arg) {
+            Ok((stmts, value)) => {
+                for stmt in stmts {
+                    println!("{:#?}", stmt);
+                }
+                if let Some(value) = value {
+                    println!("{:#?}", value.clear_pos());
+                }
+            }
+            Err(err) => eprintln!("{}", err),
+        },
+        "load" | "type" | "ast" => eprintln!(":{} needs an argument", command),
+        _ => eprintln!("Unknown command :{}, try :help", command),
+    }
+    true
+}
+
+/// Rewrites files in the canonical format. With `--check` they're left alone, and it fails
+/// if any would change.
+fn fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let mut status = 0;
+    for path in args.iter().filter(|arg| *arg != "--check") {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("Unable to read {}: {}", path, err);
+                status = 1;
+                continue;
+            }
+        };
+        let formatted = match libretto::format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                status = 1;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} isn't formatted", path);
+            status = 1;
+        } else if let Err(err) = std::fs::write(path, formatted) {
+            eprintln!("Unable to write {}: {}", path, err);
+            status = 1;
+        }
+    }
+    status
+}
+
+fn load(scope: &mut Scope, path: &str) {
+    match scope.load_file(path) {
+        Ok(()) => println!("Loaded {}", path),
+        Err(err) => eprintln!("{}", scope.render_error(&err)),
+    }
+}
@@ -0,0 +1,259 @@
+use crate::ast::{add, ordering, values_equal, Expr, ExprDesc, Pos};
+use crate::error::{EvalError, EvalErrorDesc};
+use crate::scope::Scope;
+use crate::strings::display;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+type ArrayFn = fn(&mut Vec<Expr>, Vec<Expr>) -> Result<ExprDesc, EvalErrorDesc>;
+type ObjectFn = fn(&mut Vec<(String, Expr)>, Vec<Expr>) -> Result<ExprDesc, EvalErrorDesc>;
+
+/// Methods on arrays that don't take a closure: name, number of arguments, and the function.
+/// There are no iterators, so `iter()` and `collect()` just copy the array.
+const ARRAY_METHODS: &[(&str, usize, ArrayFn)] = &[
+    ("len", 0, |items, _| Ok(ExprDesc::Int(items.len() as i32))),
+    ("is_empty", 0, |items, _| Ok(ExprDesc::Bool(items.is_empty()))),
+    ("iter", 0, |items, _| Ok(ExprDesc::Array(items.clone()))),
+    ("collect", 0, |items, _| Ok(ExprDesc::Array(items.clone()))),
+    ("first", 0, |items, _| Ok(option(items.first().cloned()))),
+    ("last", 0, |items, _| Ok(option(items.last().cloned()))),
+    ("pop", 0, |items, _| Ok(option(items.pop()))),
+    ("rev", 0, |items, _| Ok(ExprDesc::Array(items.iter().rev().cloned().collect()))),
+    ("enumerate", 0, |items, _| {
+        let pairs = items.iter().enumerate().map(|(i, item)| {
+            ExprDesc::Tuple(vec![ExprDesc::Int(i as i32).into(), item.clone()]).into()
+        });
+        Ok(ExprDesc::Array(pairs.collect()))
+    }),
+    ("sum", 0, |items, _| {
+        let mut total: Expr = ExprDesc::Int(0).into();
+        for item in items.iter() {
+            total = add(&total, item).map_err(|desc| desc.or_invalid("sum() needs numbers"))?.into();
+        }
+        Ok(total.desc)
+    }),
+    ("sort", 0, |items, _| {
+        *items = merge_sort(items.clone(), &mut |a, b| total_ordering(a, b))?;
+        Ok(ExprDesc::Unit)
+    }),
+    ("push", 1, |items, mut args| {
+        items.push(args.remove(0));
+        Ok(ExprDesc::Unit)
+    }),
+    ("get", 1, |items, args| Ok(option(index(&args[0], items.len(), false).ok().map(|i| items[i].clone())))),
+    ("contains", 1, |items, args| Ok(ExprDesc::Bool(items.iter().any(|item| values_equal(item, &args[0]))))),
+    ("remove", 1, |items, args| Ok(items.remove(index(&args[0], items.len(), false)?).desc)),
+    ("zip", 1, |items, args| match &args[0].desc {
+        ExprDesc::Array(others) => {
+            let pairs = items.iter().zip(others.iter()).map(|(a, b)| {
+                ExprDesc::Tuple(vec![a.clone(), b.clone()]).into()
+            });
+            Ok(ExprDesc::Array(pairs.collect()))
+        }
+        _ => Err(EvalErrorDesc::InvalidType("zip() takes an array")),
+    }),
+    ("join", 1, |items, args| match &args[0].desc {
+        ExprDesc::String(separator) => {
+            let parts: Vec<String> = items.iter().map(display).collect();
+            Ok(ExprDesc::String(parts.join(separator)))
+        }
+        _ => Err(EvalErrorDesc::InvalidType("join() takes a string")),
+    }),
+    ("insert", 2, |items, mut args| {
+        // inserting at the end is fine, like rust
+        let at = index(&args[0], items.len(), true)?;
+        items.insert(at, args.remove(1));
+        Ok(ExprDesc::Unit)
+    }),
+];
+
+/// Methods on objects, which work like a map from string keys to values
+const OBJECT_METHODS: &[(&str, usize, ObjectFn)] = &[
+    ("len", 0, |children, _| Ok(ExprDesc::Int(children.len() as i32))),
+    ("is_empty", 0, |children, _| Ok(ExprDesc::Bool(children.is_empty()))),
+    ("keys", 0, |children, _| {
+        let keys = children.iter().map(|(key, _)| ExprDesc::String(key.clone()).into());
+        Ok(ExprDesc::Array(keys.collect()))
+    }),
+    ("values", 0, |children, _| Ok(ExprDesc::Array(children.iter().map(|(_, value)| value.clone()).collect()))),
+    ("get", 1, |children, args| {
+        let key = key(&args[0])?;
+        Ok(option(children.iter().find(|(name, _)| name == key).map(|(_, value)| value.clone())))
+    }),
+    ("contains_key", 1, |children, args| {
+        let key = key(&args[0])?;
+        Ok(ExprDesc::Bool(children.iter().any(|(name, _)| name == key)))
+    }),
+    ("remove", 1, |children, args| {
+        let key = key(&args[0])?;
+        let position = children.iter().position(|(name, _)| name == key);
+        Ok(option(position.map(|i| children.remove(i).1)))
+    }),
+    ("insert", 2, |children, mut args| {
+        let value = args.remove(1);
+        let key = key(&args[0])?;
+        Ok(option(match children.iter_mut().find(|(name, _)| name == key) {
+            Some((_, old)) => Some(std::mem::replace(old, value)),
+            None => {
+                children.push((key.to_owned(), value));
+                None
+            }
+        }))
+    }),
+];
+
+fn option(value: Option<Expr>) -> ExprDesc {
+    ExprDesc::Option(Box::new(value))
+}
+
+/// An index that's in `0..len`, or `0..=len` if it can be just past the end
+fn index(index: &Expr, len: usize, past_end: bool) -> Result<usize, EvalErrorDesc> {
+    match index.desc {
+        ExprDesc::Int(i) => match usize::try_from(i) {
+            Ok(at) if at < len || (past_end && at == len) => Ok(at),
+            _ => Err(EvalErrorDesc::IndexOutOfBounds(i, len)),
+        },
+        _ => Err(EvalErrorDesc::InvalidType("Arrays are indexed by ints")),
+    }
+}
+
+fn key(key: &Expr) -> Result<&str, EvalErrorDesc> {
+    match &key.desc {
+        ExprDesc::String(key) => Ok(key),
+        _ => Err(EvalErrorDesc::InvalidType("Object keys are strings")),
+    }
+}
+
+fn total_ordering(a: &Expr, b: &Expr) -> Result<Ordering, EvalErrorDesc> {
+    ordering(a, b)?.ok_or(EvalErrorDesc::InvalidType("Cannot compare NaN"))
+}
+
+/// `Less`, `Equal` or `Greater`, what `cmp()` returns and `sort_by()` expects
+fn ordering_value(ordering: Ordering) -> ExprDesc {
+    let name = match ordering {
+        Ordering::Less => "Less",
+        Ordering::Equal => "Equal",
+        Ordering::Greater => "Greater",
+    };
+    ExprDesc::NamedTuple(name.to_owned(), vec![])
+}
+
+pub(crate) fn cmp(a: &Expr, b: &Expr) -> Result<ExprDesc, EvalErrorDesc> {
+    Ok(ordering_value(total_ordering(a, b)?))
+}
+
+/// A stable merge sort that stops at the first error from `cmp`. Unlike `slice::sort_by`, a
+/// comparison that isn't a total order can't make it panic.
+fn merge_sort<E, F>(mut items: Vec<Expr>, cmp: &mut F) -> Result<Vec<Expr>, E>
+where
+    F: FnMut(&Expr, &Expr) -> Result<Ordering, E>,
+{
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, cmp)?.into_iter().peekable();
+    let mut right = merge_sort(right, cmp)?.into_iter().peekable();
+    let mut result = vec![];
+    loop {
+        let take_left = match (left.peek(), right.peek()) {
+            (Some(a), Some(b)) => cmp(a, b)? != Ordering::Greater,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return Ok(result),
+        };
+        result.extend(if take_left { left.next() } else { right.next() });
+    }
+}
+
+const SORT_BY_RESULT: &str = "sort_by() needs the closure to return Less, Equal or Greater";
+
+fn call_bool(closure: &Expr, args: Vec<Expr>, scope: &mut Scope, pos: Pos, message: &'static str) -> Result<bool, EvalError> {
+    match scope.call_closure(closure, args, pos)?.desc {
+        ExprDesc::Bool(b) => Ok(b),
+        _ => Err(EvalErrorDesc::InvalidType(message).with_pos(pos)),
+    }
+}
+
+/// Array methods, including the ones that call a closure
+pub(crate) fn array_method(
+    items: &mut Vec<Expr>,
+    name: &str,
+    mut args: Vec<Expr>,
+    scope: &mut Scope,
+    pos: Pos,
+) -> Result<ExprDesc, EvalError> {
+    Ok(match (name, args.len()) {
+        ("map", 1) => {
+            let mut result = vec![];
+            for item in items.iter() {
+                result.push(scope.call_closure(&args[0], vec![item.clone()], pos)?);
+            }
+            ExprDesc::Array(result)
+        }
+        ("filter", 1) => {
+            let mut result = vec![];
+            for item in items.iter() {
+                let message = "filter() needs the closure to return a bool";
+                if call_bool(&args[0], vec![item.clone()], scope, pos, message)? {
+                    result.push(item.clone());
+                }
+            }
+            ExprDesc::Array(result)
+        }
+        ("any", 1) | ("all", 1) => {
+            let all = name == "all";
+            for item in items.iter() {
+                let message = "any() and all() need the closure to return a bool";
+                if call_bool(&args[0], vec![item.clone()], scope, pos, message)? != all {
+                    return Ok(ExprDesc::Bool(!all));
+                }
+            }
+            ExprDesc::Bool(all)
+        }
+        ("fold", 2) => {
+            let closure = args.remove(1);
+            let mut acc = args.remove(0);
+            for item in items.iter() {
+                acc = scope.call_closure(&closure, vec![acc, item.clone()], pos)?;
+            }
+            acc.desc
+        }
+        ("sort_by", 1) => {
+            let mut cmp = |a: &Expr, b: &Expr| {
+                let result = scope.call_closure(&args[0], vec![a.clone(), b.clone()], pos)?;
+                match &result.desc {
+                    ExprDesc::NamedTuple(name, items) if items.is_empty() => match name.as_str() {
+                        "Less" => Ok(Ordering::Less),
+                        "Equal" => Ok(Ordering::Equal),
+                        "Greater" => Ok(Ordering::Greater),
+                        _ => Err(EvalErrorDesc::InvalidType(SORT_BY_RESULT).with_pos(pos)),
+                    },
+                    _ => Err(EvalErrorDesc::InvalidType(SORT_BY_RESULT).with_pos(pos)),
+                }
+            };
+            // sort a copy, so the array is left as it was if the closure fails
+            *items = merge_sort(items.clone(), &mut cmp)?;
+            ExprDesc::Unit
+        }
+        _ => match ARRAY_METHODS.iter().find(|(method, _, _)| *method == name) {
+            Some((_, arity, f)) if *arity == args.len() => f(items, args).map_err(|desc| desc.with_pos(pos))?,
+            Some((_, arity, _)) => {
+                return Err(EvalErrorDesc::FunctionWrongNumberArgs(*arity, args.len()).with_pos(pos))
+            }
+            None => return Err(EvalErrorDesc::UnknownFunction(name.to_owned()).with_pos(pos)),
+        },
+    })
+}
+
+pub(crate) fn object_method(
+    children: &mut Vec<(String, Expr)>,
+    name: &str,
+    args: Vec<Expr>,
+) -> Result<ExprDesc, EvalErrorDesc> {
+    match OBJECT_METHODS.iter().find(|(method, _, _)| *method == name) {
+        Some((_, arity, f)) if *arity == args.len() => f(children, args),
+        Some((_, arity, _)) => Err(EvalErrorDesc::FunctionWrongNumberArgs(*arity, args.len())),
+        None => Err(EvalErrorDesc::UnknownFunction(name.to_owned())),
+    }
+}
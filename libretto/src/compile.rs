@@ -0,0 +1,956 @@
+use crate::ast::{Access, Args, Expr, ExprDesc, IfCond, Pattern, Place, Pos, Statement, Type};
+use crate::error::{EvalError, EvalErrorDesc};
+use crate::native::NativeFn;
+use crate::schema::{Signature, TypeDecl};
+use crate::scope::Callable;
+use std::sync::{Arc, Mutex, Weak};
+
+/// A step of a compiled member access. Method args and indexes are taken from the stack.
+#[derive(PartialEq, Debug, Clone)]
+pub enum AccessOp {
+    Field(String),
+    /// The method name and number of args
+    Method(String, usize),
+    /// Where the index was written
+    Index(Pos),
+}
+
+impl AccessOp {
+    /// How many values this step takes from the stack
+    pub fn args(&self) -> usize {
+        match self {
+            AccessOp::Field(_) => 0,
+            AccessOp::Method(_, count) => *count,
+            AccessOp::Index(_) => 1,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BinOp {
+    Plus,
+    Minus,
+    Times,
+    Divide,
+    Modulo,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Neq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+/// A single instruction for the VM. Most arguments are indices into the tables of the `Chunk`,
+/// and jump targets are instruction offsets.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Op {
+    Const(usize),
+    Unit,
+    /// Move a value out of a local slot (primitives are copied)
+    Load(usize),
+    /// Look up a variable that isn't local to the function, by name
+    LoadName(usize),
+    /// Clone the value in a local slot for `&x`, leaving it in place
+    Copy(usize),
+    CopyName(usize),
+    Store(usize),
+    /// Unbind the local slots in a range, at the end of a block
+    Clear(usize, usize),
+    Pop,
+    PopN(usize),
+
+    /// Match a pattern against the popped value, erroring with the message if it doesn't match
+    Bind(usize, &'static str),
+    /// Match a pattern against the popped value, jumping if it doesn't match
+    BindOr(usize, usize),
+    /// Match a pattern against a copy of the top value, which is left on the stack.
+    /// Jumps if it doesn't match.
+    TestPattern(usize, usize),
+    Unmatched,
+
+    Array(usize),
+    Tuple(usize),
+    Object(usize),
+    Struct(usize),
+    NamedTuple(usize, usize),
+    Some,
+
+    Binary(BinOp),
+    Neg,
+    Not,
+    Cast(Type),
+    Range(bool),
+
+    Jump(usize),
+    JumpIfFalse(usize, &'static str),
+    /// Short-circuiting `&&` and `||`: jump if the popped bool decides the result
+    AndJump(usize),
+    OrJump(usize),
+    ExpectBool,
+
+    /// Call a named function: call site, number of args
+    Call(usize, usize),
+    /// Call a local, which might be a closure or might be shadowing a function: slot, call site, args
+    CallLocal(usize, usize, usize),
+    /// Call the closure below the args on the stack
+    CallValue(usize),
+
+    /// Member access on a value on the stack, after the args to any method calls
+    Member(usize),
+    MemberLocal(usize, usize),
+    MemberName(usize, usize),
+
+    /// Assign the value below any indexes on the stack: slot or name, then the access chain
+    Assign(usize, usize),
+    AssignName(usize, usize),
+
+    IterStart,
+    IterNext(usize),
+    IterPop,
+
+    /// Make a closure from a lambda, taking the values it captures from the stack
+    Lambda(usize),
+    DefineFn(usize),
+    Fail(usize),
+}
+
+#[derive(PartialEq, Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub positions: Vec<Pos>,
+    pub consts: Vec<Expr>,
+    pub names: Vec<String>,
+    /// The variable name for each local slot
+    pub slots: Vec<String>,
+    /// Patterns, and the slots their bindings go in
+    pub patterns: Vec<(Pattern, Vec<usize>)>,
+    /// Struct names and keys
+    pub shapes: Vec<(String, Vec<String>)>,
+    /// Member access chains, for both reading and assignment
+    pub accesses: Vec<Vec<AccessOp>>,
+    pub calls: Vec<CallSite>,
+    pub lambdas: Vec<Arc<Function>>,
+    pub fns: Vec<(String, Arc<Function>)>,
+    pub errors: Vec<EvalError>,
+    /// Inner function definitions need a scope of their own to live in
+    pub defines_fns: bool,
+}
+
+/// A named function call. The function it finds is remembered until the scope's functions
+/// or variables change, so that calls in a loop don't look it up by name every time.
+pub struct CallSite {
+    pub name: Arc<str>,
+    cached: Mutex<Option<(u64, Cached)>>,
+}
+
+/// Holding a function weakly stops a recursive function from keeping itself alive
+enum Cached {
+    Compiled(Weak<Function>),
+    Native(NativeFn),
+}
+
+impl CallSite {
+    fn new(name: &str) -> Self {
+        CallSite {
+            name: name.into(),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The function found by the last call, if the scope hasn't changed since
+    pub(crate) fn cached(&self, version: u64) -> Option<Callable> {
+        match &*self.cached.lock().ok()? {
+            Some((found, Cached::Compiled(f))) if *found == version => f.upgrade().map(Callable::Compiled),
+            Some((found, Cached::Native(native))) if *found == version => Some(Callable::Native(native.clone())),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn remember(&self, version: u64, callable: &Callable) {
+        let cached = match callable {
+            Callable::Compiled(f) => Cached::Compiled(Arc::downgrade(f)),
+            Callable::Native(native) => Cached::Native(native.clone()),
+            Callable::Closure(..) => return,
+        };
+        if let Ok(mut slot) = self.cached.lock() {
+            *slot = Some((version, cached));
+        }
+    }
+}
+
+impl PartialEq for CallSite {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl std::fmt::Debug for CallSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CallSite({})", self.name)
+    }
+}
+
+/// A function definition, along with its bytecode
+#[derive(PartialEq, Debug)]
+pub struct Function {
+    pub args: Args,
+    /// The variables a lambda uses from where it was made. Their values go in the slots after
+    /// the args.
+    pub captures: Args,
+    /// The declared types, which are all `any` for a function made with `compile`
+    pub signature: Signature,
+    pub body: Expr,
+    pub chunk: Chunk,
+}
+
+impl Function {
+    pub fn compile(args: Args, body: Expr) -> Self {
+        let mut compiler = Compiler::new(None, vec![]);
+        for arg in args.iter() {
+            compiler.declare(arg, false);
+        }
+        compiler.expr(&body);
+        Function::new(args, vec![], body, compiler.chunk)
+    }
+
+    fn new(args: Args, captures: Args, body: Expr, chunk: Chunk) -> Self {
+        Function {
+            signature: Signature {
+                args: vec![TypeDecl::Any; args.len()],
+                ret: TypeDecl::Any,
+            },
+            args,
+            captures,
+            body,
+            chunk,
+        }
+    }
+
+    /// Compiles a lambda inside a function whose locals (and those of the functions around
+    /// it) are `outer`. Returns the captures in the order the lambda expects them, or the
+    /// error to give when it's made.
+    fn lambda(args: &[String], body: &Expr, outer: Vec<String>) -> (Self, Vec<Capture>, Option<EvalError>) {
+        // The captures are only known once the whole body has been compiled, and then it's
+        // compiled again so that they get the slots after the args
+        let mut captures: Vec<Capture> = vec![];
+        loop {
+            let mut compiler = Compiler::new(Some(vec![]), outer.clone());
+            for arg in args.iter() {
+                compiler.declare(arg, false);
+            }
+            for capture in captures.iter() {
+                compiler.declare(&capture.name, false);
+            }
+            compiler.expr(body);
+            let found = compiler.captures.unwrap_or_default();
+            if found.is_empty() {
+                let names = captures.iter().map(|capture| capture.name.clone()).collect();
+                let function = Function::new(args.to_vec(), names, body.clone(), compiler.chunk);
+                return (function, captures, compiler.assigns_capture);
+            }
+            captures.extend(found);
+        }
+    }
+
+    pub fn with_signature(mut self, signature: Signature) -> Self {
+        self.signature = signature;
+        self
+    }
+}
+
+struct Local {
+    name: String,
+    slot: usize,
+    mutable: bool,
+}
+
+/// A variable a lambda uses from outside it
+struct Capture {
+    name: String,
+    /// Whether it's only used with `&`, `.clone()` or called, so the lambda takes a copy and
+    /// leaves the variable where it was
+    copy: bool,
+}
+
+struct Loop {
+    start: usize,
+    breaks: Vec<usize>,
+    depth: usize,
+}
+
+struct Compiler {
+    chunk: Chunk,
+    /// Variables currently in scope, innermost last
+    locals: Vec<Local>,
+    loops: Vec<Loop>,
+    /// The height of the value stack at the current instruction
+    depth: usize,
+    /// In a lambda, the variables from outside it that were found while compiling
+    captures: Option<Vec<Capture>>,
+    /// The locals of the functions around a lambda, which a call by name might be to
+    outer: Vec<String>,
+    /// Assigning to a captured variable is an error when the lambda is made
+    assigns_capture: Option<EvalError>,
+}
+
+impl Compiler {
+    fn new(captures: Option<Vec<Capture>>, outer: Vec<String>) -> Self {
+        Compiler {
+            chunk: Chunk::default(),
+            locals: vec![],
+            loops: vec![],
+            depth: 0,
+            captures,
+            outer,
+            assigns_capture: None,
+        }
+    }
+
+    fn emit(&mut self, op: Op, pos: Pos) -> usize {
+        let (pops, pushes) = self.stack_effect(&op);
+        self.depth = self.depth + pushes - pops;
+        self.chunk.code.push(op);
+        self.chunk.positions.push(pos);
+        self.chunk.code.len() - 1
+    }
+
+    fn stack_effect(&self, op: &Op) -> (usize, usize) {
+        let args = |access: usize| -> usize {
+            self.chunk.accesses[access].iter().map(AccessOp::args).sum()
+        };
+        match op {
+            Op::Const(_) | Op::Unit | Op::Load(_)
+            | Op::LoadName(_)
+            | Op::Copy(_)
+            | Op::CopyName(_) => (0, 1),
+            Op::Store(_) | Op::Pop | Op::Bind(_, _) | Op::BindOr(_, _) | Op::Unmatched => (1, 0),
+            Op::PopN(n) => (*n, 0),
+            Op::Clear(_, _)
+            | Op::TestPattern(_, _)
+            | Op::Jump(_)
+            | Op::ExpectBool
+            | Op::IterPop
+            | Op::DefineFn(_)
+            | Op::Fail(_) => (0, 0),
+            Op::Array(n) | Op::Tuple(n) | Op::NamedTuple(_, n) => (*n, 1),
+            Op::Object(shape) | Op::Struct(shape) => (self.chunk.shapes[*shape].1.len(), 1),
+            Op::Some | Op::Neg | Op::Not | Op::Cast(_) => (1, 1),
+            Op::Binary(_) | Op::Range(_) => (2, 1),
+            Op::JumpIfFalse(_, _) | Op::AndJump(_) | Op::OrJump(_) => (1, 0),
+            Op::Call(_, n) | Op::CallLocal(_, _, n) => (*n, 1),
+            Op::CallValue(n) => (n + 1, 1),
+            Op::Member(access) => (args(*access) + 1, 1),
+            Op::MemberLocal(_, access) | Op::MemberName(_, access) => (args(*access), 1),
+            Op::Assign(_, access) | Op::AssignName(_, access) => (args(*access) + 1, 0),
+            Op::IterStart => (1, 0),
+            Op::IterNext(_) => (0, 1),
+            Op::Lambda(idx) => (self.chunk.lambdas[*idx].captures.len(), 1),
+        }
+    }
+
+    fn here(&self) -> usize {
+        self.chunk.code.len()
+    }
+
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.chunk.code[at] {
+            Op::Jump(t)
+            | Op::JumpIfFalse(t, _)
+            | Op::AndJump(t)
+            | Op::OrJump(t)
+            | Op::BindOr(_, t)
+            | Op::TestPattern(_, t)
+            | Op::IterNext(t) => *t = target,
+            op => unreachable!("Cannot patch {:?}", op),
+        }
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        match self.chunk.names.iter().position(|n| n == name) {
+            Some(idx) => idx,
+            None => {
+                self.chunk.names.push(name.to_owned());
+                self.chunk.names.len() - 1
+            }
+        }
+    }
+
+    fn call_site(&mut self, name: &str) -> usize {
+        match self.chunk.calls.iter().position(|site| &*site.name == name) {
+            Some(idx) => idx,
+            None => {
+                self.chunk.calls.push(CallSite::new(name));
+                self.chunk.calls.len() - 1
+            }
+        }
+    }
+
+    fn fail(&mut self, error: EvalError) {
+        let pos = error.pos;
+        self.chunk.errors.push(error);
+        self.emit(Op::Fail(self.chunk.errors.len() - 1), pos);
+    }
+
+    fn declare(&mut self, name: &str, mutable: bool) -> usize {
+        let slot = self.chunk.slots.len();
+        self.chunk.slots.push(name.to_owned());
+        self.locals.push(Local {
+            name: name.to_owned(),
+            slot,
+            mutable,
+        });
+        slot
+    }
+
+    fn resolve(&self, name: &str) -> Option<&Local> {
+        self.locals.iter().rev().find(|local| local.name == name)
+    }
+
+    /// In a lambda, a variable that isn't local is captured. Until the captures get their
+    /// own slots, it reads from a slot that's never filled.
+    fn capture(&mut self, name: &str, copy: bool) -> Option<usize> {
+        let captures = self.captures.as_mut()?;
+        match captures.iter_mut().find(|capture| capture.name == name) {
+            Some(capture) => capture.copy &= copy,
+            None => captures.push(Capture {
+                name: name.to_owned(),
+                copy,
+            }),
+        }
+        self.chunk.slots.push(name.to_owned());
+        Some(self.chunk.slots.len() - 1)
+    }
+
+    /// Moves or copies a variable onto the stack
+    fn variable(&mut self, name: &str, copy: bool, pos: Pos) {
+        let slot = match self.resolve(name) {
+            Some(local) => Some(local.slot),
+            None => self.capture(name, copy),
+        };
+        match (slot, copy) {
+            (Some(slot), false) => self.emit(Op::Load(slot), pos),
+            (Some(slot), true) => self.emit(Op::Copy(slot), pos),
+            (None, false) => {
+                let name = self.name(name);
+                self.emit(Op::LoadName(name), pos)
+            }
+            (None, true) => {
+                let name = self.name(name);
+                self.emit(Op::CopyName(name), pos)
+            }
+        };
+    }
+
+    fn lambda(&mut self, args: &[String], body: &Expr, pos: Pos) {
+        let mut outer = self.outer.clone();
+        outer.extend(self.locals.iter().map(|local| local.name.clone()));
+        let (function, captures, error) = Function::lambda(args, body, outer);
+        if let Some(error) = error {
+            self.fail(error);
+            self.emit(Op::Unit, pos);
+            return;
+        }
+        for capture in captures {
+            self.variable(&capture.name, capture.copy, pos);
+        }
+        self.chunk.lambdas.push(Arc::new(function));
+        self.emit(Op::Lambda(self.chunk.lambdas.len() - 1), pos);
+    }
+
+    /// Returns the markers needed by `end_scope`
+    fn begin_scope(&self) -> (usize, usize) {
+        (self.locals.len(), self.chunk.slots.len())
+    }
+
+    fn end_scope(&mut self, (locals, slots): (usize, usize), pos: Pos) {
+        self.locals.truncate(locals);
+        if self.chunk.slots.len() > slots {
+            self.emit(Op::Clear(slots, self.chunk.slots.len()), pos);
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> usize {
+        let mut bindings = vec![];
+        pattern_bindings(pattern, &mut bindings);
+        let slots = bindings
+            .into_iter()
+            .map(|(name, mutable)| self.declare(&name, mutable))
+            .collect();
+        self.chunk.patterns.push((pattern.clone(), slots));
+        self.chunk.patterns.len() - 1
+    }
+
+    /// Binds the value on top of the stack
+    fn bind(&mut self, pattern: &Pattern, message: &'static str, pos: Pos) {
+        match pattern {
+            Pattern::Ident(name) => {
+                let slot = self.declare(name, false);
+                self.emit(Op::Store(slot), pos);
+            }
+            Pattern::MutIdent(name) => {
+                let slot = self.declare(name, true);
+                self.emit(Op::Store(slot), pos);
+            }
+            _ => {
+                let pattern = self.pattern(pattern);
+                self.emit(Op::Bind(pattern, message), pos);
+            }
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Let(pattern, _, value) => {
+                self.expr(value);
+                self.bind(pattern, "if let pattern", value.pos);
+            }
+            Statement::Assign(place, value) => {
+                self.expr(value);
+                self.assign(place);
+            }
+            Statement::ExprDesc(expr) => {
+                self.expr(expr);
+                self.emit(Op::Pop, expr.pos);
+            }
+            Statement::FnDefn(name, args, signature, body) => {
+                let function = Function::compile(args.clone(), body.clone()).with_signature(signature.clone());
+                self.chunk.fns.push((name.clone(), Arc::new(function)));
+                self.chunk.defines_fns = true;
+                self.emit(Op::DefineFn(self.chunk.fns.len() - 1), body.pos);
+            }
+            Statement::Use(..) | Statement::Mod(..) | Statement::Decl(..) => {
+                unreachable!("use, mod and declarations are only parsed at the top level")
+            }
+        }
+    }
+
+    fn assign(&mut self, place: &Place) {
+        let members = self.access(&place.members);
+        match self.resolve(&place.name) {
+            Some(local) if local.mutable => {
+                let slot = local.slot;
+                self.emit(Op::Assign(slot, members), place.pos);
+            }
+            Some(_) => {
+                let count = self.chunk.accesses[members].iter().map(AccessOp::args).sum::<usize>();
+                self.emit(Op::PopN(count + 1), place.pos);
+                self.fail(EvalErrorDesc::AssignToImmutable(place.name.clone()).with_pos(place.pos));
+            }
+            // Captured variables are copies, so changing them wouldn't do what you expect
+            None if self.captures.is_some() => {
+                let error = EvalErrorDesc::AssignToImmutable(place.name.clone()).with_pos(place.pos);
+                self.assigns_capture.get_or_insert_with(|| error.clone());
+                let count = self.chunk.accesses[members].iter().map(AccessOp::args).sum::<usize>();
+                self.emit(Op::PopN(count + 1), place.pos);
+                self.fail(error);
+            }
+            None => {
+                let name = self.name(&place.name);
+                self.emit(Op::AssignName(name, members), place.pos);
+            }
+        }
+    }
+
+    /// Compiles the args and indexes in an access chain, which the VM takes from the stack
+    fn access(&mut self, items: &[Access]) -> usize {
+        let access = items
+            .iter()
+            .map(|item| match item {
+                Access::Field(name) => AccessOp::Field(name.clone()),
+                Access::Method(name, args) => AccessOp::Method(name.clone(), self.exprs(args)),
+                Access::Index(index, pos) => {
+                    self.expr(index);
+                    AccessOp::Index(*pos)
+                }
+            })
+            .collect();
+        self.chunk.accesses.push(access);
+        self.chunk.accesses.len() - 1
+    }
+
+    fn exprs(&mut self, items: &[Expr]) -> usize {
+        for item in items {
+            self.expr(item);
+        }
+        items.len()
+    }
+
+    fn shape(&mut self, name: &str, items: &[(String, Expr)]) -> usize {
+        for (_key, value) in items {
+            self.expr(value);
+        }
+        let keys = items.iter().map(|(key, _)| key.clone()).collect();
+        self.chunk.shapes.push((name.to_owned(), keys));
+        self.chunk.shapes.len() - 1
+    }
+
+    fn binary(&mut self, a: &Expr, b: &Expr, op: BinOp, pos: Pos) {
+        self.expr(a);
+        self.expr(b);
+        self.emit(Op::Binary(op), pos);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        let pos = expr.pos;
+        if is_const(&expr.desc) {
+            self.chunk.consts.push(expr.clone());
+            self.emit(Op::Const(self.chunk.consts.len() - 1), pos);
+            return;
+        }
+        match &expr.desc {
+            ExprDesc::Unit => {
+                self.emit(Op::Unit, pos);
+            }
+            ExprDesc::Float(_)
+            | ExprDesc::Int(_)
+            | ExprDesc::Long(_)
+            | ExprDesc::Double(_)
+            | ExprDesc::Bool(_)
+            | ExprDesc::Char(_)
+            | ExprDesc::String(_)
+            | ExprDesc::Closure(_, _)
+            | ExprDesc::Moved => {
+                self.chunk.consts.push(expr.clone());
+                self.emit(Op::Const(self.chunk.consts.len() - 1), pos);
+            }
+            ExprDesc::Ident(name) => self.variable(name, false, pos),
+
+            ExprDesc::Array(items) => {
+                let count = self.exprs(items);
+                self.emit(Op::Array(count), pos);
+            }
+            ExprDesc::Tuple(items) => {
+                let count = self.exprs(items);
+                self.emit(Op::Tuple(count), pos);
+            }
+            ExprDesc::Object(items) => {
+                let shape = self.shape("", items);
+                self.emit(Op::Object(shape), pos);
+            }
+            ExprDesc::Struct(name, items) => {
+                let shape = self.shape(name, items);
+                self.emit(Op::Struct(shape), pos);
+            }
+            ExprDesc::NamedTuple(name, items) => {
+                let count = self.exprs(items);
+                let name = self.name(name);
+                self.emit(Op::NamedTuple(name, count), pos);
+            }
+            ExprDesc::Option(inner) => match inner.as_ref() {
+                Some(value) => {
+                    self.expr(value);
+                    self.emit(Op::Some, pos);
+                }
+                None => {
+                    self.chunk.consts.push(expr.clone());
+                    self.emit(Op::Const(self.chunk.consts.len() - 1), pos);
+                }
+            },
+
+            ExprDesc::Plus(a, b) => self.binary(a, b, BinOp::Plus, pos),
+            ExprDesc::Minus(a, b) => self.binary(a, b, BinOp::Minus, pos),
+            ExprDesc::Times(a, b) => self.binary(a, b, BinOp::Times, pos),
+            ExprDesc::Divide(a, b) => self.binary(a, b, BinOp::Divide, pos),
+            ExprDesc::Modulo(a, b) => self.binary(a, b, BinOp::Modulo, pos),
+            ExprDesc::BitAnd(a, b) => self.binary(a, b, BinOp::BitAnd, pos),
+            ExprDesc::BitOr(a, b) => self.binary(a, b, BinOp::BitOr, pos),
+            ExprDesc::BitXor(a, b) => self.binary(a, b, BinOp::BitXor, pos),
+            ExprDesc::Shl(a, b) => self.binary(a, b, BinOp::Shl, pos),
+            ExprDesc::Shr(a, b) => self.binary(a, b, BinOp::Shr, pos),
+            ExprDesc::Eq(a, b) => self.binary(a, b, BinOp::Eq, pos),
+            ExprDesc::Neq(a, b) => self.binary(a, b, BinOp::Neq, pos),
+            ExprDesc::Lt(a, b) => self.binary(a, b, BinOp::Lt, pos),
+            ExprDesc::Gt(a, b) => self.binary(a, b, BinOp::Gt, pos),
+            ExprDesc::Le(a, b) => self.binary(a, b, BinOp::Le, pos),
+            ExprDesc::Ge(a, b) => self.binary(a, b, BinOp::Ge, pos),
+
+            ExprDesc::And(a, b) | ExprDesc::Or(a, b) => {
+                self.expr(a);
+                let jump = match &expr.desc {
+                    ExprDesc::And(_, _) => self.emit(Op::AndJump(0), a.pos),
+                    _ => self.emit(Op::OrJump(0), a.pos),
+                };
+                self.expr(b);
+                self.emit(Op::ExpectBool, b.pos);
+                self.patch(jump);
+            }
+
+            ExprDesc::Neg(a) => {
+                self.expr(a);
+                self.emit(Op::Neg, pos);
+            }
+            ExprDesc::Not(a) => {
+                self.expr(a);
+                self.emit(Op::Not, pos);
+            }
+            ExprDesc::Ref(a) => match &a.desc {
+                ExprDesc::Ident(name) => self.variable(name, true, pos),
+                _ => self.expr(a),
+            },
+            ExprDesc::Cast(a, typ) => {
+                self.expr(a);
+                self.emit(Op::Cast(typ.clone()), pos);
+            }
+            ExprDesc::Range(start, end, inclusive) => {
+                self.expr(start);
+                self.expr(end);
+                self.emit(Op::Range(*inclusive), pos);
+            }
+
+            ExprDesc::Block(stmts, last) => {
+                let scope = self.begin_scope();
+                for stmt in stmts {
+                    self.statement(stmt);
+                }
+                self.expr(last);
+                self.end_scope(scope, pos);
+            }
+
+            ExprDesc::FnCall(name, args) => {
+                let count = self.exprs(args);
+                let slot = match self.resolve(name) {
+                    Some(local) => Some(local.slot),
+                    // Calling a closure from around a lambda captures a copy of it
+                    None if self.outer.iter().any(|outer| outer == name) => self.capture(name, true),
+                    None => None,
+                };
+                let site = self.call_site(name);
+                match slot {
+                    Some(slot) => self.emit(Op::CallLocal(slot, site, count), pos),
+                    None => self.emit(Op::Call(site, count), pos),
+                };
+            }
+            ExprDesc::Call(target, args) => {
+                self.expr(target);
+                let count = self.exprs(args);
+                self.emit(Op::CallValue(count), pos);
+            }
+            ExprDesc::Lambda(args, body) => self.lambda(args, body, pos),
+
+            ExprDesc::MemberAccess(target, items) => {
+                let access = self.access(items);
+                match &target.desc {
+                    ExprDesc::Ident(name) => match self.resolve(name).map(|local| local.slot) {
+                        Some(slot) => {
+                            self.emit(Op::MemberLocal(slot, access), pos);
+                        }
+                        // a lambda that only clones a variable leaves it where it was
+                        None if self.captures.is_some() => {
+                            let clone = matches!(&items[..], [Access::Method(method, args), ..] if method == "clone" && args.is_empty());
+                            let slot = self.capture(name, clone).unwrap_or_default();
+                            self.emit(Op::MemberLocal(slot, access), pos);
+                        }
+                        None => {
+                            let name = self.name(name);
+                            self.emit(Op::MemberName(name, access), pos);
+                        }
+                    },
+                    _ => {
+                        self.expr(target);
+                        self.emit(Op::Member(access), pos);
+                    }
+                }
+            }
+
+            ExprDesc::IfChain(chain, else_) => {
+                let depth = self.depth;
+                let mut ends = vec![];
+                for (cond, body) in chain {
+                    let scope = self.begin_scope();
+                    let skip = match cond {
+                        IfCond::Value(value) => {
+                            self.expr(value);
+                            self.emit(Op::JumpIfFalse(0, "If condition must be a bool"), pos)
+                        }
+                        IfCond::IfLet(pattern, value) => {
+                            self.expr(value);
+                            let pattern = self.pattern(pattern);
+                            self.emit(Op::BindOr(pattern, 0), pos)
+                        }
+                    };
+                    self.expr(body);
+                    self.end_scope(scope, pos);
+                    ends.push(self.emit(Op::Jump(0), pos));
+                    self.patch(skip);
+                    self.depth = depth;
+                }
+                match else_ {
+                    Some(body) => self.expr(body),
+                    None => {
+                        self.emit(Op::Unit, pos);
+                    }
+                }
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+
+            ExprDesc::Match(value, cases) => {
+                self.expr(value);
+                let depth = self.depth;
+                let mut ends = vec![];
+                for (pattern, guard, body) in cases {
+                    let scope = self.begin_scope();
+                    let pattern = self.pattern(pattern);
+                    let mut skips = vec![self.emit(Op::TestPattern(pattern, 0), pos)];
+                    if let Some(guard) = guard {
+                        self.expr(guard);
+                        skips.push(self.emit(Op::JumpIfFalse(0, "Match guard must be a bool"), pos));
+                    }
+                    self.emit(Op::Pop, pos);
+                    self.expr(body);
+                    let slots = scope.1;
+                    self.end_scope(scope, pos);
+                    ends.push(self.emit(Op::Jump(0), pos));
+                    for skip in skips {
+                        self.patch(skip);
+                    }
+                    self.depth = depth;
+                    // a failed guard leaves its bindings behind
+                    if guard.is_some() && self.chunk.slots.len() > slots {
+                        self.emit(Op::Clear(slots, self.chunk.slots.len()), pos);
+                    }
+                }
+                self.emit(Op::Unmatched, pos);
+                for end in ends {
+                    self.patch(end);
+                }
+                self.depth = depth;
+            }
+
+            ExprDesc::For(pattern, iterable, body) => {
+                self.expr(iterable);
+                self.emit(Op::IterStart, iterable.pos);
+                let depth = self.depth;
+                let start = self.here();
+                let next = self.emit(Op::IterNext(0), pos);
+                let scope = self.begin_scope();
+                self.bind(pattern, "for loop pattern", pos);
+                self.loop_body(body, start, depth);
+                self.end_scope(scope, pos);
+                let breaks = self.loops.pop().map_or(vec![], |l| l.breaks);
+                self.emit(Op::Jump(start), pos);
+                for at in breaks {
+                    self.patch(at);
+                }
+                self.emit(Op::IterPop, pos);
+                self.patch(next);
+                self.emit(Op::Unit, pos);
+            }
+
+            ExprDesc::While(cond, body) => {
+                let depth = self.depth;
+                let start = self.here();
+                let scope = self.begin_scope();
+                let exit = match cond.as_ref() {
+                    IfCond::Value(value) => {
+                        self.expr(value);
+                        self.emit(
+                            Op::JumpIfFalse(0, "While condition must be a bool"),
+                            value.pos,
+                        )
+                    }
+                    IfCond::IfLet(pattern, value) => {
+                        self.expr(value);
+                        let pattern = self.pattern(pattern);
+                        self.emit(Op::BindOr(pattern, 0), pos)
+                    }
+                };
+                self.loop_body(body, start, depth);
+                self.end_scope(scope, pos);
+                let breaks = self.loops.pop().map_or(vec![], |l| l.breaks);
+                self.emit(Op::Jump(start), pos);
+                self.patch(exit);
+                for at in breaks {
+                    self.patch(at);
+                }
+                self.emit(Op::Unit, pos);
+            }
+
+            ExprDesc::Break | ExprDesc::Continue => {
+                let depth = self.depth;
+                match self.loops.last() {
+                    None => self.fail(EvalErrorDesc::BreakOutsideLoop.with_pos(pos)),
+                    Some(l) => {
+                        let (extra, start) = (depth - l.depth, l.start);
+                        if extra > 0 {
+                            self.emit(Op::PopN(extra), pos);
+                        }
+                        if let ExprDesc::Break = expr.desc {
+                            let at = self.emit(Op::Jump(0), pos);
+                            if let Some(l) = self.loops.last_mut() {
+                                l.breaks.push(at);
+                            }
+                        } else {
+                            self.emit(Op::Jump(start), pos);
+                        }
+                    }
+                }
+                // Nothing after this runs, but the surrounding code expects a value
+                self.depth = depth + 1;
+            }
+        }
+    }
+
+    /// Compiles the body of a loop, discarding its value. The loop stays on `self.loops` so
+    /// the caller can patch the breaks.
+    fn loop_body(&mut self, body: &Expr, start: usize, depth: usize) {
+        self.loops.push(Loop {
+            start,
+            breaks: vec![],
+            depth,
+        });
+        self.expr(body);
+        self.emit(Op::Pop, body.pos);
+    }
+}
+
+/// Like `pattern_names`, but also records whether each binding is `mut`
+/// A value that can be a constant. Structs and variants are always built at runtime, so that
+/// they're checked against their declarations.
+fn is_const(desc: &ExprDesc) -> bool {
+    match desc {
+        ExprDesc::Struct(..) | ExprDesc::NamedTuple(..) => false,
+        ExprDesc::Array(items) | ExprDesc::Tuple(items) => items.iter().all(|item| is_const(&item.desc)),
+        ExprDesc::Object(items) => items.iter().all(|(_, item)| is_const(&item.desc)),
+        ExprDesc::Option(inner) => inner.as_ref().as_ref().is_none_or(|inner| is_const(&inner.desc)),
+        _ => !desc.needs_evaluation(),
+    }
+}
+
+fn pattern_bindings(pattern: &Pattern, bindings: &mut Vec<(String, bool)>) {
+    match pattern {
+        Pattern::Any | Pattern::Const(_) => (),
+        Pattern::Ident(name) => bindings.push((name.clone(), false)),
+        Pattern::MutIdent(name) => bindings.push((name.clone(), true)),
+        Pattern::Tuple(items) | Pattern::TupleStruct(_, items) => {
+            for item in items {
+                pattern_bindings(item, bindings);
+            }
+        }
+        Pattern::Struct(_, items) => {
+            for (_, item) in items {
+                pattern_bindings(item, bindings);
+            }
+        }
+        Pattern::Or(alternatives) => pattern_bindings(&alternatives[0], bindings),
+        Pattern::Range(..) | Pattern::Rest(None) => (),
+        Pattern::Bind(name, inner) => {
+            bindings.push((name.clone(), false));
+            pattern_bindings(inner, bindings);
+        }
+        Pattern::Slice(items) => {
+            for item in items {
+                pattern_bindings(item, bindings);
+            }
+        }
+        Pattern::Rest(Some(name)) => bindings.push((name.clone(), false)),
+    }
+}
@@ -29,32 +29,77 @@ where
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for Deserializer<'de> {
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
     type Error = Error;
 
     forward_to_deserialize_any! {
         bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf option
+        option
     }
 
+    /// Values are visited the way ron does it, so that `#[serde(untagged)]`, `#[serde(flatten)]`
+    /// and types like `serde_json::Value` can be read. Struct and variant names are dropped.
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        let pos = self.input.pos;
         match &self.input.desc {
-            ExprDesc::Float(f) => visitor.visit_f32(*f),
+            ExprDesc::Float(f) => visitor.visit_f64(ast::widen(*f)),
             ExprDesc::Int(i) => visitor.visit_i32(*i),
+            ExprDesc::Long(i) => match (*i as i64, *i as u64) {
+                (small, _) if i128::from(small) == *i => visitor.visit_i64(small),
+                (_, small) if i128::from(small) == *i => visitor.visit_u64(small),
+                _ => visitor.visit_i128(*i),
+            },
+            ExprDesc::Double(f) => visitor.visit_f64(*f),
             ExprDesc::Bool(b) => visitor.visit_bool(*b),
             ExprDesc::Char(c) => visitor.visit_char(*c),
             ExprDesc::String(s) => visitor.visit_borrowed_str(s),
             ExprDesc::Option(inner) => match &**inner {
                 None => visitor.visit_none(),
-                Some(s) => visitor.visit_some(Deserializer::from_expr(&s)),
+                Some(s) => visitor.visit_some(Deserializer::from_expr(s)),
             },
+            ExprDesc::Unit => visitor.visit_unit(),
+            ExprDesc::NamedTuple(_, items) if items.is_empty() => visitor.visit_unit(),
+            ExprDesc::Array(items) | ExprDesc::Tuple(items) | ExprDesc::NamedTuple(_, items) => {
+                visitor.visit_seq(Items::new(items)).map_err(|e| e.with_pos(pos))
+            }
+            ExprDesc::Struct(_, items) | ExprDesc::Object(items) => {
+                visitor.visit_map(Pairs::new(items)).map_err(|e| e.with_pos(pos))
+            }
             s => Err(ErrorDesc::Unevaluated(format!("{:?}", s)).with_pos(self.input.pos)),
         }
     }
 
+    /// Bytes are written as a `vec!` of numbers, and a string's bytes can be read too
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match &self.input.desc {
+            ExprDesc::String(s) => visitor.visit_borrowed_bytes(s.as_bytes()),
+            ExprDesc::Array(items) => {
+                let bytes = items
+                    .iter()
+                    .map(|item| match item.desc {
+                        ExprDesc::Int(i) if (0..=255).contains(&i) => Ok(i as u8),
+                        _ => Err(ErrorDesc::Message("Expected a byte".to_owned()).with_pos(item.pos)),
+                    })
+                    .collect::<Result<_>>()?;
+                visitor.visit_byte_buf(bytes)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
     fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
@@ -90,7 +135,17 @@ impl<'de, 'a> de::Deserializer<'de> for Deserializer<'de> {
         V: Visitor<'de>,
     {
         let pos = self.input.pos;
-        if let ExprDesc::Struct(sname, _items) = &self.input.desc {
+        if let ExprDesc::NamedTuple(tname, contents) = &self.input.desc {
+            if name != tname {
+                Err(ErrorDesc::WrongName(name.to_owned(), tname.to_owned()).with_pos(pos))
+            } else if contents.len() != 1 {
+                Err(ErrorDesc::WrongTupleLength(1, contents.len()).with_pos(pos))
+            } else {
+                visitor
+                    .visit_newtype_struct(Deserializer::from_expr(&contents[0]))
+                    .map_err(|e| e.with_pos(pos))
+            }
+        } else if let ExprDesc::Struct(sname, _items) = &self.input.desc {
             if sname != name {
                 Err(ErrorDesc::WrongName(name.to_owned(), sname.to_owned())
                     .with_pos(self.input.pos))
@@ -122,7 +177,7 @@ impl<'de, 'a> de::Deserializer<'de> for Deserializer<'de> {
         V: Visitor<'de>,
     {
         match &self.input.desc {
-            ExprDesc::Tuple(contents) => visitor.visit_seq(Items::new(contents))
+            ExprDesc::Tuple(contents) | ExprDesc::Array(contents) => visitor.visit_seq(Items::new(contents))
                     .map_err(|e| e.with_pos(self.input.pos))
             ,
             _ => Err(ErrorDesc::ExpectedSequence.with_pos(self.input.pos)),
@@ -145,7 +200,18 @@ impl<'de, 'a> de::Deserializer<'de> for Deserializer<'de> {
             } else if contents.len() != len {
                 Err(ErrorDesc::WrongTupleLength(len, contents.len()).with_pos(self.input.pos))
             } else {
-                visitor.visit_unit()
+                visitor
+                    .visit_seq(Items::new(contents))
+                    .map_err(|e| e.with_pos(self.input.pos))
+            }
+        } else if let ExprDesc::Tuple(contents) = &self.input.desc {
+            // RON lets the name be left out
+            if contents.len() != len {
+                Err(ErrorDesc::WrongTupleLength(len, contents.len()).with_pos(self.input.pos))
+            } else {
+                visitor
+                    .visit_seq(Items::new(contents))
+                    .map_err(|e| e.with_pos(self.input.pos))
             }
         } else {
             Err(ErrorDesc::ExpectedNamedTuple.with_pos(self.input.pos))
@@ -156,7 +222,8 @@ impl<'de, 'a> de::Deserializer<'de> for Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        if let ExprDesc::Object(items) = &self.input.desc {
+        // `#[serde(flatten)]` reads structs as maps
+        if let ExprDesc::Object(items) | ExprDesc::Struct(_, items) = &self.input.desc {
             visitor.visit_map(Pairs::new(items))
                     .map_err(|e| e.with_pos(self.input.pos))
         } else {
@@ -182,6 +249,11 @@ impl<'de, 'a> de::Deserializer<'de> for Deserializer<'de> {
                 Err(ErrorDesc::WrongName(ename.to_string(), name.to_string())
                     .with_pos(self.input.pos))
             }
+        } else if let ExprDesc::Object(items) = &self.input.desc {
+            // A RON struct without its name
+            visitor
+                .visit_map(Pairs::new(items))
+                .map_err(|e| e.with_pos(self.input.pos))
         } else {
             Err(ErrorDesc::ExpectedMap.with_pos(self.input.pos))
         }
@@ -211,7 +283,7 @@ impl<'de, 'a> de::Deserializer<'de> for Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        self.deserialize_any(visitor)
+        visitor.visit_unit()
     }
 }
 
@@ -220,7 +292,7 @@ struct Items<'a> {
     index: usize,
 }
 
-impl<'a, 'de> Items<'a> {
+impl<'a> Items<'a> {
     fn new(contents: &'a [Expr]) -> Self {
         Items { contents, index: 0 }
     }
@@ -231,7 +303,7 @@ struct Pairs<'a> {
     index: usize,
 }
 
-impl<'a, 'de> Pairs<'a> {
+impl<'a> Pairs<'a> {
     fn new(contents: &'a [(String, Expr)]) -> Self {
         Pairs { contents, index: 0 }
     }
@@ -256,6 +328,23 @@ impl<'a> SeqAccess<'a> for Items<'a> {
     }
 }
 
+/// Object keys are strings, so keys of other types are parsed out of them, like serde_json does
+macro_rules! parse_keys {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+            where
+                V: Visitor<'de>,
+            {
+                match self.input.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
 pub struct KeyDeserializer<'de> {
     input: &'de str,
 }
@@ -266,7 +355,7 @@ impl<'de> KeyDeserializer<'de> {
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for KeyDeserializer<'de> {
+impl<'de> de::Deserializer<'de> for KeyDeserializer<'de> {
     type Error = Error;
 
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
@@ -276,11 +365,40 @@ impl<'de, 'a> de::Deserializer<'de> for KeyDeserializer<'de> {
         visitor.visit_borrowed_str(self.input)
     }
 
+    parse_keys! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
     forward_to_deserialize_any! {
-        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf option
+        str string bytes byte_buf option
         unit unit_struct newtype_struct seq tuple
-        tuple_struct map struct enum identifier ignored_any
+        tuple_struct map struct identifier ignored_any
+    }
+
+    // enums with unit variants can be keys too
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_enum(de::value::BorrowedStrDeserializer::new(self.input))
     }
 }
 
@@ -304,7 +422,7 @@ impl<'a> MapAccess<'a> for Pairs<'a> {
     {
         let (_key, v) = &self.contents[self.index];
         self.index += 1;
-        seed.deserialize(Deserializer::from_expr(&v))
+        seed.deserialize(Deserializer::from_expr(v))
     }
 }
 
@@ -355,7 +473,7 @@ impl<'a> VariantAccess<'a> for Enum<'a> {
             if v.len() == 1 {
                 seed.deserialize(Deserializer::from_expr(&v[0]))
             } else {
-                Err(ErrorDesc::WrongTupleLength(0, v.len()).with_pos(self.expr.pos))
+                Err(ErrorDesc::WrongTupleLength(1, v.len()).with_pos(self.expr.pos))
             }
         } else {
             Err(ErrorDesc::ExpectedNamedTuple.with_pos(self.expr.pos))
@@ -0,0 +1,333 @@
+use crate::ast::Pos;
+use crate::error::{DeserializeError, DeserializeErrorDesc, Error, EvalError, EvalErrorDesc, TraceFrame};
+use crate::parser::{ParseError, Rule};
+use pest::error::{ErrorVariant, LineColLocation};
+
+/// Spans longer than this only show their first and last lines
+const MAX_LINES: usize = 5;
+/// Backtraces longer than this only show the innermost calls
+const MAX_TRACE: usize = 10;
+
+/// An error that's ready to show to a person: what went wrong, where, and maybe how to fix it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub pos: Pos,
+    /// Extra lines like "help: declare it with `let mut x`"
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(error: &Error) -> Self {
+        match error {
+            Error::EvalError(err) => Self::eval(err),
+            Error::DeserializeError(err) => Self::deserialize(err),
+            Error::ParseError(err) => Self::message(syntax_message(err), syntax_pos(err)),
+            Error::Message(message) => Self::message(message.clone(), Pos::default()),
+            Error::Syntax => Self::message("Unknown syntax error".to_owned(), Pos::default()),
+        }
+    }
+
+    fn message(message: String, pos: Pos) -> Self {
+        Diagnostic {
+            message,
+            pos,
+            notes: vec![],
+        }
+    }
+
+    fn eval(err: &EvalError) -> Self {
+        let mut notes = match &err.desc {
+            EvalErrorDesc::MemberMovedValue => vec![
+                "note: values are moved when they're used, use `&name` to read one without moving it"
+                    .to_owned(),
+            ],
+            EvalErrorDesc::AssignToImmutable(name) => {
+                vec![format!("help: declare it with `let mut {}`", name)]
+            }
+            EvalErrorDesc::MissingReference(name) if name.contains("::") => {
+                vec!["help: modules need to be loaded with `mod name;` first".to_owned()]
+            }
+            EvalErrorDesc::ImportCycle(files) => files
+                .windows(2)
+                .map(|pair| format!("note: `{}` loads `{}`", pair[0], pair[1]))
+                .collect(),
+            EvalErrorDesc::SyntaxErrors(errors) | EvalErrorDesc::TypeErrors(errors) => errors
+                .iter()
+                .map(|err| format!("note: {}", err))
+                .collect(),
+            EvalErrorDesc::UnknownField(err) => match &err.suggestion {
+                Some(suggestion) => vec![format!("help: did you mean `{}`?", suggestion)],
+                None => vec![],
+            },
+            EvalErrorDesc::OutOfFuel => {
+                vec!["note: the script took too many steps, it might be stuck in a loop".to_owned()]
+            }
+            EvalErrorDesc::TooDeep(_) => vec!["note: this might be infinite recursion".to_owned()],
+            EvalErrorDesc::BreakOutsideLoop => {
+                vec!["note: a function can't break out of a loop in its caller".to_owned()]
+            }
+            _ => vec![],
+        };
+        notes.extend(backtrace(&err.trace));
+        Diagnostic {
+            message: err.desc.to_string(),
+            pos: err.pos,
+            notes,
+        }
+    }
+
+    fn deserialize(err: &DeserializeError) -> Self {
+        let message = match &err.desc {
+            DeserializeErrorDesc::EvalError(inner) => return Self::eval(inner),
+            DeserializeErrorDesc::WrongName(found, expected) => {
+                format!("Expected a `{}`, found `{}`", expected, found)
+            }
+            DeserializeErrorDesc::WrongTupleLength(expected, found) => {
+                format!("Expected {} items, found {}", expected, found)
+            }
+            _ => err.to_string(),
+        };
+        Diagnostic {
+            message,
+            pos: err.pos,
+            notes: vec!["note: while converting a script value to rust".to_owned()],
+        }
+    }
+
+    /// Formats the diagnostic like rustc does, with the lines of `source` that it points at
+    /// underlined.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut out = format!("error: {}\n", self.message);
+        let (start, end) = (self.pos.start, self.pos.end.max(self.pos.start));
+        if start.0 == 0 {
+            out += &format!(" --> {}\n", path);
+            for note in self.notes.iter() {
+                out += &format!(" = {}\n", note);
+            }
+            return out;
+        }
+
+        let gutter = " ".repeat(end.0.to_string().len());
+        out += &format!("{}--> {}:{}:{}\n", gutter, path, start.0, start.1);
+        out += &format!("{} |\n", gutter);
+        let lines: Vec<&str> = source.lines().collect();
+        for line in start.0..=end.0 {
+            if end.0 - start.0 >= MAX_LINES && line > start.0 + 1 && line + 1 < end.0 {
+                if line == start.0 + 2 {
+                    out += "...\n";
+                }
+                continue;
+            }
+            let text = lines.get(line - 1).copied().unwrap_or("");
+            let from = if line == start.0 {
+                start.1
+            } else {
+                text.chars().take_while(|c| c.is_whitespace()).count() + 1
+            };
+            let to = if line == end.0 {
+                end.1
+            } else {
+                text.chars().count() + 1
+            };
+            // keep tabs so the carets line up with the text above them
+            let indent: String = text
+                .chars()
+                .take(from.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            out += &format!("{:>width$} | {}\n", line, text, width = gutter.len());
+            out += &format!(
+                "{} | {}{}\n",
+                gutter,
+                indent,
+                "^".repeat(to.saturating_sub(from).max(1))
+            );
+        }
+        if !self.notes.is_empty() {
+            out += &format!("{} |\n", gutter);
+        }
+        for note in self.notes.iter() {
+            out += &format!("{} = {}\n", gutter, note);
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error: {}", self.message)?;
+        if self.pos.start.0 != 0 {
+            write!(f, " at {}:{}", self.pos.start.0, self.pos.start.1)?;
+        }
+        for note in self.notes.iter() {
+            write!(f, "\n = {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// A note for each call that led to an error, innermost first
+fn backtrace(trace: &[TraceFrame]) -> Vec<String> {
+    let mut notes: Vec<String> = trace
+        .iter()
+        .take(MAX_TRACE)
+        .map(|frame| {
+            if frame.pos.is_empty() {
+                format!("note: in `{}`", frame.name)
+            } else {
+                format!(
+                    "note: in `{}`, called at {}:{}",
+                    frame.name, frame.pos.start.0, frame.pos.start.1
+                )
+            }
+        })
+        .collect();
+    if trace.len() > MAX_TRACE {
+        notes.push(format!("note: ... and {} more calls", trace.len() - MAX_TRACE));
+    }
+    notes
+}
+
+pub(crate) fn syntax_error(err: &ParseError, file: usize) -> EvalError {
+    EvalErrorDesc::Syntax(syntax_message(err)).with_pos(Pos {
+        file,
+        ..syntax_pos(err)
+    })
+}
+
+/// The same error, but with the rules it expected named the way `syntax_message` names them
+pub(crate) fn readable(err: &ParseError) -> ParseError {
+    let mut err = err.clone();
+    err.variant = ErrorVariant::CustomError {
+        message: syntax_message(&err),
+    };
+    err
+}
+
+fn syntax_message(err: &ParseError) -> String {
+    match &err.variant {
+        ErrorVariant::ParsingError {
+            positives,
+            negatives,
+        } => {
+            let names = |rules: &[Rule]| {
+                let mut names: Vec<String> = vec![];
+                for name in rules.iter().map(rule_name) {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                match names.split_last() {
+                    Some((last, init)) if !init.is_empty() => format!("{} or {}", init.join(", "), last),
+                    _ => names.concat(),
+                }
+            };
+            match (positives.is_empty(), negatives.is_empty()) {
+                (false, true) => format!("expected {}", names(positives)),
+                (true, false) => format!("unexpected {}", names(negatives)),
+                (false, false) => format!(
+                    "unexpected {}, expected {}",
+                    names(negatives),
+                    names(positives)
+                ),
+                (true, true) => "unknown parsing error".to_owned(),
+            }
+        }
+        ErrorVariant::CustomError { message } => message.clone(),
+    }
+}
+
+/// What a grammar rule is called in messages, which is what someone writing a script would
+/// call it rather than what the grammar does
+fn rule_name(rule: &Rule) -> String {
+    let name = match rule {
+        Rule::value
+        | Rule::unary
+        | Rule::cast
+        | Rule::subject
+        | Rule::comma_values
+        | Rule::tuple
+        | Rule::unit
+        | Rule::block
+        | Rule::if_chain
+        | Rule::match_
+        | Rule::fncall
+        | Rule::lambda
+        | Rule::struct_
+        | Rule::paren_struct
+        | Rule::option
+        | Rule::named_tuple
+        | Rule::object
+        | Rule::anon_struct
+        | Rule::array
+        | Rule::list
+        | Rule::format_macro
+        | Rule::json
+        | Rule::const_
+        | Rule::path => "expression",
+        Rule::bool => "`true` or `false`",
+        Rule::string => "string",
+        Rule::char => "character",
+        Rule::float | Rule::signed_int | Rule::unsigned_int | Rule::digits => "number",
+        Rule::call_args => "`(`",
+        Rule::index => "`[`",
+        Rule::binop | Rule::binop_post | Rule::unary_op => "operator",
+        Rule::assign_op => "`=`",
+        Rule::range_op => "`..`",
+        Rule::statement | Rule::toplevel_statement => "statement",
+        Rule::let_binding => "`let`",
+        Rule::const_binding => "`const`",
+        Rule::use_stmt => "`use`",
+        Rule::mod_stmt => "`mod`",
+        Rule::fndefn => "`fn`",
+        Rule::struct_decl => "`struct`",
+        Rule::enum_decl => "`enum`",
+        Rule::for_loop => "`for`",
+        Rule::while_loop => "`while`",
+        Rule::break_ => "`break`",
+        Rule::continue_ => "`continue`",
+        Rule::match_guard => "`if`",
+        Rule::match_arm => "match arm",
+        Rule::if_cond => "condition",
+        Rule::assignment | Rule::place => "assignment",
+        Rule::ident | Rule::upper_ident | Rule::mut_ident => "name",
+        Rule::pattern
+        | Rule::single_pattern
+        | Rule::range_pattern
+        | Rule::bind_pattern
+        | Rule::slice_pattern
+        | Rule::rest_pattern
+        | Rule::tuple_struct_pattern
+        | Rule::tuple_pattern
+        | Rule::struct_pattern => "pattern",
+        Rule::type_
+        | Rule::prim_type
+        | Rule::vec_type
+        | Rule::option_type
+        | Rule::unit_type
+        | Rule::tuple_type => "type",
+        Rule::args | Rule::arg | Rule::lambda_args => "argument",
+        Rule::field_decls => "`{`",
+        Rule::field_decl | Rule::comma_pairs | Rule::pair => "field",
+        Rule::variant_decl => "variant",
+        Rule::EOI => "end of input",
+        other => return format!("{:?}", other).trim_end_matches('_').replace('_', " "),
+    };
+    name.to_owned()
+}
+
+fn syntax_pos(err: &ParseError) -> Pos {
+    match err.line_col {
+        LineColLocation::Pos(start) => Pos {
+            start,
+            end: (start.0, start.1 + 1),
+            file: 0,
+        },
+        LineColLocation::Span(start, end) => Pos {
+            start,
+            end,
+            file: 0,
+        },
+    }
+}
@@ -6,6 +6,17 @@ use std::error::Error as StdError;
 pub struct EvalError {
     pub desc: EvalErrorDesc,
     pub pos: Pos,
+    /// The calls that led to the error, innermost first. It's boxed so that it doesn't make
+    /// every `Result` bigger.
+    pub trace: Box<[TraceFrame]>,
+}
+
+/// A function call that was running when an error happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub name: String,
+    /// Where it was called from
+    pub pos: Pos,
 }
 
 impl PartialEq for EvalError {
@@ -21,10 +32,74 @@ pub enum EvalErrorDesc {
     CannotGetMember(String, &'static str),
     MissingReference(String),
     UnknownFunction(String),
+    /// A method that values of this kind don't have
+    NoMethod(String, &'static str),
     MemberMovedValue,
+    AssignToImmutable(String),
     FunctionValue,
     FunctionWrongNumberArgs(usize, usize),
+    NotCallable(&'static str),
+    /// A native function argument (by index) couldn't be converted from the script value
+    NativeArgument(usize, String),
+    NativeReturn(String),
+    /// A `use` or `mod` file that couldn't be read: the path, and why
+    ModuleNotFound(String, String),
+    /// The files that `use` or `mod` each other, ending with the one that closes the loop
+    ImportCycle(Vec<String>),
+    /// Source that couldn't be parsed
+    Syntax(String),
+    /// When a file has more than one syntax error, they're all reported
+    SyntaxErrors(Vec<EvalError>),
+    /// A value whose type is known not to fit where it's used: what was expected, and what
+    /// was found
+    TypeMismatch(String, String),
+    /// Like `SyntaxErrors`, when checking a file finds more than one problem
+    TypeErrors(Vec<EvalError>),
+    /// A declared struct or variant was built with a field it doesn't have
+    UnknownField(Box<UnknownField>),
+    MissingField(String, String),
+    /// A field of a declared struct or variant with a value of the wrong type
+    FieldType(Box<FieldType>),
+    /// A declared variant was built with the wrong number of fields: expected, then found
+    FieldCount(String, usize, usize),
+    UnknownType(String),
     Unmatched(String),
+    /// No arm of a `match` matched this value, shown by `Expr::shape`
+    NonExhaustive(String),
+    /// The index, and the length of what was indexed
+    IndexOutOfBounds(i32, usize),
+    DivideByZero,
+    IntegerOverflow,
+    /// The script used up the fuel in its `Limits`
+    OutOfFuel,
+    /// Calls nested deeper than the limits allow, with how many calls deep it got
+    TooDeep(usize),
+    /// A string or array grew past the limit, which is given
+    TooLarge(usize),
+    /// Control flow for `break` and `continue`, caught by the enclosing loop
+    Break,
+    Continue,
+    BreakOutsideLoop,
+}
+
+// The field errors are boxed so that they don't make every `EvalError` bigger
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct UnknownField {
+    pub typ: String,
+    pub field: String,
+    /// A declared field with a similar name
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FieldType {
+    pub typ: String,
+    pub field: String,
+    /// The declared type
+    pub expected: String,
+    /// The shape of the value, like `Expr::shape`
+    pub found: String,
 }
 
 impl From<EvalErrorDesc> for EvalError {
@@ -32,20 +107,117 @@ impl From<EvalErrorDesc> for EvalError {
         EvalError {
             desc: other,
             pos: Pos::default(),
+            trace: Box::new([]),
         }
     }
 }
 
 impl EvalErrorDesc {
     pub fn with_pos(self, pos: Pos) -> EvalError {
-        EvalError { desc: self, pos }
+        EvalError {
+            desc: self,
+            pos,
+            trace: Box::new([]),
+        }
+    }
+
+    /// Keeps specific errors, but replaces a generic type error with a better message
+    pub fn or_invalid(self, message: &'static str) -> EvalErrorDesc {
+        match self {
+            EvalErrorDesc::InvalidType(_) => EvalErrorDesc::InvalidType(message),
+            other => other,
+        }
+    }
+}
+
+impl std::fmt::Display for EvalErrorDesc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalErrorDesc::InvalidType(message) => write!(f, "{}", message),
+            EvalErrorDesc::MissingMember(name) => write!(f, "No member named `{}`", name),
+            EvalErrorDesc::CannotGetMember(name, kind) => {
+                write!(f, "Cannot get member `{}` of {}", name, kind)
+            }
+            EvalErrorDesc::MissingReference(name) => write!(f, "Cannot find `{}`", name),
+            EvalErrorDesc::UnknownFunction(name) => write!(f, "Unknown function `{}`", name),
+            EvalErrorDesc::NoMethod(name, kind) => write!(f, "No method `{}` on {}", name, kind),
+            EvalErrorDesc::MemberMovedValue => write!(f, "Use of a moved value"),
+            EvalErrorDesc::AssignToImmutable(name) => {
+                write!(f, "Cannot assign twice to immutable variable `{}`", name)
+            }
+            EvalErrorDesc::FunctionValue => write!(f, "Cannot use a function as a value"),
+            EvalErrorDesc::FunctionWrongNumberArgs(expected, found) => write!(
+                f,
+                "Function takes {} argument{} but {} {} given",
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found,
+                if *found == 1 { "was" } else { "were" }
+            ),
+            EvalErrorDesc::NotCallable(kind) => write!(f, "Cannot call a {}", kind),
+            EvalErrorDesc::NativeArgument(index, message) => {
+                write!(f, "Bad argument {} to a native function: {}", index + 1, message)
+            }
+            EvalErrorDesc::NativeReturn(message) => {
+                write!(f, "Native function returned a bad value: {}", message)
+            }
+            EvalErrorDesc::ModuleNotFound(path, reason) => {
+                write!(f, "Cannot load `{}`: {}", path, reason)
+            }
+            EvalErrorDesc::ImportCycle(files) => write!(f, "Import cycle: {}", files.join(" -> ")),
+            EvalErrorDesc::Syntax(message) => write!(f, "Invalid syntax: {}", message),
+            EvalErrorDesc::SyntaxErrors(errors) => write!(f, "{} syntax errors", errors.len()),
+            EvalErrorDesc::TypeMismatch(expected, found) => write!(f, "Expected {}, found {}", expected, found),
+            EvalErrorDesc::TypeErrors(errors) => write!(f, "{} type errors", errors.len()),
+            EvalErrorDesc::UnknownField(err) => write!(f, "`{}` has no field `{}`", err.typ, err.field),
+            EvalErrorDesc::MissingField(typ, field) => write!(f, "`{}` is missing the field `{}`", typ, field),
+            EvalErrorDesc::FieldType(err) => write!(
+                f,
+                "Field `{}` of `{}` should be `{}`, found `{}`",
+                err.field, err.typ, err.expected, err.found
+            ),
+            EvalErrorDesc::FieldCount(typ, expected, found) => write!(
+                f,
+                "`{}` takes {} field{} but {} {} given",
+                typ,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found,
+                if *found == 1 { "was" } else { "were" }
+            ),
+            EvalErrorDesc::UnknownType(name) => write!(f, "Unknown type `{}`", name),
+            EvalErrorDesc::Unmatched(what) => write!(f, "Value didn't match the {}", what),
+            EvalErrorDesc::NonExhaustive(shape) => write!(f, "No match arm for `{}`", shape),
+            EvalErrorDesc::IndexOutOfBounds(index, len) => {
+                write!(f, "Index {} is out of bounds for length {}", index, len)
+            }
+            EvalErrorDesc::DivideByZero => write!(f, "Division by zero"),
+            EvalErrorDesc::IntegerOverflow => write!(f, "Integer overflow"),
+            EvalErrorDesc::OutOfFuel => write!(f, "Ran out of fuel"),
+            EvalErrorDesc::TooDeep(depth) => write!(f, "Calls nested too deeply ({} calls)", depth),
+            EvalErrorDesc::TooLarge(limit) => write!(f, "Value is bigger than the limit of {}", limit),
+            EvalErrorDesc::Break | EvalErrorDesc::BreakOutsideLoop => {
+                write!(f, "`break` outside of a loop")
+            }
+            EvalErrorDesc::Continue => write!(f, "`continue` outside of a loop"),
+        }
+    }
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.pos.is_empty() {
+            write!(f, "{}", self.desc)
+        } else {
+            write!(f, "{} at {}:{}", self.desc, self.pos.start.0, self.pos.start.1)
+        }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct DeserializeError {
-    pos: Pos,
-    desc: DeserializeErrorDesc,
+    pub(crate) pos: Pos,
+    pub(crate) desc: DeserializeErrorDesc,
 }
 
 // impl PartialEq for DeserializeError {
@@ -134,13 +306,13 @@ impl From<pest::error::Error<crate::parser::Rule>> for Error {
 }
 
 impl std::fmt::Display for Error {
+    #[allow(deprecated)]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.description())
-        // match *self {
-        //     Error::IoError(ref s) => write!(f, "{}", s),
-        //     Error::Message(ref s) => write!(f, "{}", s),
-        //     Error::Parser(_, pos) => write!(f, "{}: {}", pos, self.description()),
-        // }
+        match self {
+            Error::EvalError(err) => write!(f, "{}", err),
+            Error::ParseError(err) => write!(f, "{}", crate::diagnostic::readable(err)),
+            _ => write!(f, "{}", self.description()),
+        }
     }
 }
 
@@ -157,6 +329,7 @@ impl ser::Error for DeserializeError {
 }
 
 impl std::fmt::Display for DeserializeError {
+    #[allow(deprecated)]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.description())
         // match *self {
@@ -188,6 +361,7 @@ impl StdError for DeserializeError {
 }
 
 impl StdError for Error {
+    #[allow(deprecated)]
     fn description(&self) -> &str {
         match &self {
             Error::EvalError(_) => "Error while evaluating",
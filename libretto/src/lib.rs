@@ -1,31 +1,111 @@
-#![allow(dead_code)]
+#![allow(dead_code, clippy::result_large_err)]
 
 mod ast;
+mod collections;
+mod compile;
 mod de;
+mod diagnostic;
 mod error;
+mod limits;
+mod native;
+mod numeric;
+mod outline;
 mod parser;
+mod printer;
+mod schema;
 mod scope;
 mod ser;
+mod strings;
+mod typecheck;
+mod typed_fn;
+mod vm;
 
-pub use ast::{Expr, ExprDesc, Pos};
+pub use ast::{Expr, ExprDesc, Pattern, Pos, Statement};
+use typecheck::check_statements;
+pub use compile::Function;
 pub use de::from_expr;
-pub use error::{DeserializeError, DeserializeErrorDesc, Error};
-pub use parser::{process_expr, process_file};
+pub use diagnostic::Diagnostic;
+pub use error::{
+    DeserializeError, DeserializeErrorDesc, Error, EvalError, EvalErrorDesc, FieldType, TraceFrame,
+    UnknownField,
+};
+pub use limits::Limits;
+pub use native::{IntoNativeFn, NativeFn};
+pub use outline::{Outline, Symbol, SymbolKind};
+pub use parser::{
+    input_is_unfinished, process_data, process_expr, process_file, process_file_recovering, process_input,
+    ParseError,
+};
+pub use printer::{format_source, to_source};
+pub use schema::{Decl, FieldDecl, Signature, TypeDecl, VariantDecl, VariantFields};
 pub use scope::Scope;
-pub use ser::to_expr;
+pub use ser::{to_expr, to_string, to_string_pretty, to_writer, to_writer_pretty};
+#[doc(hidden)]
+pub use typed_fn::call_once;
+pub use typed_fn::{FnArgs, IntoArgs, TypedFn};
 
 pub fn eval_expr(input: &str) -> Result<Expr, error::EvalError> {
-    process_expr(input).unwrap().into_eval(&mut Scope::new())
+    process_expr(input)
+        .map_err(|err| diagnostic::syntax_error(&err, 0))?
+        .into_eval(&mut Scope::new())
 }
 
 pub fn eval_file(input: &str) -> Result<Scope, error::Error> {
     let mut scope = Scope::new();
-    for stmt in process_file(input)? {
+    let stmts = process_file(input)?;
+    check_statements(&stmts, &scope)?;
+    for stmt in stmts {
         stmt.eval(&mut scope)?;
     }
     Ok(scope)
 }
 
+/// Everything that can be found wrong with a script without running it: syntax errors in
+/// any of its items, then type errors in the ones that parsed
+pub fn check_file(input: &str) -> Vec<Diagnostic> {
+    let (stmts, errors) = process_file_recovering(input);
+    let mut errors: Vec<error::EvalError> =
+        errors.iter().map(|err| diagnostic::syntax_error(err, 0)).collect();
+    match check_statements(&stmts, &Scope::new()) {
+        Ok(()) => (),
+        Err(error::EvalError {
+            desc: error::EvalErrorDesc::TypeErrors(type_errors),
+            ..
+        }) => errors.extend(type_errors),
+        Err(err) => errors.push(err),
+    }
+    errors.into_iter().map(|err| Diagnostic::new(&err.into())).collect()
+}
+
+/// Evaluate a script file, along with any files it loads with `use` or `mod`
+pub fn eval_path<P: AsRef<std::path::Path>>(path: P) -> Result<Scope, error::Error> {
+    let mut scope = Scope::new();
+    scope.load_file(path)?;
+    Ok(scope)
+}
+
+/// Read a data file: a RON value that can use libretto expressions, after any `const`s and
+/// functions it declares
+pub fn from_data_str<T: serde::de::DeserializeOwned>(input: &str) -> Result<T, error::Error> {
+    let mut scope = Scope::new();
+    let (stmts, value) = process_data(input)?;
+    check_statements(&stmts, &scope)?;
+    for stmt in stmts {
+        stmt.eval(&mut scope)?;
+    }
+    Ok(from_expr(&value.into_eval(&mut scope)?)?)
+}
+
+/// Read a data file from disk. Its errors can only be shown with the file's source by a
+/// scope that still has it, which `Scope::load_data` keeps.
+pub fn from_data_path<T, P>(path: P) -> Result<T, error::Error>
+where
+    T: serde::de::DeserializeOwned,
+    P: AsRef<std::path::Path>,
+{
+    Scope::new().load_data(path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,6 +245,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn range() {
+        assert_eq!(
+            parser::process_expr("0..n + 1"),
+            Ok(ExprDesc::Block(
+                vec![],
+                Box::new(
+                    ExprDesc::Range(
+                        Box::new(0.into()),
+                        Box::new(
+                            ExprDesc::Plus(
+                                Box::new(ExprDesc::Ident("n".into()).into()),
+                                Box::new(1.into())
+                            )
+                            .into()
+                        ),
+                        false
+                    )
+                    .into()
+                )
+            )
+            .into())
+        );
+    }
+
+    #[test]
+    fn logical_ops() {
+        let ident = |name: &str| Box::new(Expr::from(ExprDesc::Ident(name.into())));
+        assert_eq!(
+            parser::process_expr("!a || b && c <= -d"),
+            Ok(ExprDesc::Block(
+                vec![],
+                Box::new(
+                    ExprDesc::Or(
+                        Box::new(ExprDesc::Not(ident("a")).into()),
+                        Box::new(
+                            ExprDesc::And(
+                                ident("b"),
+                                Box::new(
+                                    ExprDesc::Le(ident("c"), Box::new(ExprDesc::Neg(ident("d")).into()))
+                                        .into()
+                                )
+                            )
+                            .into()
+                        )
+                    )
+                    .into()
+                )
+            )
+            .into())
+        );
+    }
+
     #[test]
     fn complex() {
         parser::process_expr(
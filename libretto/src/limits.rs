@@ -0,0 +1,111 @@
+use crate::ast::{Expr, ExprDesc, Pos};
+use crate::error::{EvalError, EvalErrorDesc};
+
+/// Bounds on how much work a script can do, so that a runaway script fails with an error
+/// instead of hanging the host or overflowing its stack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Evaluation steps allowed for each call from the host, or `None` for no limit
+    pub fuel: Option<u64>,
+    /// How deeply script functions and closures can call each other. Each call takes native
+    /// stack too, so the thread running a script needs room for this many of them.
+    pub max_depth: usize,
+    /// How many bytes of the native stack nested calls can use, or `None` to only count
+    /// calls. Useful on threads with a small stack, or in debug builds, which use far more
+    /// stack per call than release builds.
+    pub max_stack: Option<usize>,
+    /// The longest string (in bytes) or array (in items) a script can build
+    pub max_alloc: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            fuel: None,
+            max_depth: 256,
+            max_stack: None,
+            max_alloc: 1 << 20,
+        }
+    }
+}
+
+/// What a scope has used of its limits so far
+#[derive(Debug, PartialEq, Default)]
+pub(crate) struct Usage {
+    pub limits: Limits,
+    fuel: u64,
+    depth: usize,
+    /// Where the stack was when the outermost call started
+    stack_base: usize,
+}
+
+impl Usage {
+    pub fn new(limits: Limits) -> Self {
+        Usage {
+            limits,
+            fuel: limits.fuel.unwrap_or(0),
+            depth: 0,
+            stack_base: 0,
+        }
+    }
+
+    /// Fills up the fuel again, for a new call from the host
+    pub fn refuel(&mut self) {
+        self.fuel = self.limits.fuel.unwrap_or(0);
+    }
+
+    /// Counts one evaluation step
+    pub fn tick(&mut self, pos: Pos) -> Result<(), EvalError> {
+        if self.limits.fuel.is_none() {
+            return Ok(());
+        }
+        if self.fuel == 0 {
+            return Err(EvalErrorDesc::OutOfFuel.with_pos(pos));
+        }
+        self.fuel -= 1;
+        Ok(())
+    }
+
+    /// Called on the way into a script function or closure; each `enter` that succeeds needs an `exit`
+    pub fn enter(&mut self, pos: Pos) -> Result<(), EvalError> {
+        if self.depth == 0 {
+            self.refuel();
+            self.stack_base = stack_address();
+        }
+        let stack_used = stack_address().abs_diff(self.stack_base);
+        if self.depth >= self.limits.max_depth
+            || self.limits.max_stack.is_some_and(|max| stack_used > max)
+        {
+            return Err(EvalErrorDesc::TooDeep(self.depth).with_pos(pos));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub fn exit(&mut self) {
+        self.depth -= 1;
+    }
+
+    pub fn check_alloc(&self, size: usize, pos: Pos) -> Result<(), EvalError> {
+        if size > self.limits.max_alloc {
+            return Err(EvalErrorDesc::TooLarge(self.limits.max_alloc).with_pos(pos));
+        }
+        Ok(())
+    }
+
+    /// Checks a value that might have grown, like a string after `+` or an array after `push`
+    pub fn check_size(&self, value: &Expr, pos: Pos) -> Result<(), EvalError> {
+        match &value.desc {
+            ExprDesc::String(s) => self.check_alloc(s.len(), pos),
+            ExprDesc::Array(items) => self.check_alloc(items.len(), pos),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Roughly where the top of the stack is, from the address of a local
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
@@ -0,0 +1,81 @@
+use crate::ast::{Expr, Pos};
+use crate::de::from_expr;
+use crate::error::{EvalError, EvalErrorDesc};
+use crate::ser::to_expr;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+type NativeBody = dyn Fn(Vec<Expr>, Pos) -> Result<Expr, EvalError> + Send + Sync;
+
+/// A rust function that can be called from a script, created by `Scope::register_fn`
+#[derive(Clone)]
+pub struct NativeFn {
+    pub arity: usize,
+    body: Arc<NativeBody>,
+}
+
+impl NativeFn {
+    pub fn call(&self, args: Vec<Expr>, pos: Pos) -> Result<Expr, EvalError> {
+        if args.len() != self.arity {
+            return Err(
+                EvalErrorDesc::FunctionWrongNumberArgs(self.arity, args.len()).with_pos(pos),
+            );
+        }
+        (self.body)(args, pos)
+    }
+}
+
+impl std::fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn/{}>", self.arity)
+    }
+}
+
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.body, &other.body)
+    }
+}
+
+/// Implemented for closures whose arguments can be deserialized from script values,
+/// and whose return value can be serialized back into one.
+pub trait IntoNativeFn<Args> {
+    fn into_native(self) -> NativeFn;
+}
+
+fn native_arg<T: DeserializeOwned>(arg: &Expr, index: usize, pos: Pos) -> Result<T, EvalError> {
+    from_expr(arg)
+        .map_err(|err| EvalErrorDesc::NativeArgument(index, err.to_string()).with_pos(pos))
+}
+
+macro_rules! impl_native_fn {
+    ($arity: expr; $( $index: expr => $typ: ident $arg: ident ),*) => {
+        impl<F, R, $( $typ ),*> IntoNativeFn<($( $typ, )*)> for F
+        where
+            F: Fn($( $typ ),*) -> R + Send + Sync + 'static,
+            R: Serialize,
+            $( $typ: DeserializeOwned, )*
+        {
+            fn into_native(self) -> NativeFn {
+                NativeFn {
+                    arity: $arity,
+                    body: Arc::new(move |_args: Vec<Expr>, pos: Pos| {
+                        $( let $arg: $typ = native_arg(&_args[$index], $index, pos)?; )*
+                        to_expr(&self($( $arg ),*)).map_err(|err| {
+                            EvalErrorDesc::NativeReturn(err.to_string()).with_pos(pos)
+                        })
+                    }),
+                }
+            }
+        }
+    };
+}
+
+impl_native_fn!(0;);
+impl_native_fn!(1; 0 => A a);
+impl_native_fn!(2; 0 => A a, 1 => B b);
+impl_native_fn!(3; 0 => A a, 1 => B b, 2 => C c);
+impl_native_fn!(4; 0 => A a, 1 => B b, 2 => C c, 3 => D d);
+impl_native_fn!(5; 0 => A a, 1 => B b, 2 => C c, 3 => D d, 4 => E e);
+impl_native_fn!(6; 0 => A a, 1 => B b, 2 => C c, 3 => D d, 4 => E e, 5 => G g);
@@ -0,0 +1,100 @@
+use crate::ast::{Expr, ExprDesc};
+use crate::error::EvalErrorDesc;
+use std::convert::TryFrom;
+
+type FloatFn = fn(f32, &[f32]) -> f32;
+type IntFn = fn(i32, &[i32]) -> Result<i32, EvalErrorDesc>;
+
+/// Methods on floats: name, number of arguments, and the function. Int arguments are promoted.
+const FLOAT_METHODS: &[(&str, usize, FloatFn)] = &[
+    ("abs", 0, |f, _| f.abs()),
+    ("signum", 0, |f, _| f.signum()),
+    ("floor", 0, |f, _| f.floor()),
+    ("ceil", 0, |f, _| f.ceil()),
+    ("round", 0, |f, _| f.round()),
+    ("sqrt", 0, |f, _| f.sqrt()),
+    ("exp", 0, |f, _| f.exp()),
+    ("ln", 0, |f, _| f.ln()),
+    ("sin", 0, |f, _| f.sin()),
+    ("cos", 0, |f, _| f.cos()),
+    ("tan", 0, |f, _| f.tan()),
+    ("asin", 0, |f, _| f.asin()),
+    ("acos", 0, |f, _| f.acos()),
+    ("atan", 0, |f, _| f.atan()),
+    ("to_degrees", 0, |f, _| f.to_degrees()),
+    ("to_radians", 0, |f, _| f.to_radians()),
+    ("min", 1, |f, args| f.min(args[0])),
+    ("max", 1, |f, args| f.max(args[0])),
+    ("atan2", 1, |f, args| f.atan2(args[0])),
+    ("powf", 1, |f, args| f.powf(args[0])),
+    ("powi", 1, |f, args| f.powi(args[0] as i32)),
+    // f32::clamp panics when min > max, scripts get the bound that was checked last instead
+    ("clamp", 2, |f, args| f.max(args[0]).min(args[1])),
+    // `a.lerp(b, t)` goes from `a` at 0.0 to `b` at 1.0
+    ("lerp", 2, |f, args| f + (args[0] - f) * args[1]),
+];
+
+/// Methods on ints that only take ints. With a float argument, the float method is used instead.
+const INT_METHODS: &[(&str, usize, IntFn)] = &[
+    ("abs", 0, |i, _| i.checked_abs().ok_or(EvalErrorDesc::IntegerOverflow)),
+    ("signum", 0, |i, _| Ok(i.signum())),
+    ("min", 1, |i, args| Ok(i.min(args[0]))),
+    ("max", 1, |i, args| Ok(i.max(args[0]))),
+    ("pow", 1, |i, args| {
+        let exp = u32::try_from(args[0])
+            .map_err(|_| EvalErrorDesc::InvalidType("pow() takes a non-negative exponent"))?;
+        i.checked_pow(exp).ok_or(EvalErrorDesc::IntegerOverflow)
+    }),
+    ("clamp", 2, |i, args| Ok(i.max(args[0]).min(args[1]))),
+];
+
+fn lookup<F: Copy>(table: &[(&str, usize, F)], name: &str, found: usize) -> Option<Result<F, EvalErrorDesc>> {
+    let (_, arity, f) = table.iter().find(|(method, _, _)| *method == name)?;
+    if *arity == found {
+        Some(Ok(*f))
+    } else {
+        Some(Err(EvalErrorDesc::FunctionWrongNumberArgs(*arity, found)))
+    }
+}
+
+fn float_args(args: &[Expr]) -> Result<Vec<f32>, EvalErrorDesc> {
+    args.iter()
+        .map(|arg| match arg.desc {
+            ExprDesc::Float(f) => Ok(f),
+            ExprDesc::Int(i) => Ok(i as f32),
+            _ => Err(EvalErrorDesc::InvalidType("Expected numbers")),
+        })
+        .collect()
+}
+
+pub(crate) fn is_float_method(name: &str) -> bool {
+    FLOAT_METHODS.iter().any(|(method, _, _)| *method == name)
+}
+
+pub(crate) fn float_method(f: f32, name: &str, args: &[Expr]) -> Result<ExprDesc, EvalErrorDesc> {
+    match lookup(FLOAT_METHODS, name, args.len()) {
+        Some(method) => Ok(ExprDesc::Float(method?(f, &float_args(args)?))),
+        None => Err(EvalErrorDesc::UnknownFunction(name.to_owned())),
+    }
+}
+
+pub(crate) fn int_method(i: i32, name: &str, args: &[Expr]) -> Result<ExprDesc, EvalErrorDesc> {
+    if name == "to_float" {
+        return match args.len() {
+            0 => Ok(ExprDesc::Float(i as f32)),
+            found => Err(EvalErrorDesc::FunctionWrongNumberArgs(0, found)),
+        };
+    }
+    let int_args: Option<Vec<i32>> = args
+        .iter()
+        .map(|arg| match arg.desc {
+            ExprDesc::Int(i) => Some(i),
+            _ => None,
+        })
+        .collect();
+    match (lookup(INT_METHODS, name, args.len()), int_args) {
+        (Some(method), Some(int_args)) => Ok(ExprDesc::Int(method?(i, &int_args)?)),
+        // a float argument, or a method that only floats have
+        _ => float_method(i as f32, name, args),
+    }
+}
@@ -0,0 +1,354 @@
+use crate::ast::Pos;
+use crate::parser::{next, parse_items_recovering, ParseResult, Rule};
+use pest::iterators::Pair;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    Const,
+    Struct,
+    Enum,
+    Module,
+    /// A field of a struct, or of an enum variant with fields
+    Field,
+    Variant,
+    /// An argument or a local variable of a function
+    Variable,
+}
+
+/// Something declared in a file, with where it is so an editor can jump to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The whole declaration
+    pub pos: Pos,
+    /// Just the name
+    pub name_pos: Pos,
+    /// How it was declared, like `fn sprite(character: String, part: String) -> String`
+    pub detail: String,
+    /// Fields of a struct, variants of an enum, or the variables of a function
+    pub children: Vec<Symbol>,
+}
+
+impl Symbol {
+    fn new(name: &Pair<Rule>, kind: SymbolKind, pos: Pos, detail: String) -> Self {
+        Symbol {
+            name: name.as_str().to_owned(),
+            kind,
+            pos,
+            name_pos: Pos::from(name),
+            detail,
+            children: vec![],
+        }
+    }
+}
+
+/// The top-level declarations of a file. Items with syntax errors are left out, so an
+/// editor can still find its way around a file that's being edited.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Outline {
+    pub symbols: Vec<Symbol>,
+}
+
+impl Outline {
+    pub fn new(source: &str) -> Self {
+        let (items, _) = parse_items_recovering(source, item_symbols);
+        Outline {
+            symbols: items.into_iter().flatten().collect(),
+        }
+    }
+
+    /// Where the name at `at` (a line and column, like in a `Pos`) was declared
+    pub fn definition(&self, source: &str, at: (usize, usize)) -> Option<&Symbol> {
+        let (before, word) = word_at(source, at);
+        if word.is_empty() {
+            return None;
+        }
+        if before.ends_with('.') {
+            return self.fields().find(|field| field.name == word);
+        }
+        self.locals(at)
+            .filter(|local| local.name == word)
+            .last()
+            .or_else(|| self.symbols.iter().find(|symbol| symbol.name == word))
+            .or_else(|| self.variants().find(|variant| variant.name == word))
+            .or_else(|| self.fields().find(|field| field.name == word))
+    }
+
+    /// The names that could finish the word at `at`. After a `.`, or inside the braces of
+    /// a struct literal, these are fields.
+    pub fn completions(&self, source: &str, at: (usize, usize)) -> Vec<&Symbol> {
+        let (before, word) = word_at(source, at);
+        let typed = at.1.saturating_sub(1).saturating_sub(before.chars().count());
+        let prefix: String = word.chars().take(typed).collect();
+        let candidates: Vec<&Symbol> = if before.ends_with('.') {
+            self.fields().collect()
+        } else if let Some(name) = struct_literal_at(source, at) {
+            self.fields_of(&name)
+        } else {
+            self.locals(at)
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .chain(self.symbols.iter())
+                .chain(self.variants())
+                .collect()
+        };
+        let mut found: Vec<&Symbol> = vec![];
+        for symbol in candidates {
+            if symbol.name.starts_with(&prefix) && !found.iter().any(|other| other.name == symbol.name) {
+                found.push(symbol);
+            }
+        }
+        found
+    }
+
+    /// The variables of the function containing `at` that are declared before it
+    fn locals(&self, at: (usize, usize)) -> impl Iterator<Item = &Symbol> {
+        self.symbols
+            .iter()
+            .filter(move |symbol| {
+                symbol.kind == SymbolKind::Function && symbol.pos.start <= at && at <= symbol.pos.end
+            })
+            .flat_map(|symbol| symbol.children.iter())
+            .filter(move |local| local.name_pos.end <= at)
+    }
+
+    fn fields(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols
+            .iter()
+            .chain(self.variants())
+            .flat_map(|symbol| symbol.children.iter())
+            .filter(|child| child.kind == SymbolKind::Field)
+    }
+
+    fn variants(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols
+            .iter()
+            .filter(|symbol| symbol.kind == SymbolKind::Enum)
+            .flat_map(|symbol| symbol.children.iter())
+    }
+
+    fn fields_of(&self, name: &str) -> Vec<&Symbol> {
+        self.symbols
+            .iter()
+            .filter(|symbol| symbol.kind == SymbolKind::Struct)
+            .chain(self.variants())
+            .filter(|symbol| symbol.name == name)
+            .flat_map(|symbol| symbol.children.iter())
+            .collect()
+    }
+}
+
+/// The symbols declared by a top-level item. A `const` with a tuple pattern declares several.
+fn item_symbols(pair: Pair<Rule>) -> ParseResult<Vec<Symbol>> {
+    let item = match pair.into_inner().next() {
+        Some(item) => item,
+        None => return Ok(vec![]),
+    };
+    let pos = Pos::from(&item);
+    let mut parts = item.clone().into_inner();
+    Ok(match item.as_rule() {
+        Rule::fndefn => {
+            let name = next(&mut parts, &item)?;
+            let mut symbol = Symbol::new(&name, SymbolKind::Function, pos, String::new());
+            let mut args = vec![];
+            for arg in next(&mut parts, &item)?.into_inner() {
+                let detail = collapse(arg.as_str());
+                args.push(detail.clone());
+                let arg_name = next(&mut arg.clone().into_inner(), &arg)?;
+                let pos = Pos::from(&arg_name);
+                symbol.children.push(Symbol::new(&arg_name, SymbolKind::Variable, pos, detail));
+            }
+            symbol.detail = format!("fn {}({})", name.as_str(), args.join(", "));
+            for part in parts {
+                match part.as_rule() {
+                    Rule::block => local_symbols(part, &mut symbol.children)?,
+                    _ => symbol.detail += &format!(" -> {}", collapse(part.as_str())),
+                }
+            }
+            vec![symbol]
+        }
+        Rule::const_binding => {
+            let pattern = next(&mut parts, &item)?;
+            let typ = collapse(next(&mut parts, &item)?.as_str());
+            let names = bound_names(pattern.clone());
+            let single = names.len() == 1;
+            names
+                .into_iter()
+                .map(|name| {
+                    let detail = match single {
+                        true => format!("const {}: {}", name.as_str(), typ),
+                        false => format!("const {}: {}", collapse(pattern.as_str()), typ),
+                    };
+                    Symbol::new(&name, SymbolKind::Const, pos, detail)
+                })
+                .collect()
+        }
+        Rule::struct_decl => {
+            let name = next(&mut parts, &item)?;
+            let detail = format!("struct {}", name.as_str());
+            let mut symbol = Symbol::new(&name, SymbolKind::Struct, pos, detail);
+            symbol.children = field_symbols(next(&mut parts, &item)?)?;
+            vec![symbol]
+        }
+        Rule::enum_decl => {
+            let name = next(&mut parts, &item)?;
+            let detail = format!("enum {}", name.as_str());
+            let mut symbol = Symbol::new(&name, SymbolKind::Enum, pos, detail);
+            for variant in parts {
+                let pos = Pos::from(&variant);
+                let detail = collapse(variant.as_str());
+                let mut variant_parts = variant.clone().into_inner();
+                let variant_name = next(&mut variant_parts, &variant)?;
+                let mut child = Symbol::new(&variant_name, SymbolKind::Variant, pos, detail);
+                if let Some(fields) = variant_parts.find(|part| part.as_rule() == Rule::field_decls) {
+                    child.children = field_symbols(fields)?;
+                }
+                symbol.children.push(child);
+            }
+            vec![symbol]
+        }
+        Rule::mod_stmt => {
+            let name = next(&mut parts, &item)?;
+            let detail = format!("mod {}", name.as_str());
+            vec![Symbol::new(&name, SymbolKind::Module, pos, detail)]
+        }
+        _ => vec![],
+    })
+}
+
+fn field_symbols(fields: Pair<Rule>) -> ParseResult<Vec<Symbol>> {
+    fields
+        .into_inner()
+        .map(|field| {
+            let mut parts = field.clone().into_inner();
+            let name = next(&mut parts, &field)?;
+            let typ = next(&mut parts, &field)?;
+            let detail = format!("{}: {}", name.as_str(), collapse(typ.as_str()));
+            Ok(Symbol::new(&name, SymbolKind::Field, Pos::from(&field), detail))
+        })
+        .collect()
+}
+
+/// Variables bound anywhere inside a function body, by `let`, `for`, `match`, `if let`
+/// and closure arguments
+fn local_symbols(pair: Pair<Rule>, symbols: &mut Vec<Symbol>) -> ParseResult<()> {
+    match pair.as_rule() {
+        Rule::let_binding => {
+            let mut parts = pair.clone().into_inner();
+            let pattern = next(&mut parts, &pair)?;
+            let typ = parts.next().filter(|part| part.as_rule() != Rule::value);
+            for name in bound_names(pattern) {
+                let detail = match &typ {
+                    Some(typ) => format!("let {}: {}", name.as_str(), collapse(typ.as_str())),
+                    None => format!("let {}", name.as_str()),
+                };
+                symbols.push(Symbol::new(&name, SymbolKind::Variable, Pos::from(&name), detail));
+            }
+            for part in pair.into_inner().skip(1) {
+                local_symbols(part, symbols)?;
+            }
+        }
+        Rule::pattern => {
+            for name in bound_names(pair) {
+                let detail = name.as_str().to_owned();
+                symbols.push(Symbol::new(&name, SymbolKind::Variable, Pos::from(&name), detail));
+            }
+        }
+        Rule::lambda_args => {
+            for name in pair.into_inner() {
+                let detail = name.as_str().to_owned();
+                symbols.push(Symbol::new(&name, SymbolKind::Variable, Pos::from(&name), detail));
+            }
+        }
+        _ => {
+            for part in pair.into_inner() {
+                local_symbols(part, symbols)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The names a pattern binds. The alternatives of an or-pattern bind the same names, so
+/// only the first one is used.
+fn bound_names(pattern: Pair<Rule>) -> Vec<Pair<Rule>> {
+    let mut names = vec![];
+    match pattern.as_rule() {
+        Rule::pattern => {
+            if let Some(first) = pattern.into_inner().next() {
+                names.extend(bound_names(first));
+            }
+        }
+        Rule::ident => names.push(pattern),
+        Rule::struct_pattern => {
+            let mut parts = pattern.into_inner().skip(1).peekable();
+            while let Some(field) = parts.next() {
+                match parts.peek().map(|part| part.as_rule()) {
+                    // `field: pattern` binds the names in the pattern
+                    Some(Rule::pattern) => names.extend(parts.next().map(bound_names).unwrap_or_default()),
+                    _ => names.push(field),
+                }
+            }
+        }
+        Rule::const_ | Rule::range_pattern => (),
+        _ => {
+            for part in pattern.into_inner() {
+                names.extend(bound_names(part));
+            }
+        }
+    }
+    names
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The text of the line before the word at `at`, and the word itself
+fn word_at(source: &str, at: (usize, usize)) -> (String, String) {
+    let line: Vec<char> = source.lines().nth(at.0.saturating_sub(1)).unwrap_or("").chars().collect();
+    let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+    let cursor = at.1.saturating_sub(1).min(line.len());
+    let start = line[..cursor].iter().rposition(|c| !is_word(c)).map_or(0, |idx| idx + 1);
+    let end = line[cursor..].iter().position(|c| !is_word(c)).map_or(line.len(), |idx| cursor + idx);
+    (line[..start].iter().collect(), line[start..end].iter().collect())
+}
+
+/// The name of the struct whose braces `at` is in, when it's where a field name goes
+fn struct_literal_at(source: &str, at: (usize, usize)) -> Option<String> {
+    let mut before: String = source.lines().take(at.0.saturating_sub(1)).collect::<Vec<_>>().join("\n");
+    let line = source.lines().nth(at.0.saturating_sub(1)).unwrap_or("");
+    before.push('\n');
+    before.extend(line.chars().take(at.1.saturating_sub(1)));
+    let mut depth = 0;
+    // the `:` of earlier fields don't matter once we're past a `,`
+    let mut after_comma = false;
+    for (idx, c) in before.char_indices().rev() {
+        match c {
+            ')' | ']' | '}' => depth += 1,
+            '(' | '[' | '{' if depth > 0 => depth -= 1,
+            // `(`, `[` and `;` mean this isn't directly in struct braces, and a value comes after `:`
+            '(' | '[' | ';' => return None,
+            ':' if depth == 0 && !after_comma => return None,
+            ',' if depth == 0 => after_comma = true,
+            '{' => {
+                let name = before[..idx].trim_end();
+                let start = name
+                    .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .map_or(0, |idx| idx + 1);
+                // the braces after `-> Type` are a function body
+                let is_return_type = name[..start].trim_end().ends_with("->");
+                let name = &name[start..];
+                return match name.chars().next() {
+                    Some(c) if c.is_uppercase() && !is_return_type => Some(name.to_owned()),
+                    _ => None,
+                };
+            }
+            _ => (),
+        }
+    }
+    None
+}
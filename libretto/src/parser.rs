@@ -1,300 +1,506 @@
-use pest::iterators::Pair;
+use pest::error::ErrorVariant;
+use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::*;
 
-use unescape;
+use crate::ast::{pattern_names, Access, Const, Expr, ExprDesc, IfCond, Pattern, Place, Pos, Statement, Type};
+use crate::schema::{Decl, FieldDecl, Signature, TypeDecl, VariantDecl, VariantFields};
+use crate::strings::{count_args, display};
+use std::convert::TryFrom;
 
-use crate::ast::{Const, Expr, ExprDesc, IfCond, Pattern, Pos, Statement, Type};
-
-#[grammar = "../grammar.pest"]
 #[derive(Parser)]
+#[grammar = "../grammar.pest"]
 pub struct MainParser;
 
-pub enum ParseError {
-    Invalid,
-}
+/// Both grammar errors from pest, and values the grammar accepts but that can't be
+/// represented (like an int that doesn't fit in an i32)
+pub type ParseError = pest::error::Error<Rule>;
 
 pub type ParseResult<T> = Result<T, ParseError>;
 
-fn unescape_string(string: &str) -> String {
-    if &string[0..1] == "\"" {
-        unescape::unescape(&string[1..string.len() - 1]).unwrap()
+fn invalid(pair: &Pair<Rule>, message: String) -> ParseError {
+    ParseError::new_from_span(ErrorVariant::CustomError { message }, pair.as_span())
+}
+
+fn unexpected(pair: &Pair<Rule>) -> ParseError {
+    invalid(pair, format!("Unexpected {:?} `{}`", pair.as_rule(), pair.as_str()))
+}
+
+/// The next child of `parent`, which the grammar should guarantee is there
+pub(crate) fn next<'a>(items: &mut Pairs<'a, Rule>, parent: &Pair<'a, Rule>) -> ParseResult<Pair<'a, Rule>> {
+    items
+        .next()
+        .ok_or_else(|| invalid(parent, format!("Incomplete {:?}", parent.as_rule())))
+}
+
+fn first_child<'a>(pair: &Pair<'a, Rule>) -> ParseResult<Pair<'a, Rule>> {
+    next(&mut pair.clone().into_inner(), pair)
+}
+
+fn unescape(pair: &Pair<Rule>, string: &str) -> ParseResult<String> {
+    unescape::unescape(string).ok_or_else(|| invalid(pair, "Invalid escape sequence".to_owned()))
+}
+
+fn unescape_string(pair: &Pair<Rule>) -> ParseResult<String> {
+    let string = pair.as_str();
+    if string.starts_with('"') {
+        unescape(pair, &string[1..string.len() - 1])
     } else {
         for i in 0..string.len() {
             if &string[i..=i] == "\"" {
-                return string[i + 1..string.len() - i].to_string();
+                return Ok(string[i + 1..string.len() - i].to_string());
             }
         }
-        panic!("Unterminated raw string")
+        Err(invalid(pair, "Unterminated raw string".to_owned()))
     }
 }
 
-pub fn parse_const(pair: Pair<Rule>) -> Expr {
-    match pair.as_rule() {
-        Rule::float => ExprDesc::Float(pair.as_str().parse::<f32>().unwrap()),
-        Rule::signed_int => ExprDesc::Int(pair.as_str().parse::<i32>().unwrap()),
-        Rule::bool => ExprDesc::Bool(pair.as_str().parse::<bool>().unwrap()),
-        Rule::char => {
-            let str = pair.as_str();
-            ExprDesc::Char(
-                unescape::unescape(&str[1..str.len() - 1])
-                    .unwrap()
-                    .parse::<char>()
-                    .unwrap(),
-            )
+fn parse_char(pair: &Pair<Rule>) -> ParseResult<char> {
+    let str = pair.as_str();
+    let mut chars = unescape(pair, &str[1..str.len() - 1])?.chars().collect::<Vec<_>>();
+    if chars.len() != 1 {
+        return Err(invalid(pair, "A char must be exactly one character".to_owned()));
+    }
+    Ok(chars.remove(0))
+}
+
+fn parse_int(pair: &Pair<Rule>) -> ParseResult<i32> {
+    match int_suffix(pair.as_str()) {
+        Some(_) => Err(invalid(pair, "64-bit numbers can't be used here".to_owned())),
+        None => i32::try_from(parse_long(pair)?)
+            .map_err(|_| invalid(pair, "Invalid int: number too large to fit in target type".to_owned())),
+    }
+}
+
+fn int_suffix(text: &str) -> Option<&'static str> {
+    ["i64", "u64", "i128"].iter().copied().find(|suffix| text.ends_with(suffix))
+}
+
+/// Any integer literal, including ones with a 64-bit suffix
+fn parse_long(pair: &Pair<Rule>) -> ParseResult<i128> {
+    let text = pair.as_str().replace('+', "");
+    let (text, range) = match int_suffix(&text) {
+        Some("i64") => (&text[..text.len() - 3], i64::MIN as i128..=i64::MAX as i128),
+        Some("u64") => (&text[..text.len() - 3], 0..=u64::MAX as i128),
+        Some(_) => (&text[..text.len() - 4], i128::MIN..=i128::MAX),
+        None => (text.as_str(), i128::MIN..=i128::MAX),
+    };
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        _ => (10, digits),
+    };
+    let digits = if negative {
+        format!("-{}", digits)
+    } else {
+        digits.to_owned()
+    };
+    match i128::from_str_radix(&digits, radix) {
+        Ok(i) if range.contains(&i) => Ok(i),
+        Ok(_) => Err(invalid(pair, "Invalid int: number too large to fit in target type".to_owned())),
+        Err(err) => Err(invalid(pair, format!("Invalid int: {}", err))),
+    }
+}
+
+fn parse_float(pair: &Pair<Rule>) -> ParseResult<f32> {
+    if pair.as_str().ends_with("f64") {
+        return Err(invalid(pair, "64-bit numbers can't be used here".to_owned()));
+    }
+    pair.as_str()
+        .parse::<f32>()
+        .map_err(|err| invalid(pair, format!("Invalid float: {}", err)))
+}
+
+/// A number without a suffix is an `i32` or `f32` if that holds it without losing anything,
+/// and a `Long` or `Double` if it doesn't
+pub fn parse_const(pair: Pair<Rule>) -> ParseResult<Expr> {
+    Ok(match pair.as_rule() {
+        Rule::float => {
+            let text = pair.as_str().trim_end_matches("f64");
+            let f: f64 = text.parse().map_err(|err| invalid(&pair, format!("Invalid float: {}", err)))?;
+            // an `f32` that prints as the same number, so `0.3` stays an `f32`
+            let narrow = f as f32;
+            if pair.as_str().ends_with("f64") || narrow.to_string().parse() != Ok(f) {
+                ExprDesc::Double(f)
+            } else {
+                ExprDesc::Float(narrow)
+            }
         }
-        Rule::string => ExprDesc::String(unescape_string(pair.as_str())),
-        _ => {
-            panic!(format!(
-                "Unreachable const {}, {:?}",
-                pair.as_str(),
-                pair.as_rule()
-            ));
+        Rule::signed_int => {
+            let i = parse_long(&pair)?;
+            match (int_suffix(pair.as_str()), i32::try_from(i)) {
+                (None, Ok(i)) => ExprDesc::Int(i),
+                _ => ExprDesc::Long(i),
+            }
         }
+        Rule::bool => ExprDesc::Bool(pair.as_str() == "true"),
+        Rule::char => ExprDesc::Char(parse_char(&pair)?),
+        Rule::string => ExprDesc::String(unescape_string(&pair)?),
+        _ => return Err(unexpected(&pair)),
     }
-    .with_span(&pair.as_span())
+    .with_span(&pair.as_span()))
 }
 
-fn parse_pair(pair: Pair<Rule>) -> (String, Expr) {
-    let mut children = pair.into_inner();
-    let key = children.next().unwrap();
-    let v = children.next().unwrap();
-    (
+fn parse_pair(pair: Pair<Rule>) -> ParseResult<(String, Expr)> {
+    let mut children = pair.clone().into_inner();
+    let key = next(&mut children, &pair)?;
+    let v = next(&mut children, &pair)?;
+    Ok((
         match key.as_rule() {
-            Rule::string => unescape_string(key.as_str()),
+            Rule::string => unescape_string(&key)?,
             Rule::ident => key.as_str().to_string(),
-            _ => unreachable!(),
+            // keys are strings, which are parsed back into numbers when they're deserialized
+            Rule::const_ => display(&parse_const(first_child(&key)?)?),
+            _ => return Err(unexpected(&key)),
         },
-        parse_expr(v),
-    )
-}
-
-fn parse_const_const(pair: Pair<Rule>) -> Const {
-    match pair.as_rule() {
-        Rule::const_ => parse_const_const(pair.into_inner().next().unwrap()),
-        Rule::float => Const::Float(pair.as_str().parse::<f32>().unwrap()),
-        Rule::signed_int => Const::Int(pair.as_str().parse::<i32>().unwrap()),
-        Rule::bool => Const::Bool(pair.as_str().parse::<bool>().unwrap()),
-        Rule::char => {
-            let str = pair.as_str();
-            Const::Char(
-                unescape::unescape(&str[1..str.len() - 1])
-                    .unwrap()
-                    .parse::<char>()
-                    .unwrap(),
-            )
-        }
-        Rule::string => Const::String(unescape_string(pair.as_str())),
-        _ => {
-            panic!(format!(
-                "Unreachable const {}, {:?}",
-                pair.as_str(),
-                pair.as_rule()
-            ));
-        }
+        parse_expr(v)?,
+    ))
+}
+
+fn parse_const_const(pair: Pair<Rule>) -> ParseResult<Const> {
+    Ok(match pair.as_rule() {
+        Rule::const_ => return parse_const_const(first_child(&pair)?),
+        Rule::float => Const::Float(parse_float(&pair)?),
+        Rule::signed_int => Const::Int(parse_int(&pair)?),
+        Rule::bool => Const::Bool(pair.as_str() == "true"),
+        Rule::char => Const::Char(parse_char(&pair)?),
+        Rule::string => Const::String(unescape_string(&pair)?),
+        _ => return Err(unexpected(&pair)),
+    })
+}
+
+fn parse_pattern(pair: Pair<Rule>) -> ParseResult<Pattern> {
+    let mut alternatives = pair
+        .clone()
+        .into_inner()
+        .map(parse_single_pattern)
+        .collect::<ParseResult<Vec<_>>>()?;
+    if alternatives.len() == 1 {
+        return Ok(alternatives.remove(0));
     }
+    let names = |pattern: &Pattern| {
+        let mut names = vec![];
+        pattern_names(pattern, &mut names);
+        names.sort();
+        names
+    };
+    if alternatives.iter().any(|alt| names(alt) != names(&alternatives[0])) {
+        return Err(invalid(
+            &pair,
+            "Every alternative must bind the same variables".to_owned(),
+        ));
+    }
+    Ok(Pattern::Or(alternatives))
 }
 
-fn parse_pattern(pattern: Pair<Rule>) -> Pattern {
+fn parse_single_pattern(pattern: Pair<Rule>) -> ParseResult<Pattern> {
     let pattern = match pattern.into_inner().next() {
-        None => return Pattern::Any,
+        None => return Ok(Pattern::Any),
         Some(item) => item,
     };
-    match pattern.as_rule() {
-        Rule::const_ => Pattern::Const(parse_const_const(pattern)),
+    Ok(match pattern.as_rule() {
+        Rule::const_ => Pattern::Const(parse_const_const(pattern)?),
         Rule::ident => Pattern::Ident(pattern.as_str().to_owned()),
+        Rule::mut_ident => Pattern::MutIdent(first_child(&pattern)?.as_str().to_owned()),
         Rule::tuple_pattern => {
+            let trailing_comma = has_trailing_comma(&pattern);
             let inner = pattern.into_inner();
-            let mut items: Vec<Pattern> = inner.map(parse_pattern).collect();
-            if items.len() == 1 {
-                return items.remove(0)
+            let mut items = inner.map(parse_pattern).collect::<ParseResult<Vec<_>>>()?;
+            if items.len() == 1 && !trailing_comma {
+                return Ok(items.remove(0));
             }
             Pattern::Tuple(items)
         }
         Rule::tuple_struct_pattern => {
-            let mut inner = pattern.into_inner();
-            let first = inner.next().unwrap();
+            let mut inner = pattern.clone().into_inner();
+            let first = next(&mut inner, &pattern)?;
             Pattern::TupleStruct(
                 first.as_str().to_owned(),
-                inner.map(parse_pattern).collect(),
+                inner.map(parse_pattern).collect::<ParseResult<_>>()?,
             )
         }
         Rule::struct_pattern => {
-            let mut inner = pattern.into_inner();
-            let first = inner.next().unwrap();
+            let mut inner = pattern.clone().into_inner();
+            let first = next(&mut inner, &pattern)?;
             let mut items = vec![];
-            loop {
-                if let None = inner.peek() {
-                    break;
-                }
-                let ident = inner.next().unwrap().as_str().to_owned();
-                let pattern = if let Some(pattern) = inner.peek() {
-                    if pattern.as_rule() == Rule::pattern {
-                        parse_pattern(inner.next().unwrap())
-                    } else {
-                        Pattern::Ident(ident.clone())
+            while let Some(ident) = inner.next() {
+                let ident = ident.as_str().to_owned();
+                let pattern = match inner.peek() {
+                    Some(pattern) if pattern.as_rule() == Rule::pattern => {
+                        parse_pattern(next(&mut inner, &pattern)?)?
                     }
-                } else {
-                    Pattern::Ident(ident.clone())
+                    _ => Pattern::Ident(ident.clone()),
                 };
                 items.push((ident, pattern))
             }
             Pattern::Struct(first.as_str().to_owned(), items)
         }
-        _ => unreachable!(),
-    }
+        Rule::range_pattern => {
+            let mut inner = pattern.clone().into_inner();
+            let start = parse_const_const(next(&mut inner, &pattern)?)?;
+            let inclusive = next(&mut inner, &pattern)?.as_str() == "..=";
+            let end = parse_const_const(next(&mut inner, &pattern)?)?;
+            Pattern::Range(start, end, inclusive)
+        }
+        Rule::bind_pattern => {
+            let mut inner = pattern.clone().into_inner();
+            let name = next(&mut inner, &pattern)?.as_str().to_owned();
+            let inner = parse_single_pattern(next(&mut inner, &pattern)?)?;
+            Pattern::Bind(name, Box::new(inner))
+        }
+        Rule::slice_pattern => {
+            let mut items = vec![];
+            for item in pattern.clone().into_inner() {
+                items.push(match item.as_rule() {
+                    Rule::rest_pattern => {
+                        Pattern::Rest(item.into_inner().next().map(|name| name.as_str().to_owned()))
+                    }
+                    _ => parse_pattern(item)?,
+                })
+            }
+            let rests = items.iter().filter(|item| matches!(item, Pattern::Rest(_))).count();
+            if rests > 1 {
+                return Err(invalid(&pattern, "Only one `..` is allowed in a slice pattern".to_owned()));
+            }
+            Pattern::Slice(items)
+        }
+        _ => return Err(unexpected(&pattern)),
+    })
 }
 
-fn parse_if_cond(pair: Pair<Rule>) -> IfCond {
-    let mut cond = pair.into_inner();
-    let first = cond.next().unwrap();
-    match first.as_rule() {
-        Rule::value => IfCond::Value(parse_expr(first)),
-        Rule::pattern => IfCond::IfLet(parse_pattern(first), parse_expr(cond.next().unwrap())),
-        _ => unreachable!(),
-    }
+/// `(a,)` is a tuple with one item, and `(a)` is just `a`
+fn has_trailing_comma(pair: &Pair<Rule>) -> bool {
+    let text = pair.as_str();
+    text.strip_suffix(')').unwrap_or(text).trim_end().ends_with(',')
 }
 
-pub fn parse_op_item(pair: Pair<Rule>) -> Expr {
+fn parse_if_cond(pair: Pair<Rule>) -> ParseResult<IfCond> {
+    let mut cond = pair.clone().into_inner();
+    let first = next(&mut cond, &pair)?;
+    Ok(match first.as_rule() {
+        Rule::value => IfCond::Value(parse_expr(first)?),
+        Rule::pattern => IfCond::IfLet(parse_pattern(first)?, parse_expr(next(&mut cond, &pair)?)?),
+        _ => return Err(unexpected(&first)),
+    })
+}
+
+fn parse_exprs(pairs: Pairs<Rule>) -> ParseResult<Vec<Expr>> {
+    pairs.map(parse_expr).collect()
+}
+
+pub fn parse_op_item(pair: Pair<Rule>) -> ParseResult<Expr> {
     let pos = Pos::from(&pair);
-    match pair.as_rule() {
+    let parent = pair.clone();
+    let mut items = pair.clone().into_inner();
+    Ok(match pair.as_rule() {
+        Rule::unary => {
+            let mut items: Vec<Pair<Rule>> = items.collect();
+            let mut value = match items.pop() {
+                Some(item) => parse_op_item(item)?,
+                None => return Err(unexpected(&parent)),
+            };
+            while let Some(op) = items.pop() {
+                let pos = Pos {
+                    start: Pos::from(&op).start,
+                    ..value.pos
+                };
+                value = match op.as_str() {
+                    "-" => ExprDesc::Neg(Box::new(value)),
+                    "!" => ExprDesc::Not(Box::new(value)),
+                    "&" => ExprDesc::Ref(Box::new(value)),
+                    _ => return Err(unexpected(&op)),
+                }
+                .with_pos(pos);
+            }
+            return Ok(value);
+        }
+
         Rule::cast => {
-            let mut items = pair.into_inner();
-            let first = parse_op_item(items.next().unwrap());
+            let first = parse_op_item(next(&mut items, &parent)?)?;
             match items.next() {
-                None => return first,
+                None => return Ok(first),
                 Some(pair) => ExprDesc::Cast(
                     Box::new(first),
                     match pair.as_str() {
                         "i32" => Type::I32,
                         "f32" => Type::F32,
-                        _ => unreachable!(),
+                        _ => return Err(unexpected(&pair)),
                     },
                 ),
             }
         }
 
         Rule::if_chain => {
-            let mut items = pair.into_inner();
-            let first_cond = parse_if_cond(items.next().unwrap());
-            let block = parse_expr(items.next().unwrap());
+            let first_cond = parse_if_cond(next(&mut items, &parent)?)?;
+            let block = parse_expr(next(&mut items, &parent)?)?;
             let mut middles = vec![(first_cond, block)];
-            loop {
-                let first = match items.next() {
-                    None => break,
-                    Some(pair) => pair,
-                };
+            while let Some(first) = items.next() {
                 match first.as_rule() {
                     Rule::block => {
-                        return ExprDesc::IfChain(middles, Some(Box::new(parse_expr(first))))
-                            .with_pos(pos)
-                    }
-                    Rule::if_cond => {
-                        middles.push((parse_if_cond(first), parse_expr(items.next().unwrap())))
+                        return Ok(
+                            ExprDesc::IfChain(middles, Some(Box::new(parse_expr(first)?)))
+                                .with_pos(pos),
+                        )
                     }
-                    _ => unreachable!(),
+                    Rule::if_cond => middles.push((
+                        parse_if_cond(first)?,
+                        parse_expr(next(&mut items, &parent)?)?,
+                    )),
+                    _ => return Err(unexpected(&first)),
                 }
             }
             ExprDesc::IfChain(middles, None)
         }
 
+        Rule::for_loop => {
+            let pattern = parse_pattern(next(&mut items, &parent)?)?;
+            let iterable = parse_expr(next(&mut items, &parent)?)?;
+            let body = parse_block(next(&mut items, &parent)?)?;
+            ExprDesc::For(pattern, Box::new(iterable), Box::new(body))
+        }
+
+        Rule::while_loop => {
+            let cond = parse_if_cond(next(&mut items, &parent)?)?;
+            let body = parse_block(next(&mut items, &parent)?)?;
+            ExprDesc::While(Box::new(cond), Box::new(body))
+        }
+
+        Rule::break_ => ExprDesc::Break,
+        Rule::continue_ => ExprDesc::Continue,
+
         Rule::match_ => {
-            let mut items = pair.into_inner();
-            let value = parse_expr(items.next().unwrap());
+            let value = parse_expr(next(&mut items, &parent)?)?;
             let mut cases = vec![];
-            loop {
-                if let None = items.peek() {
-                    break;
-                }
-                let pattern = parse_pattern(items.next().unwrap());
-                let body = parse_expr(items.next().unwrap());
-                cases.push((pattern, body))
+            for arm in items {
+                let mut items = arm.clone().into_inner();
+                let pattern = parse_pattern(next(&mut items, &arm)?)?;
+                let mut body = next(&mut items, &arm)?;
+                let guard = if body.as_rule() == Rule::match_guard {
+                    let guard = parse_expr(first_child(&body)?)?;
+                    body = next(&mut items, &arm)?;
+                    Some(guard)
+                } else {
+                    None
+                };
+                cases.push((pattern, guard, parse_expr(body)?))
             }
             ExprDesc::Match(Box::new(value), cases)
         }
 
         Rule::subject => {
-            let mut items = pair.into_inner();
-            let first = parse_op_item(items.next().unwrap());
-            let access: Vec<(String, Option<Vec<Expr>>)> = items
-                .into_iter()
-                .map(|pair| match pair.as_rule() {
+            let mut first = parse_op_item(next(&mut items, &parent)?)?;
+            let mut access = vec![];
+            for pair in items {
+                match pair.as_rule() {
                     Rule::fncall => {
-                        let mut items = pair.into_inner();
-                        let name = items.next().unwrap().as_str().to_string();
-                        let args = items.map(parse_expr).collect();
-                        (name, Some(args))
+                        let mut items = pair.clone().into_inner();
+                        let name = next(&mut items, &pair)?.as_str().to_string();
+                        let args = parse_exprs(items)?;
+                        access.push(Access::Method(name, args))
                     }
-                    _ => (pair.as_str().to_owned(), None),
-                })
-                .collect();
+                    Rule::index => access.push(parse_index(&pair)?),
+                    Rule::call_args => {
+                        if !access.is_empty() {
+                            first = ExprDesc::MemberAccess(Box::new(first), access).with_pos(pos);
+                            access = vec![];
+                        }
+                        first = ExprDesc::Call(Box::new(first), parse_exprs(pair.into_inner())?)
+                            .with_pos(pos);
+                    }
+                    _ => access.push(Access::Field(pair.as_str().to_owned())),
+                }
+            }
             if access.is_empty() {
-                return first;
+                return Ok(first);
             } else {
                 ExprDesc::MemberAccess(Box::new(first), access)
             }
         }
-        Rule::object => ExprDesc::Object(pair.into_inner().map(parse_pair).collect()),
-        Rule::array => ExprDesc::Array(pair.into_inner().map(parse_expr).collect()),
+        Rule::object | Rule::anon_struct => {
+            ExprDesc::Object(items.map(parse_pair).collect::<ParseResult<_>>()?)
+        }
+        Rule::array | Rule::list => ExprDesc::Array(parse_exprs(items)?),
+        Rule::format_macro => {
+            let template = next(&mut items, &parent)?;
+            let text = unescape_string(&template)?;
+            let expected = count_args(&text).map_err(|message| invalid(&template, message))?;
+            let mut args = vec![ExprDesc::String(text).with_span(&template.as_span())];
+            args.extend(parse_exprs(items)?);
+            if args.len() - 1 != expected {
+                return Err(invalid(
+                    &parent,
+                    format!("format! expects {} arguments but got {}", expected, args.len() - 1),
+                ));
+            }
+            ExprDesc::FnCall("format!".to_owned(), args)
+        }
         Rule::tuple => {
-            let mut items: Vec<Expr> = pair.into_inner().map(parse_expr).collect();
-            if items.len() == 1 {
-                return items.remove(0);
+            let mut items = parse_exprs(items)?;
+            if items.len() == 1 && !has_trailing_comma(&pair) {
+                return Ok(items.remove(0));
             } else {
                 ExprDesc::Tuple(items)
             }
         }
-        Rule::const_ => return parse_const(pair.into_inner().next().unwrap()),
-        Rule::option => ExprDesc::Option(Box::new(pair.into_inner().next().map(parse_expr))),
-        Rule::ident => ExprDesc::Ident(pair.as_str().to_string()),
+        Rule::const_ => return parse_const(next(&mut items, &parent)?),
+        Rule::option => ExprDesc::Option(Box::new(items.next().map(parse_expr).transpose()?)),
+        Rule::ident | Rule::path => ExprDesc::Ident(pair.as_str().to_string()),
         Rule::upper_ident => ExprDesc::NamedTuple(pair.as_str().to_string(), vec![]),
         Rule::value => return parse_expr(pair),
+        Rule::block => return parse_block(pair),
         Rule::unit => ExprDesc::Unit,
-        Rule::struct_ => {
-            let mut items = pair.into_inner();
-            let key = items.next().unwrap().as_str().to_string();
-            ExprDesc::Struct(key, items.map(parse_pair).collect())
+        Rule::struct_ | Rule::paren_struct => {
+            let key = next(&mut items, &parent)?.as_str().to_string();
+            ExprDesc::Struct(key, items.map(parse_pair).collect::<ParseResult<_>>()?)
         }
         Rule::named_tuple => {
-            let mut items = pair.into_inner();
-            let key = items.next().unwrap().as_str().to_string();
-            ExprDesc::NamedTuple(key, items.map(parse_expr).collect())
+            let key = next(&mut items, &parent)?.as_str().to_string();
+            ExprDesc::NamedTuple(key, parse_exprs(items)?)
         }
         Rule::fncall => {
-            let mut items = pair.into_inner();
-            let key = items.next().unwrap().as_str().to_string();
-            ExprDesc::FnCall(key, items.map(parse_expr).collect())
+            let key = next(&mut items, &parent)?.as_str().to_string();
+            ExprDesc::FnCall(key, parse_exprs(items)?)
         }
-        _ => {
-            panic!(format!(
-                "Unreachable op item {}, {:?}",
-                pair.as_str(),
-                pair.as_rule()
-            ));
+        Rule::lambda => {
+            let args = next(&mut items, &parent)?
+                .into_inner()
+                // lambda arguments are `any` whatever type they are written with
+                .filter(|pair| pair.as_rule() == Rule::ident)
+                .map(|pair| pair.as_str().to_owned())
+                .collect();
+            ExprDesc::Lambda(args, Box::new(parse_expr(next(&mut items, &parent)?)?))
         }
+        _ => return Err(unexpected(&pair)),
     }
-    .with_pos(pos)
+    .with_pos(pos))
 }
 
+type OpItems<'a> = Vec<(Pair<'a, Rule>, Expr)>;
+
 macro_rules! make_ops {
     ($current: ident, $next: ident; $( $op: expr, $constr: path );*) => {
-        fn $current(input: (Expr, Vec<(&str, Expr)>)) -> Expr {
+        fn $current(input: (Expr, OpItems)) -> ParseResult<Expr> {
             let (first, items) = input;
             let ln = items.len();
             for i in 0..ln {
                 let i = ln-1-i;
                 $(
-                    if items[i].0 == $op {
+                    if items[i].0.as_str() == $op {
                         let (left, right) = items.split_at(i);
                         let mut right = right.to_vec();
                         let (_op, expr) = right.remove(0);
-                        let left = $current((first, left.to_vec()));
-                        let right = $next((expr, right.to_vec()));
-                        let pos = Pos { start: left.pos.start, end: right.pos.end };
-                        return $constr(
+                        let left = $current((first, left.to_vec()))?;
+                        let right = $next((expr, right.to_vec()))?;
+                        let pos = Pos { end: right.pos.end, ..left.pos };
+                        return Ok($constr(
                             Box::new(left),
                             Box::new(right),
-                        ).with_pos(pos);
+                        ).with_pos(pos));
                     }
                 )*
             }
@@ -304,118 +510,365 @@ macro_rules! make_ops {
     };
 }
 
-make_ops!(make_op_tree, make_op_2; "==", ExprDesc::Eq; "!=", ExprDesc::Neq; "<", ExprDesc::Lt; ">", ExprDesc::Gt);
-make_ops!(make_op_2, make_op_3; "-", ExprDesc::Minus; "+", ExprDesc::Plus);
-make_ops!(make_op_3, make_op_4; "*", ExprDesc::Times; "/", ExprDesc::Divide);
+// From lowest to highest precedence, following rust
+make_ops!(make_op_tree, make_op_1; "..", ExprDesc::range; "..=", ExprDesc::range_inclusive);
+make_ops!(make_op_1, make_op_2; "||", ExprDesc::Or);
+make_ops!(make_op_2, make_op_3; "&&", ExprDesc::And);
+make_ops!(make_op_3, make_op_4; "==", ExprDesc::Eq; "!=", ExprDesc::Neq; "<", ExprDesc::Lt; ">", ExprDesc::Gt; "<=", ExprDesc::Le; ">=", ExprDesc::Ge);
+make_ops!(make_op_4, make_op_5; "|", ExprDesc::BitOr);
+make_ops!(make_op_5, make_op_6; "^", ExprDesc::BitXor);
+make_ops!(make_op_6, make_op_7; "&", ExprDesc::BitAnd);
+make_ops!(make_op_7, make_op_8; "<<", ExprDesc::Shl; ">>", ExprDesc::Shr);
+make_ops!(make_op_8, make_op_9; "-", ExprDesc::Minus; "+", ExprDesc::Plus);
+make_ops!(make_op_9, make_op_10; "*", ExprDesc::Times; "/", ExprDesc::Divide; "%", ExprDesc::Modulo);
 
-fn make_op_4(input: (Expr, Vec<(&str, Expr)>)) -> Expr {
-    if !input.1.is_empty() {
-        panic!("Invalid binop tree, there are none left");
+fn make_op_10(input: (Expr, OpItems)) -> ParseResult<Expr> {
+    match input.1.first() {
+        Some((op, _)) => Err(invalid(op, format!("Unknown operator `{}`", op.as_str()))),
+        None => Ok(input.0),
     }
-    input.0
 }
 
-pub fn parse_expr(pair: Pair<Rule>) -> Expr {
+pub fn parse_expr(pair: Pair<Rule>) -> ParseResult<Expr> {
     if pair.as_rule() == Rule::block {
         return parse_block(pair);
     }
     if pair.as_rule() != Rule::value {
-        panic!(
-            "Invalid use of parse_expr. Must be a 'value' : {} {:?}",
-            pair.as_str(),
-            pair
-        );
+        return Err(unexpected(&pair));
     }
-    let mut items = pair.into_inner();
-    let first = parse_op_item(items.next().unwrap());
+    let mut items = pair.clone().into_inner();
+    let first = parse_op_item(next(&mut items, &pair)?)?;
     let rest = items
         .map(|rule| {
-            let mut items = rule.into_inner();
-            let op = items.next().unwrap().as_str();
-            let v = parse_op_item(items.next().unwrap());
-            (op, v)
+            let mut items = rule.clone().into_inner();
+            let op = next(&mut items, &rule)?;
+            let v = parse_op_item(next(&mut items, &rule)?)?;
+            Ok((op, v))
         })
-        .collect();
+        .collect::<ParseResult<_>>()?;
     make_op_tree((first, rest))
 }
 
-pub fn parse_stmt(pair: Pair<Rule>) -> Statement {
-    let pair = pair.into_inner().next().unwrap();
-    match pair.as_rule() {
+fn parse_index(pair: &Pair<Rule>) -> ParseResult<Access> {
+    let index = parse_expr(first_child(pair)?)?;
+    let pos = index.pos;
+    Ok(Access::Index(Box::new(index), pos))
+}
+
+fn parse_assignment(pair: Pair<Rule>) -> ParseResult<Statement> {
+    let mut items = pair.clone().into_inner();
+    let place = next(&mut items, &pair)?;
+    let pos = Pos::from(&place);
+    let mut names = place.clone().into_inner();
+    let place = Place {
+        name: names.next().ok_or_else(|| unexpected(&place))?.as_str().to_owned(),
+        members: names
+            .map(|pair| match pair.as_rule() {
+                Rule::index => parse_index(&pair),
+                _ => Ok(Access::Field(pair.as_str().to_owned())),
+            })
+            .collect::<ParseResult<_>>()?,
+        pos,
+    };
+    let op = next(&mut items, &pair)?;
+    let value = parse_expr(next(&mut items, &pair)?)?;
+    let value = if op.as_str() == "=" {
+        value
+    } else {
+        let current = Box::new(place.to_expr());
+        let pos = Pos {
+            end: value.pos.end,
+            ..pos
+        };
+        let value = Box::new(value);
+        match op.as_str() {
+            "+=" => ExprDesc::Plus(current, value),
+            "-=" => ExprDesc::Minus(current, value),
+            "*=" => ExprDesc::Times(current, value),
+            "/=" => ExprDesc::Divide(current, value),
+            _ => return Err(unexpected(&op)),
+        }
+        .with_pos(pos)
+    };
+    Ok(Statement::Assign(place, value))
+}
+
+fn parse_type_decl(pair: Pair<Rule>) -> ParseResult<TypeDecl> {
+    let mut items = pair.clone().into_inner();
+    Ok(match pair.as_rule() {
+        Rule::prim_type => match pair.as_str() {
+            "f32" => TypeDecl::Float,
+            "i32" => TypeDecl::Int,
+            "f64" => TypeDecl::Double,
+            "i64" => TypeDecl::Long,
+            "bool" => TypeDecl::Bool,
+            "char" => TypeDecl::Char,
+            "String" => TypeDecl::String,
+            "any" => TypeDecl::Any,
+            _ => return Err(unexpected(&pair)),
+        },
+        Rule::vec_type => TypeDecl::Vec(Box::new(parse_type_decl(next(&mut items, &pair)?)?)),
+        Rule::option_type => TypeDecl::Option(Box::new(parse_type_decl(next(&mut items, &pair)?)?)),
+        Rule::unit_type => TypeDecl::Unit,
+        Rule::tuple_type => TypeDecl::Tuple(items.map(parse_type_decl).collect::<ParseResult<_>>()?),
+        Rule::upper_ident => TypeDecl::Named(pair.as_str().to_owned()),
+        _ => return Err(unexpected(&pair)),
+    })
+}
+
+fn parse_field_decls(pair: Pair<Rule>) -> ParseResult<Vec<FieldDecl>> {
+    pair.into_inner()
+        .map(|field| {
+            let pos = Pos::from(&field);
+            let mut items = field.clone().into_inner();
+            let name = next(&mut items, &field)?.as_str().to_owned();
+            let typ = parse_type_decl(next(&mut items, &field)?)?;
+            let default = items.next().map(parse_expr).transpose()?;
+            Ok(FieldDecl { name, typ, default, pos })
+        })
+        .collect()
+}
+
+fn parse_variant_decl(pair: Pair<Rule>) -> ParseResult<VariantDecl> {
+    let pos = Pos::from(&pair);
+    let mut items = pair.clone().into_inner();
+    let name = next(&mut items, &pair)?.as_str().to_owned();
+    let fields = match items.next() {
+        None => VariantFields::Unit,
+        Some(fields) if fields.as_rule() == Rule::field_decls => VariantFields::Struct(parse_field_decls(fields)?),
+        Some(types) => VariantFields::Tuple(types.into_inner().map(parse_type_decl).collect::<ParseResult<_>>()?),
+    };
+    Ok(VariantDecl { name, fields, pos })
+}
+
+pub fn parse_stmt(pair: Pair<Rule>) -> ParseResult<Statement> {
+    let pair = first_child(&pair)?;
+    let mut items = pair.clone().into_inner();
+    Ok(match pair.as_rule() {
         Rule::const_binding | Rule::let_binding => {
-            let mut items = pair.into_inner();
-            let pattern = parse_pattern(items.next().unwrap());
-            let value = parse_expr(items.next().unwrap());
-            Statement::Let(pattern, value)
+            let pattern = parse_pattern(next(&mut items, &pair)?)?;
+            let mut value = next(&mut items, &pair)?;
+            let typ = match value.as_rule() {
+                Rule::value => None,
+                _ => {
+                    let typ = parse_type_decl(value)?;
+                    value = next(&mut items, &pair)?;
+                    Some(typ)
+                }
+            };
+            Statement::Let(pattern, typ, parse_expr(value)?)
         }
-        Rule::value => Statement::ExprDesc(parse_expr(pair)),
+        Rule::use_stmt => {
+            let pos = Pos::from(&pair);
+            Statement::Use(unescape_string(&next(&mut items, &pair)?)?, pos)
+        }
+        Rule::mod_stmt => {
+            let pos = Pos::from(&pair);
+            Statement::Mod(next(&mut items, &pair)?.as_str().to_owned(), pos)
+        }
+        Rule::struct_decl => {
+            let pos = Pos::from(&pair);
+            let name = next(&mut items, &pair)?.as_str().to_owned();
+            let fields = parse_field_decls(next(&mut items, &pair)?)?;
+            Statement::Decl(Decl::Struct(name, fields), pos)
+        }
+        Rule::enum_decl => {
+            let pos = Pos::from(&pair);
+            let name = next(&mut items, &pair)?.as_str().to_owned();
+            let variants = items.map(parse_variant_decl).collect::<ParseResult<_>>()?;
+            Statement::Decl(Decl::Enum(name, variants), pos)
+        }
+        Rule::value => Statement::ExprDesc(parse_expr(pair)?),
+        Rule::assignment => parse_assignment(pair)?,
+        Rule::for_loop | Rule::while_loop => Statement::ExprDesc(parse_op_item(pair)?),
         Rule::fndefn => {
-            let mut items = pair.into_inner();
-            let ident = items.next().unwrap().as_str().to_owned();
-            let args = items
-                .next()
-                .unwrap()
-                .into_inner()
-                .map(|pair| pair.as_str().to_owned())
-                .collect();
-            let value = parse_block(items.next().unwrap());
-            Statement::FnDefn(ident, args, value)
+            let ident = next(&mut items, &pair)?.as_str().to_owned();
+            let mut args = vec![];
+            let mut signature = Signature::default();
+            for arg in next(&mut items, &pair)?.into_inner() {
+                let mut parts = arg.clone().into_inner();
+                args.push(next(&mut parts, &arg)?.as_str().to_owned());
+                signature.args.push(parse_type_decl(next(&mut parts, &arg)?)?);
+            }
+            let mut body = next(&mut items, &pair)?;
+            if body.as_rule() != Rule::block {
+                signature.ret = parse_type_decl(body)?;
+                body = next(&mut items, &pair)?;
+            }
+            Statement::FnDefn(ident, args, signature, parse_block(body)?)
         }
-        _ => {
-            panic!(format!(
-                "Unreachable stmt {}, {:?}",
-                pair.as_str(),
-                pair.as_rule()
-            ));
+        _ => return Err(unexpected(&pair)),
+    })
+}
+
+pub fn process_file(text: &str) -> Result<Vec<Statement>, ParseError> {
+    let mut stmts = vec![];
+    for pair in MainParser::parse(Rule::file, text)? {
+        if let Rule::toplevel_statement = pair.as_rule() {
+            stmts.push(parse_stmt(pair)?)
         }
     }
+    Ok(stmts)
 }
 
-pub fn process_file(text: &str) -> Result<Vec<Statement>, pest::error::Error<Rule>> {
-    match MainParser::parse(Rule::file, text) {
-        Ok(v) => {
-            let mut stmts = vec![];
-            for pair in v {
-                if let Rule::toplevel_statement = pair.as_rule() {
-                    stmts.push(parse_stmt(pair))
+/// Like `process_file`, but a broken top-level item is skipped so that parsing can carry
+/// on and report every broken item at once. Items are expected to start at the beginning
+/// of a line, like `fn` definitions usually do.
+pub fn process_file_recovering(text: &str) -> (Vec<Statement>, Vec<ParseError>) {
+    parse_items_recovering(text, parse_stmt)
+}
+
+/// Runs `parse` on each top-level item that parses, blanking out the ones that don't
+pub(crate) fn parse_items_recovering<T>(
+    text: &str,
+    parse: impl Fn(Pair<Rule>) -> ParseResult<T>,
+) -> (Vec<T>, Vec<ParseError>) {
+    let mut text = text.to_owned();
+    let mut errors: Vec<ParseError> = vec![];
+    loop {
+        let err = match MainParser::parse(Rule::file, &text) {
+            Err(err) => err,
+            Ok(pairs) => {
+                let mut items = vec![];
+                for pair in pairs {
+                    if let Rule::toplevel_statement = pair.as_rule() {
+                        match parse(pair) {
+                            Ok(item) => items.push(item),
+                            Err(err) => errors.push(err),
+                        }
+                    }
                 }
+                errors.sort_by_key(error_offset);
+                return (items, errors);
             }
-            Ok(stmts)
+        };
+        let offset = error_offset(&err);
+        // Blank out the item containing the error, keeping newlines so positions stay the same
+        let start = item_starts(&text)
+            .filter(|&start| start <= offset)
+            .last()
+            .unwrap_or(0);
+        let end = item_starts(&text)
+            .find(|&start| start > offset)
+            .unwrap_or(text.len());
+        if errors.last().map(error_offset) != Some(offset) {
+            errors.push(err);
         }
-        Err(e) => Err(e),
+        let blank: String = text[start..end]
+            .chars()
+            .map(|c| if c == '\n' { '\n' } else { ' ' })
+            .collect();
+        text.replace_range(start..end, &blank);
     }
 }
 
-pub fn parse_block(pair: Pair<Rule>) -> Expr {
+fn error_offset(err: &ParseError) -> usize {
+    match err.location {
+        pest::error::InputLocation::Pos(pos) => pos,
+        pest::error::InputLocation::Span((start, _)) => start,
+    }
+}
+
+/// Byte offsets of the lines that could start a top-level item
+fn item_starts(text: &str) -> impl Iterator<Item = usize> + '_ {
+    std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(idx, _)| idx + 1))
+        .filter(move |&start| match text[start..].chars().next() {
+            Some(c) => !c.is_whitespace() && !"})]".contains(c) && !text[start..].starts_with("//"),
+            None => false,
+        })
+}
+
+pub fn parse_block(pair: Pair<Rule>) -> ParseResult<Expr> {
     let mut items = vec![];
     let pos = Pos::from(&pair);
     for item in pair.into_inner() {
         match item.as_rule() {
-            Rule::statement => items.push(parse_stmt(item)),
-            Rule::value => return ExprDesc::Block(items, Box::new(parse_expr(item))).with_pos(pos),
+            Rule::statement => items.push(parse_stmt(item)?),
+            Rule::assignment => items.push(parse_assignment(item)?),
+            Rule::value => {
+                return Ok(ExprDesc::Block(items, Box::new(parse_expr(item)?)).with_pos(pos))
+            }
             _ => (),
         }
     }
-    unreachable!()
+    Ok(ExprDesc::Block(items, Box::new(ExprDesc::Unit.with_pos(pos))).with_pos(pos))
 }
 
-pub fn process_expr(text: &str) -> Result<Expr, pest::error::Error<Rule>> {
-    match MainParser::parse(Rule::expr, text) {
-        Ok(v) => {
-            let mut items = vec![];
-            for item in v {
-                match item.as_rule() {
-                    Rule::statement => items.push(parse_stmt(item)),
-                    Rule::value => {
-                        let mut pos = Pos::from(&item);
-                        pos.start = (0, 0);
-                        return Ok(ExprDesc::Block(items, Box::new(parse_expr(item))).with_pos(pos));
-                    }
-                    _ => (),
-                }
+/// Parses a line of REPL input: statements, then maybe a value to show
+pub fn process_input(text: &str) -> Result<(Vec<Statement>, Option<Expr>), ParseError> {
+    let mut stmts = vec![];
+    for item in MainParser::parse(Rule::input, text)? {
+        match item.as_rule() {
+            Rule::statement | Rule::toplevel_statement => stmts.push(parse_stmt(item)?),
+            Rule::value => return Ok((stmts, Some(parse_expr(item)?))),
+            _ => (),
+        }
+    }
+    Ok((stmts, None))
+}
+
+/// Whether more lines could finish a REPL input: it stops parsing where the text runs out,
+/// or at a string or comment that's never closed
+pub fn input_is_unfinished(text: &str) -> bool {
+    let err = match MainParser::parse(Rule::input, text) {
+        Ok(_) => return false,
+        Err(err) => err,
+    };
+    let mut offset = error_offset(&err);
+    if text[offset..].trim().is_empty() {
+        return true;
+    }
+    // the `r` of a raw string is taken for a name
+    if text[..offset].ends_with('r') && (text[offset..].starts_with('#') || text[offset..].starts_with('"')) {
+        offset -= 1;
+    }
+    let rest = &text[offset..];
+    let literal = if rest.starts_with("/*") {
+        Rule::block_comment
+    } else if rest.starts_with('"') || rest.starts_with("r\"") || rest.starts_with("r#") {
+        Rule::string
+    } else {
+        return false;
+    };
+    MainParser::parse(literal, rest).is_err()
+}
+
+/// Parses a data file: top-level items, then the value it holds
+pub fn process_data(text: &str) -> Result<(Vec<Statement>, Expr), ParseError> {
+    let mut stmts = vec![];
+    for item in MainParser::parse(Rule::data, text)? {
+        match item.as_rule() {
+            Rule::toplevel_statement => stmts.push(parse_stmt(item)?),
+            Rule::value => return Ok((stmts, parse_expr(item)?)),
+            _ => (),
+        }
+    }
+    Err(ParseError::new_from_pos(
+        ErrorVariant::CustomError {
+            message: "Expected a value".to_owned(),
+        },
+        pest::Position::from_start(text),
+    ))
+}
+
+pub fn process_expr(text: &str) -> Result<Expr, ParseError> {
+    let mut items = vec![];
+    for item in MainParser::parse(Rule::expr, text)? {
+        match item.as_rule() {
+            Rule::statement => items.push(parse_stmt(item)?),
+            Rule::value => {
+                let mut pos = Pos::from(&item);
+                pos.start = (0, 0);
+                return Ok(ExprDesc::Block(items, Box::new(parse_expr(item)?)).with_pos(pos));
             }
-            unreachable!()
+            _ => (),
         }
-        Err(e) => Err(e),
     }
+    Err(ParseError::new_from_pos(
+        ErrorVariant::CustomError {
+            message: "Expected a value".to_owned(),
+        },
+        pest::Position::from_start(text),
+    ))
 }
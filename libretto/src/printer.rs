@@ -0,0 +1,968 @@
+use crate::ast::{Access, Const, Expr, ExprDesc, IfCond, Pattern, Place, Pos, Statement, Type};
+use crate::error::Error;
+use crate::parser::process_file;
+use crate::schema::{Decl, FieldDecl, Signature, TypeDecl, VariantFields};
+
+/// Lines are broken up when they'd be longer than this
+const WIDTH: usize = 100;
+
+/// Formats a script file canonically. Comments are kept, next to the items they were next to.
+pub fn format_source(source: &str) -> Result<String, Error> {
+    let stmts = process_file(source)?;
+    let mut printer = Printer::new(source);
+    printer.file(&stmts);
+    Ok(printer.out)
+}
+
+/// Writes out top-level statements as a script file
+pub fn to_source(stmts: &[Statement]) -> String {
+    let mut printer = Printer::new("");
+    printer.file(stmts);
+    printer.out
+}
+
+/// Writes a value, on one line or broken up like `Display` does
+pub(crate) fn value_source(value: &Expr, pretty: bool) -> String {
+    let mut printer = Printer::new("");
+    printer.flat = !pretty;
+    printer.expr(value);
+    printer.out
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut printer = Printer::new("");
+        printer.expr(self);
+        f.write_str(&printer.out)
+    }
+}
+
+impl std::fmt::Display for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut printer = Printer::new("");
+        printer.statement(self, false);
+        f.write_str(&printer.out)
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&pattern(self))
+    }
+}
+
+/// A `//` or `/* */` comment, which the parser skips over
+#[derive(Debug, Clone)]
+struct Comment {
+    text: String,
+    start: (usize, usize),
+    end: (usize, usize),
+}
+
+/// Finds the comments in a source file, with the same lines and columns that a `Pos` has
+fn comments(source: &str) -> Vec<Comment> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut comments = vec![];
+    let mut positions = Vec::with_capacity(chars.len() + 1);
+    let (mut line, mut col) = (1, 1);
+    for &c in &chars {
+        positions.push((line, col));
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    positions.push((line, col));
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        match chars[i] {
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            '"' | '\'' => {
+                let quote = chars[i];
+                i += 1;
+                while i < chars.len() && chars[i] != quote {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                i += 1;
+                continue;
+            }
+            'r' if (i == 0 || !is_ident_char(chars[i - 1])) && matches!(chars.get(i + 1), Some('#') | Some('"')) => {
+                let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+                if chars.get(i + 1 + hashes) != Some(&'"') {
+                    i += 1;
+                    continue;
+                }
+                i += hashes + 2;
+                let close: Vec<char> = std::iter::once('"').chain(std::iter::repeat_n('#', hashes)).collect();
+                while i < chars.len() && !chars[i..].starts_with(&close) {
+                    i += 1;
+                }
+                i += close.len();
+                continue;
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+        let end = i.min(chars.len());
+        comments.push(Comment {
+            text: chars[start..end].iter().collect::<String>().trim_end().to_owned(),
+            start: positions[start],
+            end: positions[end],
+        });
+    }
+    comments
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// How tightly an expression binds, so that parentheses can be added where they're needed.
+/// Binary operators go from `..` up to `*`, the same as in the parser.
+fn precedence(desc: &ExprDesc) -> u8 {
+    match desc {
+        ExprDesc::Lambda(..) | ExprDesc::Closure(..) => 0,
+        ExprDesc::Range(..) => 1,
+        ExprDesc::Or(..) => 2,
+        ExprDesc::And(..) => 3,
+        ExprDesc::Eq(..)
+        | ExprDesc::Neq(..)
+        | ExprDesc::Lt(..)
+        | ExprDesc::Gt(..)
+        | ExprDesc::Le(..)
+        | ExprDesc::Ge(..) => 4,
+        ExprDesc::BitOr(..) => 5,
+        ExprDesc::BitXor(..) => 6,
+        ExprDesc::BitAnd(..) => 7,
+        ExprDesc::Shl(..) | ExprDesc::Shr(..) => 8,
+        ExprDesc::Plus(..) | ExprDesc::Minus(..) => 9,
+        ExprDesc::Times(..) | ExprDesc::Divide(..) | ExprDesc::Modulo(..) => 10,
+        ExprDesc::Neg(_) | ExprDesc::Not(_) | ExprDesc::Ref(_) => 11,
+        ExprDesc::Cast(..) => 12,
+        _ => 13,
+    }
+}
+
+/// The operator and operands of a binary expression
+fn binary(desc: &ExprDesc) -> Option<(&'static str, &Expr, &Expr)> {
+    Some(match desc {
+        ExprDesc::Range(a, b, inclusive) => (if *inclusive { "..=" } else { ".." }, a, b),
+        ExprDesc::Or(a, b) => ("||", a, b),
+        ExprDesc::And(a, b) => ("&&", a, b),
+        ExprDesc::Eq(a, b) => ("==", a, b),
+        ExprDesc::Neq(a, b) => ("!=", a, b),
+        ExprDesc::Lt(a, b) => ("<", a, b),
+        ExprDesc::Gt(a, b) => (">", a, b),
+        ExprDesc::Le(a, b) => ("<=", a, b),
+        ExprDesc::Ge(a, b) => (">=", a, b),
+        ExprDesc::BitOr(a, b) => ("|", a, b),
+        ExprDesc::BitXor(a, b) => ("^", a, b),
+        ExprDesc::BitAnd(a, b) => ("&", a, b),
+        ExprDesc::Shl(a, b) => ("<<", a, b),
+        ExprDesc::Shr(a, b) => (">>", a, b),
+        ExprDesc::Plus(a, b) => ("+", a, b),
+        ExprDesc::Minus(a, b) => ("-", a, b),
+        ExprDesc::Times(a, b) => ("*", a, b),
+        ExprDesc::Divide(a, b) => ("/", a, b),
+        ExprDesc::Modulo(a, b) => ("%", a, b),
+        _ => return None,
+    })
+}
+
+fn float(f: f32) -> String {
+    if f.is_nan() {
+        "(0.0 / 0.0)".to_owned()
+    } else if f.is_infinite() {
+        format!("({}1.0 / 0.0)", if f < 0.0 { "-" } else { "" })
+    } else {
+        // `Display` never uses an exponent, which the grammar doesn't have
+        let text = f.to_string();
+        if text.contains('.') {
+            text
+        } else {
+            text + ".0"
+        }
+    }
+}
+
+/// A `Long`, with the smallest suffix that holds it
+pub(crate) fn long(i: i128) -> String {
+    if i64::MIN as i128 <= i && i <= i64::MAX as i128 {
+        format!("{}i64", i)
+    } else if 0 <= i && i <= u64::MAX as i128 {
+        format!("{}u64", i)
+    } else {
+        format!("{}i128", i)
+    }
+}
+
+pub(crate) fn double(f: f64) -> String {
+    if !f.is_finite() {
+        return float(f as f32);
+    }
+    let text = f.to_string();
+    if text.contains('.') {
+        text + "f64"
+    } else {
+        text + ".0f64"
+    }
+}
+
+fn escape(text: &str, quote: char) -> String {
+    let mut escaped = String::new();
+    escaped.push(quote);
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c == quote => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push(quote);
+    escaped
+}
+
+/// Keys that aren't lowercase identifiers are written as strings
+fn key(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_ident = matches!(chars.next(), Some(c) if c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_ident {
+        name.to_owned()
+    } else {
+        escape(name, '"')
+    }
+}
+
+fn constant(value: &Const) -> String {
+    match value {
+        Const::Float(f) => float(*f),
+        Const::Int(i) => i.to_string(),
+        Const::Bool(b) => b.to_string(),
+        Const::Char(c) => escape(&c.to_string(), '\''),
+        Const::String(s) => escape(s, '"'),
+    }
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    let list = |items: &[Pattern]| items.iter().map(self::pattern).collect::<Vec<_>>().join(", ");
+    match pattern {
+        Pattern::Ident(name) => name.clone(),
+        Pattern::MutIdent(name) => format!("mut {}", name),
+        Pattern::Const(value) => constant(value),
+        Pattern::Any => "_".to_owned(),
+        Pattern::TupleStruct(name, items) if items.is_empty() => name.clone(),
+        Pattern::TupleStruct(name, items) => format!("{}({})", name, list(items)),
+        Pattern::Tuple(items) if items.len() == 1 => format!("({},)", list(items)),
+        Pattern::Tuple(items) => format!("({})", list(items)),
+        Pattern::Struct(name, fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(field, value)| match value {
+                    Pattern::Ident(name) if name == field => field.clone(),
+                    _ => format!("{}: {}", field, self::pattern(value)),
+                })
+                .collect();
+            format!("{} {{ {} }}", name, fields.join(", "))
+        }
+        Pattern::Or(alternatives) => {
+            alternatives.iter().map(self::pattern).collect::<Vec<_>>().join(" | ")
+        }
+        Pattern::Range(start, end, inclusive) => {
+            format!("{}{}{}", constant(start), if *inclusive { "..=" } else { ".." }, constant(end))
+        }
+        Pattern::Bind(name, inner) if matches!(**inner, Pattern::Or(_)) => {
+            format!("{} @ ({})", name, self::pattern(inner))
+        }
+        Pattern::Bind(name, inner) => format!("{} @ {}", name, self::pattern(inner)),
+        Pattern::Slice(items) => format!("[{}]", list(items)),
+        Pattern::Rest(Some(name)) => format!("{} @ ..", name),
+        Pattern::Rest(None) => "..".to_owned(),
+    }
+}
+
+fn signature(name: &str, args: &[String], signature: &Signature) -> String {
+    let args: Vec<String> = args
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| format!("{}: {}", arg, signature.args.get(i).unwrap_or(&TypeDecl::Any)))
+        .collect();
+    match signature.ret {
+        TypeDecl::Any => format!("fn {}({})", name, args.join(", ")),
+        ref ret => format!("fn {}({}) -> {}", name, args.join(", "), ret),
+    }
+}
+
+/// Where a statement is in the source, as near as the AST knows
+fn statement_pos(stmt: &Statement) -> Pos {
+    match stmt {
+        Statement::Let(_, _, value) | Statement::ExprDesc(value) => value.pos,
+        Statement::Assign(place, value) => Pos {
+            start: place.pos.start,
+            ..value.pos
+        },
+        Statement::FnDefn(_, _, _, body) => body.pos,
+        Statement::Use(_, pos) | Statement::Mod(_, pos) | Statement::Decl(_, pos) => *pos,
+    }
+}
+
+/// Writes the AST out as source. Everything that fits on a line goes on one line, and
+/// anything else is broken up with one item per line, like rustfmt does.
+struct Printer<'a> {
+    out: String,
+    indent: usize,
+    /// Writes everything on one line, except blocks with statements, to see whether it fits
+    flat: bool,
+    comments: Vec<Comment>,
+    next_comment: usize,
+    source: Vec<&'a str>,
+    /// The source line of the last thing written, so blank lines and comments go back where
+    /// they were
+    line: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str) -> Self {
+        Printer {
+            out: String::new(),
+            indent: 0,
+            flat: false,
+            comments: comments(source),
+            next_comment: 0,
+            source: source.lines().collect(),
+            line: 0,
+        }
+    }
+
+    /// What `print` writes when everything is on one line, if it can be
+    fn flat(&self, print: impl FnOnce(&mut Printer)) -> Option<String> {
+        let mut printer = Printer::new("");
+        printer.flat = true;
+        print(&mut printer);
+        match printer.out.contains('\n') {
+            true => None,
+            false => Some(printer.out),
+        }
+    }
+
+    fn fits(&self, text: &str) -> bool {
+        let column = self.out.len() - self.out.rfind('\n').map_or(0, |idx| idx + 1);
+        self.flat || column + text.chars().count() < WIDTH
+    }
+
+    /// Whether there are comments that haven't been written yet before the end of `pos`
+    fn has_comments(&self, pos: Pos) -> bool {
+        match self.comments.get(self.next_comment) {
+            Some(comment) => !pos.is_empty() && comment.start < pos.end,
+            None => false,
+        }
+    }
+
+    /// Takes the next comment that hasn't been written yet, if it's before `at`
+    fn comment_before(&mut self, at: (usize, usize)) -> Option<Comment> {
+        let comment = self.comments.get(self.next_comment).filter(|comment| comment.start < at)?.clone();
+        self.next_comment += 1;
+        Some(comment)
+    }
+
+    /// Starts a new line. With `blank` it's `Some(true)` there's a blank line first, and with
+    /// `Some(false)` there is if the source had one before `line`.
+    fn break_line(&mut self, line: usize, blank: Option<bool>) {
+        if self.out.is_empty() {
+            return;
+        }
+        let blank = match blank {
+            None => false,
+            Some(true) => true,
+            Some(false) => {
+                let between = self.source.get(self.line..line.saturating_sub(1)).unwrap_or(&[]);
+                self.line > 0 && between.iter().any(|text| text.trim().is_empty())
+            }
+        };
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        if blank {
+            self.out.push('\n');
+        }
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(self.indent));
+    }
+
+    /// Moves to a new line for something at `pos`, writing the comments before it first
+    fn start_line(&mut self, pos: Pos, mut blank: Option<bool>) {
+        while let Some(Comment { text, start, end }) = self.comment_before(pos.start) {
+            self.break_line(start.0, blank);
+            self.out.push_str(&text);
+            self.line = end.0;
+            blank = Some(false);
+        }
+        self.break_line(pos.start.0, blank);
+    }
+
+    /// Writes the comments inside something that was just written, and after it on the same line
+    fn end_line(&mut self, pos: Pos) {
+        if pos.is_empty() {
+            return;
+        }
+        let mut after_line_comment = false;
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if !(comment.start < pos.end || comment.start.0 == pos.end.0) {
+                break;
+            }
+            let Comment { text, end, .. } = comment.clone();
+            self.next_comment += 1;
+            if after_line_comment {
+                self.break_line(0, None);
+            } else {
+                self.out.push(' ');
+            }
+            after_line_comment = text.starts_with("//");
+            self.out.push_str(&text);
+            self.line = self.line.max(end.0);
+        }
+        self.line = self.line.max(pos.end.0);
+    }
+
+    /// Writes items on their own lines, indented, with the comments around them. The
+    /// comments before `end` that are left go after the last item.
+    fn lines<T>(
+        &mut self,
+        items: &[T],
+        separator: &str,
+        end: Pos,
+        pos: impl Fn(&T) -> Pos,
+        mut print: impl FnMut(&mut Self, &T),
+    ) {
+        self.indent += 4;
+        for (i, item) in items.iter().enumerate() {
+            let pos = pos(item);
+            self.start_line(pos, if i == 0 { None } else { Some(false) });
+            print(self, item);
+            self.out.push_str(separator);
+            self.end_line(pos);
+        }
+        if !end.is_empty() {
+            while let Some(Comment { text, start, end }) = self.comment_before(end.end) {
+                self.break_line(start.0, Some(false));
+                self.out.push_str(&text);
+                self.line = end.0;
+            }
+        }
+        self.indent -= 4;
+        self.break_line(0, None);
+    }
+
+    /// A comma separated list in brackets, on one line if it fits
+    fn list(&mut self, open: &str, items: &[Expr], close: &str, pos: Pos) {
+        self.out.push_str(open);
+        let flat = self.flat(|printer| printer.comma_separated(items));
+        match flat {
+            Some(flat) if self.fits(&format!("{}{}", flat, close)) && !self.has_comments(pos) => {
+                self.out.push_str(&flat)
+            }
+            _ if items.is_empty() && !self.has_comments(pos) => (),
+            _ if self.overflows(items, pos) => {
+                let (last, init) = items.split_last().unwrap_or_else(|| unreachable!());
+                for item in init {
+                    self.expr(item);
+                    self.out.push_str(", ");
+                }
+                self.expr_desc(last);
+            }
+            _ => {
+                self.lines(items, ",", pos, |item| item.pos, |printer, item| printer.expr(item));
+                // every item already has a comma after it
+                return self.out.push_str(close.trim_start_matches(','));
+            }
+        }
+        self.out.push_str(close);
+    }
+
+    /// Whether the last item of a list can start on the same line and break up inside, like
+    /// `push(Bone {` or `map(|x| {`
+    fn overflows(&self, items: &[Expr], pos: Pos) -> bool {
+        let (last, init) = match items.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        let overflows = match &last.desc {
+            ExprDesc::Struct(..) | ExprDesc::Object(..) | ExprDesc::Array(..) | ExprDesc::Match(..) => true,
+            ExprDesc::Block(..) => true,
+            ExprDesc::Lambda(_, body) => matches!(body.desc, ExprDesc::Block(..)),
+            ExprDesc::Closure(f, _) => matches!(f.body.desc, ExprDesc::Block(..)),
+            _ => false,
+        };
+        let init = self.flat(|printer| {
+            printer.comma_separated(init);
+            printer.out.push_str(", ");
+        });
+        overflows && !self.has_comments(pos) && init.is_some_and(|init| self.fits(&init))
+    }
+
+    fn comma_separated(&mut self, items: &[Expr]) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.expr(item);
+        }
+    }
+
+    /// `key: value` pairs in braces
+    fn fields(&mut self, open: &str, fields: &[(String, Expr)], pos: Pos) {
+        self.out.push_str(open);
+        let flat = self.flat(|printer| {
+            for (i, (name, value)) in fields.iter().enumerate() {
+                printer.out.push_str(if i == 0 { " " } else { ", " });
+                printer.out.push_str(&key(name));
+                printer.out.push_str(": ");
+                printer.expr(value);
+            }
+            printer.out.push_str(" }");
+        });
+        match flat {
+            Some(flat) if fields.is_empty() => self.out.push_str(&flat[1..]),
+            Some(flat) if self.fits(&flat) && !self.has_comments(pos) => self.out.push_str(&flat),
+            _ => {
+                let pos_of = |(_, value): &(String, Expr)| value.pos;
+                self.lines(fields, ",", pos, pos_of, |printer, (name, value)| {
+                    printer.out.push_str(&key(name));
+                    printer.out.push_str(": ");
+                    printer.expr(value);
+                });
+                self.out.push('}');
+            }
+        }
+    }
+
+    /// Writes an expression, using one line if it fits
+    fn expr(&mut self, expr: &Expr) {
+        if !self.flat {
+            if let Some(flat) = self.flat(|printer| printer.expr(expr)) {
+                if self.fits(&flat) && !self.has_comments(expr.pos) {
+                    self.out.push_str(&flat);
+                    return;
+                }
+            }
+        }
+        self.expr_desc(expr);
+    }
+
+    /// Writes an expression, wrapped in parentheses if it binds less tightly than `precedence`
+    fn operand(&mut self, expr: &Expr, precedence: u8) {
+        if self::precedence(&expr.desc) < precedence {
+            self.out.push('(');
+            self.expr(expr);
+            self.out.push(')');
+        } else {
+            self.expr(expr);
+        }
+    }
+
+    /// Conditions are followed by a block, so a struct literal there needs parentheses
+    fn condition(&mut self, expr: &Expr) {
+        match &expr.desc {
+            ExprDesc::Struct(..) | ExprDesc::Object(..) => {
+                self.out.push('(');
+                self.expr(expr);
+                self.out.push(')');
+            }
+            _ => self.expr(expr),
+        }
+    }
+
+    fn if_cond(&mut self, cond: &IfCond) {
+        match cond {
+            IfCond::Value(value) => self.condition(value),
+            IfCond::IfLet(pattern, value) => {
+                self.out.push_str(&format!("let {} = ", self::pattern(pattern)));
+                self.condition(value);
+            }
+        }
+    }
+
+    /// Writes a block. Unless it's `always_break`, a block with only a value can go on one line.
+    fn block(&mut self, block: &Expr, always_break: bool) {
+        let (stmts, value) = match &block.desc {
+            ExprDesc::Block(stmts, value) => (&stmts[..], Some(value.as_ref()).filter(|value| value.desc != ExprDesc::Unit)),
+            _ => (&[][..], Some(block)),
+        };
+        if stmts.is_empty() && !self.has_comments(block.pos) {
+            match value {
+                None => return self.out.push_str("{}"),
+                Some(value) if !always_break => {
+                    let flat = self.flat(|printer| printer.expr(value));
+                    if let Some(flat) = flat.filter(|flat| self.fits(&format!("{{ {} }}", flat))) {
+                        return self.out.push_str(&format!("{{ {} }}", flat));
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.out.push('{');
+        let items: Vec<Result<&Statement, &Expr>> = stmts.iter().map(Ok).chain(value.map(Err)).collect();
+        let pos = |item: &Result<&Statement, &Expr>| match item {
+            Ok(stmt) => statement_pos(stmt),
+            Err(value) => value.pos,
+        };
+        self.lines(&items, "", block.pos, pos, |printer, item| match item {
+            Ok(stmt) => printer.statement(stmt, false),
+            Err(value) => printer.expr(value),
+        });
+        self.out.push('}');
+    }
+
+    fn expr_desc(&mut self, expr: &Expr) {
+        let pos = expr.pos;
+        if let Some((op, left, right)) = binary(&expr.desc) {
+            let precedence = precedence(&expr.desc);
+            self.operand(left, precedence);
+            if let ExprDesc::Range(..) = expr.desc {
+                self.out.push_str(op);
+                return self.operand(right, precedence + 1);
+            }
+            let right_flat = self.flat(|printer| printer.operand(right, precedence + 1));
+            match right_flat {
+                Some(flat) if self.fits(&format!(" {} {}", op, flat)) => {
+                    self.out.push_str(&format!(" {} {}", op, flat));
+                }
+                _ => {
+                    self.indent += 4;
+                    self.break_line(0, None);
+                    self.out.push_str(op);
+                    self.out.push(' ');
+                    self.operand(right, precedence + 1);
+                    self.indent -= 4;
+                }
+            }
+            return;
+        }
+        match &expr.desc {
+            ExprDesc::Float(f) => self.out.push_str(&float(*f)),
+            ExprDesc::Int(i) => self.out.push_str(&i.to_string()),
+            ExprDesc::Long(i) => self.out.push_str(&long(*i)),
+            ExprDesc::Double(f) => self.out.push_str(&double(*f)),
+            ExprDesc::Bool(b) => self.out.push_str(&b.to_string()),
+            ExprDesc::Char(c) => self.out.push_str(&escape(&c.to_string(), '\'')),
+            ExprDesc::String(s) => self.out.push_str(&escape(s, '"')),
+            ExprDesc::Ident(name) => self.out.push_str(name),
+            ExprDesc::Unit => self.out.push_str("()"),
+            ExprDesc::Break => self.out.push_str("break"),
+            ExprDesc::Continue => self.out.push_str("continue"),
+            // only while evaluating, after a variable's value was used
+            ExprDesc::Moved => self.out.push_str("<moved>"),
+
+            ExprDesc::Array(items) => self.list("vec![", items, "]", pos),
+            ExprDesc::Tuple(items) if items.len() == 1 => self.list("(", items, ",)", pos),
+            ExprDesc::Tuple(items) => self.list("(", items, ")", pos),
+            ExprDesc::Object(fields) => self.fields("{", fields, pos),
+            ExprDesc::Struct(name, fields) => self.fields(&format!("{} {{", name), fields, pos),
+            ExprDesc::NamedTuple(name, items) if items.is_empty() => self.out.push_str(name),
+            ExprDesc::NamedTuple(name, items) => self.list(&format!("{}(", name), items, ")", pos),
+            ExprDesc::Option(inner) => match inner.as_ref() {
+                Some(inner) => self.list("Some(", std::slice::from_ref(inner), ")", pos),
+                None => self.out.push_str("None"),
+            },
+            ExprDesc::FnCall(name, args) => self.list(&format!("{}(", name), args, ")", pos),
+            ExprDesc::Call(target, args) => {
+                match &target.desc {
+                    // `name(args)` would be a call to a function instead of the variable
+                    ExprDesc::Ident(_) => {
+                        self.out.push('(');
+                        self.expr(target);
+                        self.out.push(')');
+                    }
+                    _ => self.operand(target, 13),
+                }
+                self.list("(", args, ")", pos);
+            }
+
+            ExprDesc::Neg(inner) | ExprDesc::Not(inner) | ExprDesc::Ref(inner) => {
+                self.out.push_str(match &expr.desc {
+                    ExprDesc::Neg(_) => "-",
+                    ExprDesc::Not(_) => "!",
+                    _ => "&",
+                });
+                let flat = self.flat(|printer| printer.operand(inner, 11));
+                // `-1` would be a negative number instead
+                let starts_with_digit = flat
+                    .as_ref()
+                    .and_then(|flat| flat.chars().next())
+                    .is_some_and(|c| c.is_ascii_digit() || c == '.');
+                if starts_with_digit && matches!(expr.desc, ExprDesc::Neg(_)) {
+                    self.out.push('(');
+                    self.expr(inner);
+                    self.out.push(')');
+                } else {
+                    self.operand(inner, 11);
+                }
+            }
+            ExprDesc::Cast(inner, typ) => {
+                self.operand(inner, 13);
+                self.out.push_str(match typ {
+                    Type::F32 => " as f32",
+                    Type::I32 => " as i32",
+                });
+            }
+            ExprDesc::MemberAccess(target, accesses) => {
+                self.operand(target, 13);
+                let mut broken = false;
+                for (i, access) in accesses.iter().enumerate() {
+                    if let Access::Index(index, _) = access {
+                        self.out.push('[');
+                        self.expr(index);
+                        self.out.push(']');
+                        continue;
+                    }
+                    let flat = self.flat(|printer| printer.access(access));
+                    // the last call can break up its arguments instead
+                    let last = i + 1 == accesses.len();
+                    if !broken && !last && !flat.as_ref().is_some_and(|flat| self.fits(flat)) {
+                        broken = true;
+                        self.indent += 4;
+                    }
+                    if broken {
+                        self.break_line(0, None);
+                    }
+                    self.access(access);
+                }
+                if broken {
+                    self.indent -= 4;
+                }
+            }
+
+            ExprDesc::Block(..) => self.block(expr, false),
+            ExprDesc::Lambda(args, body) => {
+                self.out.push_str(&format!("|{}| ", args.join(", ")));
+                self.expr(body);
+            }
+            ExprDesc::Closure(f, _) => {
+                self.out.push_str(&format!("|{}| ", f.args.join(", ")));
+                self.expr(&f.body);
+            }
+            ExprDesc::IfChain(branches, otherwise) => {
+                // all the branches go on one line, or none of them do
+                let one_line = self.flat;
+                for (i, (cond, body)) in branches.iter().enumerate() {
+                    self.out.push_str(if i == 0 { "if " } else { " else if " });
+                    self.if_cond(cond);
+                    self.out.push(' ');
+                    self.block(body, !one_line);
+                }
+                if let Some(otherwise) = otherwise {
+                    self.out.push_str(" else ");
+                    self.block(otherwise, !one_line);
+                }
+            }
+            ExprDesc::Match(value, arms) => {
+                self.out.push_str("match ");
+                self.condition(value);
+                self.out.push_str(" {");
+                let pos_of = |(_, _, body): &(Pattern, Option<Expr>, Expr)| body.pos;
+                self.lines(arms, ",", pos, pos_of, |printer, (pattern, guard, body)| {
+                    printer.out.push_str(&self::pattern(pattern));
+                    if let Some(guard) = guard {
+                        printer.out.push_str(" if ");
+                        printer.expr(guard);
+                    }
+                    printer.out.push_str(" => ");
+                    printer.expr(body);
+                });
+                self.out.push('}');
+            }
+            ExprDesc::For(pattern, iterable, body) => {
+                self.out.push_str(&format!("for {} in ", self::pattern(pattern)));
+                self.condition(iterable);
+                self.out.push(' ');
+                self.block(body, true);
+            }
+            ExprDesc::While(cond, body) => {
+                self.out.push_str("while ");
+                self.if_cond(cond);
+                self.out.push(' ');
+                self.block(body, true);
+            }
+            _ => unreachable!("binary operators are handled above"),
+        }
+    }
+
+    fn access(&mut self, access: &Access) {
+        match access {
+            Access::Field(name) => {
+                self.out.push('.');
+                self.out.push_str(name);
+            }
+            Access::Method(name, args) => self.list(&format!(".{}(", name), args, ")", Pos::default()),
+            Access::Index(index, _) => {
+                self.out.push('[');
+                self.expr(index);
+                self.out.push(']');
+            }
+        }
+    }
+
+    fn place(&mut self, place: &Place) {
+        self.out.push_str(&place.name);
+        for access in &place.members {
+            self.access(access);
+        }
+    }
+
+    /// Writes a statement. At the top of a file `let` is written `const`.
+    fn statement(&mut self, stmt: &Statement, top: bool) {
+        match stmt {
+            Statement::Let(pattern, typ, value) => {
+                self.out.push_str(if top { "const " } else { "let " });
+                self.out.push_str(&self::pattern(pattern));
+                match typ {
+                    Some(typ) => self.out.push_str(&format!(": {}", typ)),
+                    None if top => self.out.push_str(": any"),
+                    None => (),
+                }
+                self.out.push_str(" = ");
+                self.expr(value);
+                self.out.push(';');
+            }
+            Statement::Assign(place, value) => {
+                self.place(place);
+                let current = place.to_expr();
+                // `x += 1` is parsed as `x = x + 1`
+                let compound = match &value.desc {
+                    ExprDesc::Plus(a, b) if **a == current => Some(("+=", b)),
+                    ExprDesc::Minus(a, b) if **a == current => Some(("-=", b)),
+                    ExprDesc::Times(a, b) if **a == current => Some(("*=", b)),
+                    ExprDesc::Divide(a, b) if **a == current => Some(("/=", b)),
+                    _ => None,
+                };
+                match compound {
+                    Some((op, value)) => {
+                        self.out.push_str(&format!(" {} ", op));
+                        self.expr(value);
+                    }
+                    None => {
+                        self.out.push_str(" = ");
+                        self.expr(value);
+                    }
+                }
+                self.out.push(';');
+            }
+            Statement::ExprDesc(value) => {
+                self.expr(value);
+                if !matches!(value.desc, ExprDesc::For(..) | ExprDesc::While(..)) {
+                    self.out.push(';');
+                }
+            }
+            Statement::FnDefn(name, args, sig, body) => {
+                self.out.push_str(&signature(name, args, sig));
+                self.out.push(' ');
+                self.block(body, true);
+            }
+            Statement::Use(path, _) => self.out.push_str(&format!("use {};", escape(path, '"'))),
+            Statement::Mod(name, _) => self.out.push_str(&format!("mod {};", name)),
+            Statement::Decl(decl, pos) => self.decl(decl, *pos),
+        }
+    }
+
+    fn decl(&mut self, decl: &Decl, pos: Pos) {
+        match decl {
+            Decl::Struct(name, fields) => {
+                self.out.push_str(&format!("struct {} {{", name));
+                self.field_decls(fields, pos);
+            }
+            Decl::Enum(name, variants) => {
+                self.out.push_str(&format!("enum {} {{", name));
+                self.lines(variants, ",", pos, |variant| variant.pos, |printer, variant| {
+                    printer.out.push_str(&variant.name);
+                    match &variant.fields {
+                        VariantFields::Unit => (),
+                        VariantFields::Tuple(types) => {
+                            let types: Vec<String> = types.iter().map(|typ| typ.to_string()).collect();
+                            printer.out.push_str(&format!("({})", types.join(", ")));
+                        }
+                        VariantFields::Struct(fields) => {
+                            let flat = printer.flat(|printer| {
+                                let fields: Vec<String> = fields.iter().map(|field| field_decl(printer, field)).collect();
+                                printer.out.push_str(&format!(" {{ {} }}", fields.join(", ")));
+                            });
+                            match flat {
+                                Some(flat) if printer.fits(&flat) && !printer.has_comments(variant.pos) => {
+                                    printer.out.push_str(&flat)
+                                }
+                                _ => {
+                                    printer.out.push_str(" {");
+                                    printer.field_decls(fields, variant.pos);
+                                }
+                            }
+                        }
+                    }
+                });
+                self.out.push('}');
+            }
+        }
+    }
+
+    fn field_decls(&mut self, fields: &[FieldDecl], pos: Pos) {
+        self.lines(fields, ",", pos, |field| field.pos, |printer, field| {
+            let text = field_decl(printer, field);
+            printer.out.push_str(&text);
+        });
+        self.out.push('}');
+    }
+
+    fn file(&mut self, stmts: &[Statement]) {
+        let spaced = |stmt: &Statement| matches!(stmt, Statement::FnDefn(..) | Statement::Decl(..));
+        for (i, stmt) in stmts.iter().enumerate() {
+            let pos = statement_pos(stmt);
+            let blank = i > 0 && (spaced(stmt) || spaced(&stmts[i - 1]));
+            self.start_line(pos, Some(blank));
+            self.statement(stmt, true);
+            self.end_line(pos);
+        }
+        while let Some(Comment { text, start, end }) = self.comment_before((usize::MAX, 0)) {
+            self.break_line(start.0, Some(false));
+            self.out.push_str(&text);
+            self.line = end.0;
+        }
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+    }
+}
+
+fn has_statements(body: &Expr) -> bool {
+    matches!(&body.desc, ExprDesc::Block(stmts, _) if !stmts.is_empty())
+}
+
+fn field_decl(printer: &Printer, field: &FieldDecl) -> String {
+    match &field.default {
+        Some(default) => {
+            let mut value = Printer::new("");
+            value.indent = printer.indent;
+            value.expr(default);
+            format!("{}: {} = {}", field.name, field.typ, value.out)
+        }
+        None => format!("{}: {}", field.name, field.typ),
+    }
+}
@@ -0,0 +1,330 @@
+use crate::ast::{widen, Expr, ExprDesc, Pos};
+use crate::error::{EvalError, EvalErrorDesc, FieldType, UnknownField};
+use crate::scope::Scope;
+
+/// A type written in a declaration, like `(f32, f32)` or `Vec<Bone>`
+#[derive(PartialEq, Debug, Clone, Default)]
+pub enum TypeDecl {
+    #[default]
+    Any,
+    Int,
+    Float,
+    /// `i64`
+    Long,
+    /// `f64`
+    Double,
+    Bool,
+    Char,
+    String,
+    Unit,
+    Tuple(Vec<TypeDecl>),
+    Vec(Box<TypeDecl>),
+    Option(Box<TypeDecl>),
+    /// A declared struct or enum
+    Named(String),
+}
+
+/// `name: type`, maybe with `= default`
+#[derive(Debug, Clone)]
+pub struct FieldDecl {
+    pub name: String,
+    pub typ: TypeDecl,
+    pub default: Option<Expr>,
+    pub pos: Pos,
+}
+
+impl PartialEq for FieldDecl {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.typ == other.typ && self.default == other.default
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum VariantFields {
+    Unit,
+    Tuple(Vec<TypeDecl>),
+    Struct(Vec<FieldDecl>),
+}
+
+#[derive(Debug, Clone)]
+pub struct VariantDecl {
+    pub name: String,
+    pub fields: VariantFields,
+    pub pos: Pos,
+}
+
+impl PartialEq for VariantDecl {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.fields == other.fields
+    }
+}
+
+/// A `struct` or `enum` declaration. Values with a declared name are checked when they're built.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Decl {
+    Struct(String, Vec<FieldDecl>),
+    Enum(String, Vec<VariantDecl>),
+}
+
+impl Decl {
+    pub fn name(&self) -> &str {
+        match self {
+            Decl::Struct(name, _) | Decl::Enum(name, _) => name,
+        }
+    }
+
+    pub fn variant(&self, name: &str) -> Option<&VariantDecl> {
+        match self {
+            Decl::Enum(_, variants) => variants.iter().find(|variant| variant.name == name),
+            Decl::Struct(_, _) => None,
+        }
+    }
+
+    /// Every default value expression, so their positions can be updated
+    pub fn defaults_mut(&mut self) -> Vec<&mut Expr> {
+        let fields: Vec<&mut FieldDecl> = match self {
+            Decl::Struct(_, fields) => fields.iter_mut().collect(),
+            Decl::Enum(_, variants) => variants
+                .iter_mut()
+                .flat_map(|variant| match &mut variant.fields {
+                    VariantFields::Struct(fields) => fields.iter_mut().collect(),
+                    _ => vec![],
+                })
+                .collect(),
+        };
+        fields.into_iter().filter_map(|field| field.default.as_mut()).collect()
+    }
+}
+
+/// The types of a function's arguments and result. The result is `any` when it's left out.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Signature {
+    pub args: Vec<TypeDecl>,
+    pub ret: TypeDecl,
+}
+
+impl std::fmt::Display for TypeDecl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypeDecl::Any => write!(f, "any"),
+            TypeDecl::Int => write!(f, "i32"),
+            TypeDecl::Float => write!(f, "f32"),
+            TypeDecl::Long => write!(f, "i64"),
+            TypeDecl::Double => write!(f, "f64"),
+            TypeDecl::Bool => write!(f, "bool"),
+            TypeDecl::Char => write!(f, "char"),
+            TypeDecl::String => write!(f, "String"),
+            TypeDecl::Unit => write!(f, "()"),
+            TypeDecl::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "({})", items.join(", "))
+            }
+            TypeDecl::Vec(item) => write!(f, "Vec<{}>", item),
+            TypeDecl::Option(item) => write!(f, "Option<{}>", item),
+            TypeDecl::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Whether a value fits a declared type. Any number fits `f32` and `f64`, and ints fit `i64`,
+/// like they do in arithmetic. They're made the declared type by `promote`.
+fn fits(value: &Expr, typ: &TypeDecl, scope: &Scope, pos: Pos) -> Result<bool, EvalError> {
+    let all = |items: &[Expr], typ: &TypeDecl| -> Result<bool, EvalError> {
+        for item in items {
+            if !fits(item, typ, scope, pos)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    };
+    Ok(match (typ, &value.desc) {
+        (TypeDecl::Any, _)
+        | (TypeDecl::Int, ExprDesc::Int(_))
+        | (TypeDecl::Float | TypeDecl::Double, ExprDesc::Int(_) | ExprDesc::Float(_))
+        | (TypeDecl::Float | TypeDecl::Double, ExprDesc::Long(_) | ExprDesc::Double(_))
+        | (TypeDecl::Long, ExprDesc::Int(_) | ExprDesc::Long(_))
+        | (TypeDecl::Bool, ExprDesc::Bool(_))
+        | (TypeDecl::Char, ExprDesc::Char(_))
+        | (TypeDecl::String, ExprDesc::String(_))
+        | (TypeDecl::Unit, ExprDesc::Unit) => true,
+        (TypeDecl::Tuple(types), ExprDesc::Tuple(items)) => {
+            if types.len() != items.len() {
+                return Ok(false);
+            }
+            for (item, typ) in items.iter().zip(types) {
+                if !fits(item, typ, scope, pos)? {
+                    return Ok(false);
+                }
+            }
+            true
+        }
+        (TypeDecl::Vec(typ), ExprDesc::Array(items)) => all(items, typ)?,
+        (TypeDecl::Option(typ), ExprDesc::Option(inner)) => match inner.as_ref() {
+            Some(inner) => fits(inner, typ, scope, pos)?,
+            None => true,
+        },
+        (TypeDecl::Named(name), desc) => match scope.lookup_decl(name) {
+            // The value was already checked when it was built, so the name is enough
+            Some(decl) => match (decl.as_ref(), desc) {
+                (Decl::Struct(name, _), ExprDesc::Struct(found, _)) => name == found,
+                (Decl::Enum(_, _), ExprDesc::Struct(variant, _))
+                | (Decl::Enum(_, _), ExprDesc::NamedTuple(variant, _)) => decl.variant(variant).is_some(),
+                _ => false,
+            },
+            None => return Err(EvalErrorDesc::UnknownType(name.clone()).with_pos(pos)),
+        },
+        _ => false,
+    })
+}
+
+/// Turns the numbers in a value that fits `typ` into the number types `typ` has, so that the
+/// field of a struct always has the type it's declared with
+pub(crate) fn promote(value: &mut Expr, typ: &TypeDecl) {
+    match (typ, &mut value.desc) {
+        (TypeDecl::Float, ExprDesc::Int(i)) => value.desc = ExprDesc::Float(*i as f32),
+        (TypeDecl::Float, ExprDesc::Long(i)) => value.desc = ExprDesc::Float(*i as f32),
+        (TypeDecl::Float, ExprDesc::Double(f)) => value.desc = ExprDesc::Float(*f as f32),
+        (TypeDecl::Double, ExprDesc::Int(i)) => value.desc = ExprDesc::Double(*i as f64),
+        (TypeDecl::Double, ExprDesc::Long(i)) => value.desc = ExprDesc::Double(*i as f64),
+        (TypeDecl::Double, ExprDesc::Float(f)) => value.desc = ExprDesc::Double(widen(*f)),
+        (TypeDecl::Long, ExprDesc::Int(i)) => value.desc = ExprDesc::Long(*i as i128),
+        (TypeDecl::Tuple(types), ExprDesc::Tuple(items)) => {
+            for (item, typ) in items.iter_mut().zip(types) {
+                promote(item, typ);
+            }
+        }
+        (TypeDecl::Vec(typ), ExprDesc::Array(items)) => items.iter_mut().for_each(|item| promote(item, typ)),
+        (TypeDecl::Option(typ), ExprDesc::Option(inner)) => {
+            if let Some(inner) = inner.as_mut() {
+                promote(inner, typ);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// The closest field name, if it's close enough to be a typo
+pub(crate) fn suggest(name: &str, fields: &[FieldDecl]) -> Option<String> {
+    fields
+        .iter()
+        .map(|field| (distance(name, &field.name), &field.name))
+        .filter(|(distance, field)| *distance <= field.len() / 3 + 1)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field.clone())
+}
+
+/// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(current)
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+fn field_type(type_name: &str, field: &str, typ: &TypeDecl, value: &Expr) -> EvalErrorDesc {
+    EvalErrorDesc::FieldType(Box::new(FieldType {
+        typ: type_name.to_owned(),
+        field: field.to_owned(),
+        expected: typ.to_string(),
+        found: value.shape(),
+    }))
+}
+
+fn check_fields(
+    type_name: &str,
+    fields: &[FieldDecl],
+    items: &mut Vec<(String, Expr)>,
+    scope: &mut Scope,
+    pos: Pos,
+) -> Result<(), EvalError> {
+    for (name, _) in items.iter() {
+        if !fields.iter().any(|field| &field.name == name) {
+            let err = UnknownField {
+                typ: type_name.to_owned(),
+                field: name.clone(),
+                suggestion: suggest(name, fields),
+            };
+            return Err(EvalErrorDesc::UnknownField(Box::new(err)).with_pos(pos));
+        }
+    }
+    // Fields end up in declaration order, with defaults filled in
+    let mut given = std::mem::take(items);
+    for field in fields {
+        let mut value = match given.iter().position(|(name, _)| name == &field.name) {
+            Some(index) => given.swap_remove(index).1,
+            None => match &field.default {
+                Some(default) => {
+                    let mut value = default.clone();
+                    value.eval(scope)?;
+                    value
+                }
+                None => {
+                    return Err(EvalErrorDesc::MissingField(type_name.to_owned(), field.name.clone())
+                        .with_pos(pos))
+                }
+            },
+        };
+        if !fits(&value, &field.typ, scope, pos)? {
+            return Err(field_type(type_name, &field.name, &field.typ, &value).with_pos(value.pos.or(pos)));
+        }
+        promote(&mut value, &field.typ);
+        items.push((field.name.clone(), value));
+    }
+    Ok(())
+}
+
+/// Checks a struct or enum variant that was just built against its declaration, if it has one
+pub(crate) fn check(value: &mut Expr, scope: &mut Scope) -> Result<(), EvalError> {
+    let pos = value.pos;
+    match &mut value.desc {
+        ExprDesc::Struct(name, items) => {
+            let decl = match scope.lookup_decl(name) {
+                Some(decl) => decl,
+                None => match scope.lookup_variant(name) {
+                    Some(decl) => decl,
+                    None => return Ok(()),
+                },
+            };
+            match decl.as_ref() {
+                Decl::Struct(_, fields) => check_fields(name, &fields[..], items, scope, pos),
+                Decl::Enum(_, _) => match decl.variant(name).map(|variant| &variant.fields) {
+                    Some(VariantFields::Struct(fields)) => check_fields(name, &fields[..], items, scope, pos),
+                    _ => Err(EvalErrorDesc::FieldCount(name.clone(), 0, items.len()).with_pos(pos)),
+                },
+            }
+        }
+        ExprDesc::NamedTuple(name, items) => {
+            let decl = match scope.lookup_variant(name) {
+                Some(decl) => decl,
+                None => return Ok(()),
+            };
+            let types: &[TypeDecl] = match decl.variant(name).map(|variant| &variant.fields) {
+                Some(VariantFields::Tuple(types)) => &types[..],
+                _ => &[],
+            };
+            if types.len() != items.len() {
+                return Err(EvalErrorDesc::FieldCount(name.clone(), types.len(), items.len()).with_pos(pos));
+            }
+            for (i, (item, typ)) in items.iter_mut().zip(types).enumerate() {
+                if !fits(item, typ, scope, pos)? {
+                    return Err(field_type(name, &i.to_string(), typ, item).with_pos(item.pos.or(pos)));
+                }
+                promote(item, typ);
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
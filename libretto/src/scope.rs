@@ -1,39 +1,155 @@
-use crate::ast::{Args, Expr, ExprDesc, Pos};
-use crate::error::{EvalError, EvalErrorDesc};
-use std::collections::HashMap;
+use crate::ast::{Args, Expr, ExprDesc, Pos, Statement};
+use crate::compile::{CallSite, Function};
+use crate::diagnostic::{syntax_error, Diagnostic};
+use crate::error::{Error, EvalError, EvalErrorDesc, TraceFrame};
+use crate::limits::{Limits, Usage};
+use crate::native::{IntoNativeFn, NativeFn};
+use crate::parser::{process_data, process_expr, process_file_recovering, process_input};
+use crate::schema::{promote, Decl, Signature, TypeDecl};
+use crate::strings::{display, format};
+use crate::typecheck::{check_statements, type_of};
+use crate::typed_fn::{FnArgs, TypedFn};
+use crate::vm::Frame;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
+/// Calls a script function once, converting the arguments with `to_expr` and the result with
+/// `from_expr`. It's the same as looking the function up with `Scope::get_function` and calling
+/// that, which is better for functions that are called more than once.
 #[macro_export]
+#[deprecated(note = "look the function up once with `Scope::get_function` and call that")]
 macro_rules! call_fn {
   ($scope: expr, $name: expr, $($arg: expr),*) => {
-    {
-      let args = vec![$( libretto::to_expr(&$arg).unwrap() ),*];
-      match $scope.call_fn_raw($name, args, libretto::Pos::default()) {
-        Err(e) => Err(e.into()),
-        Ok(result) => libretto::from_expr(&result)
-      }
-    }
+    $crate::call_once(&mut $scope, $name, ($( $arg, )*))
   };
 }
 
-#[derive(Debug, PartialEq)]
+/// Every change to what a name could call gets a new version, unique across all scopes, so
+/// that call sites know when to look their function up again
+static VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct SingleScope {
     id: usize,
     vbls: HashMap<String, Expr>,
-    fns: HashMap<String, (Args, Expr)>,
+    mutable: HashSet<String>,
+    fns: HashMap<String, Arc<Function>>,
+    natives: HashMap<String, NativeFn>,
+    /// Loaded with `mod name;`, and reached with `name::item`
+    modules: HashMap<String, Arc<SingleScope>>,
+    /// Declared structs and enums, by name
+    decls: HashMap<String, Arc<Decl>>,
 }
 
+/// One of the scopes on the stack. A module's scope is shared with the scope that loaded it
+/// while one of its functions runs, and only copied if something changes it.
+// a block's own scope isn't boxed, which would be an allocation for every block
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq)]
-pub struct Scope(Vec<SingleScope>);
+enum Layer {
+    Own(SingleScope),
+    Module(Arc<SingleScope>),
+}
+
+impl std::ops::Deref for Layer {
+    type Target = SingleScope;
+
+    fn deref(&self) -> &SingleScope {
+        match self {
+            Layer::Own(scope) => scope,
+            Layer::Module(scope) => scope,
+        }
+    }
+}
+
+impl std::ops::DerefMut for Layer {
+    fn deref_mut(&mut self) -> &mut SingleScope {
+        match self {
+            Layer::Own(scope) => scope,
+            Layer::Module(scope) => Arc::make_mut(scope),
+        }
+    }
+}
+
+/// The files read by `use` and `mod`, shared between a scope and the modules it loads
+#[derive(Debug, PartialEq, Default)]
+struct Loader {
+    /// A file's id is its index + 1, so that 0 can mean source that didn't come from a file
+    files: Vec<PathBuf>,
+    /// The text of each file when it was last loaded, for showing errors
+    sources: Vec<String>,
+    /// The files currently being evaluated, innermost last
+    loading: Vec<usize>,
+}
+
+impl Loader {
+    fn file_id(&mut self, path: &Path) -> usize {
+        match self.files.iter().position(|file| file == path) {
+            Some(idx) => idx + 1,
+            None => {
+                self.files.push(path.to_owned());
+                self.sources.push(String::new());
+                self.files.len()
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) enum Callable {
+    /// A variable holding a closure
+    Closure(Arc<Function>, Arc<[Expr]>),
+    Compiled(Arc<Function>),
+    Native(NativeFn),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Scope {
+    scopes: Vec<Layer>,
+    /// Bytecode functions waiting on a call, whose locals are still visible by name
+    pub(crate) frames: Vec<Frame>,
+    bytecode: bool,
+    loader: Loader,
+    pub(crate) usage: Usage,
+    /// The functions being called, innermost last, for the trace on errors
+    calls: Vec<(Arc<str>, Pos)>,
+    /// Changes when functions are defined or variables are bound; see `CallSite`
+    version: u64,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Scope {
     pub fn new() -> Self {
-        Scope(vec![SingleScope::globals()])
+        Scope {
+            scopes: vec![Layer::Own(SingleScope::globals())],
+            frames: vec![],
+            bytecode: true,
+            loader: Loader::default(),
+            usage: Usage::default(),
+            calls: vec![],
+            version: next_version(),
+        }
     }
     pub fn push(&mut self) {
-        self.0.insert(0, SingleScope::empty());
+        self.scopes.insert(0, Layer::Own(SingleScope::empty()));
     }
     pub fn pop(&mut self) {
-        self.0.remove(0);
+        let scope = self.scopes.remove(0);
+        if !scope.fns.is_empty() || !scope.natives.is_empty() {
+            self.version = next_version();
+        }
     }
 
     pub fn call_fn_raw(
@@ -42,87 +158,584 @@ impl Scope {
         args: Vec<Expr>,
         pos: Pos,
     ) -> Result<Expr, EvalError> {
-        let mut scopesi = self.0.iter();
-        let (fargs, mut body) = loop {
-            if let Some(scope) = scopesi.next() {
-                if let Some(f) = scope.fns.get(name) {
-                    if f.0.len() != args.len() {
-                        return Err(
-                            EvalErrorDesc::FunctionWrongNumberArgs(f.0.len(), args.len())
-                                .with_pos(pos),
-                        );
-                    }
-                    break f.clone();
+        if let Some((module, rest)) = name.split_once("::") {
+            return self.call_in_module(module, rest, args, pos);
+        }
+        match self.lookup_fn(name) {
+            Some(callable) => self.traced(name.into(), pos, |scope| scope.call_callable(callable, args, pos)),
+            None => self.call_builtin(name, args, pos),
+        }
+    }
+
+    /// Calls the function a compiled call names. It's only looked up by name if the scope
+    /// has changed since the last time the call ran.
+    pub(crate) fn call_site(
+        &mut self,
+        site: &CallSite,
+        args: Vec<Expr>,
+        pos: Pos,
+    ) -> Result<Expr, EvalError> {
+        let callable = match site.cached(self.version) {
+            Some(callable) => callable,
+            None => match self.lookup_unhidden_fn(&site.name) {
+                Some(callable) => {
+                    site.remember(self.version, &callable);
+                    callable
                 }
-            } else {
-                if name == "log" {
-                    let args = args
-                        .into_iter()
-                        .map(|m| match m.desc {
-                            ExprDesc::String(s) => s,
-                            _ => format!("{:?}", m),
-                        })
-                        .collect::<Vec<String>>()
-                        .concat();
-                    println!("{} at {}:{}", args, pos.start.0, pos.start.1);
-                    return Ok(ExprDesc::Unit.into());
+                None => return self.call_fn_raw(&site.name, args, pos),
+            },
+        };
+        self.traced(site.name.clone(), pos, |scope| scope.call_callable(callable, args, pos))
+    }
+
+    /// Calls a function found earlier by `get_function`, along with the module it's in
+    pub(crate) fn call_resolved(
+        &mut self,
+        name: &Arc<str>,
+        callable: &Callable,
+        module: Option<&Arc<SingleScope>>,
+        args: Vec<Expr>,
+        pos: Pos,
+    ) -> Result<Expr, EvalError> {
+        match module {
+            Some(module) => self.call_with_module(module.clone(), name, callable.clone(), args, pos),
+            None => self.traced(name.clone(), pos, |scope| scope.call_callable(callable.clone(), args, pos)),
+        }
+    }
+
+    /// Runs `call` with `name` on the call stack, so errors get a trace
+    fn traced(
+        &mut self,
+        name: Arc<str>,
+        pos: Pos,
+        call: impl FnOnce(&mut Self) -> Result<Expr, EvalError>,
+    ) -> Result<Expr, EvalError> {
+        self.calls.push((name, pos));
+        let mut result = call(self);
+        if let Err(err) = &mut result {
+            // the innermost call fills in the trace, so callers further out keep it
+            if err.trace.is_empty() {
+                err.trace = self
+                    .calls
+                    .iter()
+                    .rev()
+                    .map(|(name, pos)| TraceFrame {
+                        name: name.to_string(),
+                        pos: *pos,
+                    })
+                    .collect();
+            }
+        }
+        self.calls.pop();
+        result
+    }
+
+    /// Calls `log` or `format!`, which aren't defined anywhere. Any other name that isn't
+    /// found is an error, which is reported where it's called rather than from inside it.
+    fn call_builtin(
+        &mut self,
+        name: &str,
+        args: Vec<Expr>,
+        pos: Pos,
+    ) -> Result<Expr, EvalError> {
+        match name {
+            "log" => self.traced(name.into(), pos, |_| {
+                let args = args.iter().map(display).collect::<Vec<String>>().concat();
+                println!("{} at {}:{}", args, pos.start.0, pos.start.1);
+                Ok(ExprDesc::Unit.into())
+            }),
+            "format!" => self.traced(name.into(), pos, |scope| match args.split_first() {
+                Some((Expr { desc: ExprDesc::String(template), .. }, args)) => {
+                    let text = format(template, args).map_err(|desc| desc.with_pos(pos))?;
+                    scope.usage.check_alloc(text.len(), pos)?;
+                    Ok(ExprDesc::String(text).with_pos(pos))
                 }
-                return Err(EvalErrorDesc::MissingReference(name.to_owned()).with_pos(pos));
+                _ => Err(EvalErrorDesc::InvalidType("format! needs a string").with_pos(pos)),
+            }),
+            _ => Err(match self.get_raw(name) {
+                Some(Expr {
+                    desc: ExprDesc::Moved,
+                    ..
+                }) => EvalErrorDesc::MemberMovedValue,
+                Some(value) => EvalErrorDesc::NotCallable(value.desc.kind()),
+                None => EvalErrorDesc::MissingReference(name.to_owned()),
+            }
+            .with_pos(pos)),
+        }
+    }
+
+    fn call_callable(&mut self, callable: Callable, mut args: Vec<Expr>, pos: Pos) -> Result<Expr, EvalError> {
+        if let Callable::Compiled(f) = &callable {
+            // an `i32` passed as an `i64` argument becomes one, like it does in a struct field
+            for (arg, typ) in args.iter_mut().zip(&f.signature.args) {
+                promote(arg, typ);
+            }
+        }
+        match callable {
+            Callable::Closure(f, captured) => self.run_closure(&f, &captured, args, pos),
+            Callable::Compiled(f) if self.bytecode => crate::vm::run(&f, args, &[], self, pos),
+            Callable::Compiled(f) => self.call_body(&f.args, f.body.clone(), args, pos),
+            Callable::Native(native) => native.call(args, pos),
+        }
+    }
+
+    /// Calls `name` with the module's contents in scope, so it can use the module's other items
+    fn call_in_module(
+        &mut self,
+        module: &str,
+        name: &str,
+        args: Vec<Expr>,
+        pos: Pos,
+    ) -> Result<Expr, EvalError> {
+        let path: Arc<str> = format!("{}::{}", module, name).into();
+        if let Some(inner) = self.module(module) {
+            if let Some(callable) = inner.lookup_fn(name) {
+                return self.call_with_module(inner, &path, callable, args, pos);
+            }
+        }
+        Err(EvalErrorDesc::MissingReference(path.to_string()).with_pos(pos))
+    }
+
+    /// Calls a function from `module` with the module on top of the stack, where the
+    /// function's body can find the module's other items
+    fn call_with_module(
+        &mut self,
+        module: Arc<SingleScope>,
+        name: &Arc<str>,
+        callable: Callable,
+        args: Vec<Expr>,
+        pos: Pos,
+    ) -> Result<Expr, EvalError> {
+        let outer = self.scopes.len();
+        self.scopes.insert(0, Layer::Module(module));
+        self.version = next_version();
+        let result = self.traced(name.clone(), pos, |scope| scope.call_callable(callable, args, pos));
+        self.scopes.remove(self.scopes.len() - outer - 1);
+        self.version = next_version();
+        result
+    }
+
+    /// The innermost module loaded as `name`
+    fn module(&self, name: &str) -> Option<Arc<SingleScope>> {
+        self.scopes.iter().find_map(|scope| scope.modules.get(name)).cloned()
+    }
+
+    /// Call a function value (the result of evaluating a lambda)
+    pub fn call_closure(
+        &mut self,
+        closure: &Expr,
+        args: Vec<Expr>,
+        pos: Pos,
+    ) -> Result<Expr, EvalError> {
+        match &closure.desc {
+            ExprDesc::Closure(f, captured) => self.run_closure(f, captured, args, pos),
+            ExprDesc::Moved => Err(EvalErrorDesc::MemberMovedValue.with_pos(pos)),
+            other => Err(EvalErrorDesc::NotCallable(other.kind()).with_pos(pos)),
+        }
+    }
+
+    fn run_closure(
+        &mut self,
+        f: &Arc<Function>,
+        captured: &[Expr],
+        args: Vec<Expr>,
+        pos: Pos,
+    ) -> Result<Expr, EvalError> {
+        if self.bytecode {
+            return crate::vm::run(f, args, captured, self, pos);
+        }
+        if f.args.len() != args.len() {
+            return Err(
+                EvalErrorDesc::FunctionWrongNumberArgs(f.args.len(), args.len()).with_pos(pos),
+            );
+        }
+        let names: Vec<String> = f.args.iter().chain(f.captures.iter()).cloned().collect();
+        let values = args.into_iter().chain(captured.iter().cloned()).collect();
+        self.call_body(&names, f.body.clone(), values, pos)
+    }
+
+    /// Finds a named function, a native function, or a variable holding a closure
+    fn lookup_fn(&self, name: &str) -> Option<Callable> {
+        self.scopes.iter().find_map(|scope| scope.lookup_fn(name))
+    }
+
+    /// Finds a named or native function that no variable in a closer scope hides. What it
+    /// finds can only change when functions are defined or variables are bound.
+    fn lookup_unhidden_fn(&self, name: &str) -> Option<Callable> {
+        for scope in self.scopes.iter() {
+            if let Some(f) = scope.fns.get(name) {
+                return Some(Callable::Compiled(f.clone()));
+            }
+            if let Some(native) = scope.natives.get(name) {
+                return Some(Callable::Native(native.clone()));
+            }
+            if scope.vbls.contains_key(name) {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Looks up a function once, to call it from rust as many times as needed. Fails if
+    /// there's no function called `name`, or if it doesn't take as many arguments as `Args`.
+    ///
+    /// ```
+    /// let mut scope = libretto::eval_file("fn add(a: i32, b: i32) -> i32 { a + b }").unwrap();
+    /// let add = scope.get_function::<(i32, i32), i32>("add").unwrap();
+    /// assert_eq!(add.call(&mut scope, (1, 2)), Ok(3));
+    /// ```
+    pub fn get_function<Args: FnArgs, R: DeserializeOwned>(
+        &self,
+        name: &str,
+    ) -> Result<TypedFn<Args, R>, Error> {
+        let missing = || EvalErrorDesc::MissingReference(name.to_owned()).with_pos(Pos::default());
+        // a function in a module keeps the module it was found in, for its other items
+        let (module, callable) = match name.split_once("::") {
+            Some((module, rest)) => {
+                let module = self.module(module).ok_or_else(missing)?;
+                let callable = module.lookup_fn(rest);
+                (Some(module), callable)
             }
+            None => (None, self.lookup_fn(name)),
         };
+        let callable = callable.ok_or_else(missing)?;
+        let arity = match &callable {
+            Callable::Closure(f, _) => f.args.len(),
+            Callable::Compiled(f) => f.args.len(),
+            Callable::Native(native) => native.arity,
+        };
+        if arity != Args::ARITY {
+            return Err(EvalErrorDesc::FunctionWrongNumberArgs(arity, Args::ARITY)
+                .with_pos(Pos::default())
+                .into());
+        }
+        Ok(TypedFn::new(name, callable, module))
+    }
+
+    /// Whether there's a named function (rather than a closure variable) to call
+    pub(crate) fn has_fn(&self, name: &str) -> bool {
+        name == "log" || name == "format!" || self.scopes.iter().any(|scope| scope.has_fn(name))
+    }
+
+    fn call_body(
+        &mut self,
+        fargs: &[String],
+        mut body: Expr,
+        args: Vec<Expr>,
+        pos: Pos,
+    ) -> Result<Expr, EvalError> {
+        if fargs.len() != args.len() {
+            return Err(
+                EvalErrorDesc::FunctionWrongNumberArgs(fargs.len(), args.len()).with_pos(pos),
+            );
+        }
+        self.usage.enter(pos)?;
         self.push();
         // let mut sub = self.sub();
         for (aname, aval) in fargs.iter().zip(args) {
             self.set_raw(aname, aval);
         }
-        body.eval(self)?;
+        let result = body.eval(self);
         self.pop();
-        return Ok(body);
+        self.usage.exit();
+        match result {
+            Err(EvalError {
+                desc: EvalErrorDesc::Break,
+                pos,
+                ..
+            })
+            | Err(EvalError {
+                desc: EvalErrorDesc::Continue,
+                pos,
+                ..
+            }) => Err(EvalErrorDesc::BreakOutsideLoop.with_pos(pos)),
+            Err(err) => Err(err),
+            Ok(()) => Ok(body),
+        }
     }
 
-    pub fn get_fn(&self, key: &str) -> Option<&(Args, Expr)> {
-        self.0[0].fns.get(key)
+    pub fn get_fn(&self, key: &str) -> Option<&Function> {
+        self.scopes[0].fns.get(key).map(|f| f.as_ref())
+    }
+
+    /// The script functions that can be called from here, sorted by name
+    pub fn functions(&self) -> Vec<(&str, &Function)> {
+        let mut found: Vec<(&str, &Function)> = vec![];
+        for scope in self.scopes.iter() {
+            for (name, f) in scope.fns.iter() {
+                if !found.iter().any(|(other, _)| other == name) {
+                    found.push((name, f));
+                }
+            }
+        }
+        found.sort_by_key(|(name, _)| *name);
+        found
+    }
+
+    pub(crate) fn lookup_signature(&self, name: &str) -> Option<&Signature> {
+        self.scopes
+            .iter()
+            .find_map(|scope| scope.fns.get(name))
+            .map(|f| &f.signature)
     }
 
     pub fn set_fn(&mut self, key: &str, args: Args, body: Expr) {
-        self.0[0].fns.insert(key.to_owned(), (args, body));
+        self.set_compiled_fn(key, Arc::new(Function::compile(args, body)));
     }
 
-    pub fn show(&self) -> String {
-        format!("{:?}", self)
+    pub(crate) fn set_compiled_fn(&mut self, key: &str, f: Arc<Function>) {
+        self.version = next_version();
+        self.scopes[0].fns.insert(key.to_owned(), f);
+    }
+
+    pub(crate) fn declare(&mut self, decl: Decl) {
+        self.scopes[0].decls.insert(decl.name().to_owned(), Arc::new(decl));
+    }
+
+    /// The declaration of a struct or enum
+    pub fn lookup_decl(&self, name: &str) -> Option<Arc<Decl>> {
+        self.scopes.iter().find_map(|scope| scope.decls.get(name).cloned())
+    }
+
+    /// The declaration of the enum that has a variant called `name`
+    pub(crate) fn lookup_variant(&self, name: &str) -> Option<Arc<Decl>> {
+        self.scopes
+            .iter()
+            .flat_map(|scope| scope.decls.values())
+            .find(|decl| decl.variant(name).is_some())
+            .cloned()
+    }
+
+    /// Bounds the work each call can do; see `Limits`
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.usage = Usage::new(limits);
     }
 
-    pub fn move_raw(&mut self, key: &str) -> Option<Expr> {
-        for scope in self.0.iter_mut() {
-            if let Some(x) = scope.vbls.remove(key) {
-                let replacement = match x.desc {
-                    ExprDesc::Float(_)
-                    | ExprDesc::Int(_)
-                    | ExprDesc::Bool(_)
-                    | ExprDesc::String(_)
-                    | ExprDesc::Char(_)
-                    | ExprDesc::Unit => x.clone(),
-                    _ => ExprDesc::Moved.match_pos(&x),
-                };
-                scope.vbls.insert(key.to_owned(), replacement);
-                return Some(x);
+    pub fn limits(&self) -> Limits {
+        self.usage.limits
+    }
+
+    /// Run named functions as bytecode (the default), or with the tree-walking interpreter
+    pub fn set_bytecode(&mut self, enabled: bool) {
+        self.bytecode = enabled;
+    }
+
+    /// Make a rust function callable from scripts. Arguments are converted with `from_expr`
+    /// and the return value with `to_expr`.
+    ///
+    /// ```
+    /// let mut scope = libretto::Scope::new();
+    /// scope.register_fn("double", |x: f32| x * 2.0);
+    /// ```
+    pub fn register_fn<Args, F: IntoNativeFn<Args>>(&mut self, key: &str, f: F) {
+        self.version = next_version();
+        self.scopes[0].natives.insert(key.to_owned(), f.into_native());
+    }
+
+    /// The file that positions with this `file` id came from, if it was loaded from one
+    pub fn file_path(&self, file: usize) -> Option<&Path> {
+        file.checked_sub(1)
+            .and_then(|idx| self.loader.files.get(idx))
+            .map(|path| path.as_path())
+    }
+
+    /// Describes an error, with a snippet of the file it happened in if it came from one
+    pub fn render_error(&self, err: &Error) -> String {
+        if let Error::EvalError(EvalError {
+            desc: EvalErrorDesc::SyntaxErrors(errors) | EvalErrorDesc::TypeErrors(errors),
+            ..
+        }) = err
+        {
+            return errors
+                .iter()
+                .map(|err| self.render_error(&err.clone().into()))
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+        let diagnostic = Diagnostic::new(err);
+        match self.file_path(diagnostic.pos.file) {
+            Some(path) => diagnostic.render(
+                &path.display().to_string(),
+                &self.loader.sources[diagnostic.pos.file - 1],
+            ),
+            None => diagnostic.to_string(),
+        }
+    }
+
+    /// Evaluate a script file into this scope. Its `use` and `mod` statements are
+    /// resolved relative to the file.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> crate::error::Result<()> {
+        Ok(self.load(path.as_ref(), Pos::default())?)
+    }
+
+    pub(crate) fn use_file(&mut self, path: &str, pos: Pos) -> Result<(), EvalError> {
+        let path = self.resolve_path(path);
+        self.load(&path, pos)
+    }
+
+    pub(crate) fn load_module(&mut self, name: &str, pos: Pos) -> Result<(), EvalError> {
+        let path = self.resolve_path(&format!("{}.lt.rs", name));
+        let mut module = Scope::new();
+        module.bytecode = self.bytecode;
+        module.usage = Usage::new(self.usage.limits);
+        module.loader = std::mem::take(&mut self.loader);
+        let result = module.load(&path, pos);
+        self.loader = std::mem::take(&mut module.loader);
+        result?;
+        let globals = match module.scopes.pop().unwrap() {
+            Layer::Own(scope) => Arc::new(scope),
+            Layer::Module(scope) => scope,
+        };
+        self.scopes[0].modules.insert(name.to_owned(), globals);
+        Ok(())
+    }
+
+    /// Paths are relative to the file being loaded, or the working directory
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        match self.loader.loading.last() {
+            Some(&file) => self.loader.files[file - 1].with_file_name(path),
+            None => PathBuf::from(path),
+        }
+    }
+
+    /// Reads a data file, like `from_data_str`, with its consts and functions going into this
+    /// scope. The file is kept track of like a script, so its errors can be rendered.
+    pub fn load_data<T: DeserializeOwned, P: AsRef<Path>>(&mut self, path: P) -> crate::error::Result<T> {
+        let path = path.as_ref();
+        let not_found = |err: std::io::Error| {
+            EvalErrorDesc::ModuleNotFound(path.display().to_string(), err.to_string()).with_pos(Pos::default())
+        };
+        let path = path.canonicalize().map_err(not_found)?;
+        let file = self.loader.file_id(&path);
+        let source = std::fs::read_to_string(&path).map_err(not_found)?;
+        let parsed = process_data(&source);
+        self.loader.sources[file - 1] = source;
+        let (stmts, value) = parsed.map_err(|err| syntax_error(&err, file))?;
+        let mut value = Statement::ExprDesc(value);
+        value.set_file(file);
+        self.loader.loading.push(file);
+        let result = self.eval_statements(stmts, file).and_then(|()| match value {
+            Statement::ExprDesc(value) => value.into_eval(self),
+            _ => unreachable!(),
+        });
+        self.loader.loading.pop();
+        Ok(crate::from_expr(&result?)?)
+    }
+
+    fn load(&mut self, path: &Path, pos: Pos) -> Result<(), EvalError> {
+        let not_found = |err: std::io::Error| {
+            EvalErrorDesc::ModuleNotFound(path.display().to_string(), err.to_string())
+                .with_pos(pos)
+        };
+        let path = path.canonicalize().map_err(not_found)?;
+        let file = self.loader.file_id(&path);
+        if let Some(idx) = self.loader.loading.iter().position(|&f| f == file) {
+            let cycle = self.loader.loading[idx..]
+                .iter()
+                .chain(std::iter::once(&file))
+                .map(|&f| self.loader.files[f - 1].display().to_string())
+                .collect();
+            return Err(EvalErrorDesc::ImportCycle(cycle).with_pos(pos));
+        }
+        let source = std::fs::read_to_string(&path).map_err(not_found)?;
+        let (stmts, errors) = process_file_recovering(&source);
+        self.loader.sources[file - 1] = source;
+        let mut errors: Vec<EvalError> = errors.iter().map(|err| syntax_error(err, file)).collect();
+        match errors.len() {
+            0 => (),
+            1 => return Err(errors.remove(0)),
+            _ => {
+                let pos = errors[0].pos;
+                return Err(EvalErrorDesc::SyntaxErrors(errors).with_pos(pos));
             }
         }
-        None
+        self.loader.loading.push(file);
+        let result = self.eval_statements(stmts, file);
+        self.loader.loading.pop();
+        result
     }
 
-    pub fn get_raw_mut(&mut self, key: &str) -> Option<&mut Expr> {
-        for scope in self.0.iter_mut() {
-            if let Some(x) = scope.vbls.get_mut(key) {
-                return Some(x);
+    pub(crate) fn eval_statements(
+        &mut self,
+        mut stmts: Vec<Statement>,
+        file: usize,
+    ) -> Result<(), EvalError> {
+        for stmt in stmts.iter_mut() {
+            stmt.set_file(file);
+        }
+        check_statements(&stmts, self)?;
+        self.usage.refuel();
+        for stmt in stmts {
+            stmt.eval(self)?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates a line typed into a REPL. Its statements stay in this scope, and the value
+    /// at the end, if there is one, is returned.
+    pub fn eval_input(&mut self, input: &str) -> crate::error::Result<Option<Expr>> {
+        let (stmts, value) = process_input(input).map_err(|err| syntax_error(&err, 0))?;
+        self.eval_statements(stmts, 0)?;
+        match value {
+            Some(mut value) => {
+                type_of(&value, self)?;
+                self.usage.refuel();
+                // looking at a variable leaves it there, like member paths already do
+                if let ExprDesc::Ident(_) = value.desc {
+                    let pos = value.pos;
+                    value = ExprDesc::Ref(Box::new(value)).with_pos(pos);
+                }
+                value.eval(self)?;
+                Ok(Some(value))
             }
+            None => Ok(None),
         }
-        None
+    }
+
+    /// The type an expression would have if it was evaluated here
+    pub fn type_of(&self, input: &str) -> crate::error::Result<TypeDecl> {
+        let value = process_expr(input).map_err(|err| syntax_error(&err, 0))?;
+        Ok(type_of(&value, self)?)
+    }
+
+    pub fn show(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// Moves a variable's value out, for using it by value
+    pub fn move_raw(&mut self, key: &str, pos: Pos) -> Result<Expr, EvalError> {
+        match self.get_raw_mut(key) {
+            Some(value) => move_value(value, pos),
+            None => Err(EvalErrorDesc::MissingReference(key.to_owned()).with_pos(pos)),
+        }
+    }
+
+    /// Like `get_raw_mut`, but also finds the locals of bytecode functions further up the stack
+    pub(crate) fn get_dynamic_mut(&mut self, key: &str) -> Option<&mut Expr> {
+        match self.frames.iter().rposition(|frame| frame.has(key)) {
+            Some(idx) => self.frames[idx].get_mut(key),
+            None => self.get_raw_mut(key),
+        }
+    }
+
+    pub(crate) fn has_local(&self, key: &str) -> bool {
+        self.scopes[0].vbls.contains_key(key)
+    }
+
+    pub(crate) fn take_local(&mut self, key: &str) -> Option<Expr> {
+        self.scopes[0].mutable.remove(key);
+        self.scopes[0].vbls.remove(key)
+    }
+
+    pub fn get_raw_mut(&mut self, key: &str) -> Option<&mut Expr> {
+        // found first, so that only the module scope that has it is copied
+        let scope = self.scopes.iter_mut().find(|scope| scope.get(key).is_some())?;
+        scope.get_mut(key)
     }
 
     pub fn get_raw(&self, key: &str) -> Option<&Expr> {
-        for scope in self.0.iter() {
-            match scope.vbls.get(key) {
+        for scope in self.scopes.iter() {
+            match scope.get(key) {
                 None => (),
                 Some(x) => return Some(x),
             }
@@ -134,14 +747,36 @@ impl Scope {
     where
         T: serde::Serialize,
     {
-        self.0[0]
+        self.version = next_version();
+        self.scopes[0]
             .vbls
             .insert(key.to_owned(), crate::ser::to_expr(&value)?);
         Ok(())
     }
 
     pub fn set_raw(&mut self, key: &str, value: Expr) {
-        self.0[0].vbls.insert(key.to_owned(), value);
+        self.version = next_version();
+        self.scopes[0].mutable.remove(key);
+        self.scopes[0].vbls.insert(key.to_owned(), value);
+    }
+
+    /// Bind a variable that can be assigned to later, like `let mut`
+    pub fn set_mutable_raw(&mut self, key: &str, value: Expr) {
+        self.version = next_version();
+        self.scopes[0].mutable.insert(key.to_owned());
+        self.scopes[0].vbls.insert(key.to_owned(), value);
+    }
+
+    /// Find a variable for assignment, which is only allowed if it was declared `mut`
+    pub fn get_assignable(&mut self, key: &str) -> Result<&mut Expr, EvalErrorDesc> {
+        let scope = match self.scopes.iter_mut().find(|scope| scope.vbls.contains_key(key)) {
+            Some(scope) => scope,
+            None => return Err(EvalErrorDesc::MissingReference(key.to_owned())),
+        };
+        if !scope.mutable.contains(key) {
+            return Err(EvalErrorDesc::AssignToImmutable(key.to_owned()));
+        }
+        Ok(scope.vbls.get_mut(key).unwrap())
     }
 }
 
@@ -150,7 +785,11 @@ impl SingleScope {
         SingleScope {
             id: 0,
             vbls: HashMap::new(),
+            mutable: HashSet::new(),
             fns: HashMap::new(),
+            natives: HashMap::new(),
+            modules: HashMap::new(),
+            decls: HashMap::new(),
         }
     }
     pub fn globals() -> Self {
@@ -172,4 +811,74 @@ impl SingleScope {
         );
         scope
     }
+
+    /// Finds a variable, or an item inside a module like `math::origin`
+    fn get(&self, key: &str) -> Option<&Expr> {
+        match key.split_once("::") {
+            Some((module, rest)) => self.modules.get(module)?.get(rest),
+            None => self.vbls.get(key),
+        }
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Expr> {
+        match key.split_once("::") {
+            Some((module, rest)) => Arc::make_mut(self.modules.get_mut(module)?).get_mut(rest),
+            None => self.vbls.get_mut(key),
+        }
+    }
+
+    fn has_fn(&self, key: &str) -> bool {
+        match key.split_once("::") {
+            Some((module, rest)) => self.modules.get(module).is_some_and(|m| m.has_fn(rest)),
+            None => self.fns.contains_key(key) || self.natives.contains_key(key),
+        }
+    }
+
+    fn lookup_fn(&self, key: &str) -> Option<Callable> {
+        if let Some((module, rest)) = key.split_once("::") {
+            return self.modules.get(module)?.lookup_fn(rest);
+        }
+        if let Some(f) = self.fns.get(key) {
+            return Some(Callable::Compiled(f.clone()));
+        }
+        if let Some(native) = self.natives.get(key) {
+            return Some(Callable::Native(native.clone()));
+        }
+        match self.vbls.get(key) {
+            Some(Expr {
+                desc: ExprDesc::Closure(f, captured),
+                ..
+            }) => Some(Callable::Closure(f.clone(), captured.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a value for `&x`. There are no references: `&x` is sugar for `x.clone()`, so the
+/// variable keeps its value and the copy can't change it.
+pub(crate) fn copy_value(value: &Expr, pos: Pos) -> Result<Expr, EvalError> {
+    match value.desc {
+        ExprDesc::Moved => Err(EvalErrorDesc::MemberMovedValue.with_pos(pos)),
+        _ => Ok(value.clone()),
+    }
+}
+
+/// Moves a value out of a variable, leaving `Moved` behind. Primitives are copied instead.
+/// A variable that was already moved out of is an error.
+pub(crate) fn move_value(value: &mut Expr, pos: Pos) -> Result<Expr, EvalError> {
+    Ok(match value.desc {
+        ExprDesc::Moved => return Err(EvalErrorDesc::MemberMovedValue.with_pos(pos)),
+        ExprDesc::Float(_)
+        | ExprDesc::Int(_)
+        | ExprDesc::Long(_)
+        | ExprDesc::Double(_)
+        | ExprDesc::Bool(_)
+        | ExprDesc::String(_)
+        | ExprDesc::Char(_)
+        | ExprDesc::Unit => value.clone(),
+        _ => {
+            let pos = value.pos;
+            std::mem::replace(value, ExprDesc::Moved.with_pos(pos))
+        }
+    })
 }
@@ -1,8 +1,10 @@
 use crate::ast::{Expr, ExprDesc};
+use crate::printer;
 
 use serde::{ser, Serialize};
+use std::io;
 
-use crate::error::DeserializeError as Error;
+use crate::error::{DeserializeError as Error, DeserializeErrorDesc as ErrorDesc};
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct Serializer;
@@ -14,6 +16,45 @@ where
     value.serialize(Serializer)
 }
 
+/// Writes a value as libretto source, all on one line, e.g. `Bone { sprite: "arm.png", offset: (0.0, 0.5) }`.
+/// `eval_expr` and `from_expr` read it back.
+pub fn to_string<T>(value: &T) -> Result<String>
+where
+    T: Serialize,
+{
+    Ok(printer::value_source(&to_expr(value)?, false))
+}
+
+/// Like `to_string`, but anything too long for a line has its items on their own lines
+pub fn to_string_pretty<T>(value: &T) -> Result<String>
+where
+    T: Serialize,
+{
+    Ok(printer::value_source(&to_expr(value)?, true))
+}
+
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
+where
+    W: io::Write,
+    T: Serialize,
+{
+    write(writer, to_string(value)?)
+}
+
+pub fn to_writer_pretty<W, T>(writer: W, value: &T) -> Result<()>
+where
+    W: io::Write,
+    T: Serialize,
+{
+    write(writer, to_string_pretty(value)?)
+}
+
+fn write<W: io::Write>(mut writer: W, text: String) -> Result<()> {
+    writer
+        .write_all(text.as_bytes())
+        .map_err(|err| ErrorDesc::Message(err.to_string()).into())
+}
+
 impl ser::Serializer for Serializer {
     type Ok = Expr;
     type Error = Error;
@@ -43,7 +84,16 @@ impl ser::Serializer for Serializer {
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
-        Ok(ExprDesc::Int(v as i32).into())
+        self.serialize_i128(i128::from(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok> {
+        // numbers that don't fit the script types are kept whole
+        if i32::MIN as i128 <= v && v <= i32::MAX as i128 {
+            Ok(ExprDesc::Int(v as i32).into())
+        } else {
+            Ok(ExprDesc::Long(v).into())
+        }
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
@@ -59,15 +109,26 @@ impl ser::Serializer for Serializer {
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
-        Ok(ExprDesc::Int(v as i32).into())
+        self.serialize_i128(i128::from(v))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok> {
+        if v > i128::MAX as u128 {
+            return Err(ErrorDesc::Message(format!("{} is too large, the largest number is an i128", v)).into());
+        }
+        self.serialize_i128(v as i128)
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
-        self.serialize_f64(f64::from(v))
+        Ok(ExprDesc::Float(v).into())
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
-        Ok(ExprDesc::Float(v as f32).into())
+        if f64::from(v as f32) == v || v.is_nan() {
+            Ok(ExprDesc::Float(v as f32).into())
+        } else {
+            Ok(ExprDesc::Double(v).into())
+        }
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok> {
@@ -78,8 +139,9 @@ impl ser::Serializer for Serializer {
         Ok(ExprDesc::String(v.to_owned()).into())
     }
 
-    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok> {
-        unimplemented!()
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
+        let bytes = v.iter().map(|byte| ExprDesc::Int(i32::from(*byte)).into()).collect();
+        Ok(ExprDesc::Array(bytes).into())
     }
 
     fn serialize_none(self) -> Result<Self::Ok> {
@@ -210,7 +272,7 @@ impl TupleTracker {
     }
 }
 
-impl<'a> ser::SerializeTuple for TupleTracker {
+impl ser::SerializeTuple for TupleTracker {
     type Ok = Expr;
     type Error = Error;
 
@@ -257,7 +319,7 @@ impl ser::SerializeTupleStruct for TupleStructTracker {
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for TupleStructTracker {
+impl ser::SerializeTupleVariant for TupleStructTracker {
     type Ok = Expr;
     type Error = Error;
 
@@ -295,10 +357,22 @@ impl ser::SerializeMap for MapTracker {
     where
         T: ?Sized + Serialize,
     {
-        match key.serialize(Serializer)?.desc {
-            ExprDesc::String(name) | ExprDesc::Ident(name) => self.key = Some(name),
-            _ => unimplemented!(),
-        }
+        // objects only have string keys, so other keys are written out like serde_json does
+        let key = key.serialize(Serializer)?;
+        self.key = Some(match key.desc {
+            ExprDesc::String(name) | ExprDesc::Ident(name) => name,
+            ExprDesc::NamedTuple(name, items) if items.is_empty() => name,
+            ExprDesc::Int(i) => i.to_string(),
+            ExprDesc::Long(i) => i.to_string(),
+            ExprDesc::Float(f) => f.to_string(),
+            ExprDesc::Double(f) => f.to_string(),
+            ExprDesc::Bool(b) => b.to_string(),
+            ExprDesc::Char(c) => c.to_string(),
+            other => {
+                let message = format!("A map key must be a string, number, char or bool, not {}", other.kind());
+                return Err(ErrorDesc::Message(message).into());
+            }
+        });
         Ok(())
     }
 
@@ -338,7 +412,7 @@ impl StructTracker {
     }
 }
 
-impl<'a> ser::SerializeStruct for StructTracker {
+impl ser::SerializeStruct for StructTracker {
     type Ok = Expr;
     type Error = Error;
 
@@ -356,7 +430,7 @@ impl<'a> ser::SerializeStruct for StructTracker {
     }
 }
 
-impl<'a> ser::SerializeStructVariant for StructTracker {
+impl ser::SerializeStructVariant for StructTracker {
     type Ok = Expr;
     type Error = Error;
 
@@ -382,11 +456,12 @@ fn test_struct() {
         seq: Vec<&'static str>,
     }
 
-    let _test = Test {
+    let test = Test {
         int: 1,
         seq: vec!["a", "b"],
     };
-    let _expected = r#"{"int":1,"seq":["a","b"]}"#;
+    let expected = r#"Test { int: 1, seq: vec!["a", "b"] }"#;
+    assert_eq!(to_string(&test).unwrap(), expected);
 }
 
 #[test]
@@ -399,19 +474,19 @@ fn test_enum() {
         Struct { a: u32 },
     }
 
-    // let u = E::Unit;
-    // let expected = r#""Unit""#;
-    // assert_eq!(to_string(&u).unwrap(), expected);
+    let u = E::Unit;
+    let expected = r#"Unit"#;
+    assert_eq!(to_string(&u).unwrap(), expected);
 
-    // let n = E::Newtype(1);
-    // let expected = r#"{"Newtype":1}"#;
-    // assert_eq!(to_string(&n).unwrap(), expected);
+    let n = E::Newtype(1);
+    let expected = r#"Newtype(1)"#;
+    assert_eq!(to_string(&n).unwrap(), expected);
 
-    // let t = E::Tuple(1, 2);
-    // let expected = r#"{"Tuple":[1,2]}"#;
-    // assert_eq!(to_string(&t).unwrap(), expected);
+    let t = E::Tuple(1, 2);
+    let expected = r#"Tuple(1, 2)"#;
+    assert_eq!(to_string(&t).unwrap(), expected);
 
-    // let s = E::Struct { a: 1 };
-    // let expected = r#"{"Struct":{"a":1}}"#;
-    // assert_eq!(to_string(&s).unwrap(), expected);
+    let s = E::Struct { a: 1 };
+    let expected = r#"Struct { a: 1 }"#;
+    assert_eq!(to_string(&s).unwrap(), expected);
 }
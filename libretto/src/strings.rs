@@ -0,0 +1,184 @@
+use crate::ast::{Expr, ExprDesc};
+use crate::error::EvalErrorDesc;
+use std::convert::TryFrom;
+
+type StringFn = fn(&str, &[Expr]) -> Result<ExprDesc, EvalErrorDesc>;
+
+/// Methods on strings: name, number of arguments, and the function
+const STRING_METHODS: &[(&str, usize, StringFn)] = &[
+    ("len", 0, |s, _| Ok(ExprDesc::Int(s.len() as i32))),
+    ("is_empty", 0, |s, _| Ok(ExprDesc::Bool(s.is_empty()))),
+    ("trim", 0, |s, _| Ok(ExprDesc::String(s.trim().to_owned()))),
+    ("to_upper", 0, |s, _| Ok(ExprDesc::String(s.to_uppercase()))),
+    ("to_lower", 0, |s, _| Ok(ExprDesc::String(s.to_lowercase()))),
+    ("to_uppercase", 0, |s, _| Ok(ExprDesc::String(s.to_uppercase()))),
+    ("to_lowercase", 0, |s, _| Ok(ExprDesc::String(s.to_lowercase()))),
+    ("chars", 0, |s, _| Ok(ExprDesc::Array(s.chars().map(|c| ExprDesc::Char(c).into()).collect()))),
+    ("parse_int", 0, |s, _| Ok(parsed(s.trim().parse().ok().map(ExprDesc::Int)))),
+    ("parse_float", 0, |s, _| Ok(parsed(s.trim().parse().ok().map(ExprDesc::Float)))),
+    ("contains", 1, |s, args| Ok(ExprDesc::Bool(s.contains(needle(&args[0])?.as_str())))),
+    ("starts_with", 1, |s, args| Ok(ExprDesc::Bool(s.starts_with(needle(&args[0])?.as_str())))),
+    ("ends_with", 1, |s, args| Ok(ExprDesc::Bool(s.ends_with(needle(&args[0])?.as_str())))),
+    ("split", 1, |s, args| {
+        let separator = needle(&args[0])?;
+        Ok(ExprDesc::Array(s.split(separator.as_str()).map(|part| ExprDesc::String(part.to_owned()).into()).collect()))
+    }),
+    ("repeat", 1, |s, args| match args[0].desc {
+        ExprDesc::Int(n) => match usize::try_from(n) {
+            Ok(n) => Ok(ExprDesc::String(s.repeat(n))),
+            Err(_) => Err(EvalErrorDesc::InvalidType("repeat() takes a non-negative count")),
+        },
+        _ => Err(EvalErrorDesc::InvalidType("repeat() takes an int")),
+    }),
+    ("replace", 2, |s, args| {
+        Ok(ExprDesc::String(s.replace(needle(&args[0])?.as_str(), &needle(&args[1])?)))
+    }),
+];
+
+fn parsed(value: Option<ExprDesc>) -> ExprDesc {
+    ExprDesc::Option(Box::new(value.map(Expr::from)))
+}
+
+/// A string or char argument, as a string
+fn needle(arg: &Expr) -> Result<String, EvalErrorDesc> {
+    match &arg.desc {
+        ExprDesc::String(s) => Ok(s.clone()),
+        ExprDesc::Char(c) => Ok(c.to_string()),
+        _ => Err(EvalErrorDesc::InvalidType("Expected a string or a char")),
+    }
+}
+
+pub(crate) fn string_method(s: &str, name: &str, args: &[Expr]) -> Result<ExprDesc, EvalErrorDesc> {
+    match STRING_METHODS.iter().find(|(method, _, _)| *method == name) {
+        Some((_, arity, f)) if *arity == args.len() => f(s, args),
+        Some((_, arity, _)) => Err(EvalErrorDesc::FunctionWrongNumberArgs(*arity, args.len())),
+        None => Err(EvalErrorDesc::UnknownFunction(name.to_owned())),
+    }
+}
+
+/// How a value is shown by `{}`: strings and chars without quotes, everything else like `{:?}`
+pub(crate) fn display(value: &Expr) -> String {
+    match &value.desc {
+        ExprDesc::String(s) => s.clone(),
+        ExprDesc::Char(c) => c.to_string(),
+        ExprDesc::Float(f) => f.to_string(),
+        _ => value.debug(),
+    }
+}
+
+/// One `{...}` in a `format!` string
+#[derive(Default)]
+struct Spec {
+    debug: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+enum Piece {
+    Text(String),
+    Arg(Spec),
+}
+
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let invalid = || format!("Invalid format spec `{{{}}}`", spec);
+    let mut result = Spec::default();
+    let spec = match spec.strip_prefix(':') {
+        Some(spec) => spec,
+        None if spec.is_empty() => return Ok(result),
+        // positional and named arguments aren't supported
+        None => return Err(invalid()),
+    };
+    let spec = match spec.strip_suffix('?') {
+        Some(spec) => {
+            result.debug = true;
+            spec
+        }
+        None => spec,
+    };
+    let (width, precision) = match spec.split_once('.') {
+        Some((width, precision)) => (width, Some(precision)),
+        None => (spec, None),
+    };
+    if !width.is_empty() {
+        result.width = Some(width.parse().map_err(|_| invalid())?);
+    }
+    if let Some(precision) = precision {
+        result.precision = Some(precision.parse().map_err(|_| invalid())?);
+    }
+    Ok(result)
+}
+
+fn parse_template(template: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = vec![];
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err("Unclosed `{` in format string".to_owned()),
+                    }
+                }
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(Piece::Arg(parse_spec(&spec)?));
+            }
+            '}' => return Err("Unmatched `}` in format string, use `}}` for a literal `}`".to_owned()),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+/// The number of arguments a `format!` string takes, or why it's invalid
+pub(crate) fn count_args(template: &str) -> Result<usize, String> {
+    let pieces = parse_template(template)?;
+    Ok(pieces.iter().filter(|piece| matches!(piece, Piece::Arg(_))).count())
+}
+
+pub(crate) fn format(template: &str, args: &[Expr]) -> Result<String, EvalErrorDesc> {
+    let pieces = parse_template(template).map_err(EvalErrorDesc::Syntax)?;
+    let mut args = args.iter();
+    let mut out = String::new();
+    for piece in pieces {
+        let spec = match piece {
+            Piece::Text(text) => {
+                out += &text;
+                continue;
+            }
+            Piece::Arg(spec) => spec,
+        };
+        let arg = args
+            .next()
+            .ok_or(EvalErrorDesc::InvalidType("Not enough arguments for the format string"))?;
+        let text = match (&arg.desc, spec.precision) {
+            (_, _) if spec.debug => arg.debug(),
+            (ExprDesc::Float(f), Some(precision)) => format!("{:.*}", precision, f),
+            (ExprDesc::String(s), Some(precision)) => s.chars().take(precision).collect(),
+            _ => display(arg),
+        };
+        let width = spec.width.unwrap_or(0);
+        out += &match arg.desc {
+            // numbers line up on the right, like rust
+            ExprDesc::Int(_) | ExprDesc::Float(_) => format!("{:>width$}", text, width = width),
+            _ => format!("{:<width$}", text, width = width),
+        };
+    }
+    Ok(out)
+}
@@ -0,0 +1,769 @@
+use crate::ast::{Access, Expr, ExprDesc, IfCond, Pattern, Pos, Statement, Type};
+use crate::error::{EvalError, EvalErrorDesc, FieldType, UnknownField};
+use crate::numeric::is_float_method;
+use crate::schema::{suggest, Decl, FieldDecl, Signature, TypeDecl, VariantFields};
+use crate::scope::Scope;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Checks a file's statements before any of them run, so that a bad edit is reported when
+/// the file is loaded instead of when the broken code is reached.
+///
+/// Types that can't be known, like arguments declared `any` or variables from the caller's
+/// scope, are `any` and fit everywhere. Only values that are known to be wrong are errors.
+pub(crate) fn check_statements(stmts: &[Statement], scope: &Scope) -> Result<(), EvalError> {
+    let mut checker = Checker::new(scope);
+    // Declarations and functions can be used before the statement that defines them
+    for stmt in stmts {
+        match stmt {
+            Statement::Decl(decl, _) => {
+                checker.decls.insert(decl.name().to_owned(), Arc::new(decl.clone()));
+            }
+            Statement::FnDefn(name, _, signature, _) => {
+                checker.fns.insert(name.clone(), signature.clone());
+            }
+            Statement::Use(..) => checker.uses_files = true,
+            _ => (),
+        }
+    }
+    for stmt in stmts {
+        checker.statement(stmt);
+    }
+    checker.finish()
+}
+
+/// The type of an expression, checking it along the way. Unlike in a file, variables from the
+/// scope have the type of their current value, since they're about to be used.
+pub(crate) fn type_of(expr: &Expr, scope: &Scope) -> Result<TypeDecl, EvalError> {
+    let mut checker = Checker::new(scope);
+    checker.scope_values = true;
+    let typ = checker.expr(expr);
+    checker.finish().map(|()| typ)
+}
+
+/// Whether a value of type `found` can be used where `expected` is declared. Any number fits
+/// `f32` and `f64`, and ints fit `i64`, like they do when a value is checked against its
+/// declaration.
+fn fits(expected: &TypeDecl, found: &TypeDecl) -> bool {
+    match (expected, found) {
+        (TypeDecl::Any, _) | (_, TypeDecl::Any) => true,
+        (TypeDecl::Float | TypeDecl::Double, found) => is_number(found),
+        (TypeDecl::Long, TypeDecl::Int) => true,
+        (TypeDecl::Tuple(expected), TypeDecl::Tuple(found)) => {
+            expected.len() == found.len() && expected.iter().zip(found).all(|(e, f)| fits(e, f))
+        }
+        (TypeDecl::Vec(expected), TypeDecl::Vec(found))
+        | (TypeDecl::Option(expected), TypeDecl::Option(found)) => fits(expected, found),
+        _ => expected == found,
+    }
+}
+
+/// A type that both `a` and `b` fit, `any` if they have nothing in common
+fn join(a: TypeDecl, b: TypeDecl) -> TypeDecl {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (a, b) if is_number(&a) && is_number(&b) => number(&a, &b),
+        (TypeDecl::Tuple(a), TypeDecl::Tuple(b)) if a.len() == b.len() => {
+            TypeDecl::Tuple(a.into_iter().zip(b).map(|(a, b)| join(a, b)).collect())
+        }
+        (TypeDecl::Vec(a), TypeDecl::Vec(b)) => TypeDecl::Vec(Box::new(join(*a, *b))),
+        (TypeDecl::Option(a), TypeDecl::Option(b)) => TypeDecl::Option(Box::new(join(*a, *b))),
+        _ => TypeDecl::Any,
+    }
+}
+
+fn is_number(typ: &TypeDecl) -> bool {
+    matches!(typ, TypeDecl::Int | TypeDecl::Float | TypeDecl::Long | TypeDecl::Double)
+}
+
+/// The type arithmetic on two numbers gives: a float if either is a float, and 64-bit if
+/// either is 64-bit
+fn number(a: &TypeDecl, b: &TypeDecl) -> TypeDecl {
+    let float = |typ| matches!(typ, &TypeDecl::Float | &TypeDecl::Double);
+    let wide = |typ| matches!(typ, &TypeDecl::Long | &TypeDecl::Double);
+    match (float(a) || float(b), wide(a) || wide(b)) {
+        (false, false) => TypeDecl::Int,
+        (false, true) => TypeDecl::Long,
+        (true, false) => TypeDecl::Float,
+        (true, true) => TypeDecl::Double,
+    }
+}
+
+fn quoted(typ: &TypeDecl) -> String {
+    format!("`{}`", typ)
+}
+
+struct Checker<'a> {
+    /// For declarations from files that were loaded before this one
+    scope: &'a Scope,
+    decls: HashMap<String, Arc<Decl>>,
+    fns: HashMap<String, Signature>,
+    globals: HashMap<String, TypeDecl>,
+    /// Variables in the blocks being checked, innermost last
+    locals: Vec<HashMap<String, TypeDecl>>,
+    /// A file that `use`s another can refer to declarations that aren't loaded yet
+    uses_files: bool,
+    /// Whether variables from the scope have the type of their value, instead of `any`
+    scope_values: bool,
+    /// Every variable name bound anywhere, since a function can use its callers' variables
+    bound: HashSet<String>,
+    /// Variables that weren't found where they were used, with where that was
+    unbound: Vec<(String, Pos)>,
+    errors: Vec<EvalError>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(scope: &'a Scope) -> Self {
+        Checker {
+            scope,
+            decls: HashMap::new(),
+            fns: HashMap::new(),
+            globals: HashMap::new(),
+            locals: vec![],
+            uses_files: false,
+            scope_values: false,
+            bound: HashSet::new(),
+            unbound: vec![],
+            errors: vec![],
+        }
+    }
+
+    fn finish(self) -> Result<(), EvalError> {
+        let mut errors = self.errors;
+        for (name, pos) in self.unbound {
+            if !self.bound.contains(&name) {
+                errors.push(EvalErrorDesc::MissingReference(name).with_pos(pos));
+            }
+        }
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => {
+                let pos = errors[0].pos;
+                Err(EvalErrorDesc::TypeErrors(errors).with_pos(pos))
+            }
+        }
+    }
+
+    fn error(&mut self, desc: EvalErrorDesc, pos: Pos) {
+        self.errors.push(desc.with_pos(pos));
+    }
+
+    fn expect(&mut self, expected: &TypeDecl, found: &TypeDecl, pos: Pos) {
+        if !fits(expected, found) {
+            self.error(EvalErrorDesc::TypeMismatch(quoted(expected), quoted(found)), pos);
+        }
+    }
+
+    fn expect_bool(&mut self, value: &Expr) {
+        let found = self.expr(value);
+        self.expect(&TypeDecl::Bool, &found, value.pos);
+    }
+
+    fn decl(&self, name: &str) -> Option<Arc<Decl>> {
+        self.decls.get(name).cloned().or_else(|| self.scope.lookup_decl(name))
+    }
+
+    /// The enum that has a variant called `name`
+    fn variant_decl(&self, name: &str) -> Option<Arc<Decl>> {
+        self.decls
+            .values()
+            .find(|decl| decl.variant(name).is_some())
+            .cloned()
+            .or_else(|| self.scope.lookup_variant(name))
+    }
+
+    /// Reports names in a written type that aren't declared
+    fn check_type(&mut self, typ: &TypeDecl, pos: Pos) {
+        match typ {
+            TypeDecl::Named(name) if self.decl(name).is_none() && !self.uses_files => {
+                self.error(EvalErrorDesc::UnknownType(name.clone()), pos);
+            }
+            TypeDecl::Tuple(items) => {
+                for item in items {
+                    self.check_type(item, pos);
+                }
+            }
+            TypeDecl::Vec(item) | TypeDecl::Option(item) => self.check_type(item, pos),
+            _ => (),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&TypeDecl> {
+        self.locals
+            .iter()
+            .rev()
+            .find_map(|locals| locals.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    fn bind_name(&mut self, name: &str, typ: TypeDecl) {
+        self.bound.insert(name.to_owned());
+        match self.locals.last_mut() {
+            Some(locals) => locals.insert(name.to_owned(), typ),
+            None => self.globals.insert(name.to_owned(), typ),
+        };
+    }
+
+    /// Gives the variables in a pattern the types they'd have when matched against `typ`
+    fn bind(&mut self, pattern: &Pattern, typ: &TypeDecl) {
+        let any = TypeDecl::Any;
+        match pattern {
+            Pattern::Ident(name) | Pattern::MutIdent(name) => self.bind_name(name, typ.clone()),
+            Pattern::Bind(name, inner) => {
+                self.bind_name(name, typ.clone());
+                self.bind(inner, typ);
+            }
+            Pattern::Tuple(items) => match typ {
+                TypeDecl::Tuple(types) if types.len() == items.len() => {
+                    for (item, typ) in items.iter().zip(types) {
+                        self.bind(item, typ);
+                    }
+                }
+                _ => items.iter().for_each(|item| self.bind(item, &any)),
+            },
+            Pattern::Slice(items) => {
+                let item_type = match typ {
+                    TypeDecl::Vec(item) => item.as_ref().clone(),
+                    _ => TypeDecl::Any,
+                };
+                for item in items {
+                    match item {
+                        Pattern::Rest(Some(name)) => self.bind_name(name, TypeDecl::Vec(Box::new(item_type.clone()))),
+                        item => self.bind(item, &item_type),
+                    }
+                }
+            }
+            Pattern::TupleStruct(name, items) => {
+                let types = match (name.as_str(), typ) {
+                    ("Some", TypeDecl::Option(inner)) => vec![inner.as_ref().clone()],
+                    _ => match self.variant_decl(name).as_deref().and_then(|decl| decl.variant(name)) {
+                        Some(variant) => match &variant.fields {
+                            VariantFields::Tuple(types) => types.clone(),
+                            _ => vec![],
+                        },
+                        None => vec![],
+                    },
+                };
+                for (i, item) in items.iter().enumerate() {
+                    self.bind(item, types.get(i).unwrap_or(&any));
+                }
+            }
+            Pattern::Struct(name, items) => {
+                let fields = self.declared_fields(name);
+                for (field, item) in items {
+                    let typ = fields
+                        .iter()
+                        .find(|decl| &decl.name == field)
+                        .map_or(TypeDecl::Any, |decl| decl.typ.clone());
+                    self.bind(item, &typ);
+                }
+            }
+            Pattern::Or(alternatives) => {
+                for alternative in alternatives {
+                    self.bind(alternative, typ);
+                }
+            }
+            Pattern::Const(_) | Pattern::Any | Pattern::Range(..) | Pattern::Rest(_) => (),
+        }
+    }
+
+    /// The fields of a declared struct, or of an enum variant with named fields
+    fn declared_fields(&self, name: &str) -> Vec<FieldDecl> {
+        match self.decl(name).as_deref() {
+            Some(Decl::Struct(_, fields)) => return fields.clone(),
+            Some(Decl::Enum(..)) => return vec![],
+            None => (),
+        }
+        match self.variant_decl(name).as_deref().and_then(|decl| decl.variant(name)) {
+            Some(variant) => match &variant.fields {
+                VariantFields::Struct(fields) => fields.clone(),
+                _ => vec![],
+            },
+            None => vec![],
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Let(pattern, typ, value) => {
+                let found = self.expr(value);
+                let typ = match typ {
+                    Some(typ) => {
+                        self.check_type(typ, value.pos);
+                        self.expect(typ, &found, value.pos);
+                        typ.clone()
+                    }
+                    None => found,
+                };
+                self.bind(pattern, &typ);
+            }
+            Statement::Assign(place, value) => {
+                let found = self.expr(value);
+                for member in place.members.iter() {
+                    match member {
+                        Access::Method(_, args) => {
+                            self.exprs(args);
+                        }
+                        Access::Index(index, _) => {
+                            self.expr(index);
+                        }
+                        Access::Field(_) => (),
+                    }
+                }
+                // A variable can be given a value of a different type, so it's only known to
+                // be one of the two afterwards
+                if place.members.is_empty() {
+                    if let Some(current) = self.lookup(&place.name).cloned() {
+                        self.bind_name(&place.name, join(current, found));
+                    }
+                }
+            }
+            Statement::ExprDesc(value) => {
+                self.expr(value);
+            }
+            Statement::FnDefn(name, args, signature, body) => {
+                for typ in signature.args.iter().chain(std::iter::once(&signature.ret)) {
+                    self.check_type(typ, body.pos);
+                }
+                self.fns.insert(name.clone(), signature.clone());
+                // Functions can't see the variables of the block they're defined in
+                let outer = std::mem::take(&mut self.locals);
+                self.bound.extend(args.iter().cloned());
+                self.locals.push(args.iter().cloned().zip(signature.args.iter().cloned()).collect());
+                let found = self.expr(body);
+                self.locals = outer;
+                let pos = match &body.desc {
+                    ExprDesc::Block(_, value) => value.pos.or(body.pos),
+                    _ => body.pos,
+                };
+                self.expect(&signature.ret, &found, pos);
+            }
+            Statement::Decl(decl, pos) => {
+                let mut fields: Vec<&FieldDecl> = vec![];
+                match decl {
+                    Decl::Struct(_, declared) => fields.extend(declared),
+                    Decl::Enum(_, variants) => {
+                        for variant in variants {
+                            match &variant.fields {
+                                VariantFields::Unit => (),
+                                VariantFields::Tuple(types) => types.iter().for_each(|typ| self.check_type(typ, *pos)),
+                                VariantFields::Struct(declared) => fields.extend(declared),
+                            }
+                        }
+                    }
+                }
+                for field in fields {
+                    self.check_type(&field.typ, *pos);
+                    if let Some(default) = &field.default {
+                        let found = self.expr(default);
+                        self.expect(&field.typ, &found, default.pos);
+                    }
+                }
+            }
+            Statement::Use(..) | Statement::Mod(..) => (),
+        }
+    }
+
+    fn exprs(&mut self, items: &[Expr]) -> Vec<TypeDecl> {
+        items.iter().map(|item| self.expr(item)).collect()
+    }
+
+    fn block<T, F: FnOnce(&mut Self) -> T>(&mut self, f: F) -> T {
+        self.locals.push(HashMap::new());
+        let result = f(self);
+        self.locals.pop();
+        result
+    }
+
+    fn if_cond(&mut self, cond: &IfCond) {
+        match cond {
+            IfCond::Value(value) => self.expect_bool(value),
+            IfCond::IfLet(pattern, value) => {
+                let typ = self.expr(value);
+                self.bind(pattern, &typ);
+            }
+        }
+    }
+
+    fn numbers(&mut self, a: &Expr, b: &Expr, pos: Pos) -> TypeDecl {
+        let (a, b) = (self.expr(a), self.expr(b));
+        self.arithmetic(a, b, pos)
+    }
+
+    /// The result of an arithmetic operator on these types
+    fn arithmetic(&mut self, a: TypeDecl, b: TypeDecl, pos: Pos) -> TypeDecl {
+        match (a, b) {
+            (a, b) if is_number(&a) && is_number(&b) => number(&a, &b),
+            (TypeDecl::Any, _) | (_, TypeDecl::Any) => TypeDecl::Any,
+            (a, b) => {
+                let found = format!("{} and {}", quoted(&a), quoted(&b));
+                self.error(EvalErrorDesc::TypeMismatch("numbers".to_owned(), found), pos);
+                TypeDecl::Any
+            }
+        }
+    }
+
+    fn struct_fields(&mut self, name: &str, fields: &[FieldDecl], items: &[(String, Expr)], pos: Pos) {
+        let found: Vec<TypeDecl> = items.iter().map(|(_, value)| self.expr(value)).collect();
+        for ((key, value), found) in items.iter().zip(found) {
+            match fields.iter().find(|field| &field.name == key) {
+                Some(field) if !fits(&field.typ, &found) => {
+                    let err = FieldType {
+                        typ: name.to_owned(),
+                        field: key.clone(),
+                        expected: field.typ.to_string(),
+                        found: found.to_string(),
+                    };
+                    self.error(EvalErrorDesc::FieldType(Box::new(err)), value.pos.or(pos));
+                }
+                Some(_) => (),
+                None => {
+                    let err = UnknownField {
+                        typ: name.to_owned(),
+                        field: key.clone(),
+                        suggestion: suggest(key, fields),
+                    };
+                    self.error(EvalErrorDesc::UnknownField(Box::new(err)), pos);
+                }
+            }
+        }
+        for field in fields {
+            if field.default.is_none() && !items.iter().any(|(key, _)| key == &field.name) {
+                self.error(EvalErrorDesc::MissingField(name.to_owned(), field.name.clone()), pos);
+            }
+        }
+    }
+
+    fn member(&mut self, typ: TypeDecl, access: &Access, pos: Pos) -> TypeDecl {
+        match access {
+            Access::Field(name) => match (&typ, name.parse::<usize>()) {
+                (TypeDecl::Tuple(items), Ok(i)) => match items.get(i) {
+                    Some(item) => item.clone(),
+                    None => {
+                        self.error(EvalErrorDesc::IndexOutOfBounds(i as i32, items.len()), pos);
+                        TypeDecl::Any
+                    }
+                },
+                (TypeDecl::Named(type_name), _) => match self.decl(type_name).as_deref() {
+                    Some(Decl::Struct(_, fields)) => match fields.iter().find(|field| &field.name == name) {
+                        Some(field) => field.typ.clone(),
+                        None => {
+                            let err = UnknownField {
+                                typ: type_name.clone(),
+                                field: name.clone(),
+                                suggestion: suggest(name, fields),
+                            };
+                            self.error(EvalErrorDesc::UnknownField(Box::new(err)), pos);
+                            TypeDecl::Any
+                        }
+                    },
+                    _ => TypeDecl::Any,
+                },
+                _ => TypeDecl::Any,
+            },
+            Access::Method(name, args) => {
+                self.exprs(args);
+                match (name.as_str(), &typ) {
+                    ("clone", _) => typ,
+                    ("to_string", _) => TypeDecl::String,
+                    ("len", _) => TypeDecl::Int,
+                    ("to_float", TypeDecl::Int) => TypeDecl::Float,
+                    (name, TypeDecl::Float) if is_float_method(name) => TypeDecl::Float,
+                    _ => TypeDecl::Any,
+                }
+            }
+            Access::Index(index, _) => {
+                self.expr(index);
+                match typ {
+                    TypeDecl::Vec(item) => *item,
+                    _ => TypeDecl::Any,
+                }
+            }
+        }
+    }
+
+    /// The type of an expression, reporting any errors inside it
+    fn expr(&mut self, expr: &Expr) -> TypeDecl {
+        let pos = expr.pos;
+        match &expr.desc {
+            ExprDesc::Float(_) => TypeDecl::Float,
+            ExprDesc::Int(_) => TypeDecl::Int,
+            ExprDesc::Bool(_) => TypeDecl::Bool,
+            ExprDesc::Char(_) => TypeDecl::Char,
+            ExprDesc::String(_) => TypeDecl::String,
+            ExprDesc::Unit => TypeDecl::Unit,
+            ExprDesc::Long(_) => TypeDecl::Long,
+            ExprDesc::Double(_) => TypeDecl::Double,
+            ExprDesc::Moved | ExprDesc::Closure(..) | ExprDesc::Break | ExprDesc::Continue => TypeDecl::Any,
+            ExprDesc::Ident(name) => match self.lookup(name) {
+                Some(typ) => typ.clone(),
+                None => {
+                    let scope = self.scope;
+                    match scope.get_raw(name) {
+                        Some(value) if self.scope_values => self.expr(value),
+                        Some(_) => TypeDecl::Any,
+                        // paths and names from other files can't be checked until those are loaded
+                        None if name.contains("::") || self.uses_files => TypeDecl::Any,
+                        None => {
+                            self.unbound.push((name.clone(), pos));
+                            TypeDecl::Any
+                        }
+                    }
+                }
+            },
+
+            ExprDesc::Array(items) => {
+                let item = self.exprs(items).into_iter().reduce(join).unwrap_or(TypeDecl::Any);
+                TypeDecl::Vec(Box::new(item))
+            }
+            ExprDesc::Tuple(items) => TypeDecl::Tuple(self.exprs(items)),
+            ExprDesc::Object(items) => {
+                for (_, value) in items {
+                    self.expr(value);
+                }
+                TypeDecl::Any
+            }
+            ExprDesc::Option(inner) => match inner.as_ref() {
+                Some(inner) => TypeDecl::Option(Box::new(self.expr(inner))),
+                None => TypeDecl::Option(Box::new(TypeDecl::Any)),
+            },
+            ExprDesc::Struct(name, items) => {
+                if let Some(Decl::Struct(_, fields)) = self.decl(name).as_deref() {
+                    self.struct_fields(name, fields, items, pos);
+                    return TypeDecl::Named(name.clone());
+                }
+                match self.variant_decl(name) {
+                    Some(decl) => {
+                        if let Some(VariantFields::Struct(fields)) = decl.variant(name).map(|variant| &variant.fields) {
+                            self.struct_fields(name, fields, items, pos);
+                        }
+                        TypeDecl::Named(decl.name().to_owned())
+                    }
+                    None => {
+                        for (_, value) in items {
+                            self.expr(value);
+                        }
+                        TypeDecl::Any
+                    }
+                }
+            }
+            ExprDesc::NamedTuple(name, items) => {
+                let found = self.exprs(items);
+                match self.variant_decl(name) {
+                    Some(decl) => {
+                        if let Some(VariantFields::Tuple(types)) = decl.variant(name).map(|variant| &variant.fields) {
+                            if types.len() != items.len() {
+                                self.error(EvalErrorDesc::FieldCount(name.clone(), types.len(), items.len()), pos);
+                            }
+                            for ((typ, found), item) in types.iter().zip(&found).zip(items) {
+                                self.expect(typ, found, item.pos.or(pos));
+                            }
+                        }
+                        TypeDecl::Named(decl.name().to_owned())
+                    }
+                    None => TypeDecl::Any,
+                }
+            }
+
+            ExprDesc::Plus(a, b) => match (self.expr(a), self.expr(b)) {
+                (TypeDecl::String, TypeDecl::String) => TypeDecl::String,
+                (TypeDecl::String, TypeDecl::Any) | (TypeDecl::Any, TypeDecl::String) => TypeDecl::Any,
+                (a, b) => self.arithmetic(a, b, pos),
+            },
+            ExprDesc::Minus(a, b) | ExprDesc::Times(a, b) | ExprDesc::Divide(a, b) | ExprDesc::Modulo(a, b) => {
+                self.numbers(a, b, pos)
+            }
+            ExprDesc::BitAnd(a, b) | ExprDesc::BitOr(a, b) | ExprDesc::BitXor(a, b) => {
+                match (self.expr(a), self.expr(b)) {
+                    (TypeDecl::Int, TypeDecl::Int) => TypeDecl::Int,
+                    (TypeDecl::Bool, TypeDecl::Bool) => TypeDecl::Bool,
+                    (TypeDecl::Any, _) | (_, TypeDecl::Any) => TypeDecl::Any,
+                    (ta, tb) => {
+                        let found = format!("{} and {}", quoted(&ta), quoted(&tb));
+                        self.error(EvalErrorDesc::TypeMismatch("two ints or two bools".to_owned(), found), pos);
+                        TypeDecl::Any
+                    }
+                }
+            }
+            ExprDesc::Shl(a, b) | ExprDesc::Shr(a, b) => {
+                let typ = self.expr(a);
+                let found = self.expr(b);
+                self.expect(&TypeDecl::Int, &found, b.pos);
+                match typ {
+                    TypeDecl::Int | TypeDecl::Long | TypeDecl::Any => typ,
+                    typ => {
+                        self.error(EvalErrorDesc::TypeMismatch("an int".to_owned(), quoted(&typ)), a.pos);
+                        TypeDecl::Any
+                    }
+                }
+            }
+            ExprDesc::Eq(a, b) | ExprDesc::Neq(a, b) => {
+                self.expr(a);
+                self.expr(b);
+                TypeDecl::Bool
+            }
+            ExprDesc::Lt(a, b) | ExprDesc::Gt(a, b) | ExprDesc::Le(a, b) | ExprDesc::Ge(a, b) => {
+                match (self.expr(a), self.expr(b)) {
+                    (ta, tb) if is_number(&ta) && is_number(&tb) => (),
+                    (TypeDecl::String, TypeDecl::String) | (TypeDecl::Char, TypeDecl::Char) => (),
+                    (TypeDecl::Any, _) | (_, TypeDecl::Any) => (),
+                    (ta, tb) => {
+                        let found = format!("{} and {}", quoted(&ta), quoted(&tb));
+                        let expected = "numbers, strings or chars".to_owned();
+                        self.error(EvalErrorDesc::TypeMismatch(expected, found), pos);
+                    }
+                }
+                TypeDecl::Bool
+            }
+            ExprDesc::And(a, b) | ExprDesc::Or(a, b) => {
+                self.expect_bool(a);
+                self.expect_bool(b);
+                TypeDecl::Bool
+            }
+            ExprDesc::Neg(value) => match self.expr(value) {
+                typ if is_number(&typ) || typ == TypeDecl::Any => typ,
+                typ => {
+                    self.error(EvalErrorDesc::TypeMismatch("a number".to_owned(), quoted(&typ)), pos);
+                    TypeDecl::Any
+                }
+            },
+            ExprDesc::Not(value) => match self.expr(value) {
+                typ @ (TypeDecl::Bool | TypeDecl::Int | TypeDecl::Any) => typ,
+                typ => {
+                    self.error(EvalErrorDesc::TypeMismatch("a bool or an int".to_owned(), quoted(&typ)), pos);
+                    TypeDecl::Any
+                }
+            },
+            ExprDesc::Ref(value) => self.expr(value),
+            ExprDesc::Cast(value, typ) => {
+                let found = self.expr(value);
+                if !is_number(&found) && found != TypeDecl::Any {
+                    self.error(EvalErrorDesc::TypeMismatch("a number".to_owned(), quoted(&found)), value.pos);
+                }
+                match typ {
+                    Type::F32 => TypeDecl::Float,
+                    Type::I32 => TypeDecl::Int,
+                }
+            }
+            ExprDesc::MemberAccess(target, accesses) => {
+                let mut typ = self.expr(target);
+                for access in accesses {
+                    typ = self.member(typ, access, pos);
+                }
+                typ
+            }
+
+            ExprDesc::Block(stmts, value) => self.block(|checker| {
+                for stmt in stmts {
+                    checker.statement(stmt);
+                }
+                checker.expr(value)
+            }),
+            ExprDesc::Lambda(args, body) => {
+                self.block(|checker| {
+                    for arg in args {
+                        checker.bind_name(arg, TypeDecl::Any);
+                    }
+                    checker.expr(body);
+                });
+                TypeDecl::Any
+            }
+            ExprDesc::FnCall(name, args) => {
+                let found = self.exprs(args);
+                if name == "format!" {
+                    return TypeDecl::String;
+                }
+                // a closure in a variable is called instead of a function with the same name
+                let signature = match self.lookup(name) {
+                    Some(_) => None,
+                    None => self
+                        .fns
+                        .get(name)
+                        .or_else(|| self.scope.lookup_signature(name))
+                        .cloned(),
+                };
+                let signature = match signature {
+                    Some(signature) => signature,
+                    None => return TypeDecl::Any,
+                };
+                if signature.args.len() != args.len() {
+                    self.error(EvalErrorDesc::FunctionWrongNumberArgs(signature.args.len(), args.len()), pos);
+                }
+                for ((expected, found), arg) in signature.args.iter().zip(&found).zip(args) {
+                    self.expect(expected, found, arg.pos);
+                }
+                signature.ret
+            }
+            ExprDesc::Call(target, args) => {
+                self.expr(target);
+                self.exprs(args);
+                TypeDecl::Any
+            }
+
+            ExprDesc::IfChain(chain, else_) => {
+                let mut result: Option<TypeDecl> = None;
+                for (cond, body) in chain {
+                    let typ = self.block(|checker| {
+                        checker.if_cond(cond);
+                        checker.expr(body)
+                    });
+                    result = Some(match result {
+                        Some(result) => join(result, typ),
+                        None => typ,
+                    });
+                }
+                match (else_, result) {
+                    (Some(else_), Some(result)) => {
+                        let typ = self.expr(else_);
+                        join(result, typ)
+                    }
+                    _ => TypeDecl::Any,
+                }
+            }
+            ExprDesc::Match(value, arms) => {
+                let typ = self.expr(value);
+                let mut result: Option<TypeDecl> = None;
+                for (pattern, guard, body) in arms {
+                    let found = self.block(|checker| {
+                        checker.bind(pattern, &typ);
+                        if let Some(guard) = guard {
+                            checker.expect_bool(guard);
+                        }
+                        checker.expr(body)
+                    });
+                    result = Some(match result {
+                        Some(result) => join(result, found),
+                        None => found,
+                    });
+                }
+                result.unwrap_or(TypeDecl::Any)
+            }
+            ExprDesc::For(pattern, iterable, body) => {
+                let item = match self.expr(iterable) {
+                    TypeDecl::Vec(item) => *item,
+                    _ => match &iterable.desc {
+                        ExprDesc::Range(..) => TypeDecl::Int,
+                        _ => TypeDecl::Any,
+                    },
+                };
+                self.block(|checker| {
+                    checker.bind(pattern, &item);
+                    checker.expr(body);
+                });
+                TypeDecl::Unit
+            }
+            ExprDesc::While(cond, body) => {
+                self.block(|checker| {
+                    checker.if_cond(cond);
+                    checker.expr(body);
+                });
+                TypeDecl::Unit
+            }
+            ExprDesc::Range(start, end, _) => {
+                self.numbers(start, end, pos);
+                TypeDecl::Any
+            }
+        }
+    }
+}
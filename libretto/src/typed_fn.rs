@@ -0,0 +1,109 @@
+use crate::ast::{Expr, Pos};
+use crate::de::from_expr;
+use crate::error::{DeserializeError, DeserializeErrorDesc, Error, Result};
+use crate::scope::{Callable, Scope, SingleScope};
+use crate::ser::to_expr;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The argument types of a function called from rust, as a tuple
+pub trait FnArgs {
+    const ARITY: usize;
+}
+
+/// Implemented for tuples of values, or references to values, that can be passed as `Args`
+pub trait IntoArgs<Args: FnArgs> {
+    fn into_args(self) -> Result<Vec<Expr>>;
+}
+
+macro_rules! impl_fn_args {
+    ($arity: expr; $( $typ: ident $arg: ident $value: ident ),*) => {
+        impl<$( $typ: Serialize ),*> FnArgs for ($( $typ, )*) {
+            const ARITY: usize = $arity;
+        }
+
+        impl<$( $typ: Serialize, $value: Borrow<$typ> ),*> IntoArgs<($( $typ, )*)> for ($( $value, )*) {
+            fn into_args(self) -> Result<Vec<Expr>> {
+                let ($( $arg, )*) = self;
+                Ok(vec![$( to_expr($arg.borrow())? ),*])
+            }
+        }
+    };
+}
+
+impl_fn_args!(0;);
+impl_fn_args!(1; A a VA);
+impl_fn_args!(2; A a VA, B b VB);
+impl_fn_args!(3; A a VA, B b VB, C c VC);
+impl_fn_args!(4; A a VA, B b VB, C c VC, D d VD);
+impl_fn_args!(5; A a VA, B b VB, C c VC, D d VD, E e VE);
+impl_fn_args!(6; A a VA, B b VB, C c VC, D d VD, E e VE, G g VG);
+
+/// A function found with `Scope::get_function`, which takes `Args` and returns `R`.
+/// It keeps calling the function that was there when it was looked up, even if the script
+/// defines another one with the same name later.
+pub struct TypedFn<Args, R> {
+    name: Arc<str>,
+    callable: Callable,
+    /// The module a `module::name` function was found in, which is put back in scope for each
+    /// call so that the function can use the module's other items
+    module: Option<Arc<SingleScope>>,
+    types: PhantomData<fn(Args) -> R>,
+}
+
+impl<Args: FnArgs, R: DeserializeOwned> TypedFn<Args, R> {
+    pub(crate) fn new(name: &str, callable: Callable, module: Option<Arc<SingleScope>>) -> Self {
+        TypedFn {
+            name: name.into(),
+            callable,
+            module,
+            types: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Converts the arguments with `to_expr` and the return value with `from_expr`
+    pub fn call<A: IntoArgs<Args>>(&self, scope: &mut Scope, args: A) -> std::result::Result<R, Error> {
+        let args = args.into_args()?;
+        let result = scope.call_resolved(&self.name, &self.callable, self.module.as_ref(), args, Pos::default())?;
+        Ok(from_expr(&result)?)
+    }
+}
+
+impl<Args, R> Clone for TypedFn<Args, R> {
+    fn clone(&self) -> Self {
+        TypedFn {
+            name: self.name.clone(),
+            callable: self.callable.clone(),
+            module: self.module.clone(),
+            types: PhantomData,
+        }
+    }
+}
+
+impl<Args, R> std::fmt::Debug for TypedFn<Args, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.name)
+    }
+}
+
+/// What `call_fn!` expands to, with the errors it has always returned
+#[doc(hidden)]
+pub fn call_once<Args, R>(scope: &mut Scope, name: &str, args: Args) -> std::result::Result<R, DeserializeError>
+where
+    Args: FnArgs + IntoArgs<Args>,
+    R: DeserializeOwned,
+{
+    let result = scope.get_function::<Args, R>(name).and_then(|function| function.call(scope, args));
+    result.map_err(|err| match err {
+        Error::DeserializeError(err) => err,
+        Error::EvalError(err) => err.into(),
+        err => DeserializeErrorDesc::Message(err.to_string()).into(),
+    })
+}
@@ -0,0 +1,551 @@
+use crate::ast::{
+    add, arithmetic, bitwise, borrowed_member_access, compare, match_pattern, shift, step_move,
+    step_mut, values_equal, Expr, ExprDesc, Pos, Step, Type,
+};
+use crate::compile::{AccessOp, BinOp, Function, Op};
+use crate::error::{EvalError, EvalErrorDesc};
+use crate::schema::check;
+use crate::scope::{copy_value, move_value, Scope};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// The locals of a function that is waiting on a call. Variables that aren't local to a
+/// function are looked up by name in the frames of its callers.
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    func: Arc<Function>,
+    locals: Vec<Option<Expr>>,
+}
+
+impl Frame {
+    fn slot(&self, name: &str) -> Option<usize> {
+        let slots = &self.func.chunk.slots;
+        (0..self.locals.len())
+            .rev()
+            .find(|slot| self.locals[*slot].is_some() && slots[*slot] == name)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.slot(name).is_some()
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Expr> {
+        match self.slot(name) {
+            Some(slot) => self.locals[slot].as_mut(),
+            None => None,
+        }
+    }
+}
+
+enum LoopIter {
+    Array(std::vec::IntoIter<Expr>),
+    Range(std::ops::Range<i32>),
+    RangeInclusive(std::ops::RangeInclusive<i32>),
+}
+
+impl Iterator for LoopIter {
+    type Item = Expr;
+    fn next(&mut self) -> Option<Expr> {
+        match self {
+            LoopIter::Array(items) => items.next(),
+            LoopIter::Range(range) => range.next().map(|i| i.into()),
+            LoopIter::RangeInclusive(range) => range.next().map(|i| i.into()),
+        }
+    }
+}
+
+/// Calls a function. A closure's captured values go in the slots after the args.
+pub fn run(
+    func: &Arc<Function>,
+    args: Vec<Expr>,
+    captured: &[Expr],
+    scope: &mut Scope,
+    pos: Pos,
+) -> Result<Expr, EvalError> {
+    if func.args.len() != args.len() {
+        return Err(
+            EvalErrorDesc::FunctionWrongNumberArgs(func.args.len(), args.len()).with_pos(pos),
+        );
+    }
+    let mut locals: Vec<Option<Expr>> = Vec::with_capacity(func.chunk.slots.len());
+    locals.extend(args.into_iter().map(Some));
+    locals.extend(captured.iter().cloned().map(Some));
+    locals.resize(func.chunk.slots.len(), None);
+    scope.usage.enter(pos)?;
+    if func.chunk.defines_fns {
+        scope.push();
+    }
+    let result = execute(func, &mut locals, scope);
+    if func.chunk.defines_fns {
+        scope.pop();
+    }
+    scope.usage.exit();
+    result
+}
+
+/// Makes our locals visible to the callee while it runs
+fn call<F>(
+    func: &Arc<Function>,
+    locals: &mut Vec<Option<Expr>>,
+    scope: &mut Scope,
+    f: F,
+) -> Result<Expr, EvalError>
+where
+    F: FnOnce(&mut Scope) -> Result<Expr, EvalError>,
+{
+    scope.frames.push(Frame {
+        func: func.clone(),
+        locals: std::mem::take(locals),
+    });
+    let result = f(scope);
+    if let Some(frame) = scope.frames.pop() {
+        *locals = frame.locals;
+    }
+    result
+}
+
+fn pop_n(stack: &mut Vec<Expr>, n: usize) -> Vec<Expr> {
+    stack.split_off(stack.len() - n)
+}
+
+fn bind(
+    func: &Function,
+    pattern: usize,
+    value: Expr,
+    locals: &mut [Option<Expr>],
+    pos: Pos,
+) -> Result<bool, EvalError> {
+    let (pattern, slots) = &func.chunk.patterns[pattern];
+    match match_pattern(pattern, value, pos)? {
+        Some(bindings) => {
+            // or-pattern alternatives can bind their names in a different order
+            for (name, value, _mutable) in bindings {
+                if let Some(slot) = slots.iter().find(|slot| func.chunk.slots[**slot] == name) {
+                    locals[*slot] = Some(value);
+                }
+            }
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Pairs up an access chain with its args and indexes from the top of the stack
+fn steps<'a>(items: &'a [AccessOp], stack: &mut Vec<Expr>) -> Vec<Step<'a>> {
+    let count = items.iter().map(AccessOp::args).sum();
+    let mut args = pop_n(stack, count).into_iter();
+    items
+        .iter()
+        .map(|item| match item {
+            AccessOp::Field(name) => Step::Field(name),
+            AccessOp::Method(name, count) => Step::Method(name, args.by_ref().take(*count).collect()),
+            AccessOp::Index(pos) => {
+                Step::Index(args.next().unwrap_or_else(|| ExprDesc::Unit.into()), *pos)
+            }
+        })
+        .collect()
+}
+
+fn binary(op: BinOp, a: &Expr, b: &Expr) -> Result<ExprDesc, EvalErrorDesc> {
+    match op {
+        BinOp::Plus => add(a, b)
+            .map_err(|desc| desc.or_invalid("Cannot add")),
+        BinOp::Minus => arithmetic(a, b, i128::checked_sub, |a, b| a - b)
+            .map_err(|desc| desc.or_invalid("Cannot subtract")),
+        BinOp::Times => arithmetic(a, b, i128::checked_mul, |a, b| a * b)
+            .map_err(|desc| desc.or_invalid("Cannot multiply")),
+        BinOp::Divide => arithmetic(a, b, i128::checked_div, |a, b| a / b)
+            .map_err(|desc| desc.or_invalid("Cannot divide")),
+        BinOp::Modulo => arithmetic(a, b, i128::checked_rem, |a, b| a % b)
+            .map_err(|desc| desc.or_invalid("Cannot take the remainder")),
+        BinOp::BitAnd => bitwise(a, b, |a, b| a & b, |a, b| a & b),
+        BinOp::BitOr => bitwise(a, b, |a, b| a | b, |a, b| a | b),
+        BinOp::BitXor => bitwise(a, b, |a, b| a ^ b, |a, b| a ^ b),
+        BinOp::Shl => shift(a, b, i32::checked_shl, i128::checked_shl),
+        BinOp::Shr => shift(a, b, i32::checked_shr, i128::checked_shr),
+        BinOp::Eq => Ok(ExprDesc::Bool(values_equal(a, b))),
+        BinOp::Neq => Ok(ExprDesc::Bool(!values_equal(a, b))),
+        BinOp::Lt => compare(a, b, |o| o == Ordering::Less),
+        BinOp::Gt => compare(a, b, |o| o == Ordering::Greater),
+        BinOp::Le => compare(a, b, |o| o != Ordering::Greater),
+        BinOp::Ge => compare(a, b, |o| o != Ordering::Less),
+    }
+}
+
+fn execute(
+    func: &Arc<Function>,
+    locals: &mut Vec<Option<Expr>>,
+    scope: &mut Scope,
+) -> Result<Expr, EvalError> {
+    let chunk = &func.chunk;
+    let mut stack: Vec<Expr> = vec![];
+    let mut iters: Vec<LoopIter> = vec![];
+    let mut ip = 0;
+    while ip < chunk.code.len() {
+        let pos = chunk.positions[ip];
+        let op = &chunk.code[ip];
+        ip += 1;
+        scope.usage.tick(pos)?;
+        match op {
+            Op::Const(idx) => stack.push(chunk.consts[*idx].clone()),
+            Op::Unit => stack.push(ExprDesc::Unit.with_pos(pos)),
+            Op::Load(slot) => match &mut locals[*slot] {
+                Some(value) => stack.push(move_value(value, pos)?),
+                None => {
+                    return Err(
+                        EvalErrorDesc::MissingReference(chunk.slots[*slot].clone()).with_pos(pos)
+                    )
+                }
+            },
+            Op::LoadName(name) => match scope.get_dynamic_mut(&chunk.names[*name]) {
+                Some(value) => stack.push(move_value(value, pos)?),
+                None => {
+                    return Err(
+                        EvalErrorDesc::MissingReference(chunk.names[*name].clone()).with_pos(pos)
+                    )
+                }
+            },
+            Op::Copy(slot) => match &locals[*slot] {
+                Some(value) => stack.push(copy_value(value, pos)?),
+                None => {
+                    return Err(
+                        EvalErrorDesc::MissingReference(chunk.slots[*slot].clone()).with_pos(pos)
+                    )
+                }
+            },
+            Op::CopyName(name) => match scope.get_dynamic_mut(&chunk.names[*name]) {
+                Some(value) => stack.push(copy_value(value, pos)?),
+                None => {
+                    return Err(
+                        EvalErrorDesc::MissingReference(chunk.names[*name].clone()).with_pos(pos)
+                    )
+                }
+            },
+            Op::Store(slot) => locals[*slot] = stack.pop(),
+            Op::Clear(start, end) => {
+                for slot in &mut locals[*start..*end] {
+                    *slot = None;
+                }
+            }
+            Op::Pop => {
+                stack.pop();
+            }
+            Op::PopN(n) => stack.truncate(stack.len() - n),
+
+            Op::Bind(pattern, message) => {
+                let value = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                if !bind(func, *pattern, value, locals, pos)? {
+                    return Err(EvalErrorDesc::Unmatched((*message).to_owned()).with_pos(pos));
+                }
+            }
+            Op::BindOr(pattern, target) => {
+                let value = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                if !bind(func, *pattern, value, locals, pos)? {
+                    ip = *target;
+                }
+            }
+            Op::TestPattern(pattern, target) => {
+                let value = stack
+                    .last()
+                    .cloned()
+                    .unwrap_or_else(|| ExprDesc::Unit.into());
+                if !bind(func, *pattern, value, locals, pos)? {
+                    ip = *target;
+                }
+            }
+            Op::Unmatched => {
+                let value = stack.pop();
+                let shape = value.map_or_else(String::new, |value| value.shape());
+                return Err(EvalErrorDesc::NonExhaustive(shape).with_pos(pos));
+            }
+
+            Op::Array(n) => {
+                let items = pop_n(&mut stack, *n);
+                stack.push(ExprDesc::Array(items).with_pos(pos));
+            }
+            Op::Tuple(n) => {
+                let items = pop_n(&mut stack, *n);
+                stack.push(ExprDesc::Tuple(items).with_pos(pos));
+            }
+            Op::Object(shape) | Op::Struct(shape) => {
+                let (name, keys) = &chunk.shapes[*shape];
+                let values = pop_n(&mut stack, keys.len());
+                let items = keys.iter().cloned().zip(values).collect();
+                let mut value = match op {
+                    Op::Object(_) => ExprDesc::Object(items),
+                    _ => ExprDesc::Struct(name.clone(), items),
+                }
+                .with_pos(pos);
+                check(&mut value, scope)?;
+                stack.push(value);
+            }
+            Op::NamedTuple(name, n) => {
+                let items = pop_n(&mut stack, *n);
+                let mut value = ExprDesc::NamedTuple(chunk.names[*name].clone(), items).with_pos(pos);
+                check(&mut value, scope)?;
+                stack.push(value);
+            }
+            Op::Some => {
+                let value = stack.pop();
+                stack.push(ExprDesc::Option(Box::new(value)).with_pos(pos));
+            }
+
+            Op::Binary(op) => {
+                let b = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                let a = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                let value = binary(*op, &a, &b).map_err(|desc| desc.with_pos(pos))?.with_pos(pos);
+                scope.usage.check_size(&value, pos)?;
+                stack.push(value);
+            }
+            Op::Neg => {
+                let value = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                let desc = match value.desc {
+                    ExprDesc::Int(i) => match i.checked_neg() {
+                        Some(i) => ExprDesc::Int(i),
+                        None => return Err(EvalErrorDesc::IntegerOverflow.with_pos(pos)),
+                    },
+                    ExprDesc::Float(f) => ExprDesc::Float(-f),
+                    ExprDesc::Long(i) => match i.checked_neg() {
+                        Some(i) => ExprDesc::Long(i),
+                        None => return Err(EvalErrorDesc::IntegerOverflow.with_pos(pos)),
+                    },
+                    ExprDesc::Double(f) => ExprDesc::Double(-f),
+                    _ => return Err(EvalErrorDesc::InvalidType("Cannot negate").with_pos(pos)),
+                };
+                stack.push(desc.with_pos(pos));
+            }
+            Op::Not => {
+                let value = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                let desc = match value.desc {
+                    ExprDesc::Bool(b) => ExprDesc::Bool(!b),
+                    ExprDesc::Int(i) => ExprDesc::Int(!i),
+                    _ => {
+                        return Err(
+                            EvalErrorDesc::InvalidType("Can only use ! on bools and ints")
+                                .with_pos(pos),
+                        )
+                    }
+                };
+                stack.push(desc.with_pos(pos));
+            }
+            Op::Cast(typ) => {
+                let value = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                let desc = match (value.desc, typ) {
+                    (ExprDesc::Float(f), Type::I32) => ExprDesc::Int(f as i32),
+                    (ExprDesc::Float(f), Type::F32) => ExprDesc::Float(f),
+                    (ExprDesc::Int(i), Type::F32) => ExprDesc::Float(i as f32),
+                    (ExprDesc::Int(i), Type::I32) => ExprDesc::Int(i),
+                    (desc, typ) => crate::ast::cast_wide(&desc, typ).map_err(|err| err.with_pos(pos))?,
+                };
+                stack.push(desc.with_pos(pos));
+            }
+            Op::Range(inclusive) => {
+                let end = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                let start = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                match (&start.desc, &end.desc) {
+                    (ExprDesc::Int(_), ExprDesc::Int(_))
+                    | (ExprDesc::Float(_), ExprDesc::Float(_)) => (),
+                    _ => {
+                        return Err(EvalErrorDesc::InvalidType(
+                            "Range bounds must both be ints or floats",
+                        )
+                        .with_pos(pos))
+                    }
+                }
+                stack.push(
+                    ExprDesc::Range(Box::new(start), Box::new(end), *inclusive).with_pos(pos),
+                );
+            }
+
+            Op::Jump(target) => ip = *target,
+            Op::JumpIfFalse(target, message) => match stack.pop().map(|value| value.desc) {
+                Some(ExprDesc::Bool(true)) => (),
+                Some(ExprDesc::Bool(false)) => ip = *target,
+                _ => return Err(EvalErrorDesc::InvalidType(message).with_pos(pos)),
+            },
+            Op::AndJump(target) | Op::OrJump(target) => {
+                let short_circuit = matches!(op, Op::OrJump(_));
+                match stack.pop().map(|value| value.desc) {
+                    Some(ExprDesc::Bool(b)) if b == short_circuit => {
+                        stack.push(ExprDesc::Bool(b).with_pos(pos));
+                        ip = *target;
+                    }
+                    Some(ExprDesc::Bool(_)) => (),
+                    _ => return Err(EvalErrorDesc::InvalidType("Expected a bool").with_pos(pos)),
+                }
+            }
+            Op::ExpectBool => match stack.last().map(|value| &value.desc) {
+                Some(ExprDesc::Bool(_)) => (),
+                _ => return Err(EvalErrorDesc::InvalidType("Expected a bool").with_pos(pos)),
+            },
+
+            Op::Call(site, n) => {
+                let args = pop_n(&mut stack, *n);
+                let site = &chunk.calls[*site];
+                let result = call(func, locals, scope, |scope| {
+                    scope.call_site(site, args, pos)
+                })?;
+                stack.push(result);
+            }
+            Op::CallLocal(slot, site, n) => {
+                let args = pop_n(&mut stack, *n);
+                let site = &chunk.calls[*site];
+                let result = match &locals[*slot] {
+                    Some(
+                        closure @ Expr {
+                            desc: ExprDesc::Closure(_, _),
+                            ..
+                        },
+                    ) => {
+                        let closure = closure.clone();
+                        call(func, locals, scope, |scope| {
+                            scope.call_closure(&closure, args, pos)
+                        })?
+                    }
+                    // A local that isn't a closure doesn't hide a function with the same name
+                    Some(value) if !scope.has_fn(&site.name) => {
+                        return Err(match value.desc {
+                            ExprDesc::Moved => EvalErrorDesc::MemberMovedValue,
+                            _ => EvalErrorDesc::NotCallable(value.desc.kind()),
+                        }
+                        .with_pos(pos))
+                    }
+                    _ => call(func, locals, scope, |scope| {
+                        scope.call_site(site, args, pos)
+                    })?,
+                };
+                stack.push(result);
+            }
+            Op::CallValue(n) => {
+                let args = pop_n(&mut stack, *n);
+                let target = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                let result = call(func, locals, scope, |scope| {
+                    scope.call_closure(&target, args, pos)
+                })?;
+                stack.push(result);
+            }
+
+            Op::Member(access) | Op::MemberLocal(_, access) | Op::MemberName(_, access) => {
+                let target = match op {
+                    Op::Member(_) => stack.pop(),
+                    _ => None,
+                };
+                let items = steps(&chunk.accesses[*access], &mut stack);
+                let result = match op {
+                    Op::MemberLocal(slot, _) => {
+                        let mut value = match locals[*slot].take() {
+                            Some(value) => value,
+                            None => {
+                                return Err(EvalErrorDesc::MissingReference(
+                                    chunk.slots[*slot].clone(),
+                                )
+                                .with_pos(pos))
+                            }
+                        };
+                        let result = borrowed_member_access(&mut value, items, scope, pos);
+                        locals[*slot] = Some(value);
+                        result?
+                    }
+                    Op::MemberName(name, _) => {
+                        let name = &chunk.names[*name];
+                        let mut value = match scope.get_dynamic_mut(name) {
+                            Some(value) => std::mem::replace(value, ExprDesc::Moved.into()),
+                            None => {
+                                return Err(
+                                    EvalErrorDesc::MissingReference(name.clone()).with_pos(pos)
+                                )
+                            }
+                        };
+                        let result = borrowed_member_access(&mut value, items, scope, pos);
+                        if let Some(v) = scope.get_dynamic_mut(name) {
+                            *v = value;
+                        }
+                        result?
+                    }
+                    _ => {
+                        let mut target = target.unwrap_or_else(|| ExprDesc::Unit.into());
+                        for step in items {
+                            target = step_move(target, step, scope, pos)?;
+                        }
+                        target
+                    }
+                };
+                stack.push(result);
+            }
+
+            Op::Assign(slot, members) => {
+                let steps = steps(&chunk.accesses[*members], &mut stack);
+                let value = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                let mut target = match locals[*slot].as_mut() {
+                    Some(target) => target,
+                    None => {
+                        return Err(EvalErrorDesc::MissingReference(chunk.slots[*slot].clone())
+                            .with_pos(pos))
+                    }
+                };
+                for step in steps {
+                    target = step_mut(target, step, pos)?;
+                }
+                *target = value;
+            }
+            Op::AssignName(name, members) => {
+                let steps = steps(&chunk.accesses[*members], &mut stack);
+                let value = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                let mut target = scope
+                    .get_assignable(&chunk.names[*name])
+                    .map_err(|desc| desc.with_pos(pos))?;
+                for step in steps {
+                    target = step_mut(target, step, pos)?;
+                }
+                *target = value;
+            }
+
+            Op::IterStart => {
+                let iterable = stack.pop().unwrap_or_else(|| ExprDesc::Unit.into());
+                iters.push(match iterable.desc {
+                    ExprDesc::Array(items) => LoopIter::Array(items.into_iter()),
+                    ExprDesc::Range(start, end, inclusive) => match (start.desc, end.desc) {
+                        (ExprDesc::Int(start), ExprDesc::Int(end)) if inclusive => {
+                            LoopIter::RangeInclusive(start..=end)
+                        }
+                        (ExprDesc::Int(start), ExprDesc::Int(end)) => LoopIter::Range(start..end),
+                        _ => {
+                            return Err(EvalErrorDesc::InvalidType(
+                                "Can only iterate over a range of ints",
+                            )
+                            .with_pos(pos))
+                        }
+                    },
+                    _ => {
+                        return Err(EvalErrorDesc::InvalidType(
+                            "Can only iterate over an array or a range",
+                        )
+                        .with_pos(pos))
+                    }
+                });
+            }
+            Op::IterNext(target) => match iters.last_mut().and_then(|iter| iter.next()) {
+                Some(item) => stack.push(item),
+                None => {
+                    iters.pop();
+                    ip = *target;
+                }
+            },
+            Op::IterPop => {
+                iters.pop();
+            }
+
+            Op::Lambda(idx) => {
+                let lambda = &chunk.lambdas[*idx];
+                let captured = pop_n(&mut stack, lambda.captures.len());
+                stack.push(ExprDesc::Closure(lambda.clone(), captured.into()).with_pos(pos));
+            }
+            Op::DefineFn(idx) => {
+                let (name, f) = &chunk.fns[*idx];
+                scope.set_compiled_fn(name, f.clone());
+            }
+            Op::Fail(idx) => return Err(chunk.errors[*idx].clone()),
+        }
+    }
+    Ok(stack.pop().unwrap_or_else(|| ExprDesc::Unit.into()))
+}
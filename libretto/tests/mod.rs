@@ -1,4 +1,3 @@
-use libretto;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -17,7 +16,8 @@ fn missing() {
             libretto::DeserializeErrorDesc::Message("missing field `name`".to_owned()).with_pos(
                 libretto::Pos {
                     start: (0, 0),
-                    end: (1, 34)
+                    end: (1, 34),
+                    file: 0,
                 }
             )
         ),
@@ -108,6 +108,7 @@ add10(awesome() * 2)
 }
 
 #[test]
+#[allow(deprecated)]
 fn file() {
     let mut scope = libretto::eval_file(
         r##"
@@ -121,6 +122,7 @@ fn party(x: any, y: any, z: any) {
 }
 
 #[test]
+#[allow(deprecated)]
 fn file_pointer() {
     let mut scope = libretto::eval_file(
         r##"
@@ -159,6 +161,7 @@ fn party(x: any, y: any, z: any, m: any) {
 // }
 
 #[test]
+#[allow(deprecated)]
 fn member_fns() {
     assert_eq!(
         libretto::call_fn!(
@@ -176,6 +179,48 @@ fn member_fns() {
     )
 }
 
+#[test]
+fn typed_functions() {
+    let mut scope = libretto::eval_file(
+        r#"
+fn scale(p: any, by: f32) { (p.x as f32 * by, p.y as f32 * by) }
+fn fail() -> i32 { 1 / 0 }
+"#,
+    )
+    .unwrap();
+    scope.register_fn("twice", |x: i32| x * 2);
+    let scale = scope.get_function::<(Point, f32), (f32, f32)>("scale").unwrap();
+    let point = Point { x: 1, y: 2, t: (0, 0.0), name: "p".into() };
+    assert_eq!(scale.call(&mut scope, (&point, 1.5)), Ok((1.5, 3.0)));
+    assert_eq!(scale.call(&mut scope, (point, 2.0)), Ok((2.0, 4.0)));
+    let twice = scope.get_function::<(i32,), i32>("twice").unwrap();
+    assert_eq!(twice.call(&mut scope, (4,)), Ok(8));
+
+    // arity and missing functions are checked when looking up
+    let err = scope.get_function::<(i32,), (f32, f32)>("scale").unwrap_err();
+    assert!(matches!(
+        err,
+        libretto::Error::EvalError(libretto::EvalError {
+            desc: libretto::EvalErrorDesc::FunctionWrongNumberArgs(2, 1),
+            ..
+        })
+    ));
+    let err = scope.get_function::<(), i32>("missing").unwrap_err();
+    assert!(matches!(
+        err,
+        libretto::Error::EvalError(libretto::EvalError {
+            desc: libretto::EvalErrorDesc::MissingReference(_),
+            ..
+        })
+    ));
+
+    // errors from running the function or converting its result are `libretto::Error`s too
+    let fail = scope.get_function::<(), i32>("fail").unwrap();
+    assert!(matches!(fail.call(&mut scope, ()), Err(libretto::Error::EvalError(_))));
+    let wrong = scope.get_function::<(i32,), String>("twice").unwrap();
+    assert!(matches!(wrong.call(&mut scope, (1,)), Err(libretto::Error::DeserializeError(_))));
+}
+
 #[test]
 fn ifs() {
     assert_eq!(
@@ -231,6 +276,81 @@ let aa = log(stuff.x + stuff.y);
     )
 }
 
+#[test]
+fn references() {
+    let mut scopes = both_modes(
+        r##"
+fn sum(v: any) { v.0 + v.1 }
+fn shared(v: any) {
+  let a = sum(&v);
+  let b = sum(&v);
+  a + b + sum(v)
+}
+fn captured(v: any) {
+  let f = |x| sum(&v) + x;
+  f(1) + f(2) + v.0
+}
+fn temporary(v: any) {
+  sum(&(v.0, v.1)) + sum(&v)
+}
+fn moved(v: any) {
+  let a = sum(v);
+  sum(v)
+}
+fn borrow_after_move(v: any) {
+  let a = sum(v);
+  sum(&v)
+}
+fn copied(v: any) {
+  let mut w = &v;
+  w.0 = 10;
+  v.0 + w.0
+}
+fn id(x: any) { x }
+fn passed_after_move(v: any) {
+  let b = v;
+  id(v)
+}
+fn compared_after_move(v: any) {
+  let b = v;
+  v == (1, 2)
+}
+"##,
+    );
+    for name in [
+        "shared",
+        "captured",
+        "temporary",
+        "moved",
+        "borrow_after_move",
+        "copied",
+        "passed_after_move",
+        "compared_after_move",
+    ] {
+        assert_same(&mut scopes, name, vec!["(1, 2)"]);
+    }
+    for scope in &mut scopes {
+        let mut call = |name: &str| {
+            let args = vec![libretto::eval_expr("(1, 2)").unwrap()];
+            scope
+                .call_fn_raw(name, args, libretto::Pos::default())
+                .map(|v| v.clear_pos())
+                .map_err(|e| e.desc)
+        };
+        let int = |n| libretto::eval_expr(n).unwrap().clear_pos();
+        assert_eq!(call("shared"), Ok(int("9")));
+        assert_eq!(call("captured"), Ok(int("10")));
+        assert_eq!(call("temporary"), Ok(int("6")));
+        // `&v` is a copy, so changing it leaves `v` alone
+        assert_eq!(call("copied"), Ok(int("11")));
+        let moved = Err(libretto::EvalErrorDesc::MemberMovedValue);
+        assert_eq!(call("moved"), moved);
+        assert_eq!(call("borrow_after_move"), moved);
+        assert_eq!(call("passed_after_move"), moved);
+        assert_eq!(call("compared_after_move"), moved);
+    }
+}
+
 #[test]
 fn matches() {
     assert_eq!(
@@ -265,6 +385,37 @@ res
     )
 }
 
+#[test]
+fn block_values() {
+    let source = r##"
+fn run(n: i32) -> i32 {
+  let y = { let two = 2; two * n };
+  let mut total = { 1 };
+  for i in 0..n {
+    match i % 3 {
+      0 => { total += i; },
+      1 => { let half = i / 2; total += half },
+      _ => {}
+    }
+  }
+  total + y + { 0 }
+}
+"##;
+    let mut scopes = both_modes(source);
+    assert_same(&mut scopes, "run", vec!["10"]);
+    assert_eq!(
+        scopes[0]
+            .call_fn_raw("run", vec![10.into()], libretto::Pos::default())
+            .map(|v| v.clear_pos())
+            .map_err(|e| e.desc),
+        Ok(44.into())
+    );
+    assert_eq!(
+        libretto::from_expr::<i32>(&libretto::eval_expr("let y = { 1 }; y + 1").unwrap()),
+        Ok(2)
+    );
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 enum Party {
     Big,
@@ -297,3 +448,1752 @@ fn enumm() {
         ])
     )
 }
+
+#[test]
+fn closures() {
+    assert_eq!(
+        libretto::from_expr::<Vec<i32>>(
+            &libretto::eval_expr(
+                r##"
+fn apply(f: any, x: any) {
+  f(x)
+}
+fn adder(n: any) {
+  |x| x + n
+}
+let offset = 10;
+let add_offset = |x| x + offset;
+let handlers = Handlers { double: |x: any| x * 2 };
+vec![1, 2].map(|x| add_offset(x)).push(apply(add_offset.clone(), 3));
+vec![
+  add_offset(1),
+  apply(|x| x * 3, 2),
+  adder(5)(1),
+  handlers.double(4),
+  vec![1, 2, 3].map(|b| b * offset).len(),
+]
+"##
+            )
+            .unwrap()
+        ),
+        Ok(vec![11, 6, 6, 8, 3])
+    )
+}
+
+#[test]
+fn call_cache() {
+    let mut scope = libretto::eval_file(
+        r#"
+fn helper() { 1 }
+fn twice() { helper() + helper() }
+"#,
+    )
+    .unwrap();
+    let twice = scope.get_function::<(), i32>("twice").unwrap();
+    assert_eq!(twice.call(&mut scope, ()).map_err(|_| ()), Ok(2));
+    // calls find a function that replaced the one they found before
+    scope.eval_input("fn helper() { 5 }").unwrap();
+    assert_eq!(twice.call(&mut scope, ()).map_err(|_| ()), Ok(10));
+}
+
+#[test]
+fn closure_wrong_args() {
+    assert_eq!(
+        libretto::eval_expr("let f = |a, b| a + b; f(1)").map_err(|e| e.desc),
+        Err(libretto::EvalErrorDesc::FunctionWrongNumberArgs(2, 1))
+    )
+}
+
+#[test]
+fn loops() {
+    assert_eq!(
+        libretto::from_expr::<Vec<i32>>(
+            &libretto::eval_expr(
+                r##"
+let res = vec![];
+for i in 0..4 {
+  if i == 1 {
+    continue;
+  };
+  res.push(i * 10);
+}
+for (a, b) in vec![(1, 2), (3, 4)] {
+  res.push(a + b)
+}
+for i in 1..=100 {
+  if i > 2 {
+    break;
+  };
+  res.push(i);
+}
+while res.len() < 10 {
+  res.push(0);
+}
+res
+"##
+            )
+            .unwrap()
+        ),
+        Ok(vec![0, 20, 30, 3, 7, 1, 2, 0, 0, 0])
+    )
+}
+
+#[test]
+fn range_bounds() {
+    let scopes = &mut both_modes(
+        r##"
+fn to_max() {
+    let mut count = 0;
+    for i in 2147483645..=2147483647 {
+        count += 1;
+    }
+    for i in -2147483648..-2147483646 {
+        count += 1;
+    }
+    count
+}
+"##,
+    );
+    for scope in scopes.iter_mut() {
+        let result = scope.call_fn_raw("to_max", vec![], libretto::Pos::default());
+        assert_eq!(result.map(|value| value.clear_pos()), Ok(5.into()));
+    }
+}
+
+#[test]
+fn range_methods() {
+    let scopes = &mut both_modes(
+        r#"
+fn collect(n: any) { (0..n).collect() }
+fn squares(n: any) { (1..=n).map(|i| i * i) }
+fn total(n: any) { (0..n).fold(0, |acc, i| acc + i) + (0..n).len() }
+fn missing(n: any) { (0..n).step_by(2) }
+"#,
+    );
+    assert_same(scopes, "collect", vec!["3"]);
+    assert_same(scopes, "squares", vec!["3"]);
+    assert_same(scopes, "total", vec!["4"]);
+    assert_same(scopes, "missing", vec!["4"]);
+    let squares = scopes[0].get_function::<(i32,), Vec<i32>>("squares").unwrap();
+    assert_eq!(squares.call(&mut scopes[0], (3,)), Ok(vec![1, 4, 9]));
+    let missing = scopes[0].get_function::<(i32,), i32>("missing").unwrap();
+    let err = missing.call(&mut scopes[0], (4,)).unwrap_err();
+    assert_eq!(err.to_string(), "No method `step_by` on range at 5:22");
+}
+
+#[test]
+fn break_outside_loop() {
+    assert_eq!(
+        libretto::eval_expr("fn stop() { break } stop()").map_err(|e| e.desc),
+        Err(libretto::EvalErrorDesc::BreakOutsideLoop)
+    )
+}
+
+#[test]
+fn assignment() {
+    assert_eq!(
+        libretto::from_expr::<(i32, f32, (f32, f32))>(
+            &libretto::eval_expr(
+                r##"
+let mut total = 0;
+for i in 0..5 {
+  total += i
+}
+total *= 2;
+let mut scale = 1.0;
+scale = scale / 4.0;
+let mut bone = Bone { offset: (0.0, 2.0) };
+bone.offset.0 = 1.0;
+bone.offset.1 -= 0.5;
+(total, scale, bone.offset)
+"##
+            )
+            .unwrap()
+        ),
+        Ok((20, 0.25, (1.0, 1.5)))
+    )
+}
+
+#[test]
+fn assign_immutable() {
+    let err = libretto::eval_expr("let x = 1;\nx = 2;\nx").unwrap_err();
+    assert_eq!(
+        err.desc,
+        libretto::EvalErrorDesc::AssignToImmutable("x".to_owned())
+    );
+    assert_eq!(err.pos.start, (2, 1));
+}
+
+#[test]
+fn operators() {
+    assert_eq!(
+        libretto::from_expr::<(bool, bool, bool, i32, i32, f32, i32, bool)>(
+            &libretto::eval_expr(
+                r##"
+let x = 3;
+let flag = false;
+(
+  x <= 3 && x >= 3,
+  !flag || missing(),
+  flag && missing(),
+  7 % 4 + -x,
+  1 << 4 | 3 & 6 ^ 1,
+  x * 0.5 + 1,
+  -(x - 10) * 2,
+  1 + 2 * 3 == 7 && 2.0 > 1,
+)
+"##
+            )
+            .unwrap()
+        ),
+        Ok((true, true, false, 0, 19, 2.5, 14, true))
+    )
+}
+
+#[test]
+fn divide_by_zero() {
+    assert_eq!(
+        libretto::eval_expr("let a = 1; a / 0").map_err(|e| e.desc),
+        Err(libretto::EvalErrorDesc::DivideByZero)
+    )
+}
+
+#[test]
+fn native_fns() {
+    let mut scope = libretto::Scope::new();
+    scope.register_fn("double", |x: f32| x * 2.0);
+    scope.register_fn("origin", || Point {
+        x: 0,
+        y: 0,
+        t: (0, 0.0),
+        name: "origin".to_owned(),
+    });
+    scope.register_fn("shift", |p: Point, dx: i32| Point { x: p.x + dx, ..p });
+    let result = libretto::process_expr("shift(origin(), 3).x + double(1.5)")
+        .unwrap()
+        .into_eval(&mut scope)
+        .unwrap();
+    assert_eq!(libretto::from_expr::<f32>(&result), Ok(6.0));
+}
+
+#[test]
+fn limits() {
+    let source = r##"
+fn forever(n: i32) -> i32 { forever(n + 1) }
+fn spin() { while true {} }
+fn count(n: i32) -> i32 {
+  let mut total = 0;
+  for i in 0..n { total += i }
+  total
+}
+fn grow() {
+  let mut s = "ab";
+  while true { s = s + s }
+}
+fn fill() {
+  let mut items = vec![];
+  while true { items.push(1) }
+}
+fn big() { "ab".repeat(1000) }
+"##;
+    for mut scope in both_modes(source) {
+        scope.set_limits(libretto::Limits {
+            fuel: Some(10_000),
+            max_depth: 3,
+            max_alloc: 100,
+            ..Default::default()
+        });
+        let mut call = |name: &str, args: &[&str]| {
+            let args = args.iter().map(|arg| libretto::eval_expr(arg).unwrap()).collect();
+            scope
+                .call_fn_raw(name, args, libretto::Pos::default())
+                .map(|v| v.clear_pos())
+                .map_err(|e| e.desc)
+        };
+        use libretto::EvalErrorDesc::{OutOfFuel, TooDeep, TooLarge};
+        assert_eq!(call("forever", &["0"]), Err(TooDeep(3)));
+        assert_eq!(call("spin", &[]), Err(OutOfFuel));
+        // Each call from the host gets a full tank
+        let sum = libretto::eval_expr("4950").unwrap().clear_pos();
+        assert_eq!(call("count", &["100"]), Ok(sum.clone()));
+        assert_eq!(call("count", &["100"]), Ok(sum));
+        assert_eq!(call("grow", &[]), Err(TooLarge(100)));
+        assert_eq!(call("fill", &[]), Err(TooLarge(100)));
+        assert_eq!(call("big", &[]), Err(TooLarge(100)));
+    }
+}
+
+#[test]
+fn default_limits() {
+    // Debug builds of the tree-walker use about 100KB of native stack for each call
+    std::thread::Builder::new()
+        .stack_size(64 << 20)
+        .spawn(|| {
+            let source = "fn count(n: i32) -> i32 { if n == 0 { 0 } else { 1 + count(n - 1) } }";
+            for mut scope in both_modes(source) {
+                assert_eq!(scope.limits(), libretto::Limits::default());
+                let mut call = |n: i32| {
+                    scope
+                        .call_fn_raw("count", vec![n.into()], libretto::Pos::default())
+                        .map(|v| v.clear_pos())
+                        .map_err(|e| e.desc)
+                };
+                assert_eq!(call(100), Ok(100.into()));
+                assert_eq!(call(1000), Err(libretto::EvalErrorDesc::TooDeep(256)));
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn native_fn_errors() {
+    let mut scope = libretto::Scope::new();
+    scope.register_fn("double", |x: f32| x * 2.0);
+    let err = libretto::process_expr("1 + double(1, 2)")
+        .unwrap()
+        .into_eval(&mut scope)
+        .unwrap_err();
+    assert_eq!(err.desc, libretto::EvalErrorDesc::FunctionWrongNumberArgs(1, 2));
+    assert_eq!(err.pos.start, (1, 5));
+    let err = libretto::process_expr(r#"double("two")"#)
+        .unwrap()
+        .into_eval(&mut scope)
+        .unwrap_err();
+    match err.desc {
+        libretto::EvalErrorDesc::NativeArgument(0, _) => (),
+        other => panic!("unexpected error {:?}", other),
+    }
+}
+
+/// Evaluates the file twice, once running functions as bytecode and once with the tree-walker
+fn both_modes(source: &str) -> Vec<libretto::Scope> {
+    let mut vm = libretto::eval_file(source).unwrap();
+    vm.set_bytecode(true);
+    let mut tree_walker = libretto::eval_file(source).unwrap();
+    tree_walker.set_bytecode(false);
+    vec![vm, tree_walker]
+}
+
+fn assert_same(scopes: &mut [libretto::Scope], name: &str, args: Vec<&str>) {
+    let results: Vec<_> = scopes
+        .iter_mut()
+        .map(|scope| {
+            let args = args
+                .iter()
+                .map(|arg| libretto::eval_expr(arg).unwrap())
+                .collect();
+            scope
+                .call_fn_raw(name, args, libretto::Pos::default())
+                .map_err(|e| e.desc)
+        })
+        .collect();
+    assert_eq!(results[0], results[1], "{}({:?})", name, args);
+}
+
+#[test]
+fn bytecode_matches_tree_walker() {
+    // The tree-walker takes far more native stack per call in debug builds than the test
+    // threads have
+    std::thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(compare_modes)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[allow(deprecated)]
+fn compare_modes() {
+    let scopes = &mut both_modes(include_str!("../../assets/skeletons.lt.rs"));
+    for arm_action in &[
+        "None",
+        "Throw((1.0, -1.0))",
+        "Throw((-1.0, 0.5))",
+        "Bow((0.5, 0.5))",
+        r#"Swing { position: 0.5, forward: true, object: "sword.png", direction: Down }"#,
+    ] {
+        for pointing in &["None", "Some((1.0, 0.0))"] {
+            for action in &["Walk", "Jump"] {
+                let ctx = format!(
+                    "Ctx {{ facing: Right, action: {}, pointing: {}, arm_action: {}, timer: 120.0 }}",
+                    action, pointing, arm_action
+                );
+                assert_same(scopes, "female", vec![&ctx, "(1.0, 0.0)"]);
+            }
+        }
+        assert_same(scopes, "tool_tip", vec![arm_action, "Left"]);
+    }
+
+    let scopes = &mut both_modes(
+        r##"
+fn fib(n: any) {
+    if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+}
+fn loops(n: any) {
+    let mut total = 0;
+    let items = vec![];
+    for i in 0..n {
+        if i % 2 == 0 { continue; };
+        let mut j = 0;
+        while j < i {
+            j += 1;
+            if j > 3 { break; };
+            total += i * j;
+        }
+        items.push((i, total));
+    }
+    for (i, t) in items.clone() {
+        if t > 30 || i == 100 { break };
+    }
+    (total, items, !(n > 3) && true, -n, n as f32 / 2.0)
+}
+fn closures(x: any) {
+    let add = |a, b| a + b + x;
+    let scale = 2;
+    let twice = |y| y * scale;
+    vec![1, 2, 3].map(|i| twice(i)).map(|i| add(i, 1))
+}
+fn nested_closures(x: any) {
+    let items = vec![1, 2];
+    let kept = vec![3];
+    let outer = |a| (|b| a + b + x)(a) + items.len() + (&kept).len();
+    outer(1) + outer(2) + kept.len()
+}
+fn assign_captured() {
+    let mut n = 0;
+    vec![1].map(|i| if i > 0 { n = i; });
+    n
+}
+fn matching(value: any) {
+    match value {
+        Some(Point {x, y}) => x + y,
+        Some(_) => -1,
+        None => 0,
+    }
+}
+fn inner() {
+    fn helper(a: any) { a * 3 }
+    helper(2) + helper(1)
+}
+fn dynamic() {
+    caller_local + 1
+}
+fn calls_dynamic() {
+    let caller_local = 41;
+    dynamic()
+}
+fn bad_break() {
+    break
+}
+fn immutable() {
+    let a = 1;
+    a = 2;
+    a
+}
+fn assigns() {
+    let mut point = Point {x: 1, y: (2, 3)};
+    point.y.1 = 10;
+    point.x += 5;
+    point
+}
+"##,
+    );
+    assert_same(scopes, "fib", vec!["8"]);
+    assert_same(scopes, "loops", vec!["10"]);
+    assert_same(scopes, "closures", vec!["10"]);
+    assert_same(scopes, "nested_closures", vec!["10"]);
+    assert_same(scopes, "assign_captured", vec![]);
+    assert_same(scopes, "matching", vec!["Some(Point {x: 1, y: 2})"]);
+    assert_same(scopes, "matching", vec!["Some(Other {x: 1, y: 2})"]);
+    assert_same(scopes, "matching", vec!["None"]);
+    assert_same(scopes, "matching", vec!["Some(3)"]);
+    assert_same(scopes, "calls_dynamic", vec![]);
+    // functions can see their callers' variables
+    assert_eq!(
+        libretto::call_fn!(scopes[0], "calls_dynamic",).map_err(|_: libretto::DeserializeError| ()),
+        Ok(42)
+    );
+    assert_same(scopes, "dynamic", vec![]);
+    assert_same(scopes, "bad_break", vec![]);
+    assert_same(scopes, "immutable", vec![]);
+    assert_same(scopes, "assigns", vec![]);
+    assert_same(scopes, "inner", vec![]);
+    assert_same(scopes, "fib", vec!["1", "2"]);
+}
+
+/// Writes script files into a fresh temporary directory
+fn script_dir(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("libretto-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (file, source) in files {
+        std::fs::write(dir.join(file), source).unwrap();
+    }
+    dir
+}
+
+#[test]
+#[allow(deprecated)]
+fn modules() {
+    let dir = script_dir(
+        "modules",
+        &[
+            (
+                "main.lt.rs",
+                r#"
+use "helpers.lt.rs";
+mod math;
+const origin: any = math::origin;
+fn run() {
+    math::vector_mag((3.0, 4.0)) + double(math::inner::one())
+}
+fn private() {
+    square(2.0)
+}
+"#,
+            ),
+            ("helpers.lt.rs", "fn double(x: any) { x * 2.0 }\nfn bad() {\n    1 / 0\n}"),
+            (
+                "math.lt.rs",
+                r#"
+mod inner;
+const origin: any = (0.0, 0.0);
+fn square(x: any) { x * x }
+fn vector_mag(v: any) {
+    let (x, y) = v;
+    square(x) + square(y)
+}
+"#,
+            ),
+            ("inner.lt.rs", "fn one() { 1.0 }"),
+        ],
+    );
+    let mut scope = libretto::eval_path(dir.join("main.lt.rs")).unwrap();
+    // a function in a module can still call the module's other functions
+    let vector_mag = scope.get_function::<((f32, f32),), f32>("math::vector_mag").unwrap();
+    for bytecode in &[true, false] {
+        scope.set_bytecode(*bytecode);
+        assert_eq!(libretto::call_fn!(scope, "run",), Ok(27.0));
+        assert_eq!(vector_mag.call(&mut scope, ((3.0, 4.0),)), Ok(25.0));
+        assert_eq!(
+            scope.call_fn_raw("private", vec![], libretto::Pos::default()).map_err(|e| e.desc),
+            Err(libretto::EvalErrorDesc::MissingReference("square".to_owned()))
+        );
+    }
+    assert_eq!(
+        scope.get_raw("origin"),
+        Some(&libretto::eval_expr("(0.0, 0.0)").unwrap().clear_pos())
+    );
+
+    // loading the module again doesn't change a function that was already looked up, or
+    // the module it runs in
+    let math = "fn square(x: any) { x }\nfn vector_mag(v: any) {\n    let (x, y) = v;\n    square(x) + square(y)\n}";
+    std::fs::write(dir.join("math.lt.rs"), math).unwrap();
+    std::fs::write(dir.join("reload.lt.rs"), "mod math;").unwrap();
+    scope.load_file(dir.join("reload.lt.rs")).unwrap();
+    for bytecode in &[true, false] {
+        scope.set_bytecode(*bytecode);
+        assert_eq!(vector_mag.call(&mut scope, ((3.0, 4.0),)), Ok(25.0));
+        let reloaded = scope.get_function::<((f32, f32),), f32>("math::vector_mag").unwrap();
+        assert_eq!(reloaded.call(&mut scope, ((3.0, 4.0),)), Ok(7.0));
+    }
+
+    // errors know which file they came from
+    let err = scope.call_fn_raw("bad", vec![], libretto::Pos::default()).unwrap_err();
+    assert_eq!(err.desc, libretto::EvalErrorDesc::DivideByZero);
+    assert_eq!(err.pos.start, (3, 5));
+    assert!(scope.file_path(err.pos.file).unwrap().ends_with("helpers.lt.rs"));
+}
+
+#[test]
+fn module_errors() {
+    let dir = script_dir(
+        "module-errors",
+        &[
+            ("a.lt.rs", "use \"b.lt.rs\";"),
+            ("b.lt.rs", "\n  use \"a.lt.rs\";"),
+            ("missing.lt.rs", "mod nowhere;"),
+            ("syntax.lt.rs", "use \"broken.lt.rs\";"),
+            ("broken.lt.rs", "fn ("),
+        ],
+    );
+    match libretto::eval_path(dir.join("a.lt.rs")) {
+        Err(libretto::Error::EvalError(err)) => {
+            match err.desc {
+                libretto::EvalErrorDesc::ImportCycle(files) => {
+                    let names: Vec<_> = files.iter().map(|f| f.rsplit('/').next().unwrap()).collect();
+                    assert_eq!(names, vec!["a.lt.rs", "b.lt.rs", "a.lt.rs"]);
+                }
+                other => panic!("Expected a cycle, got {:?}", other),
+            }
+            assert_eq!(err.pos.start, (2, 3));
+            assert_eq!(err.pos.file, 2);
+        }
+        other => panic!("Expected an error, got {:?}", other.map(|_| ())),
+    }
+    match libretto::eval_path(dir.join("missing.lt.rs")) {
+        Err(libretto::Error::EvalError(libretto::EvalError {
+            desc: libretto::EvalErrorDesc::ModuleNotFound(path, _),
+            ..
+        })) => assert!(path.ends_with("nowhere.lt.rs")),
+        other => panic!("Expected a missing module, got {:?}", other.map(|_| ())),
+    }
+    let mut scope = libretto::Scope::new();
+    match scope.load_file(dir.join("syntax.lt.rs")) {
+        Err(libretto::Error::EvalError(libretto::EvalError {
+            desc: libretto::EvalErrorDesc::Syntax(_),
+            pos,
+            ..
+        })) => assert!(scope.file_path(pos.file).unwrap().ends_with("broken.lt.rs")),
+        other => panic!("Expected a syntax error, got {:?}", other),
+    }
+}
+
+#[test]
+fn diagnostics() {
+    let source = "fn add(a: any, b: any) {\n    a + b\n}\nfn run() {\n    let x = 1;\n    add(x)\n}\n";
+    // the call is checked when the file is loaded
+    let err = libretto::eval_file(source).unwrap_err();
+    assert_eq!(
+        libretto::Diagnostic::new(&err).render("test.lt.rs", source),
+        "error: Function takes 2 arguments but 1 was given
+ --> test.lt.rs:6:5
+  |
+6 |     add(x)
+  |     ^^^^^^
+"
+    );
+
+    let mut scope = libretto::eval_file("fn add(a: any, b: any) {\n    a + b\n}\n").unwrap();
+    let err = scope
+        .call_fn_raw("add", vec![1.into(), true.into()], libretto::Pos::default())
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Cannot add at 2:5");
+
+    let dir = script_dir(
+        "diagnostics",
+        &[(
+            "immutable.lt.rs",
+            "fn reassign() {\n\tlet a = 1;\n\ta = 2;\n}\nreassign();\n",
+        )],
+    );
+    let mut scope = libretto::Scope::new();
+    let err = scope.load_file(dir.join("immutable.lt.rs")).unwrap_err();
+    let rendered = scope.render_error(&err);
+    assert!(rendered.starts_with("error: Cannot assign twice to immutable variable `a`\n --> "));
+    assert!(rendered.ends_with(
+        "immutable.lt.rs:3:2
+  |
+3 | \ta = 2;
+  | \t^^
+  |
+  = help: declare it with `let mut a`
+  = note: in `reassign`, called at 5:1
+"
+    ));
+}
+
+#[test]
+fn traces() {
+    let source = "fn outer(v: any) {\n    middle(v)\n}\nfn middle(v: any) {\n    inner(v) + 1\n}\nfn inner(v: any) {\n    v.missing\n}\nfn calls_missing() {\n    ok()\n}\n";
+    for mut scope in both_modes(source) {
+        let args = vec![libretto::eval_expr("(1, 2)").unwrap()];
+        let err = scope
+            .call_fn_raw("outer", args, libretto::Pos::default())
+            .unwrap_err();
+        let trace: Vec<_> = err
+            .trace
+            .iter()
+            .map(|frame| (frame.name.as_str(), frame.pos.start))
+            .collect();
+        assert_eq!(
+            trace,
+            vec![("inner", (5, 5)), ("middle", (2, 5)), ("outer", (0, 0))]
+        );
+        assert_eq!(
+            libretto::Diagnostic::new(&err.into()).notes,
+            vec![
+                "note: in `inner`, called at 5:5",
+                "note: in `middle`, called at 2:5",
+                "note: in `outer`",
+            ]
+        );
+
+        // a function that isn't found was never called, so it isn't in the trace
+        let err = scope
+            .call_fn_raw("calls_missing", vec![], libretto::Pos::default())
+            .unwrap_err();
+        assert_eq!(err.desc, libretto::EvalErrorDesc::MissingReference("ok".into()));
+        assert_eq!(
+            libretto::Diagnostic::new(&err.into()).notes,
+            vec!["note: in `calls_missing`"]
+        );
+        let err = scope
+            .call_fn_raw("ok", vec![], libretto::Pos::default())
+            .unwrap_err();
+        assert!(err.trace.is_empty());
+    }
+}
+
+#[test]
+fn parser_never_panics() {
+    assert_eq!(libretto::eval_expr("0x1F + 0b11 - 0o7"), Ok(27.into()));
+    for source in &[
+        "999999999999999999999999999999999999999999",
+        "'ab'",
+        r#""\q""#,
+        "1 +",
+        "fn (",
+        "vec![1, 2",
+        "",
+    ] {
+        match libretto::eval_expr(source) {
+            Err(libretto::EvalError {
+                desc: libretto::EvalErrorDesc::Syntax(_),
+                ..
+            }) => (),
+            other => panic!("Expected a syntax error for {:?}, got {:?}", source, other),
+        }
+    }
+
+    // every truncation of a real script should fail cleanly
+    let source = include_str!("../../assets/skeletons.lt.rs");
+    for end in (0..source.len()).step_by(101) {
+        if source.is_char_boundary(end) {
+            let _ = libretto::process_file(&source[..end]);
+        }
+    }
+}
+
+#[test]
+fn parse_recovery() {
+    let source = "fn good() { 1 }
+fn broken() {
+    let x = ;
+}
+fn also_good() { 2 }
+fn too_big() { 999999999999999999999999999999999999999999 }
+const fine: any = 3;
+";
+    let (stmts, errors) = libretto::process_file_recovering(source);
+    assert_eq!(stmts.len(), 3);
+    let lines: Vec<_> = errors
+        .iter()
+        .map(|err| match err.line_col {
+            pest::error::LineColLocation::Pos((line, _))
+            | pest::error::LineColLocation::Span((line, _), _) => line,
+        })
+        .collect();
+    assert_eq!(lines, vec![3, 6]);
+
+    let dir = script_dir("parse-recovery", &[("broken.lt.rs", source)]);
+    let mut scope = libretto::Scope::new();
+    let err = scope.load_file(dir.join("broken.lt.rs")).unwrap_err();
+    match &err {
+        libretto::Error::EvalError(libretto::EvalError {
+            desc: libretto::EvalErrorDesc::SyntaxErrors(errors),
+            ..
+        }) => assert_eq!(errors.len(), 2),
+        other => panic!("Expected syntax errors, got {:?}", other),
+    }
+    let rendered = scope.render_error(&err);
+    assert!(rendered.contains("3 |     let x = ;"));
+    assert!(rendered.contains("error: Invalid syntax: expected expression\n"));
+    assert!(rendered.contains("Invalid syntax: Invalid int"));
+
+    // messages name what was expected, rather than the grammar's rules
+    let err = libretto::eval_expr("let total = 1 +;\ntotal").unwrap_err();
+    assert_eq!(err.desc, libretto::EvalErrorDesc::Syntax("expected expression".to_owned()));
+    let err = libretto::eval_file("fn add(a: any, b: any) { a + }").unwrap_err();
+    assert!(err.to_string().ends_with("= expected expression"), "{}", err);
+}
+
+#[test]
+fn patterns() {
+    let scopes = &mut both_modes(
+        r##"
+fn classify(value: any) {
+    match value {
+        None => "nothing",
+        Some(0) | Some(1) => "small",
+        Some(n @ 2..=9) if n % 2 == 0 => "even digit",
+        Some(2..=9) => "odd digit",
+        Some(_) => "big",
+    }
+}
+fn unit(x: any) {
+    match x {
+        0.0..=1.0 => "unit",
+        _ => "other",
+    }
+}
+fn letter(c: any) {
+    match c {
+        'a'..='z' | 'A'..='Z' => true,
+        _ => false,
+    }
+}
+fn slices(items: any) {
+    match items {
+        [] => (0, 0),
+        [only] => (only, 0),
+        [first, .., 9] => (first, 9),
+        [first, rest @ ..] => (first, rest.len()),
+    }
+}
+fn swap(pair: any) {
+    match pair {
+        Left((a, b)) | Right((b, a)) => (a, b),
+    }
+}
+fn action(arm: any) {
+    match arm {
+        Throw(v) => v,
+        None => (0.0, 0.0),
+    }
+}
+fn strict(x: any) {
+    match x {
+        Point { x: 0, y } => y,
+    }
+}
+"##,
+    );
+    let cases: &[(&str, &[&str], &str)] = &[
+        ("classify", &["None"], r#""nothing""#),
+        ("classify", &["Some(1)"], r#""small""#),
+        ("classify", &["Some(4)"], r#""even digit""#),
+        ("classify", &["Some(7)"], r#""odd digit""#),
+        ("classify", &["Some(10)"], r#""big""#),
+        ("unit", &["0.5"], r#""unit""#),
+        ("unit", &["1.5"], r#""other""#),
+        ("letter", &["'q'"], "true"),
+        ("letter", &["'7'"], "false"),
+        ("slices", &["vec![]"], "(0, 0)"),
+        ("slices", &["vec![5]"], "(5, 0)"),
+        ("slices", &["vec![1, 2, 9]"], "(1, 9)"),
+        ("slices", &["vec![1, 2, 3]"], "(1, 2)"),
+        ("swap", &["Left((1, 2))"], "(1, 2)"),
+        ("swap", &["Right((1, 2))"], "(2, 1)"),
+        ("action", &["None"], "(0.0, 0.0)"),
+        ("action", &["Throw((1.0, 2.0))"], "(1.0, 2.0)"),
+    ];
+    for scope in scopes.iter_mut() {
+        for (name, args, expected) in cases {
+            let values = args.iter().map(|arg| libretto::eval_expr(arg).unwrap()).collect();
+            assert_eq!(
+                scope
+                    .call_fn_raw(name, values, libretto::Pos::default())
+                    .map(|result| result.clear_pos()),
+                Ok(libretto::eval_expr(expected).unwrap().clear_pos()),
+                "{}({:?})",
+                name,
+                args
+            );
+        }
+        let err = scope
+            .call_fn_raw(
+                "strict",
+                vec![libretto::eval_expr("Point { x: 1, y: Some(vec![1, 2]) }").unwrap()],
+                libretto::Pos::default(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.desc,
+            libretto::EvalErrorDesc::NonExhaustive("Point { x: 1, y: Some(vec![..]) }".to_owned())
+        );
+    }
+
+    match libretto::process_file("fn f(x: any) { match x { Some(a) | None => 1 } }") {
+        Err(err) => assert!(err.to_string().contains("Every alternative must bind the same variables")),
+        Ok(_) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn numeric_methods() {
+    let cases = &[
+        ("2.7.floor()", "2.0"),
+        ("2.2.ceil()", "3.0"),
+        ("(-2.5).round()", "-3.0"),
+        ("(-0.5).signum()", "-1.0"),
+        ("3.0.powi(2)", "9.0"),
+        ("4.0.powf(0.5)", "2.0"),
+        ("0.0.exp()", "1.0"),
+        ("1.0.ln()", "0.0"),
+        ("1.5.clamp(0.0, 1.0)", "1.0"),
+        ("2.0.lerp(4.0, 0.25)", "2.5"),
+        ("0.0.asin() + 1.0.acos() + 0.0.atan()", "0.0"),
+        ("180.0.to_radians().to_degrees()", "180.0"),
+        ("2.0.min(1)", "1.0"),
+        ("(-3).abs()", "3"),
+        ("3.min(5)", "3"),
+        ("3.max(5)", "5"),
+        ("2.pow(10)", "1024"),
+        ("12.clamp(0, 10)", "10"),
+        ("3.to_float()", "3.0"),
+        ("3.max(4.5)", "4.5"),
+        ("4.sqrt()", "2.0"),
+        ("1 + 0.5", "1.5"),
+        ("3 as f32 / 2.0", "1.5"),
+    ];
+    let errors = &[
+        ("2.pow(-1)", libretto::EvalErrorDesc::InvalidType("pow() takes a non-negative exponent")),
+        ("2.pow(40)", libretto::EvalErrorDesc::IntegerOverflow),
+        ("1.0.clamp(0.0)", libretto::EvalErrorDesc::FunctionWrongNumberArgs(2, 1)),
+        ("1.0.min(true)", libretto::EvalErrorDesc::InvalidType("Expected numbers")),
+        ("1.frobnicate()", libretto::EvalErrorDesc::UnknownFunction("frobnicate".to_owned())),
+    ];
+    let source: String = cases
+        .iter()
+        .map(|(expr, _)| *expr)
+        .chain(errors.iter().map(|(expr, _)| *expr))
+        .enumerate()
+        .map(|(i, expr)| format!("fn f{}() {{ {} }}\n", i, expr))
+        .collect();
+    for scope in both_modes(&source).iter_mut() {
+        for (i, (expr, expected)) in cases.iter().enumerate() {
+            assert_eq!(
+                scope
+                    .call_fn_raw(&format!("f{}", i), vec![], libretto::Pos::default())
+                    .map(|value| value.clear_pos()),
+                Ok(libretto::eval_expr(expected).unwrap().clear_pos()),
+                "{}",
+                expr
+            );
+        }
+        for (i, (expr, expected)) in errors.iter().enumerate() {
+            let name = format!("f{}", cases.len() + i);
+            let err = scope.call_fn_raw(&name, vec![], libretto::Pos::default()).unwrap_err();
+            assert_eq!(&err.desc, expected, "{}", expr);
+        }
+    }
+}
+
+#[test]
+fn strings() {
+    let cases = &[
+        (r#""female" + "_arm.png""#, r#""female_arm.png""#),
+        (r#"format!("{}_{}.png", "female", "arm")"#, r#""female_arm.png""#),
+        (r#"format!("{} at {:.2}", 'x', 1.0 / 3.0)"#, r#""x at 0.33""#),
+        (r#"format!("{:?} {} {}", "hi", 2.5, Some((1, true)))"#, r#""\"hi\" 2.5 Some((1, true))""#),
+        (r#"format!("[{:4}|{:4}] {{}}", 7, "ab")"#, r#""[   7|ab  ] {}""#),
+        (r#""abc".len()"#, "3"),
+        (r#""female_arm.png".contains("arm")"#, "true"),
+        (r#""female_arm.png".starts_with("male")"#, "false"),
+        (r#""female_arm.png".ends_with(".png")"#, "true"),
+        (r#""a,b,c".split(',')"#, r#"vec!["a", "b", "c"]"#),
+        (r#""Female".to_upper()"#, r#""FEMALE""#),
+        (r#""Female".to_lower()"#, r#""female""#),
+        (r#""female_arm".replace("arm", "leg")"#, r#""female_leg""#),
+        (r#"" 1.5 ".parse_float()"#, "Some(1.5)"),
+        (r#""one".parse_float()"#, "None"),
+        (r#""42".parse_int()"#, "Some(42)"),
+        (r#""ab".repeat(2)"#, r#""abab""#),
+        ("12.to_string() + 0.5.to_string()", r#""120.5""#),
+    ];
+    let source: String = cases
+        .iter()
+        .enumerate()
+        .map(|(i, (expr, _))| format!("fn f{}() {{ {} }}\n", i, expr))
+        .collect();
+    for scope in both_modes(&source).iter_mut() {
+        for (i, (expr, expected)) in cases.iter().enumerate() {
+            assert_eq!(
+                scope
+                    .call_fn_raw(&format!("f{}", i), vec![], libretto::Pos::default())
+                    .map(|value| value.clear_pos()),
+                Ok(libretto::eval_expr(expected).unwrap().clear_pos()),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    for source in &[
+        r#"fn f() { format!("{} {}", 1) }"#,
+        r#"fn f() { format!("{}", 1, 2) }"#,
+        r#"fn f() { format!("{name}", 1) }"#,
+        r#"fn f() { format!("{", 1) }"#,
+    ] {
+        assert!(libretto::process_file(source).is_err(), "{}", source);
+    }
+    let mut scope = libretto::eval_file(r#"fn f(n: any) { "a" + n }"#).unwrap();
+    assert_eq!(
+        scope.call_fn_raw("f", vec![1.into()], libretto::Pos::default()).unwrap_err().desc,
+        libretto::EvalErrorDesc::InvalidType("Cannot add")
+    );
+
+    // sprite names are built from the character's name
+    let mut scope = libretto::eval_file(include_str!("../../assets/skeletons.lt.rs")).unwrap();
+    let ctx = "Ctx { facing: Right, action: Walk, pointing: None, arm_action: None, timer: 0.0 }";
+    let skeleton = scope
+        .call_fn_raw(
+            "female",
+            vec![libretto::eval_expr(ctx).unwrap(), libretto::eval_expr("(1.0, 0.0)").unwrap()],
+            libretto::Pos::default(),
+        )
+        .unwrap();
+    assert!(skeleton.debug().contains(r#"bones: vec![Bone { sprite: "female_arm.png""#));
+}
+
+#[test]
+fn collections() {
+    let cases = &[
+        ("let v = vec![1, 2, 3]; v[1]", "2"),
+        ("let v = vec![1, 2]; let first = v[0]; v.len() + first", "3"),
+        ("let mut v = vec![1, 2, 3]; v[0] = 5; v[0] += 1; v", "vec![6, 2, 3]"),
+        (
+            "let mut bones = vec![Bone { rotation: 0.0 }]; bones[0].rotation = 1.5; bones[0].rotation",
+            "1.5",
+        ),
+        ("let grid = vec![vec![1, 2], vec![3, 4]]; let i = 1; grid[i][i - 1]", "3"),
+        (r#"let o = { a: 1, b: 2 }; o["b"]"#, "2"),
+        (r#"let mut o = { a: 1 }; o["a"] = 3; o.insert("c", 4); o"#, "{ a: 3, c: 4 }"),
+        (r#"let o = { a: 1 }; (o.get("a"), o.get("z"), o.contains_key("a"))"#, "(Some(1), None, true)"),
+        (r#"let mut o = { a: 1, b: 2 }; let a = o.remove("a"); (a, o.keys(), o.values())"#, r#"(Some(1), vec!["b"], vec![2])"#),
+        ("let v = vec![1, 2]; (v.get(1), v.get(2), v.get(-1))", "(Some(2), None, None)"),
+        ("let mut v = vec![1, 2]; let last = v.pop(); v.insert(0, 0); v.insert(2, 3); (last, v)", "(Some(2), vec![0, 1, 3])"),
+        ("let mut v = vec![1, 2, 3]; let second = v.remove(1); (second, v)", "(2, vec![1, 3])"),
+        ("vec![1, 2, 3, 4].iter().map(|x| x * 2).filter(|x| x > 4).collect()", "vec![6, 8]"),
+        ("vec![1, 2, 3].fold(0, |acc, x| acc + x * x)", "14"),
+        ("(vec![1, 2].any(|x| x > 1), vec![1, 2].all(|x| x > 1), vec![].all(|x| false))", "(true, false, true)"),
+        ("(vec![1, 2, 3].sum(), vec![0.5, 1].sum())", "(6, 1.5)"),
+        (r#"vec!["a", "b"].enumerate()"#, r#"vec![(0, "a"), (1, "b")]"#),
+        ("vec![1, 2, 3].zip(vec![4, 5])", "vec![(1, 4), (2, 5)]"),
+        ("vec![1, 2, 3].rev()", "vec![3, 2, 1]"),
+        ("let mut v = vec![3, 1, 2]; v.sort(); v", "vec![1, 2, 3]"),
+        (
+            "let mut v = vec![(1, 'a'), (3, 'b'), (1, 'c')]; v.sort_by(|a, b| b.0.cmp(a.0)); v",
+            "vec![(3, 'b'), (1, 'a'), (1, 'c')]",
+        ),
+        (r#"vec![1, 2].contains(2) && !vec!["a"].contains("b")"#, "true"),
+        (r#"vec!["female", "arm"].join("_")"#, r#""female_arm""#),
+        ("(vec![1].first(), vec![].last())", "(Some(1), None)"),
+    ];
+    let source: String = cases
+        .iter()
+        .enumerate()
+        .map(|(i, (expr, _))| format!("fn f{}() {{ {} }}\n", i, expr))
+        .collect();
+    for scope in both_modes(&source).iter_mut() {
+        for (i, (expr, expected)) in cases.iter().enumerate() {
+            assert_eq!(
+                scope
+                    .call_fn_raw(&format!("f{}", i), vec![], libretto::Pos::default())
+                    .map(|value| value.clear_pos()),
+                Ok(libretto::eval_expr(expected).unwrap().clear_pos()),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    let scopes = &mut both_modes(
+        "fn get(v: any, i: any) {
+    v[
+        i
+    ]
+}
+fn set(i: any) {
+    let mut v = vec![1];
+    v[i] = 2;
+    v
+}
+fn remove(i: any) {
+    let mut v = vec![1];
+    v.remove(i)
+}
+fn field(t: any) {
+    t.2
+}
+fn sort_badly() {
+    let mut v = vec![2, 1];
+    v.sort_by(|a, b| a < b);
+    v
+}
+",
+    );
+    for scope in scopes.iter_mut() {
+        let mut call = |name: &str, args: Vec<&str>| {
+            let args = args.iter().map(|arg| libretto::eval_expr(arg).unwrap()).collect();
+            scope.call_fn_raw(name, args, libretto::Pos::default()).unwrap_err()
+        };
+        let err = call("get", vec!["vec![1, 2]", "2"]);
+        assert_eq!(err.desc, libretto::EvalErrorDesc::IndexOutOfBounds(2, 2));
+        // the error points at the index, not the whole expression
+        assert_eq!((err.pos.start, err.pos.end), ((3, 9), (3, 10)));
+        assert_eq!(call("get", vec!["vec![1, 2]", "-1"]).desc, libretto::EvalErrorDesc::IndexOutOfBounds(-1, 2));
+        assert_eq!(call("get", vec!["(1, 2)", "0"]).desc, libretto::EvalErrorDesc::InvalidType("Can only index arrays and objects"));
+        assert_eq!(call("set", vec!["1"]).pos.start, (8, 7));
+        assert_eq!(call("remove", vec!["1"]).desc, libretto::EvalErrorDesc::IndexOutOfBounds(1, 1));
+        assert_eq!(call("field", vec!["(1, 2)"]).desc, libretto::EvalErrorDesc::IndexOutOfBounds(2, 2));
+        assert_eq!(
+            call("sort_badly", vec![]).desc,
+            libretto::EvalErrorDesc::InvalidType("sort_by() needs the closure to return Less, Equal or Greater")
+        );
+    }
+
+    // a sort that fails leaves the array as it was
+    let mut scope = libretto::Scope::new();
+    scope.eval_input("let mut v = vec![2.0, (-1.0).sqrt(), 1.0];").unwrap();
+    assert!(scope.eval_input("v.sort_by(|a, b| a < b)").is_err());
+    assert!(scope.eval_input("v.sort()").is_err());
+    assert_eq!(scope.eval_input("v.len()").unwrap().map(|value| value.clear_pos()), Some(3.into()));
+}
+
+#[test]
+fn declarations() {
+    let scopes = &mut both_modes(
+        "struct Bone {
+    sprite: String,
+    offset: (f32, f32),
+    flip: bool = false,
+    scale: f32 = 1.0,
+}
+enum Shape {
+    Capsule { width: f32, height: f32 },
+    Ball(f32),
+    Point,
+}
+struct Skeleton { shape: Shape, bones: Vec<Bone>, name: Option<String> }
+fn bone() {
+    Bone { offset: (0, 0.5), sprite: \"arm.png\" }
+}
+fn offset(y: any) {
+    Bone {
+        sprite: \"arm.png\",
+        offset: (0.0, y),
+    }
+}
+fn shapes() {
+    vec![Capsule { width: 1.0, height: 2 }, Ball(0.5), Point]
+}
+fn skeleton(shape: any) {
+    Skeleton { shape: shape, bones: vec![bone()], name: None }
+}
+fn undeclared() {
+    Ctx { anything: 1 }
+}
+",
+    );
+    for scope in scopes.iter_mut() {
+        let mut call = |name: &str, args: Vec<&str>| {
+            let args = args.iter().map(|arg| libretto::eval_expr(arg).unwrap()).collect();
+            scope.call_fn_raw(name, args, libretto::Pos::default()).map(|value| value.clear_pos())
+        };
+        let value = |expr: &str| Ok(libretto::eval_expr(expr).unwrap().clear_pos());
+        // defaults are filled in, fields end up in declaration order, and ints given for
+        // `f32`s become floats
+        assert_eq!(
+            call("bone", vec![]),
+            value(r#"Bone { sprite: "arm.png", offset: (0.0, 0.5), flip: false, scale: 1.0 }"#)
+        );
+        assert_eq!(call("shapes", vec![]), value("vec![Capsule { width: 1.0, height: 2.0 }, Ball(0.5), Point]"));
+        assert!(call("skeleton", vec!["Ball(1.0)"]).is_ok());
+        assert_eq!(call("undeclared", vec![]), value("Ctx { anything: 1 }"));
+        // values that can't be checked when the file is loaded are checked when they're built,
+        // and the error points at the field's value
+        let err = call("offset", vec![r#""up""#]).unwrap_err();
+        assert_eq!(err.to_string(), "Field `offset` of `Bone` should be `(f32, f32)`, found `(0.0, \"up\")` at 19:17");
+        assert!(call("skeleton", vec!["Bone { sprite: \"x\", offset: (0.0, 0.0) }"]).is_err());
+    }
+
+    let source = "struct Bone { sprite: String, offset: (f32, f32), scale: f32 = 1.0 }
+enum Shape { Ball(f32) }
+fn typo() {
+    Bone { sprit: \"arm.png\", offset: (0.0, 0.0) }
+}
+fn missing() {
+    Bone { sprite: \"arm.png\" }
+}
+fn wrong_type() {
+    Bone { sprite: \"arm.png\", offset: (0.0, \"up\") }
+}
+fn ball() {
+    Ball(0.5, 1.0)
+}
+struct Broken { part: Part }
+";
+    let errors = match libretto::eval_file(source) {
+        Err(libretto::Error::EvalError(libretto::EvalError {
+            desc: libretto::EvalErrorDesc::TypeErrors(errors),
+            ..
+        })) => errors,
+        other => panic!("{:?}", other),
+    };
+    let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "`Bone` has no field `sprit` at 4:5",
+            "`Bone` is missing the field `sprite` at 4:5",
+            "`Bone` is missing the field `offset` at 7:5",
+            "Field `offset` of `Bone` should be `(f32, f32)`, found `(f32, String)` at 10:39",
+            "`Ball` takes 1 field but 2 were given at 13:5",
+            "Unknown type `Part` at 15:1",
+        ]
+    );
+    assert_eq!(
+        libretto::Diagnostic::new(&errors[0].clone().into()).notes,
+        vec!["help: did you mean `sprite`?".to_owned()]
+    );
+}
+
+#[test]
+fn type_check() {
+    let scopes = &mut both_modes(
+        "const origin: (f32, f32) = (0, 0);
+struct Point { x: f32, y: f32 }
+fn scale(x: f32, by: i32) -> f32 {
+    x * by
+}
+fn point(p: (f32, f32)) -> Point {
+    let (x, y) = p;
+    Point { x: scale(x, 2), y: y }
+}
+fn names(first: String, count: i32) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for i in 0..count {
+        names.push(format!(\"{}{}\", first, i));
+    }
+    names
+}
+fn anything(x: any) -> Option<any> {
+    Some(x + 1)
+}
+fn doubled(items: Vec<f32>) -> Vec<f32> {
+    items.map(|x: f32| x * 2.0)
+}
+fn whole() -> Point {
+    Point { x: 1, y: 2 }
+}
+",
+    );
+    for scope in scopes.iter_mut() {
+        let mut call = |name: &str, args: Vec<&str>| {
+            let args = args.iter().map(|arg| libretto::eval_expr(arg).unwrap()).collect();
+            scope.call_fn_raw(name, args, libretto::Pos::default()).map(|value| value.clear_pos())
+        };
+        let value = |expr: &str| Ok(libretto::eval_expr(expr).unwrap().clear_pos());
+        assert_eq!(call("point", vec!["(1.5, 2.0)"]), value("Point { x: 3.0, y: 2.0 }"));
+        assert_eq!(call("names", vec![r#""a""#, "2"]), value(r#"vec!["a0", "a1"]"#));
+        assert_eq!(call("anything", vec!["1.5"]), value("Some(2.5)"));
+        assert_eq!(call("doubled", vec!["vec![0.5, 1.0]"]), value("vec![1.0, 2.0]"));
+        // ints given for `f32` fields are made floats
+        assert_eq!(call("whole", vec![]), value("Point { x: 1.0, y: 2.0 }"));
+    }
+
+    for (source, message) in &[
+        (r#"fn f() -> i32 { "a" }"#, "Expected `i32`, found `String` at 1:17"),
+        ("fn f(x: f32) { x }\nfn g() { f(true) }", "Expected `f32`, found `bool` at 2:12"),
+        ("fn f(x: f32) { x }\nfn g() { f(1, 2) }", "Function takes 1 argument but 2 were given at 2:10"),
+        ("fn f() { 1 + true }", "Expected numbers, found `i32` and `bool` at 1:10"),
+        ("fn f() { if 1 { 2 } else { 3 } }", "Expected `bool`, found `i32` at 1:13"),
+        (r#"fn f() { let (a, b) = (1, "x"); a * b }"#, "Expected numbers, found `i32` and `String` at 1:33"),
+        (r#"const a: f32 = "x";"#, "Expected `f32`, found `String` at 1:16"),
+        (r#"fn f() { let v: Vec<i32> = vec!["a"]; v }"#, "Expected `Vec<i32>`, found `Vec<String>` at 1:28"),
+        ("struct P { x: f32 }\nfn f(p: P) -> String { p.x }", "Expected `String`, found `f32` at 2:24"),
+        ("fn f(b: Bon) { b }", "Unknown type `Bon` at 1:14"),
+        ("struct P { x: f32 }\nfn b(p: P) -> f32 { p.z }", "`P` has no field `z` at 2:21"),
+        ("fn f(p: (f32, f32)) -> f32 { p.5 }", "Index 5 is out of bounds for length 2 at 1:30"),
+        ("fn h() { zzz }", "Cannot find `zzz` at 1:10"),
+        ("fn f() { let total = 1; totl + 1 }", "Cannot find `totl` at 1:25"),
+    ] {
+        match libretto::eval_file(source) {
+            Err(err) => assert_eq!(&err.to_string(), message, "{}", source),
+            Ok(_) => panic!("{} should not type check", source),
+        }
+    }
+    // a misspelled field suggests the one that was meant
+    match libretto::eval_file("struct P { x: f32 }\nfn b(p: P) -> f32 { p.z }") {
+        Err(libretto::Error::EvalError(libretto::EvalError {
+            desc: libretto::EvalErrorDesc::UnknownField(err),
+            ..
+        })) => assert_eq!(err.suggestion, Some("x".to_owned())),
+        other => panic!("Expected an unknown field, got {:?}", other.map(|_| ())),
+    }
+
+    // a bad edit to the skeletons is rejected when it's loaded, before anything runs
+    let source = include_str!("../../assets/skeletons.lt.rs").replace("scale: 0.3,", r#"scale: "big","#);
+    let dir = script_dir("type_check", &[("skeletons.lt.rs", &source)]);
+    let mut scope = libretto::Scope::new();
+    let err = scope.load_file(dir.join("skeletons.lt.rs")).unwrap_err();
+    assert!(scope
+        .render_error(&err)
+        .starts_with("error: Field `scale` of `Skeleton` should be `f32`, found `String`\n"));
+    assert!(scope.get_fn("female").is_none());
+}
+
+#[test]
+fn repl_input() {
+    let mut scope = libretto::Scope::new();
+    let mut eval = |input: &str| {
+        let value = scope.eval_input(input).map_err(|err| err.to_string())?;
+        Ok(value.map(|value| value.clear_pos()))
+    };
+    assert_eq!(eval("let mut x = 2;"), Ok(None));
+    assert_eq!(eval("fn double(n: i32) -> i32 { n * 2 }"), Ok(None));
+    assert_eq!(eval("x += 1; double(x)"), Ok(Some(6.into())));
+    // values that aren't moved by the input are still there for the next one
+    assert_eq!(eval("let v = vec![1, 2]; v.len()"), Ok(Some(2.into())));
+    assert_eq!(eval("&v"), Ok(Some(libretto::Expr::array(vec![1.into(), 2.into()]))));
+    // and printing a variable doesn't move it
+    assert_eq!(eval("v"), Ok(Some(libretto::Expr::array(vec![1.into(), 2.into()]))));
+    assert_eq!(eval("v"), Ok(Some(libretto::Expr::array(vec![1.into(), 2.into()]))));
+    assert_eq!(eval("let p = (1, vec![2]); p.1"), Ok(Some(libretto::Expr::array(vec![2.into()]))));
+    assert_eq!(eval("p.1"), Ok(Some(libretto::Expr::array(vec![2.into()]))));
+    assert_eq!(eval("double(true)"), Err("Expected `i32`, found `bool` at 1:8".to_owned()));
+
+    assert_eq!(scope.type_of("double(x) + 0.5").map(|typ| typ.to_string()), Ok("f32".to_owned()));
+    assert_eq!(scope.type_of("v").map(|typ| typ.to_string()), Ok("Vec<i32>".to_owned()));
+    let names: Vec<&str> = scope.functions().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["double"]);
+    assert_eq!(scope.get_fn("double").unwrap().signature.ret, libretto::TypeDecl::Int);
+
+    // brackets in strings, chars and comments don't keep an input open
+    for input in ["fn f() {\n", "vec![1,\n", "1 +\n", "let s = \"a {\n", "let s = r#\"(\n", "/* {\n"] {
+        assert!(libretto::input_is_unfinished(input), "{:?}", input);
+    }
+    for input in ["let s = \"}\";\n", "let c = '{';\n", "1 // {\n", "1 }\n", "\"a\" \"b\"\n"] {
+        assert!(!libretto::input_is_unfinished(input), "{:?}", input);
+    }
+}
+
+#[test]
+fn outline() {
+    let source = include_str!("../../assets/skeletons.lt.rs");
+    let outline = libretto::Outline::new(source);
+    let names: Vec<&str> = outline.symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+    assert_eq!(&names[..6], &["Bone", "Shape", "Skeleton", "run_freq", "leg_pos", "vx_sin"]);
+    let female = outline.symbols.iter().find(|symbol| symbol.name == "female").unwrap();
+    assert_eq!(female.detail, "fn female(context: any, velocity: any) -> Skeleton");
+    assert_eq!(female.kind, libretto::SymbolKind::Function);
+
+    // where the cursor is, counting from 1 like a `Pos`
+    let find = |needle: &str| {
+        let offset = source.find(needle).unwrap();
+        let line = source[..offset].matches('\n').count() + 1;
+        let col = offset - source[..offset].rfind('\n').map_or(0, |idx| idx + 1) + 1;
+        (line, col)
+    };
+    let definition = |at| outline.definition(source, at).map(|symbol| (symbol.detail.as_str(), symbol.name_pos.start));
+    assert_eq!(definition(find("run_freq)")), Some(("const run_freq: f32", (22, 7))));
+    assert_eq!(definition(find("vx_sin(context, velocity).abs()")), Some(("fn vx_sin(context: any, velocity: any) -> f32", (25, 4))));
+    assert_eq!(definition(find("velocity).abs()")), Some(("velocity: any", (33, 30))));
+    assert_eq!(definition(find("Capsule")), Some(("Capsule { width: f32, height: f32 }", (11, 5))));
+
+    let source = "struct Point { x: f32, y: f32 }
+fn point(scale: f32) -> Point {
+    let size = 2;
+    Point { x: scale * si, y: 1 }
+}";
+    let outline = libretto::Outline::new(source);
+    let completions = |at| {
+        let found = outline.completions(source, at);
+        found.iter().map(|symbol| symbol.name.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(completions((4, 26)), vec!["size"]);
+    assert_eq!(completions((4, 29)), vec!["y"]);
+    assert_eq!(completions((4, 13)), vec!["x", "y"]);
+    assert_eq!(completions((3, 5)), vec!["scale", "Point", "point"]);
+
+    let diagnostics = libretto::check_file("fn f() -> i32 { \"a\" }\nfn g( { }\nfn h(x: f32) { x }");
+    let messages: Vec<_> = diagnostics.iter().map(|diagnostic| (diagnostic.pos.start, diagnostic.message.as_str())).collect();
+    assert_eq!(messages.len(), 2, "{:?}", messages);
+    assert_eq!(messages[1], ((1, 17), "Expected `i32`, found `String`"));
+    let diagnostics = libretto::check_file("fn h() { zzz }");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!((diagnostics[0].pos.start, diagnostics[0].message.as_str()), ((1, 10), "Cannot find `zzz`"));
+}
+
+#[test]
+fn format() {
+    let source = include_str!("../../assets/skeletons.lt.rs");
+    let formatted = libretto::format_source(source).unwrap();
+    // the same program, positions aside, with the same comments
+    let reprint = |source: &str| libretto::to_source(&libretto::process_file(source).unwrap());
+    assert_eq!(reprint(&formatted), reprint(source));
+    let comments = |source: &str| source.lines().filter_map(|line| line.find("//").map(|idx| line[idx..].to_owned())).collect::<Vec<_>>();
+    assert_eq!(comments(&formatted), comments(source));
+    assert_eq!(libretto::format_source(&formatted).unwrap(), formatted);
+
+    let source = "// one
+const  a:i32=-(1+2)*3; // two
+fn f(x:i32)->f32{
+    let mut w=vec![1,2,3];w[0]+=x;
+
+
+    /* three */
+    for i in 0..3 { w.push(i) }
+    match w.len() {n if n>3=>1.0, _=>2.0}
+}";
+    let expected = "// one
+const a: i32 = -(1 + 2) * 3; // two
+
+fn f(x: i32) -> f32 {
+    let mut w = vec![1, 2, 3];
+    w[0] += x;
+
+    /* three */
+    for i in 0..3 {
+        w.push(i)
+    }
+    match w.len() {
+        n if n > 3 => 1.0,
+        _ => 2.0,
+    }
+}
+";
+    assert_eq!(libretto::format_source(source).unwrap(), expected);
+
+    let value = libretto::to_expr(&("a\"b", vec![Some(1.5), None], -2)).unwrap();
+    assert_eq!(value.to_string(), r#"("a\"b", vec![Some(1.5), None], -2)"#);
+    assert_eq!(libretto::eval_expr(&value.to_string()).unwrap(), value);
+}
+
+#[test]
+fn to_string() {
+    let parties = vec![
+        Party::Big,
+        Party::Tall(3),
+        Party::Popular { people: 12, reach: 2.5 },
+    ];
+    let text = libretto::to_string(&parties).unwrap();
+    assert_eq!(text, "vec![Big, Tall(3), Popular { people: 12, reach: 2.5 }]");
+
+    let points: Vec<Point> = (0..4)
+        .map(|i| Point { x: i, y: -i, t: (i, i as f32 / 2.0), name: format!("point \"{}\"", i) })
+        .collect();
+    let mut file = vec![];
+    libretto::to_writer_pretty(&mut file, &points).unwrap();
+    let text = String::from_utf8(file).unwrap();
+    assert!(text.starts_with("vec![\n    Point { x: 0, y: 0, t: (0, 0.0), name: \"point \\\"0\\\"\" },\n"), "{}", text);
+    let value = libretto::eval_expr(&text).unwrap();
+    assert_eq!(libretto::from_expr::<Vec<Point>>(&value), Ok(points));
+}
+
+/// Checks that a value comes back the same from ron, and from libretto both directly and
+/// through source text
+fn conforms<T>(value: T)
+where
+    T: Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    let ron_text = ron::ser::to_string(&value).unwrap();
+    let from_ron: T = ron::de::from_str(&ron_text).unwrap_or_else(|err| panic!("{}: {}", ron_text, err));
+    assert_eq!(from_ron, value, "ron");
+    round_trips(from_ron);
+}
+
+fn round_trips<T>(value: T)
+where
+    T: Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    let direct: T = libretto::from_expr(&libretto::to_expr(&value).unwrap()).unwrap();
+    assert_eq!(direct, value);
+    let text = libretto::to_string(&value).unwrap();
+    let parsed: T = libretto::from_expr(&libretto::eval_expr(&text).unwrap()).unwrap();
+    assert_eq!(parsed, value, "{}", text);
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Wide {
+    small: u8,
+    long: i64,
+    unsigned: u64,
+    double: f64,
+    float: f32,
+    #[serde(with = "bytes")]
+    bytes: Vec<u8>,
+}
+
+/// Like `serde_bytes`, writes a `Vec<u8>` with `serialize_bytes`
+mod bytes {
+    pub fn serialize<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        struct Bytes;
+        impl<'de> serde::de::Visitor<'de> for Bytes {
+            type Value = Vec<u8>;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("bytes")
+            }
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                Ok(v.to_vec())
+            }
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                Ok(v)
+            }
+        }
+        deserializer.deserialize_byte_buf(Bytes)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Unit;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Meters(f32);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Pair(i32, String);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+enum Untagged {
+    Number(i32),
+    Text(String),
+    Point { x: f32, y: f32 },
+    List(Vec<Untagged>),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Flattened {
+    name: String,
+    #[serde(flatten)]
+    extra: std::collections::BTreeMap<String, i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Ord, Eq)]
+enum Key {
+    Left,
+    Right,
+}
+
+#[test]
+fn serde_conformance() {
+    conforms(Wide {
+        small: 255,
+        long: i64::MIN + 1,
+        unsigned: u64::MAX,
+        double: 0.1,
+        float: 0.1,
+        bytes: vec![0, 1, 255],
+    });
+    conforms((i64::MAX, 3_000_000_000u32, 1e300f64, -0.5f64, f32::MAX));
+    conforms((5u8,));
+    conforms(((1, 2),));
+    conforms(((), Unit, Meters(1.5), Pair(-1, "a \"b\"\n".to_owned())));
+    conforms((Some(Some(1)), Some(None::<i32>), None::<Option<i32>>, 'x', "☃".to_owned()));
+    conforms(vec![Party::Big, Party::Tall(3), Party::Popular { people: 12, reach: 2.5 }]);
+    conforms(vec![
+        Untagged::Number(4),
+        Untagged::Text("four".to_owned()),
+        Untagged::Point { x: 1.0, y: -2.0 },
+        Untagged::List(vec![Untagged::Number(1)]),
+    ]);
+    let mut extra = std::collections::BTreeMap::new();
+    extra.insert("a".to_owned(), 1);
+    extra.insert("b".to_owned(), 2);
+    // ron 0.5 can't read flattened structs
+    round_trips(Flattened { name: "flat".to_owned(), extra });
+    let mut keys = std::collections::BTreeMap::new();
+    keys.insert(-3i64, vec![true]);
+    keys.insert(10_000_000_000, vec![]);
+    conforms(keys);
+    let mut keys = std::collections::BTreeMap::new();
+    keys.insert(Key::Left, 'l');
+    keys.insert(Key::Right, 'r');
+    conforms(keys);
+
+    let json: serde_json::Value = serde_json::from_str(r#"{"a": [1, 2.5, 0.1, "x", null, true], "b": {"c": -9007199254740993}}"#).unwrap();
+    conforms(json);
+
+    // scripts can convert the 64-bit numbers
+    let value = libretto::eval_expr("(7i64 as i32 + 1, 0.5f64 as f32, 1u64 as f32)").unwrap();
+    assert_eq!(libretto::from_expr::<(i32, f32, f32)>(&value), Ok((8, 0.5, 1.0)));
+    let err = libretto::eval_expr("5000000000i64 as i32").unwrap_err();
+    assert_eq!(err.desc, libretto::EvalErrorDesc::IntegerOverflow);
+
+    // and use them with operators, mixed with `i32`s and `f32`s
+    let number = |text: &str| libretto::eval_expr(text).map(|value| value.clear_pos().desc);
+    assert_eq!(number("0.1f64 + 1.0"), Ok(libretto::ExprDesc::Double(0.1 + 1.0)));
+    assert_eq!(number("0.1f64 > 0.0"), Ok(libretto::ExprDesc::Bool(true)));
+    assert_eq!(number("5000000000i64 * 2 - 1"), Ok(libretto::ExprDesc::Long(9_999_999_999)));
+    assert_eq!(number("-(5000000000i64 % 3)"), Ok(libretto::ExprDesc::Long(-2)));
+    assert_eq!(number("1i64 == 1"), Ok(libretto::ExprDesc::Bool(true)));
+    let mut scopes = both_modes(
+        "fn scaled(x: any) { x * 2.0 }
+fn later(t: any) { t + 1 > t }
+fn half(t: any) { t / 2 }
+",
+    );
+    for scope in &mut scopes {
+        let mut call = |name: &str, arg: libretto::Expr| {
+            scope.call_fn_raw(name, vec![arg], libretto::Pos::default()).map(|value| value.clear_pos().desc)
+        };
+        let double = libretto::to_expr(&0.1f64).unwrap();
+        assert_eq!(double.desc, libretto::ExprDesc::Double(0.1));
+        assert_eq!(call("scaled", double), Ok(libretto::ExprDesc::Double(0.2)));
+        let long = libretto::to_expr(&5_000_000_000i64).unwrap();
+        assert_eq!(call("later", long.clone()), Ok(libretto::ExprDesc::Bool(true)));
+        assert_eq!(call("half", long), Ok(libretto::ExprDesc::Long(2_500_000_000)));
+        assert_eq!(call("scaled", 1.5f32.into()), Ok(libretto::ExprDesc::Float(3.0)));
+    }
+
+    // numbers without a suffix are only `i32`s and `f32`s when nothing is lost
+    let numbers = libretto::from_data_str::<(f64, f64, u64, f32, i32)>("(0.3, 1.5e300, 3000000000, 0.3, -7)");
+    assert_eq!(numbers, Ok((0.3, 1.5e300, 3_000_000_000, 0.3, -7)));
+    assert_eq!(number("0.5"), Ok(libretto::ExprDesc::Float(0.5)));
+    assert_eq!(number("0.123456789"), Ok(libretto::ExprDesc::Double(0.123456789)));
+    assert_eq!(number("-3000000000"), Ok(libretto::ExprDesc::Long(-3_000_000_000)));
+    let json: serde_json::Value = libretto::from_data_str("vec![0.3, 3000000000]").unwrap();
+    assert_eq!(json.to_string(), "[0.3,3000000000]");
+
+    // and they have types, `i64` and `f64`
+    let mut scopes = both_modes(
+        "struct Wide { x: f64, n: i64 }
+fn wide(x: f64, n: i64) -> Wide { Wide { x: x, n: n } }
+fn pair(x: f64, n: i64) -> (f64, i64) { (x, n) }
+fn shifted(n: i64) -> i64 { n << 40 >> 2 }
+",
+    );
+    for scope in &mut scopes {
+        let pair = scope.get_function::<(f32, i32), (f64, i64)>("pair").unwrap();
+        assert_eq!(pair.call(scope, (0.3, 2)), Ok((0.3, 2)));
+        let value = scope.call_fn_raw("wide", vec![0.3f32.into(), 2.into()], libretto::Pos::default()).unwrap();
+        assert_eq!(value.clear_pos(), libretto::eval_expr("Wide { x: 0.3f64, n: 2i64 }").unwrap().clear_pos());
+        let shifted = scope.get_function::<(i64,), i64>("shifted").unwrap();
+        assert_eq!(shifted.call(scope, (3,)), Ok(3 << 38));
+    }
+    for (source, message) in [
+        ("fn f(n: i64) -> i32 { n }", "Expected `i32`, found `i64` at 1:23"),
+        ("fn f(x: f64) -> i64 { x }", "Expected `i64`, found `f64` at 1:23"),
+        ("fn f(x: f64) -> f64 { x << 1 }", "Expected an int, found `f64` at 1:23"),
+    ] {
+        match libretto::eval_file(source) {
+            Err(err) => assert_eq!(&err.to_string(), message, "{}", source),
+            Ok(_) => panic!("{} should not type check", source),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Weapon {
+    name: String,
+    kinds: Vec<Kind>,
+    sprites: Vec<(f32, String)>,
+    scale: Option<f32>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+enum Kind {
+    Projectile { launcher: Launcher, damage: i32 },
+    Edible(f32),
+    Fertilizer,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+enum Launcher {
+    Hand,
+    Bow,
+}
+
+#[test]
+fn data_files() {
+    let weapons: std::collections::BTreeMap<String, Weapon> = libretto::from_data_str(
+        r#"
+// consts and functions can come before the value
+const base_damage: i32 = 5;
+fn sprite(name: String) -> (f32, String) {
+    (0.0, format!("{}.png", name))
+}
+
+{
+    "apple": Weapon(
+        name: "Apple",
+        kinds: [Projectile(launcher: Hand, damage: base_damage / 5), Edible(5.0)],
+        sprites: [sprite("apple"), (70.0, "apple_rotten.png")],
+        scale: Some(0.5),
+    ),
+    /* a struct's name can be left out */
+    "arrow": (
+        name: "Arrow",
+        kinds: [Projectile(launcher: Bow, damage: base_damage * 2), Fertilizer,],
+        sprites: vec![sprite("arrow")],
+        scale: None,
+    ),
+}
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        weapons["apple"].kinds,
+        vec![Kind::Projectile { launcher: Launcher::Hand, damage: 1 }, Kind::Edible(5.0)]
+    );
+    assert_eq!(weapons["apple"].sprites[0], (0.0, "apple.png".to_owned()));
+    assert_eq!(
+        weapons["arrow"],
+        Weapon {
+            name: "Arrow".to_owned(),
+            kinds: vec![Kind::Projectile { launcher: Launcher::Bow, damage: 10 }, Kind::Fertilizer],
+            sprites: vec![(0.0, "arrow.png".to_owned())],
+            scale: None,
+        }
+    );
+    assert_eq!(libretto::from_data_str::<Pair>("(3, \"x\")"), Ok(Pair(3, "x".to_owned())));
+    assert!(libretto::from_data_str::<i32>("const x: i32 = 1;").is_err());
+    // RON's containers are only for data files
+    for source in ["[1, 2]", "(x: 1)", "Point(x: 1)"] {
+        assert!(libretto::eval_expr(source).is_err(), "{}", source);
+        assert!(libretto::from_data_str::<serde_json::Value>(source).is_ok(), "{}", source);
+    }
+
+    // the game's RON assets all load as they are
+    let mut files = 0;
+    for entry in std::fs::read_dir("../assets").unwrap() {
+        let path = entry.unwrap().path();
+        // scenery.ron is an empty placeholder
+        if path.extension().is_some_and(|ext| ext == "ron") && std::fs::metadata(&path).unwrap().len() > 0 {
+            let mut scope = libretto::Scope::new();
+            if let Err(err) = scope.load_data::<serde_json::Value, _>(&path) {
+                panic!("{}", scope.render_error(&err));
+            }
+            files += 1;
+        }
+    }
+    assert_eq!(files, 3);
+
+    // errors point into the file they came from
+    let dir = script_dir("data_files", &[("broken.ron", "{\n    \"rock\": (kinds: [Tool] sprites: []),\n}\n")]);
+    let mut scope = libretto::Scope::new();
+    let err = scope.load_data::<serde_json::Value, _>(dir.join("broken.ron")).unwrap_err();
+    let rendered = scope.render_error(&err);
+    assert!(rendered.contains("broken.ron:2:"), "{}", rendered);
+    let err = libretto::from_data_path::<i32, _>(dir.join("missing.ron")).unwrap_err();
+    assert!(err.to_string().contains("missing.ron"), "{}", err);
+
+    // data files read the way ron reads them
+    let source = "(
+    exponent: 1e5,
+    small: -2.5e-3,
+    exact: 0.3,
+    huge: 1.5e300,
+    big: 3000000000,
+    names: {1: \"one\", -2: \"minus two\"},
+    chars: {'a': true},
+    bools: {false: 'b'},
+)
+";
+    let dir = script_dir("data_files_ron", &[("numbers.ron", source)]);
+    let numbers: Numbers = libretto::from_data_path(dir.join("numbers.ron")).unwrap();
+    assert_eq!(Ok(&numbers), ron::de::from_str::<Numbers>(source).as_ref());
+    assert_eq!((numbers.exact, numbers.huge, numbers.big), (0.3, 1.5e300, 3_000_000_000));
+    // map keys other than strings are only for data files
+    assert!(libretto::eval_expr("{1: 2}").is_err());
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Numbers {
+    exponent: f32,
+    small: f64,
+    exact: f64,
+    huge: f64,
+    big: u64,
+    names: std::collections::BTreeMap<i32, String>,
+    chars: std::collections::BTreeMap<char, bool>,
+    bools: std::collections::BTreeMap<bool, char>,
+}
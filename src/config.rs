@@ -1,6 +1,4 @@
-use ron::de::from_reader;
 use serde::Deserialize;
-use std::fs::File;
 use std::sync::Mutex;
 
 pub static CONFIG_FILE: &'static str = "assets/config.ron";
@@ -30,9 +28,8 @@ impl Default for Config {
     }
 }
 
-pub fn read(path: &str) -> ron::de::Result<Config> {
-    let f = File::open(path).expect("Failed opening file");
-    from_reader(f)
+pub fn read(path: &str) -> Result<Config, libretto::Error> {
+    libretto::from_data_path(path)
 }
 
 // fn get() -> &mut Config {
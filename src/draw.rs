@@ -206,7 +206,10 @@ impl<'a> System<'a> for Draw {
                     let r = collider.position().rotation.angle() * 180.0 / std::f32::consts::PI;
                     match skeleton_map.draw_new(&skeleton, &mut rd, &sheet, v, p.into(), r, 1.0) {
                         Ok(()) => (),
-                        Err(err) => println!("Failed to draw! Scripting error {:?}", err),
+                        Err(err) => println!(
+                            "Failed to draw!\n{}",
+                            skeleton_map.scope.render_error(&err)
+                        ),
                     };
                 }
             }
@@ -196,7 +196,14 @@ mod test {
 
         let mut skeletons = crate::skeletons::read(skel_file).unwrap();
         let sk = crate::skeletons::component::Skeleton::new("female");
-        let res = libretto::call_fn!(skeletons.scope, "female", sk, na::Vector2::new(0.0, 0.0));
+        let female = skeletons
+            .scope
+            .get_function::<(
+                crate::skeletons::component::Skeleton,
+                na::Vector2<f32>,
+            ), crate::skeletons::new::Skeleton>("female")
+            .unwrap();
+        let res = female.call(&mut skeletons.scope, (sk, na::Vector2::new(0.0, 0.0)));
         assert_eq!(res, Ok(crate::skeletons::new::Skeleton::default()))
         // skeletons.unwrap();
     }
@@ -439,14 +439,8 @@ impl<'a> System<'a> for PlayerSwing {
                     .unwrap()
                     .position()
                     .clone();
-                match libretto::call_fn!(
-                    skeleton_fns.scope,
-                    "tool_tip",
-                    skeleton.arm_action,
-                    skeleton.facing
-                ) {
+                match skeleton_fns.tool_tip(&skeleton.arm_action, skeleton.facing) {
                     Ok(tool_tip) => {
-                        let tool_tip: (f32, f32) = tool_tip;
                         let collider_body = physics.collider(player.tool).unwrap().body();
                         let body = physics.rigid_body_mut(collider_body).unwrap();
                         if let Some(body) = body.downcast_mut::<RigidBody<_>>() {
@@ -458,7 +452,10 @@ impl<'a> System<'a> for PlayerSwing {
                             ))
                         }
                     }
-                    Err(err) => println!("Failed to get tool tip {:?}", err),
+                    Err(err) => println!(
+                        "Failed to get tool tip\n{}",
+                        skeleton_fns.scope.render_error(&err)
+                    ),
                 };
             } else if let ArmAction::Swing { .. } = &skeleton.arm_action {
                 skeleton.arm_action = ArmAction::None;
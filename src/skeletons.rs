@@ -1,5 +1,5 @@
 use ron::de::from_reader;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fs::File};
 
 pub mod component {
@@ -111,7 +111,7 @@ pub mod new {
         1.0
     }
 
-    #[derive(Debug, Deserialize, Clone, PartialEq)]
+    #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
     pub struct Bone {
         pub sprite: String,
         // #[serde(default = "Animated::origin")]
@@ -126,7 +126,7 @@ pub mod new {
         pub rotation: f32,
     }
 
-    #[derive(Debug, Deserialize, PartialEq)]
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
     pub struct Skeleton {
         pub shape: Shape,
         pub scale: f32,
@@ -149,20 +149,45 @@ pub mod new {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub enum Shape {
     Capsule { width: f32, height: f32 },
     Ball { radius: f32 },
 }
 
+type DrawFn = libretto::TypedFn<(component::Skeleton, na::Vector2<f32>), new::Skeleton>;
+
 pub struct Skeletons {
     pub scope: libretto::Scope,
+    /// The function that draws each kind of skeleton, looked up the first time it's drawn
+    draw_fns: HashMap<String, DrawFn>,
+    /// `None` when the script doesn't have a `tool_tip`, which only matters once a tool is swung
+    tool_tip: Option<libretto::TypedFn<(component::ArmAction, component::Facing), (f32, f32)>>,
 }
 
 pub fn read(path: &str) -> Result<Skeletons, libretto::Error> {
-    let f = std::fs::read_to_string(path).expect("Failed opening file");
-    let scope = libretto::eval_file(&f)?;
-    Ok(Skeletons { scope })
+    let mut scope = libretto::Scope::new();
+    // Skeletons are drawn every frame, so a script stuck in a loop should fail instead of hanging
+    scope.set_limits(libretto::Limits {
+        fuel: Some(100_000),
+        ..Default::default()
+    });
+    let tool_tip = match scope.load_file(path).map(|()| scope.get_function("tool_tip")) {
+        Ok(Ok(tool_tip)) => Some(tool_tip),
+        Ok(Err(libretto::Error::EvalError(libretto::EvalError {
+            desc: libretto::EvalErrorDesc::MissingReference(_),
+            ..
+        }))) => None,
+        Ok(Err(err)) | Err(err) => {
+            println!("{}", scope.render_error(&err));
+            return Err(err);
+        }
+    };
+    Ok(Skeletons {
+        scope,
+        draw_fns: HashMap::new(),
+        tool_tip,
+    })
 }
 
 pub mod draw {
@@ -178,11 +203,28 @@ pub mod draw {
             rotation: f32,
             scale: f32,
         ) -> Result<(), libretto::Error> {
-            let sk: new::Skeleton =
-                libretto::call_fn!(self.scope, &state.name, state, velocity.linear)?;
+            if !self.draw_fns.contains_key(&state.name) {
+                let draw = self.scope.get_function(&state.name)?;
+                self.draw_fns.insert(state.name.clone(), draw);
+            }
+            let sk = self.draw_fns[&state.name].call(&mut self.scope, (state, velocity.linear))?;
             sk.draw(rd, &sheet, position, rotation, scale);
             Ok(())
         }
+
+        /// Where the end of a swung tool is, relative to the skeleton
+        pub fn tool_tip(
+            &mut self,
+            arm_action: &component::ArmAction,
+            facing: component::Facing,
+        ) -> Result<(f32, f32), libretto::Error> {
+            match &self.tool_tip {
+                Some(tool_tip) => tool_tip.call(&mut self.scope, (arm_action, facing)),
+                None => Err(libretto::EvalErrorDesc::MissingReference("tool_tip".to_owned())
+                    .with_pos(libretto::Pos::default())
+                    .into()),
+            }
+        }
     }
 
     impl new::Skeleton {